@@ -0,0 +1,256 @@
+#![cfg_attr(not(test), no_std)]
+
+/// Bitmap of physical frames, one bit per frame.
+///
+/// A set bit means the frame is free. Frames that are not usable memory
+/// (holes between memory map regions, reserved memory) stay cleared,
+/// so a single bitmap can span every usable region.
+pub struct SnFrameBitmap<'a> {
+    words: &'a mut [u64],
+    /// Frame number of the first bit
+    first_frame: u64,
+    /// Number of frames covered by the bitmap
+    frame_count: u64,
+    /// Number of frames that were ever marked free
+    usable_count: u64,
+    /// Number of frames currently free
+    free_count: u64,
+    /// No free frames below this word
+    next_free_word: usize,
+}
+
+/// Number of `u64` words needed to cover `frame_count` frames
+pub const fn words_for_frames(frame_count: u64) -> usize {
+    frame_count.div_ceil(64) as usize
+}
+
+fn align_up(value: u64, align: u64) -> u64 {
+    value.div_ceil(align) * align
+}
+
+impl<'a> SnFrameBitmap<'a> {
+    /// Creates a bitmap covering `frame_count` frames starting at `first_frame`.
+    /// All frames start out as used.
+    pub fn new(words: &'a mut [u64], first_frame: u64, frame_count: u64) -> SnFrameBitmap<'a> {
+        assert!(words.len() >= words_for_frames(frame_count), "bitmap too small");
+        words.fill(0);
+
+        SnFrameBitmap {
+            words,
+            first_frame,
+            frame_count,
+            usable_count: 0,
+            free_count: 0,
+            next_free_word: 0,
+        }
+    }
+
+    fn index_of(&self, frame: u64) -> Option<usize> {
+        if frame < self.first_frame || frame >= self.first_frame + self.frame_count {
+            return None;
+        }
+        Some((frame - self.first_frame) as usize)
+    }
+
+    fn set(&mut self, index: usize) {
+        self.words[index / 64] |= 1 << (index % 64);
+        self.free_count += 1;
+        self.next_free_word = self.next_free_word.min(index / 64);
+    }
+
+    fn clear(&mut self, index: usize) {
+        self.words[index / 64] &= !(1 << (index % 64));
+        self.free_count -= 1;
+    }
+
+    fn is_set(&self, index: usize) -> bool {
+        self.words[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    /// Marks frames of usable memory as free. Frames outside the bitmap are ignored.
+    pub fn add_usable(&mut self, first_frame: u64, count: u64) {
+        for frame in first_frame..first_frame + count {
+            if let Some(index) = self.index_of(frame)
+                && !self.is_set(index)
+            {
+                self.set(index);
+                self.usable_count += 1;
+            }
+        }
+    }
+
+    /// Takes frames out of the pool permanently, e.g. the bitmap's own storage.
+    pub fn reserve(&mut self, first_frame: u64, count: u64) {
+        for frame in first_frame..first_frame + count {
+            if let Some(index) = self.index_of(frame)
+                && self.is_set(index)
+            {
+                self.clear(index);
+                self.usable_count -= 1;
+            }
+        }
+    }
+
+    /// Returns true if the frame is free
+    pub fn is_free(&self, frame: u64) -> bool {
+        self.index_of(frame).is_some_and(|index| self.is_set(index))
+    }
+
+    /// Allocates any free frame
+    pub fn allocate(&mut self) -> Option<u64> {
+        self.allocate_below(u64::MAX)
+    }
+
+    /// Allocates a free frame with a frame number below `limit`
+    pub fn allocate_below(&mut self, limit: u64) -> Option<u64> {
+        for word_index in self.next_free_word..self.words.len() {
+            let word = self.words[word_index];
+            if word == 0 {
+                // Nothing free up to here
+                if word_index == self.next_free_word {
+                    self.next_free_word += 1;
+                }
+                continue;
+            }
+
+            let index = word_index * 64 + word.trailing_zeros() as usize;
+            let frame = self.first_frame + index as u64;
+            if frame >= limit || index as u64 >= self.frame_count {
+                return None;
+            }
+
+            self.clear(index);
+            return Some(frame);
+        }
+
+        None
+    }
+
+    /// Allocates `count` physically contiguous frames, with the first frame number
+    /// aligned to `align` frames and the last frame below `limit`.
+    ///
+    /// Returns the first frame number.
+    pub fn allocate_contiguous(&mut self, count: u64, align: u64, limit: u64) -> Option<u64> {
+        if count == 0 {
+            return None;
+        }
+
+        let end = (self.first_frame + self.frame_count).min(limit);
+        let mut start = align_up(self.first_frame, align.max(1));
+
+        while start + count <= end {
+            match (start..start + count).find(|frame| !self.is_free(*frame)) {
+                Some(used) => start = align_up(used + 1, align.max(1)),
+                None => {
+                    for frame in start..start + count {
+                        let index = self.index_of(frame).unwrap();
+                        self.clear(index);
+                    }
+                    return Some(start);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns a frame to the pool
+    pub fn free(&mut self, frame: u64) {
+        let index = self.index_of(frame).expect("freeing a frame outside of the bitmap");
+        assert!(!self.is_set(index), "double free of frame {:#x}", frame);
+        self.set(index);
+    }
+
+    /// Returns `count` contiguous frames to the pool
+    pub fn free_contiguous(&mut self, first_frame: u64, count: u64) {
+        for frame in first_frame..first_frame + count {
+            self.free(frame);
+        }
+    }
+
+    /// Number of usable frames managed by this bitmap
+    pub fn total_frames(&self) -> u64 {
+        self.usable_count
+    }
+
+    /// Number of free frames
+    pub fn free_frames(&self) -> u64 {
+        self.free_count
+    }
+
+    /// Number of allocated frames
+    pub fn used_frames(&self) -> u64 {
+        self.usable_count - self.free_count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_bitmap_allocate() {
+        let mut words = [0u64; 4];
+        let mut bitmap = SnFrameBitmap::new(&mut words, 0x100, 200);
+
+        // Two regions with a hole in between
+        bitmap.add_usable(0x100, 10);
+        bitmap.add_usable(0x180, 20);
+        assert_eq!(bitmap.total_frames(), 30);
+        assert_eq!(bitmap.free_frames(), 30);
+
+        for i in 0..10 {
+            assert_eq!(bitmap.allocate(), Some(0x100 + i));
+        }
+        // Skips over the hole
+        assert_eq!(bitmap.allocate(), Some(0x180));
+        assert_eq!(bitmap.used_frames(), 11);
+
+        bitmap.free(0x105);
+        assert_eq!(bitmap.allocate(), Some(0x105));
+    }
+
+    #[test]
+    fn test_frame_bitmap_exhaustion() {
+        let mut words = [0u64; 1];
+        let mut bitmap = SnFrameBitmap::new(&mut words, 0, 64);
+        bitmap.add_usable(0, 3);
+        bitmap.reserve(1, 1);
+
+        assert_eq!(bitmap.allocate(), Some(0));
+        assert_eq!(bitmap.allocate(), Some(2));
+        assert_eq!(bitmap.allocate(), None);
+        assert_eq!(bitmap.total_frames(), 2);
+    }
+
+    #[test]
+    fn test_frame_bitmap_below_limit() {
+        let mut words = [0u64; 2];
+        let mut bitmap = SnFrameBitmap::new(&mut words, 0, 128);
+        bitmap.add_usable(100, 10);
+
+        assert_eq!(bitmap.allocate_below(100), None);
+        assert_eq!(bitmap.allocate_below(101), Some(100));
+        assert_eq!(bitmap.allocate_below(101), None);
+    }
+
+    #[test]
+    fn test_frame_bitmap_contiguous() {
+        let mut words = [0u64; 2];
+        let mut bitmap = SnFrameBitmap::new(&mut words, 0, 128);
+        bitmap.add_usable(0, 128);
+        bitmap.reserve(5, 1);
+
+        // The run before the reserved frame is too short
+        assert_eq!(bitmap.allocate_contiguous(8, 1, u64::MAX), Some(6));
+        assert_eq!(bitmap.allocate_contiguous(4, 16, u64::MAX), Some(0));
+        // Alignment skips to the next multiple of 16
+        assert_eq!(bitmap.allocate_contiguous(4, 16, u64::MAX), Some(16));
+        // Does not fit below the limit
+        assert_eq!(bitmap.allocate_contiguous(48, 16, 64), None);
+
+        bitmap.free_contiguous(6, 8);
+        assert!(bitmap.is_free(6));
+        assert!(!bitmap.is_free(16));
+    }
+}
@@ -21,7 +21,7 @@ pub struct SnHardwareInfo<'a> {
 }
 
 pub fn init() {
-    printk!("acpi: initializing");
+    printk!("initializing");
     if let Some(req) = crate::limine::RSDP_REQUEST.get_response() {
         let addr = req.address();
         let handler = SnAcpiHandler::new();
@@ -30,17 +30,17 @@ pub fn init() {
         let acpi_table = acpi_result.as_ref().unwrap();
 
         if let Ok(acpi) = acpi_table.dsdt() {
-            printk!("acpi: DSDT: {:#x}", acpi.address);
+            printk!("DSDT: {:#x}", acpi.address);
         }
         for acpi in acpi_table.ssdts() {
-            printk!("acpi: SSDT: {:#x}", acpi.address);
+            printk!("SSDT: {:#x}", acpi.address);
         }
 
         let madt = acpi_table.find_table::<Madt>().unwrap();
-        printk!("acpi: MADT: {:#x}", madt.physical_start());
+        printk!("MADT: {:#x}", madt.physical_start());
 
         let fadt = acpi_table.find_table::<Fadt>().unwrap();
-        printk!("acpi: FADT: {:#x}", fadt.physical_start());
+        printk!("FADT: {:#x}", fadt.physical_start());
 
         let (interrupt_model, processor_info) = madt
             .get()
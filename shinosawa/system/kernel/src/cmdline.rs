@@ -0,0 +1,106 @@
+use conquer_once::spin::OnceCell;
+use log::LevelFilter;
+
+use crate::drivers::tty::SnTtyId;
+
+/// Program started as the first user process when there is no `init=`
+pub const DEFAULT_INIT: &str = "SNSW:/shinosawa/system/kotono";
+
+static BOOT_OPTIONS: OnceCell<SnBootOptions<'static>> = OnceCell::uninit();
+
+/// Options given on the kernel command line, as space separated `key=value` pairs:
+///
+/// - `log=<spec>`: which messages are logged, see `SnLogFilter`
+/// - `log.serial=<level>` and `log.fb=<level>`: what each sink shows,
+///   `log.fb=off` keeps the log off the screen
+/// - `init=<path>`: the first user program
+/// - `test=<filter>`: only run the tests with this in their name
+/// - `font=<name>`: console font, the Limine module whose path ends with it
+///   or a path on the VFS like `SNSW:/fonts/console.psf`
+/// - `keymap=<layout>`: keyboard layout, one of `us`, `uk`, `de`, `fr`, `no`,
+///   `fi`, `jp`, `dvorak`, `dvp` and `colemak`
+/// - `tty=<console|serial>`: terminal the first user program reads from,
+///   `serial` for headless runs
+#[derive(Debug)]
+pub struct SnBootOptions<'a> {
+    pub log: Option<&'a str>,
+    pub log_serial: Option<LevelFilter>,
+    pub log_fb: Option<LevelFilter>,
+    pub init: &'a str,
+    pub test_filter: Option<&'a str>,
+    pub font: Option<&'a str>,
+    pub keymap: Option<&'a str>,
+    pub tty: SnTtyId,
+}
+
+impl<'a> SnBootOptions<'a> {
+    pub fn parse(cmdline: &'a str) -> SnBootOptions<'a> {
+        let mut options = SnBootOptions {
+            log: None,
+            log_serial: None,
+            log_fb: None,
+            init: DEFAULT_INIT,
+            test_filter: None,
+            font: None,
+            keymap: None,
+            tty: SnTtyId::Console,
+        };
+
+        for option in cmdline.split_whitespace() {
+            let Some((key, value)) = option.split_once('=') else {
+                log::warn!("ignoring option {} without a value", option);
+                continue;
+            };
+
+            match key {
+                "log" => options.log = Some(value),
+                "log.serial" | "log.fb" => {
+                    let Ok(level) = value.parse::<LevelFilter>() else {
+                        log::warn!("invalid level {} for {}", value, key);
+                        continue;
+                    };
+                    if key == "log.serial" {
+                        options.log_serial = Some(level);
+                    } else {
+                        options.log_fb = Some(level);
+                    }
+                }
+                "init" => options.init = value,
+                "test" => options.test_filter = Some(value),
+                "font" => options.font = Some(value),
+                "keymap" => options.keymap = Some(value),
+                "tty" => match value {
+                    "console" => options.tty = SnTtyId::Console,
+                    "serial" => options.tty = SnTtyId::Serial,
+                    _ => log::warn!("unknown tty {}", value),
+                },
+                _ => log::warn!("unknown option {}", key),
+            }
+        }
+
+        options
+    }
+}
+
+/// Parses the command line given by the bootloader
+pub fn init() {
+    BOOT_OPTIONS.init_once(|| SnBootOptions::parse(crate::limine::cmdline()));
+}
+
+pub fn options() -> &'static SnBootOptions<'static> {
+    BOOT_OPTIONS.get().expect("boot options used before cmdline::init")
+}
+
+#[test_case]
+fn test_boot_options() {
+    let options = SnBootOptions::parse("log=debug,fs=trace log.fb=off init=SNSW:/other bogus test=logbuf tty=serial");
+
+    assert_eq!(options.log, Some("debug,fs=trace"));
+    assert_eq!(options.log_fb, Some(LevelFilter::Off));
+    assert_eq!(options.log_serial, None);
+    assert_eq!(options.init, "SNSW:/other");
+    assert_eq!(options.test_filter, Some("logbuf"));
+    assert_eq!(options.tty, SnTtyId::Serial);
+
+    assert_eq!(SnBootOptions::parse("").init, DEFAULT_INIT);
+}
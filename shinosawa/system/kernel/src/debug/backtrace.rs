@@ -0,0 +1,80 @@
+use crate::{
+    hal::interface::{cpu, paging},
+    memory::SnVirtAddr,
+    process::thread::USER_SPACE_END,
+};
+
+use super::symbols::{self, SnDemangle};
+
+/// Frames printed at most, in case the chain is corrupted
+const MAX_FRAMES: usize = 32;
+
+/// Walks the frame pointer chain, yielding return addresses.
+///
+/// Every frame starts with the frame pointer of the caller, followed by the
+/// return address. The walk stops at a frame pointer that is null, misaligned,
+/// unmapped, crosses between user and kernel space or does not move up the stack.
+pub fn walk(frame_pointer: u64) -> impl Iterator<Item = u64> {
+    let user = frame_pointer < USER_SPACE_END;
+    let mut frame_pointer = frame_pointer;
+
+    core::iter::from_fn(move || {
+        if frame_pointer == 0 || frame_pointer % 8 != 0 || (frame_pointer < USER_SPACE_END) != user {
+            return None;
+        }
+        let return_address_ptr = frame_pointer.checked_add(8)?;
+        if !paging::is_mapped(SnVirtAddr::new(frame_pointer))
+            || !paging::is_mapped(SnVirtAddr::new(return_address_ptr))
+        {
+            return None;
+        }
+
+        let (next, return_address) = unsafe {
+            (*(frame_pointer as *const u64), *(return_address_ptr as *const u64))
+        };
+        frame_pointer = if next > frame_pointer { next } else { 0 };
+
+        if return_address == 0 {
+            return None;
+        }
+        Some(return_address)
+    })
+    .take(MAX_FRAMES)
+}
+
+/// Prints the call chain starting at `frame_pointer`, after the address
+/// execution stopped at, if known.
+pub fn print_backtrace(instruction_pointer: Option<u64>, frame_pointer: u64) {
+    log::error!("backtrace:");
+
+    let mut idx = 0;
+    if let Some(instruction_pointer) = instruction_pointer {
+        print_frame(idx, instruction_pointer, instruction_pointer);
+        idx += 1;
+    }
+    for return_address in walk(frame_pointer) {
+        // The return address can be past the end of a function that does
+        // not return, the call itself is right before it
+        print_frame(idx, return_address, return_address - 1);
+        idx += 1;
+    }
+}
+
+fn print_frame(idx: usize, addr: u64, lookup_addr: u64) {
+    match symbols::resolve(lookup_addr) {
+        Some((symbol, _)) => log::error!(
+            "  #{:<2} {:#018x} {}+{:#x}",
+            idx,
+            addr,
+            SnDemangle(symbol.name),
+            addr - symbol.start
+        ),
+        None => log::error!("  #{:<2} {:#018x}", idx, addr),
+    }
+}
+
+/// Prints the call chain of the caller
+#[inline(never)]
+pub fn print_current() {
+    print_backtrace(None, cpu::frame_pointer());
+}
@@ -0,0 +1,582 @@
+use core::fmt::{self, Write};
+
+use alloc::boxed::Box;
+use conquer_once::spin::OnceCell;
+use spin::Mutex;
+
+use crate::{
+    hal::interface::{
+        cpu::{self, GDB_REGISTER_COUNT, SnCpuContext},
+        instruct,
+        interrupt::{BREAKPOINT_VECTOR, DEBUG_TRAP},
+        paging,
+    },
+    interrupt,
+    memory::SnVirtAddr,
+    printk,
+    process::thread::{self, SnThreadInfo},
+    serial::{self, COM2, SnSerialWriter},
+};
+
+/// IRQ of the second serial port
+const COM2_IRQ: u8 = 3;
+/// Largest packet we accept or send, without the framing
+const PACKET_SIZE: usize = 1024;
+const MAX_BREAKPOINTS: usize = 32;
+/// Sent by GDB to stop the target (Ctrl-C)
+const INTERRUPT_BYTE: u8 = 0x03;
+const INT3: u8 = 0xcc;
+const SIGTRAP: u8 = 5;
+/// Thread id of the trapped code when no thread is running
+const IDLE_THREAD_ID: u64 = 0x7fff_ffff;
+
+/// What to do after a packet
+enum SnGdbAction {
+    Stay,
+    Continue,
+    Step,
+}
+
+/// Reply being built, sent with `send_reply`
+struct SnPacket {
+    buf: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl SnPacket {
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len < PACKET_SIZE {
+            self.buf[self.len] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn push_str(&mut self, s: &str) {
+        s.bytes().for_each(|byte| self.push(byte));
+    }
+
+    fn push_hex(&mut self, byte: u8) {
+        hex_digits(byte).into_iter().for_each(|digit| self.push(digit));
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl fmt::Write for SnPacket {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s);
+        Ok(())
+    }
+}
+
+/// Writes text to a packet hex encoded, as some replies need
+struct SnHexWriter<'a>(&'a mut SnPacket);
+
+impl fmt::Write for SnHexWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.bytes().for_each(|byte| self.0.push_hex(byte));
+        Ok(())
+    }
+}
+
+/// State kept between stops
+struct SnDebugState {
+    /// Software breakpoints, with the byte int3 replaced
+    breakpoints: [Option<(u64, u8)>; MAX_BREAKPOINTS],
+    /// Thread picked with `Hg`, for register access. None is the trapped thread.
+    selected_thread: Option<u64>,
+    /// Id of the thread that trapped
+    current_thread: u64,
+}
+
+struct SnGdbStub {
+    serial: SnSerialWriter,
+    /// Start of a packet the interrupt handler took from the port
+    pending: Option<u8>,
+    packet: [u8; PACKET_SIZE],
+    reply: SnPacket,
+    state: SnDebugState,
+}
+
+static GDB: OnceCell<Mutex<SnGdbStub>> = OnceCell::uninit();
+
+/// Sets up the GDB remote stub on COM2.
+///
+/// GDB can attach at any time, or stop the kernel with Ctrl-C.
+pub fn init() {
+    printk!("listening on COM2");
+    let serial = unsafe { serial::init_port(COM2) };
+
+    GDB.init_once(move || {
+        Mutex::new(SnGdbStub {
+            serial,
+            pending: None,
+            packet: [0; PACKET_SIZE],
+            reply: SnPacket { buf: [0; PACKET_SIZE], len: 0 },
+            state: SnDebugState {
+                breakpoints: [None; MAX_BREAKPOINTS],
+                selected_thread: None,
+                current_thread: IDLE_THREAD_ID,
+            },
+        })
+    });
+    DEBUG_TRAP.init_once(move || trap);
+
+    interrupt::request_isa_irq(COM2_IRQ, Box::new(|| {
+        let Some(mut stub) = GDB.get().unwrap().try_lock() else {
+            return false;
+        };
+        let Some(byte) = stub.serial.try_receive_byte() else {
+            return false;
+        };
+
+        if byte == INTERRUPT_BYTE || byte == b'$' {
+            if byte == b'$' {
+                stub.pending = Some(byte);
+            }
+            drop(stub);
+            // Stop here, the stub takes over in the breakpoint handler
+            instruct::breakpoint();
+        }
+        true
+    }))
+    .expect("debug::gdb: cannot register the serial interrupt");
+}
+
+/// Runs the stub until GDB resumes execution
+fn trap(context: &mut SnCpuContext, vector: u8) {
+    // A breakpoint in the stub itself would wait forever
+    let Some(mut guard) = GDB.get().and_then(|gdb| gdb.try_lock()) else {
+        return;
+    };
+    let stub = &mut *guard;
+
+    context.set_single_step(false);
+    let rip = context.instruction_pointer() as u64;
+    // int3 stops right after itself
+    if vector == BREAKPOINT_VECTOR && stub.state.find_breakpoint(rip - 1).is_some() {
+        context.set_instruction_pointer((rip - 1) as usize);
+    }
+
+    stub.state.current_thread = IDLE_THREAD_ID;
+    thread::for_each_thread(|info| {
+        if info.running {
+            stub.state.current_thread = info.id;
+        }
+    });
+    stub.state.selected_thread = None;
+
+    // GDB attaching sends a packet first, otherwise tell it why we stopped
+    if stub.pending.is_none() {
+        stub.reply.clear();
+        stub.state.stop_reply(&mut stub.reply);
+        stub.send_reply();
+    }
+
+    loop {
+        let len = stub.receive_packet();
+
+        stub.reply.clear();
+        let action = stub.state.handle_packet(&stub.packet[..len], &mut stub.reply, context);
+
+        match action {
+            SnGdbAction::Stay => stub.send_reply(),
+            SnGdbAction::Continue => break,
+            SnGdbAction::Step => {
+                context.set_single_step(true);
+                break;
+            }
+        }
+    }
+}
+
+impl SnGdbStub {
+    /// Waits for a packet with a valid checksum, acknowledging it.
+    ///
+    /// Returns the length of the data in `packet`.
+    fn receive_packet(&mut self) -> usize {
+        loop {
+            let start = self.pending.take().unwrap_or_else(|| self.serial.receive_byte());
+            if start != b'$' {
+                continue;
+            }
+
+            let mut len = 0;
+            let mut sum: u8 = 0;
+            loop {
+                let byte = self.serial.receive_byte();
+                if byte == b'#' {
+                    break;
+                }
+                if len < PACKET_SIZE {
+                    self.packet[len] = byte;
+                    len += 1;
+                }
+                sum = sum.wrapping_add(byte);
+            }
+
+            let high = hex_digit(self.serial.receive_byte());
+            let low = hex_digit(self.serial.receive_byte());
+            match (high, low) {
+                (Some(high), Some(low)) if high << 4 | low == sum => {
+                    self.serial.send_byte(b'+');
+                    return len;
+                }
+                _ => self.serial.send_byte(b'-'),
+            }
+        }
+    }
+
+    /// Sends `reply` until GDB acknowledges it
+    fn send_reply(&mut self) {
+        loop {
+            let sum = self.reply.as_bytes().iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+
+            self.serial.send_byte(b'$');
+            for byte in self.reply.as_bytes() {
+                self.serial.send_byte(*byte);
+            }
+            self.serial.send_byte(b'#');
+            for digit in hex_digits(sum) {
+                self.serial.send_byte(digit);
+            }
+
+            loop {
+                match self.serial.receive_byte() {
+                    b'+' => return,
+                    b'-' => break,
+                    _ => continue,
+                }
+            }
+        }
+    }
+}
+
+impl SnDebugState {
+    fn stop_reply(&self, reply: &mut SnPacket) {
+        reply.push(b'T');
+        reply.push_hex(SIGTRAP);
+        let _ = write!(reply, "thread:{:x};", self.current_thread);
+    }
+
+    fn handle_packet(&mut self, packet: &[u8], reply: &mut SnPacket, context: &mut SnCpuContext) -> SnGdbAction {
+        let Some((&command, args)) = packet.split_first() else {
+            return SnGdbAction::Stay;
+        };
+
+        match command {
+            b'?' => self.stop_reply(reply),
+            b'g' => self.with_registers(context, |registers| {
+                for idx in 0..GDB_REGISTER_COUNT {
+                    let (value, size) = registers.gdb_register(idx).unwrap();
+                    value.to_le_bytes()[..size].iter().for_each(|byte| reply.push_hex(*byte));
+                }
+            }),
+            b'G' => {
+                let ok = self.with_registers(context, |registers| {
+                    let mut rest = args;
+                    for idx in 0..GDB_REGISTER_COUNT {
+                        let size = registers.gdb_register(idx).unwrap().1;
+                        let Some(value) = rest.get(..size * 2).and_then(parse_hex_le) else {
+                            break;
+                        };
+                        registers.set_gdb_register(idx, value);
+                        rest = &rest[size * 2..];
+                    }
+                    true
+                });
+                reply_ok(reply, ok);
+            }
+            b'p' => {
+                let value = parse_hex(args).and_then(|idx| {
+                    self.with_registers(context, |registers| registers.gdb_register(idx as usize))
+                });
+                match value {
+                    Some((value, size)) => {
+                        value.to_le_bytes()[..size].iter().for_each(|byte| reply.push_hex(*byte))
+                    }
+                    None => reply.push_str("E01"),
+                }
+            }
+            b'P' => {
+                let ok = split(args, b'=').and_then(|(idx, value)| {
+                    let (idx, value) = (parse_hex(idx)?, parse_hex_le(value)?);
+                    Some(self.with_registers(context, |registers| {
+                        registers.set_gdb_register(idx as usize, value)
+                    }))
+                });
+                reply_ok(reply, ok == Some(true));
+            }
+            b'm' => match split(args, b',').and_then(|(addr, len)| Some((parse_hex(addr)?, parse_hex(len)?))) {
+                Some((addr, len)) => {
+                    let len = len.min((PACKET_SIZE / 2) as u64);
+                    if is_accessible(addr, len) {
+                        for offset in 0..len {
+                            reply.push_hex(unsafe { *((addr + offset) as *const u8) });
+                        }
+                    } else {
+                        reply.push_str("E14");
+                    }
+                }
+                None => reply.push_str("E01"),
+            },
+            b'M' => {
+                let ok = split(args, b',')
+                    .and_then(|(addr, rest)| Some((parse_hex(addr)?, split(rest, b':')?.1)))
+                    .is_some_and(|(addr, data)| write_memory(addr, data));
+                reply_ok(reply, ok);
+            }
+            b'Z' | b'z' => {
+                // Only software breakpoints, an empty reply tells GDB the others aren't supported
+                if let Some(addr) = parse_breakpoint(args) {
+                    let ok = if command == b'Z' {
+                        self.insert_breakpoint(addr)
+                    } else {
+                        self.remove_breakpoint(addr)
+                    };
+                    reply_ok(reply, ok);
+                }
+            }
+            b'c' | b's' => {
+                if let Some(addr) = parse_hex(args) {
+                    context.set_instruction_pointer(addr as usize);
+                }
+                return if command == b'c' { SnGdbAction::Continue } else { SnGdbAction::Step };
+            }
+            b'D' => {
+                self.remove_all_breakpoints();
+                reply.push_str("OK");
+                return SnGdbAction::Continue;
+            }
+            b'k' => {
+                self.remove_all_breakpoints();
+                return SnGdbAction::Continue;
+            }
+            b'H' => {
+                if let Some((&b'g', thread_id)) = args.split_first() {
+                    // 0 is any thread and -1 all of them
+                    self.selected_thread = parse_hex(thread_id).filter(|id| *id != 0);
+                }
+                reply.push_str("OK");
+            }
+            b'T' => {
+                let alive = parse_hex(args)
+                    .is_some_and(|id| id == self.current_thread || self.thread_info(id).is_some());
+                reply_ok(reply, alive);
+            }
+            b'q' => self.handle_query(args, reply),
+            _ => {}
+        }
+
+        SnGdbAction::Stay
+    }
+
+    fn handle_query(&mut self, query: &[u8], reply: &mut SnPacket) {
+        if query.starts_with(b"Supported") {
+            let _ = write!(reply, "PacketSize={:x}", PACKET_SIZE);
+        } else if query == b"Attached" {
+            reply.push_str("1");
+        } else if query == b"C" {
+            let _ = write!(reply, "QC{:x}", self.current_thread);
+        } else if query == b"fThreadInfo" {
+            reply.push(b'm');
+            if self.current_thread == IDLE_THREAD_ID {
+                let _ = write!(reply, "{:x},", IDLE_THREAD_ID);
+            }
+            thread::for_each_thread(|info| {
+                let _ = write!(reply, "{:x},", info.id);
+            });
+            // Drop the trailing comma
+            if reply.as_bytes().last() == Some(&b',') {
+                reply.len -= 1;
+            }
+        } else if query == b"sThreadInfo" {
+            reply.push_str("l");
+        } else if let Some(thread_id) = query.strip_prefix(b"ThreadExtraInfo,") {
+            let mut description = SnHexWriter(reply);
+            match parse_hex(thread_id).and_then(|id| self.thread_info(id)) {
+                Some(info) => {
+                    let _ = write!(
+                        description,
+                        "{} thread, process {}{}",
+                        if info.kernel { "kernel" } else { "user" },
+                        info.process_id,
+                        if info.running { ", running" } else { "" }
+                    );
+                }
+                None => {
+                    let _ = description.write_str("idle");
+                }
+            }
+        }
+    }
+
+    fn thread_info(&self, id: u64) -> Option<SnThreadInfo> {
+        let mut found = None;
+        thread::for_each_thread(|info| {
+            if info.id == id {
+                found = Some(info);
+            }
+        });
+        found
+    }
+
+    /// Runs `func` with the registers of the selected thread.
+    ///
+    /// The trapped thread uses the context from the trap, the others the one
+    /// saved when they were switched out.
+    fn with_registers<F, R>(&self, context: &mut SnCpuContext, func: F) -> R
+    where
+        F: FnOnce(&mut SnCpuContext) -> R,
+    {
+        let saved = self
+            .selected_thread
+            .filter(|id| *id != self.current_thread)
+            .and_then(|id| self.thread_info(id))
+            .filter(|info| !info.running && info.context != 0);
+
+        match saved {
+            Some(info) => func(unsafe { &mut *(info.context as *mut SnCpuContext) }),
+            None => func(context),
+        }
+    }
+
+    fn find_breakpoint(&self, addr: u64) -> Option<usize> {
+        self.breakpoints.iter().position(|breakpoint| matches!(breakpoint, Some((bp_addr, _)) if *bp_addr == addr))
+    }
+
+    fn insert_breakpoint(&mut self, addr: u64) -> bool {
+        if self.find_breakpoint(addr).is_some() {
+            return true;
+        }
+        let Some(slot) = self.breakpoints.iter().position(Option::is_none) else {
+            return false;
+        };
+        if !is_accessible(addr, 1) {
+            return false;
+        }
+
+        let original = unsafe { *(addr as *const u8) };
+        if !write_bytes(addr, &[INT3]) {
+            return false;
+        }
+        self.breakpoints[slot] = Some((addr, original));
+        true
+    }
+
+    fn remove_breakpoint(&mut self, addr: u64) -> bool {
+        let Some(slot) = self.find_breakpoint(addr) else {
+            return false;
+        };
+        let (addr, original) = self.breakpoints[slot].take().unwrap();
+        write_bytes(addr, &[original])
+    }
+
+    fn remove_all_breakpoints(&mut self) {
+        for (addr, original) in self.breakpoints.iter_mut().filter_map(Option::take) {
+            write_bytes(addr, &[original]);
+        }
+    }
+}
+
+fn reply_ok(reply: &mut SnPacket, ok: bool) {
+    reply.push_str(if ok { "OK" } else { "E01" });
+}
+
+/// Checks that `len` bytes from `addr` are mapped
+fn is_accessible(addr: u64, len: u64) -> bool {
+    let Some(end) = addr.checked_add(len) else {
+        return false;
+    };
+    let mut page = addr & !0xfff;
+    while page < end {
+        if !paging::is_mapped(SnVirtAddr::new(page)) {
+            return false;
+        }
+        page += 0x1000;
+    }
+    true
+}
+
+/// Writes to memory, including read-only kernel code
+fn write_bytes(addr: u64, bytes: &[u8]) -> bool {
+    if !is_accessible(addr, bytes.len() as u64) {
+        return false;
+    }
+
+    cpu::without_write_protect(|| {
+        for (offset, byte) in bytes.iter().enumerate() {
+            unsafe { *((addr + offset as u64) as *mut u8) = *byte };
+        }
+    });
+    true
+}
+
+/// Writes hex encoded data to memory
+fn write_memory(addr: u64, data: &[u8]) -> bool {
+    let mut bytes = [0u8; PACKET_SIZE / 2];
+    let len = data.len() / 2;
+    if len > bytes.len() {
+        return false;
+    }
+    for (idx, pair) in data.chunks_exact(2).enumerate() {
+        let (Some(high), Some(low)) = (hex_digit(pair[0]), hex_digit(pair[1])) else {
+            return false;
+        };
+        bytes[idx] = high << 4 | low;
+    }
+    write_bytes(addr, &bytes[..len])
+}
+
+/// Parses `0,<addr>,<kind>` of a software breakpoint packet
+fn parse_breakpoint(args: &[u8]) -> Option<u64> {
+    let rest = args.strip_prefix(b"0,")?;
+    let (addr, _kind) = split(rest, b',')?;
+    parse_hex(addr)
+}
+
+fn split(bytes: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let idx = bytes.iter().position(|byte| *byte == separator)?;
+    Some((&bytes[..idx], &bytes[idx + 1..]))
+}
+
+fn hex_digits(byte: u8) -> [u8; 2] {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    [DIGITS[(byte >> 4) as usize], DIGITS[(byte & 0xf) as usize]]
+}
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|digit| digit as u8)
+}
+
+/// Parses a big endian hex number, as used for addresses and lengths
+fn parse_hex(bytes: &[u8]) -> Option<u64> {
+    if bytes.is_empty() || bytes.len() > 16 {
+        return None;
+    }
+    bytes.iter().try_fold(0u64, |value, byte| Some(value << 4 | hex_digit(*byte)? as u64))
+}
+
+/// Parses a little endian hex number, as used for register values
+fn parse_hex_le(bytes: &[u8]) -> Option<u64> {
+    if bytes.is_empty() || bytes.len() > 16 || bytes.len() % 2 != 0 {
+        return None;
+    }
+    bytes.chunks_exact(2).enumerate().try_fold(0u64, |value, (idx, pair)| {
+        let byte = hex_digit(pair[0])? << 4 | hex_digit(pair[1])?;
+        Some(value | (byte as u64) << (idx * 8))
+    })
+}
+
+#[test_case]
+fn test_parse_hex() {
+    assert_eq!(parse_hex(b"ffffffff80001000"), Some(0xffff_ffff_8000_1000));
+    assert_eq!(parse_hex(b"-1"), None);
+    assert_eq!(parse_hex_le(b"00100080ffffffff"), Some(0xffff_ffff_8000_1000));
+}
@@ -0,0 +1,11 @@
+/// Stack walking
+pub mod backtrace;
+/// Kernel symbol table
+pub mod symbols;
+/// GDB remote stub
+#[cfg(feature = "gdb")]
+pub mod gdb;
+
+pub fn init() {
+    symbols::init();
+}
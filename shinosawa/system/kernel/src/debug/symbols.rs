@@ -0,0 +1,182 @@
+use core::fmt::{self, Write};
+
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use object::{Object, ObjectSymbol, SymbolKind};
+
+use crate::{limine::EXECUTABLE_FILE_REQUEST, printk};
+
+/// A function in the kernel image
+pub struct SnSymbol {
+    pub start: u64,
+    pub size: u64,
+    /// Mangled name, as found in the ELF symbol table
+    pub name: &'static str,
+}
+
+/// Function symbols of the kernel, sorted by address
+static SYMBOLS: OnceCell<Vec<SnSymbol>> = OnceCell::uninit();
+
+/// Loads the symbol table from the kernel ELF, which Limine keeps in memory
+pub fn init() {
+    let Some(response) = EXECUTABLE_FILE_REQUEST.get_response() else {
+        log::warn!("no kernel file, backtraces won't have symbols");
+        return;
+    };
+    let file = response.file();
+    let data: &'static [u8] = unsafe { core::slice::from_raw_parts(file.addr(), file.size() as usize) };
+
+    let Ok(obj) = object::File::parse(data) else {
+        log::warn!("cannot parse the kernel file");
+        return;
+    };
+
+    let mut symbols: Vec<SnSymbol> = obj
+        .symbols()
+        .filter(|symbol| symbol.kind() == SymbolKind::Text && symbol.address() != 0)
+        .filter_map(|symbol| {
+            Some(SnSymbol {
+                start: symbol.address(),
+                size: symbol.size(),
+                name: symbol.name().ok()?,
+            })
+        })
+        .collect();
+    symbols.sort_unstable_by_key(|symbol| symbol.start);
+
+    printk!("loaded {} symbols", symbols.len());
+    SYMBOLS.init_once(move || symbols);
+}
+
+/// Finds the function containing `addr`, with the offset into it
+pub fn resolve(addr: u64) -> Option<(&'static SnSymbol, u64)> {
+    let symbols = SYMBOLS.get()?;
+    let idx = symbols.partition_point(|symbol| symbol.start <= addr).checked_sub(1)?;
+    let symbol = &symbols[idx];
+
+    let offset = addr - symbol.start;
+    // Symbols without a size are assembly labels, trust them
+    if symbol.size != 0 && offset >= symbol.size {
+        return None;
+    }
+    Some((symbol, offset))
+}
+
+/// Displays a legacy Rust mangled name (`_ZN...E`) as a readable path,
+/// dropping the hash. Other names are displayed as they are.
+///
+/// Nothing is allocated, so it can be used while panicking.
+pub struct SnDemangle<'a>(pub &'a str);
+
+impl fmt::Display for SnDemangle<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(mangled) = self.0.strip_prefix("_ZN").and_then(|name| name.strip_suffix('E')) else {
+            return f.write_str(self.0);
+        };
+        if parts(mangled).any(|part| part.is_none()) {
+            return f.write_str(self.0);
+        }
+
+        let mut parts = parts(mangled).flatten().peekable();
+        let mut first = true;
+        while let Some(part) = parts.next() {
+            // The last part is the hash, h followed by 16 hex digits
+            if parts.peek().is_none() && is_hash(part) {
+                break;
+            }
+            if !first {
+                f.write_str("::")?;
+            }
+            first = false;
+
+            // Identifiers starting with `_$` escape a leading `$`
+            let part = if part.starts_with("_$") { &part[1..] } else { part };
+            unescape(part, f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits the `<length><identifier>` parts of a mangled path.
+/// Yields None once if the name is malformed.
+fn parts(mut rest: &str) -> impl Iterator<Item = Option<&str>> {
+    core::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let part = rest[..digits]
+            .parse::<usize>()
+            .ok()
+            .and_then(|len| Some((rest.get(digits..digits + len)?, digits + len)));
+        match part {
+            Some((part, consumed)) => {
+                rest = &rest[consumed..];
+                Some(Some(part))
+            }
+            None => {
+                rest = "";
+                Some(None)
+            }
+        }
+    })
+}
+
+fn is_hash(part: &str) -> bool {
+    part.len() == 17 && part.starts_with('h') && part[1..].bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Replaces the `$..$` escapes used by the legacy mangling
+fn unescape(mut part: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    while !part.is_empty() {
+        if let Some(rest) = part.strip_prefix("..") {
+            f.write_str("::")?;
+            part = rest;
+            continue;
+        }
+        if let Some(rest) = part.strip_prefix('$') {
+            if let Some(end) = rest.find('$') {
+                let escape = &rest[..end];
+                let replacement = match escape {
+                    "SP" => Some('@'),
+                    "BP" => Some('*'),
+                    "RF" => Some('&'),
+                    "LT" => Some('<'),
+                    "GT" => Some('>'),
+                    "LP" => Some('('),
+                    "RP" => Some(')'),
+                    "C" => Some(','),
+                    _ => escape
+                        .strip_prefix('u')
+                        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                        .and_then(char::from_u32),
+                };
+                if let Some(c) = replacement {
+                    f.write_char(c)?;
+                    part = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+
+        let c = part.chars().next().unwrap();
+        f.write_char(c)?;
+        part = &part[c.len_utf8()..];
+    }
+    Ok(())
+}
+
+#[test_case]
+fn test_demangle() {
+    use alloc::format;
+
+    assert_eq!(
+        format!("{}", SnDemangle("_ZN23shinosawa_system_kernel5panic10rust_panic17h0123456789abcdefE")),
+        "shinosawa_system_kernel::panic::rust_panic"
+    );
+    assert_eq!(
+        format!("{}", SnDemangle("_ZN4core3ptr46drop_in_place$LT$alloc..vec..Vec$LT$u8$GT$$GT$17h0123456789abcdefE")),
+        "core::ptr::drop_in_place<alloc::vec::Vec<u8>>"
+    );
+    assert_eq!(format!("{}", SnDemangle("kmain")), "kmain");
+}
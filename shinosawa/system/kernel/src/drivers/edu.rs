@@ -0,0 +1,96 @@
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use alloc::boxed::Box;
+
+use crate::{hal::interface::paging, printk};
+
+use super::pci;
+
+/// QEMU's educational device, `-device edu`
+const VENDOR_ID: u16 = 0x1234;
+const DEVICE_ID: u16 = 0x11e8;
+
+// Registers in BAR0
+const REG_INTERRUPT_STATUS: u64 = 0x24;
+#[cfg(test)]
+const REG_INTERRUPT_RAISE: u64 = 0x60;
+const REG_INTERRUPT_ACK: u64 = 0x64;
+const REGISTERS_SIZE: u64 = 0x100;
+
+/// Status bit set by `raise_interrupt`
+#[cfg(test)]
+const INTERRUPT_TEST: u32 = 1 << 0;
+
+/// Virtual address of the registers, 0 without the device
+static REGISTERS: AtomicU64 = AtomicU64::new(0);
+/// Interrupts received so far
+static INTERRUPTS: AtomicUsize = AtomicUsize::new(0);
+
+fn read(reg: u64) -> u32 {
+    unsafe { ((REGISTERS.load(Ordering::Acquire) + reg) as *const u32).read_volatile() }
+}
+
+fn write(reg: u64, value: u32) {
+    unsafe { ((REGISTERS.load(Ordering::Acquire) + reg) as *mut u32).write_volatile(value) }
+}
+
+fn handle_interrupt() -> bool {
+    let status = read(REG_INTERRUPT_STATUS);
+    if status == 0 {
+        return false;
+    }
+    write(REG_INTERRUPT_ACK, status);
+    INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+    true
+}
+
+/// Sets up the device if there is one, with its interrupt delivered by message
+pub fn init() {
+    let Some(device) = pci::find_devices(VENDOR_ID, DEVICE_ID).next() else {
+        return;
+    };
+    let Some(bar) = device.memory_bar(0) else {
+        return;
+    };
+    REGISTERS.store(paging::map_mmio(bar, REGISTERS_SIZE).as_u64(), Ordering::Release);
+
+    // Kept for good, like the device
+    match device.enable_message_interrupts(Box::new(handle_interrupt)) {
+        Some(_) => printk!("edu device ready"),
+        None => {
+            log::warn!("edu device has no message interrupts, leaving it alone");
+            REGISTERS.store(0, Ordering::Release);
+        }
+    }
+}
+
+/// Asks the device for an interrupt, false without the device
+#[cfg(test)]
+pub fn raise_interrupt() -> bool {
+    if REGISTERS.load(Ordering::Acquire) == 0 {
+        return false;
+    }
+    write(REG_INTERRUPT_RAISE, INTERRUPT_TEST);
+    true
+}
+
+#[cfg(test)]
+pub fn interrupts() -> usize {
+    INTERRUPTS.load(Ordering::Relaxed)
+}
+
+#[test_case]
+fn test_msi_fires() {
+    use core::time::Duration;
+
+    use crate::hal::interface::clock;
+
+    let before = interrupts();
+    assert!(raise_interrupt(), "no edu device, run with -device edu");
+
+    let deadline = clock::uptime() + Duration::from_secs(1);
+    while interrupts() == before && clock::uptime() < deadline {
+        core::hint::spin_loop();
+    }
+    assert!(interrupts() > before);
+}
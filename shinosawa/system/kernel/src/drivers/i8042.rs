@@ -0,0 +1,265 @@
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use crate::printk;
+
+const DATA_PORT: u16 = 0x60;
+/// Status when read, command when written
+const COMMAND_PORT: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+const COMMAND_READ_CONFIG: u8 = 0x20;
+const COMMAND_WRITE_CONFIG: u8 = 0x60;
+const COMMAND_DISABLE_SECOND: u8 = 0xa7;
+const COMMAND_ENABLE_SECOND: u8 = 0xa8;
+const COMMAND_TEST_SECOND: u8 = 0xa9;
+const COMMAND_SELF_TEST: u8 = 0xaa;
+const COMMAND_TEST_FIRST: u8 = 0xab;
+const COMMAND_DISABLE_FIRST: u8 = 0xad;
+const COMMAND_ENABLE_FIRST: u8 = 0xae;
+/// The next data byte goes to the second port
+const COMMAND_WRITE_SECOND: u8 = 0xd4;
+/// Pulses the CPU reset line
+const COMMAND_PULSE_RESET: u8 = 0xfe;
+
+const CONFIG_FIRST_IRQ: u8 = 1 << 0;
+const CONFIG_SECOND_IRQ: u8 = 1 << 1;
+const CONFIG_SECOND_CLOCK_DISABLED: u8 = 1 << 5;
+/// Scancode set 2 from the keyboard is translated to set 1
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+const DEVICE_RESET: u8 = 0xff;
+const DEVICE_ACK: u8 = 0xfa;
+const DEVICE_RESEND: u8 = 0xfe;
+const DEVICE_RESET_PASSED: u8 = 0xaa;
+
+/// Status polls before giving up on the controller or a device
+const TIMEOUT: usize = 100_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnPs2Port {
+    /// Where the keyboard sits, IRQ 1
+    First,
+    /// Where the mouse sits, IRQ 12
+    Second,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnPs2Error {
+    Timeout,
+    /// The device answered something other than an acknowledgement
+    NoAck(u8),
+    SelfTestFailed(u8),
+}
+
+/// Ports of the controller with a working device
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SnPs2Ports {
+    pub first: bool,
+    pub second: bool,
+}
+
+/// Serializes commands, which take several port accesses
+static CONTROLLER: Mutex<()> = Mutex::new(());
+
+fn status() -> u8 {
+    unsafe { Port::new(COMMAND_PORT).read() }
+}
+
+fn wait_input_empty() -> Result<(), SnPs2Error> {
+    (0..TIMEOUT)
+        .any(|_| status() & STATUS_INPUT_FULL == 0)
+        .then_some(())
+        .ok_or(SnPs2Error::Timeout)
+}
+
+fn send_command(command: u8) -> Result<(), SnPs2Error> {
+    wait_input_empty()?;
+    unsafe { Port::new(COMMAND_PORT).write(command) };
+    Ok(())
+}
+
+fn write_data(byte: u8) -> Result<(), SnPs2Error> {
+    wait_input_empty()?;
+    unsafe { Port::new(DATA_PORT).write(byte) };
+    Ok(())
+}
+
+/// Waits for a byte from the controller or a device
+fn read_response() -> Result<u8, SnPs2Error> {
+    if (0..TIMEOUT).any(|_| status() & STATUS_OUTPUT_FULL != 0) {
+        Ok(read_data())
+    } else {
+        Err(SnPs2Error::Timeout)
+    }
+}
+
+/// Drops whatever devices sent before being set up
+fn flush_output() {
+    for _ in 0..TIMEOUT {
+        if status() & STATUS_OUTPUT_FULL == 0 {
+            return;
+        }
+        read_data();
+    }
+}
+
+fn read_config() -> Result<u8, SnPs2Error> {
+    send_command(COMMAND_READ_CONFIG)?;
+    read_response()
+}
+
+fn write_config(config: u8) -> Result<(), SnPs2Error> {
+    send_command(COMMAND_WRITE_CONFIG)?;
+    write_data(config)
+}
+
+/// Resets the machine through the controller, returns if that did nothing
+pub fn pulse_reset() {
+    let _lock = CONTROLLER.lock();
+    let _ = send_command(COMMAND_PULSE_RESET);
+}
+
+/// Reads the byte a device sent, for the interrupt handlers
+pub fn read_data() -> u8 {
+    unsafe { Port::new(DATA_PORT).read() }
+}
+
+/// Sends a byte to the device on `port` and waits for it to be acknowledged.
+///
+/// The answer would go to the interrupt handler, so this is only for
+/// setting devices up before `enable_irqs`.
+pub fn send(port: SnPs2Port, byte: u8) -> Result<(), SnPs2Error> {
+    let _lock = CONTROLLER.lock();
+    // Devices ask again if the byte got garbled
+    for _ in 0..3 {
+        if port == SnPs2Port::Second {
+            send_command(COMMAND_WRITE_SECOND)?;
+        }
+        write_data(byte)?;
+        match read_response()? {
+            DEVICE_ACK => return Ok(()),
+            DEVICE_RESEND => continue,
+            other => return Err(SnPs2Error::NoAck(other)),
+        }
+    }
+    Err(SnPs2Error::NoAck(DEVICE_RESEND))
+}
+
+/// Sends a byte to the device on `port`, and returns the byte it answers with
+pub fn query(port: SnPs2Port, byte: u8) -> Result<u8, SnPs2Error> {
+    send(port, byte)?;
+    let _lock = CONTROLLER.lock();
+    read_response()
+}
+
+/// Resets the device on `port`, which answers with its self-test result
+fn reset_device(port: SnPs2Port) -> Result<(), SnPs2Error> {
+    match query(port, DEVICE_RESET)? {
+        DEVICE_RESET_PASSED => {}
+        other => return Err(SnPs2Error::SelfTestFailed(other)),
+    }
+    // Mice follow with their device id
+    let _lock = CONTROLLER.lock();
+    flush_output();
+    Ok(())
+}
+
+fn init_controller() -> Result<SnPs2Ports, SnPs2Error> {
+    let _lock = CONTROLLER.lock();
+
+    // Keep the devices quiet while setting up
+    send_command(COMMAND_DISABLE_FIRST)?;
+    send_command(COMMAND_DISABLE_SECOND)?;
+    flush_output();
+
+    let mut config = read_config()?;
+    config &= !(CONFIG_FIRST_IRQ | CONFIG_SECOND_IRQ);
+    // The keyboard driver decodes scancode set 1
+    config |= CONFIG_TRANSLATION;
+    write_config(config)?;
+
+    // Some controllers reset themselves on the self test
+    send_command(COMMAND_SELF_TEST)?;
+    match read_response()? {
+        SELF_TEST_PASSED => {}
+        other => return Err(SnPs2Error::SelfTestFailed(other)),
+    }
+    write_config(config)?;
+
+    // A controller with a second port clocks it once enabled
+    send_command(COMMAND_ENABLE_SECOND)?;
+    let dual = read_config()? & CONFIG_SECOND_CLOCK_DISABLED == 0;
+    send_command(COMMAND_DISABLE_SECOND)?;
+
+    send_command(COMMAND_TEST_FIRST)?;
+    let mut ports = SnPs2Ports {
+        first: read_response()? == PORT_TEST_PASSED,
+        second: false,
+    };
+    if dual {
+        send_command(COMMAND_TEST_SECOND)?;
+        ports.second = read_response()? == PORT_TEST_PASSED;
+    }
+
+    if ports.first {
+        send_command(COMMAND_ENABLE_FIRST)?;
+    }
+    if ports.second {
+        send_command(COMMAND_ENABLE_SECOND)?;
+    }
+    flush_output();
+    Ok(ports)
+}
+
+/// Sets up the controller and resets the devices on it, with their
+/// interrupts off until `enable_irqs`.
+///
+/// Returns the ports with a working device.
+pub fn init() -> SnPs2Ports {
+    printk!("initializing PS/2 controller");
+    let mut ports = match init_controller() {
+        Ok(ports) => ports,
+        Err(err) => {
+            log::warn!("no usable PS/2 controller: {:?}", err);
+            return SnPs2Ports::default();
+        }
+    };
+
+    for (port, present) in [(SnPs2Port::First, &mut ports.first), (SnPs2Port::Second, &mut ports.second)] {
+        if !*present {
+            continue;
+        }
+        if let Err(err) = reset_device(port) {
+            printk!("no device on PS/2 {:?} port: {:?}", port, err);
+            *present = false;
+        }
+    }
+
+    printk!("PS/2 ports in use: first {}, second {}", ports.first, ports.second);
+    ports
+}
+
+/// Lets the devices on `ports` raise their IRQs, once their handlers are in place
+pub fn enable_irqs(ports: SnPs2Ports) {
+    let _lock = CONTROLLER.lock();
+    flush_output();
+    let result = read_config().and_then(|mut config| {
+        if ports.first {
+            config |= CONFIG_FIRST_IRQ;
+        }
+        if ports.second {
+            config |= CONFIG_SECOND_IRQ;
+        }
+        write_config(config)
+    });
+
+    if let Err(err) = result {
+        log::warn!("cannot enable PS/2 interrupts: {:?}", err);
+    }
+}
@@ -0,0 +1,160 @@
+use alloc::collections::vec_deque::VecDeque;
+
+use core::sync::atomic::{AtomicU16, Ordering};
+
+/// Kinds of `SnInputEvent`
+pub const EVENT_KEY: u8 = 0;
+pub const EVENT_MOUSE: u8 = 1;
+
+/// Shift bits in `SnInputEvent::modifiers`
+pub const MODIFIER_SHIFT: u16 = 1 << 0;
+pub const MODIFIER_CTRL: u16 = 1 << 1;
+pub const MODIFIER_ALT: u16 = 1 << 2;
+pub const MODIFIER_ALTGR: u16 = 1 << 3;
+pub const MODIFIER_CAPSLOCK: u16 = 1 << 4;
+pub const MODIFIER_NUMLOCK: u16 = 1 << 5;
+
+/// Mouse buttons in `SnInputEvent::buttons`
+pub const BUTTON_LEFT: u8 = 1 << 0;
+pub const BUTTON_RIGHT: u8 = 1 << 1;
+pub const BUTTON_MIDDLE: u8 = 1 << 2;
+
+/// Modifiers held on the keyboard, mouse events carry them too
+static MODIFIERS: AtomicU16 = AtomicU16::new(0);
+
+pub fn set_modifiers(modifiers: u16) {
+    MODIFIERS.store(modifiers, Ordering::Relaxed);
+}
+
+pub fn modifiers() -> u16 {
+    MODIFIERS.load(Ordering::Relaxed)
+}
+
+/// A key going down or up, or the mouse moving, as read from the console
+/// in raw mode
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SnInputEvent {
+    /// `EVENT_KEY` or `EVENT_MOUSE`
+    pub kind: u8,
+    /// Key: `pc_keyboard::KeyCode` of the key
+    pub code: u8,
+    /// Key: 1 when the key went down, 0 when it went up
+    pub pressed: u8,
+    /// Mouse: `BUTTON_` bits held after the event
+    pub buttons: u8,
+    /// `MODIFIER_` bits at the time of the event
+    pub modifiers: u16,
+    /// Mouse: motion, y grows downwards like on the screen
+    pub dx: i16,
+    pub dy: i16,
+    /// Mouse: wheel steps, positive away from the user
+    pub wheel: i16,
+    /// Key: character the key types with the current layout, 0 if none
+    pub ch: u32,
+}
+
+impl SnInputEvent {
+    pub const SIZE: usize = core::mem::size_of::<SnInputEvent>();
+
+    pub fn key(code: u8, pressed: bool, ch: u32) -> SnInputEvent {
+        SnInputEvent {
+            kind: EVENT_KEY,
+            code,
+            pressed: pressed as u8,
+            modifiers: modifiers(),
+            ch,
+            ..Default::default()
+        }
+    }
+
+    pub fn mouse(buttons: u8, dx: i16, dy: i16, wheel: i16) -> SnInputEvent {
+        SnInputEvent {
+            kind: EVENT_MOUSE,
+            buttons,
+            modifiers: modifiers(),
+            dx,
+            dy,
+            wheel,
+            ..Default::default()
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0] = self.kind;
+        bytes[1] = self.code;
+        bytes[2] = self.pressed;
+        bytes[3] = self.buttons;
+        bytes[4..6].copy_from_slice(&self.modifiers.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.dx.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.dy.to_le_bytes());
+        bytes[10..12].copy_from_slice(&self.wheel.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.ch.to_le_bytes());
+        bytes
+    }
+}
+
+/// Bounded queue filled by interrupt handlers.
+///
+/// The space is allocated up front so pushing never allocates, the oldest
+/// entry goes when it is full.
+pub struct SnInputQueue<T> {
+    entries: VecDeque<T>,
+    capacity: usize,
+    /// Entries lost to a full queue
+    pub dropped: u64,
+}
+
+impl<T> SnInputQueue<T> {
+    pub fn new(capacity: usize) -> SnInputQueue<T> {
+        SnInputQueue {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+            dropped: 0,
+        }
+    }
+
+    pub fn push(&mut self, entry: T) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+            self.dropped += 1;
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.entries.pop_front()
+    }
+
+    /// Entries that fit before the oldest ones get dropped
+    pub fn space(&self) -> usize {
+        self.capacity - self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[test_case]
+fn test_input_queue() {
+    let mut queue = SnInputQueue::new(2);
+    queue.push(1);
+    queue.push(2);
+    queue.push(3);
+    assert_eq!(queue.dropped, 1);
+    assert_eq!(queue.pop(), Some(2));
+    assert_eq!(queue.space(), 1);
+
+    let event = SnInputEvent { modifiers: MODIFIER_SHIFT, ..SnInputEvent::key(0x10, true, 'Q' as u32) };
+    assert_eq!(event.to_bytes()[..6], [EVENT_KEY, 0x10, 1, 0, 1, 0]);
+    assert_eq!(event.to_bytes()[12..], [b'Q', 0, 0, 0]);
+
+    let event = SnInputEvent::mouse(BUTTON_LEFT, -1, 2, 0);
+    assert_eq!(event.to_bytes()[6..10], [0xff, 0xff, 2, 0]);
+}
@@ -1 +1,7 @@
-pub mod ps2_keyboard;
\ No newline at end of file
+pub mod edu;
+pub mod i8042;
+pub mod input;
+pub mod ps2_keyboard;
+pub mod ps2_mouse;
+pub mod pci;
+pub mod tty;
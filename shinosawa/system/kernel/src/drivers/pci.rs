@@ -0,0 +1,315 @@
+use alloc::{boxed::Box, vec::Vec};
+use conquer_once::spin::OnceCell;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use crate::{
+    hal::interface::{interrupt::msi_message, paging},
+    interrupt::{self, SnInterruptHandle, SnInterruptHandler},
+    memory::SnPhysAddr,
+    printk,
+};
+
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+
+// Configuration space registers
+const REG_VENDOR_ID: u8 = 0x00;
+const REG_DEVICE_ID: u8 = 0x02;
+const REG_COMMAND: u8 = 0x04;
+const REG_STATUS: u8 = 0x06;
+const REG_CLASS: u8 = 0x08;
+const REG_HEADER_TYPE: u8 = 0x0e;
+const REG_BAR0: u8 = 0x10;
+const REG_CAPABILITIES: u8 = 0x34;
+
+const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+pub const CAP_MSI: u8 = 0x05;
+pub const CAP_MSIX: u8 = 0x11;
+
+const MSI_CONTROL_ENABLE: u16 = 1 << 0;
+const MSI_CONTROL_MULTIPLE_ENABLE: u16 = 0b111 << 4;
+const MSI_CONTROL_64BIT: u16 = 1 << 7;
+
+const MSIX_CONTROL_TABLE_SIZE: u16 = 0x7ff;
+const MSIX_CONTROL_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_CONTROL_ENABLE: u16 = 1 << 15;
+const MSIX_ENTRY_SIZE: u64 = 16;
+const MSIX_VECTOR_MASKED: u32 = 1 << 0;
+
+/// Serializes the two step config space access
+static CONFIG_LOCK: Mutex<()> = Mutex::new(());
+
+pub static PCI_DEVICES: OnceCell<Vec<SnPciDevice>> = OnceCell::uninit();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SnPciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl SnPciAddress {
+    fn config_address(&self, offset: u8) -> u32 {
+        (1 << 31)
+            | (self.bus as u32) << 16
+            | (self.device as u32) << 11
+            | (self.function as u32) << 8
+            | (offset & 0xfc) as u32
+    }
+
+    pub fn read_u32(&self, offset: u8) -> u32 {
+        let _lock = CONFIG_LOCK.lock();
+        unsafe {
+            Port::new(CONFIG_ADDRESS).write(self.config_address(offset));
+            Port::new(CONFIG_DATA).read()
+        }
+    }
+
+    pub fn write_u32(&self, offset: u8, value: u32) {
+        let _lock = CONFIG_LOCK.lock();
+        unsafe {
+            Port::new(CONFIG_ADDRESS).write(self.config_address(offset));
+            Port::new(CONFIG_DATA).write(value);
+        }
+    }
+
+    pub fn read_u16(&self, offset: u8) -> u16 {
+        (self.read_u32(offset) >> ((offset & 2) * 8)) as u16
+    }
+
+    pub fn write_u16(&self, offset: u8, value: u16) {
+        let shift = (offset & 2) * 8;
+        let old = self.read_u32(offset) & !(0xffff << shift);
+        self.write_u32(offset, old | (value as u32) << shift);
+    }
+
+    pub fn read_u8(&self, offset: u8) -> u8 {
+        (self.read_u32(offset) >> ((offset & 3) * 8)) as u8
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SnPciDevice {
+    pub address: SnPciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+}
+
+impl SnPciDevice {
+    fn probe(address: SnPciAddress) -> Option<SnPciDevice> {
+        let vendor_id = address.read_u16(REG_VENDOR_ID);
+        if vendor_id == 0xffff {
+            return None;
+        }
+        let class = address.read_u32(REG_CLASS);
+
+        Some(SnPciDevice {
+            address,
+            vendor_id,
+            device_id: address.read_u16(REG_DEVICE_ID),
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+        })
+    }
+
+    /// Walks the capability list, returning the offset of each capability with its id
+    pub fn capabilities(&self) -> impl Iterator<Item = (u8, u8)> + '_ {
+        let mut next = if self.address.read_u16(REG_STATUS) & STATUS_CAPABILITIES != 0 {
+            self.address.read_u8(REG_CAPABILITIES) & 0xfc
+        } else {
+            0
+        };
+        // A broken list could loop forever
+        let mut remaining = 48;
+
+        core::iter::from_fn(move || {
+            if next == 0 || remaining == 0 {
+                return None;
+            }
+            remaining -= 1;
+
+            let offset = next;
+            let id = self.address.read_u8(offset);
+            next = self.address.read_u8(offset + 1) & 0xfc;
+            Some((offset, id))
+        })
+    }
+
+    /// Returns the offset of a capability
+    pub fn find_capability(&self, id: u8) -> Option<u8> {
+        self.capabilities().find(|(_, cap_id)| *cap_id == id).map(|(offset, _)| offset)
+    }
+
+    /// Returns the physical address of a memory BAR
+    pub fn memory_bar(&self, index: u8) -> Option<SnPhysAddr> {
+        let offset = REG_BAR0 + index * 4;
+        let low = self.address.read_u32(offset);
+        if low & 1 != 0 {
+            // I/O space
+            return None;
+        }
+
+        let mut addr = (low & !0xf) as u64;
+        if (low >> 1) & 0b11 == 0b10 {
+            // 64-bit BAR, the high half is in the next one
+            addr |= (self.address.read_u32(offset + 4) as u64) << 32;
+        }
+
+        Some(SnPhysAddr::new(addr))
+    }
+
+    fn disable_intx(&self) {
+        let command = self.address.read_u16(REG_COMMAND);
+        self.address.write_u16(REG_COMMAND, command | COMMAND_INTX_DISABLE);
+    }
+
+    /// Enables MSI with a single vector, dispatched to `handler`
+    pub fn enable_msi(&self, handler: Box<SnInterruptHandler>) -> Option<SnInterruptHandle> {
+        let cap = self.find_capability(CAP_MSI)?;
+        let (vector, handle) = interrupt::allocate_vector(handler)?;
+        let Some((address, data)) = msi_message(vector, None) else {
+            interrupt::free_vector(handle);
+            return None;
+        };
+
+        let control = self.address.read_u16(cap + 2);
+        self.address.write_u32(cap + 4, address as u32);
+        if control & MSI_CONTROL_64BIT != 0 {
+            self.address.write_u32(cap + 8, (address >> 32) as u32);
+            self.address.write_u16(cap + 12, data as u16);
+        } else {
+            self.address.write_u16(cap + 8, data as u16);
+        }
+
+        self.disable_intx();
+        self.address.write_u16(
+            cap + 2,
+            (control & !MSI_CONTROL_MULTIPLE_ENABLE) | MSI_CONTROL_ENABLE,
+        );
+
+        Some(handle)
+    }
+
+    /// Number of MSI-X table entries, if the device supports MSI-X
+    pub fn msix_table_size(&self) -> Option<u16> {
+        let cap = self.find_capability(CAP_MSIX)?;
+        Some((self.address.read_u16(cap + 2) & MSIX_CONTROL_TABLE_SIZE) + 1)
+    }
+
+    /// Enables MSI-X and points table entry `entry` at a new vector dispatched to `handler`.
+    ///
+    /// Can be called once per entry, the other entries stay masked.
+    pub fn enable_msix(&self, entry: u16, handler: Box<SnInterruptHandler>) -> Option<SnInterruptHandle> {
+        let cap = self.find_capability(CAP_MSIX)?;
+        let control = self.address.read_u16(cap + 2);
+        let table_size = (control & MSIX_CONTROL_TABLE_SIZE) + 1;
+        if entry >= table_size {
+            return None;
+        }
+
+        let table = self.address.read_u32(cap + 4);
+        let bar = self.memory_bar((table & 0b111) as u8)?;
+        let table_phys = SnPhysAddr::new(bar.as_u64() + (table & !0b111) as u64);
+        let table_virt = paging::map_mmio(table_phys, table_size as u64 * MSIX_ENTRY_SIZE);
+
+        let (vector, handle) = interrupt::allocate_vector(handler)?;
+        let Some((address, data)) = msi_message(vector, None) else {
+            interrupt::free_vector(handle);
+            return None;
+        };
+
+        let entry_ptr = (table_virt.as_u64() + entry as u64 * MSIX_ENTRY_SIZE) as *mut u32;
+        unsafe {
+            entry_ptr.write_volatile(address as u32);
+            entry_ptr.add(1).write_volatile((address >> 32) as u32);
+            entry_ptr.add(2).write_volatile(data);
+            let vector_control = entry_ptr.add(3).read_volatile();
+            entry_ptr.add(3).write_volatile(vector_control & !MSIX_VECTOR_MASKED);
+        }
+
+        self.disable_intx();
+        self.address.write_u16(
+            cap + 2,
+            (control & !MSIX_CONTROL_FUNCTION_MASK) | MSIX_CONTROL_ENABLE,
+        );
+
+        Some(handle)
+    }
+
+    /// Sends the device's interrupts to `handler`, by the first MSI-X entry
+    /// when it has MSI-X and by MSI otherwise
+    pub fn enable_message_interrupts(&self, handler: Box<SnInterruptHandler>) -> Option<SnInterruptHandle> {
+        match self.msix_table_size() {
+            Some(_) => self.enable_msix(0, handler),
+            None => self.enable_msi(handler),
+        }
+    }
+}
+
+/// Scans every bus for devices
+fn enumerate() -> Vec<SnPciDevice> {
+    let mut devices = Vec::new();
+
+    for bus in 0..=255u8 {
+        for device in 0..32u8 {
+            let address = SnPciAddress { bus, device, function: 0 };
+            let Some(first) = SnPciDevice::probe(address) else {
+                continue;
+            };
+            devices.push(first);
+
+            if address.read_u8(REG_HEADER_TYPE) & 0x80 != 0 {
+                // Multi-function device
+                for function in 1..8u8 {
+                    let address = SnPciAddress { bus, device, function };
+                    if let Some(device) = SnPciDevice::probe(address) {
+                        devices.push(device);
+                    }
+                }
+            }
+        }
+    }
+
+    devices
+}
+
+pub fn init() {
+    printk!("enumerating devices");
+    let devices = enumerate();
+
+    for device in devices.iter() {
+        let msi = device.find_capability(CAP_MSI).is_some();
+        let msix = device.msix_table_size();
+        printk!(
+            "{:02x}:{:02x}.{} {:04x}:{:04x} class {:02x}{:02x}{:02x}{}{}",
+            device.address.bus,
+            device.address.device,
+            device.address.function,
+            device.vendor_id,
+            device.device_id,
+            device.class,
+            device.subclass,
+            device.prog_if,
+            if msi { " MSI" } else { "" },
+            if msix.is_some() { " MSI-X" } else { "" }
+        );
+    }
+
+    PCI_DEVICES.init_once(move || devices);
+}
+
+/// Returns the devices matching a vendor and device id
+pub fn find_devices(vendor_id: u16, device_id: u16) -> impl Iterator<Item = &'static SnPciDevice> {
+    PCI_DEVICES
+        .get()
+        .into_iter()
+        .flatten()
+        .filter(move |device| device.vendor_id == vendor_id && device.device_id == device_id)
+}
@@ -1,43 +1,125 @@
 use conquer_once::spin::OnceCell;
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+use pc_keyboard::{
+    layouts::{self, AnyLayout},
+    DecodedKey, HandleControl, KeyCode, KeyState, Keyboard, Modifiers, ScancodeSet1,
+};
 use spin::Mutex;
 
-use crate::{interrupt::INTERRUPT_CONTROLLER, print, printk};
+use alloc::boxed::Box;
+
+use crate::{interrupt, logger, printk};
+
+use super::{i8042, input::{self, SnInputEvent}, tty};
+
+/// Lines Page Up and Page Down scroll the screen by
+const SCROLL_LINES: isize = 10;
 
 const KEYBOARD_IRQ: u8 = 0x01;
 
-static KEYBOARD: OnceCell< Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>>> = OnceCell::uninit();
+/// Layout used when there is no `keymap=`
+const DEFAULT_LAYOUT: &str = "us";
+
+static KEYBOARD: OnceCell<Mutex<Keyboard<AnyLayout, ScancodeSet1>>> = OnceCell::uninit();
+
+/// Finds a keyboard layout by name, as given to `keymap=`
+fn layout_by_name(name: &str) -> Option<AnyLayout> {
+    Some(match name {
+        "us" => AnyLayout::Us104Key(layouts::Us104Key),
+        "uk" => AnyLayout::Uk105Key(layouts::Uk105Key),
+        "de" => AnyLayout::De105Key(layouts::De105Key),
+        "fr" | "azerty" => AnyLayout::Azerty(layouts::Azerty),
+        "no" => AnyLayout::No105Key(layouts::No105Key),
+        "fi" | "se" => AnyLayout::FiSe105Key(layouts::FiSe105Key),
+        "jp" => AnyLayout::Jis109Key(layouts::Jis109Key),
+        "dvorak" => AnyLayout::Dvorak104Key(layouts::Dvorak104Key),
+        "dvp" => AnyLayout::DVP104Key(layouts::DVP104Key),
+        "colemak" => AnyLayout::Colemak(layouts::Colemak),
+        _ => return None,
+    })
+}
+
+fn new_keyboard(layout: AnyLayout) -> Keyboard<AnyLayout, ScancodeSet1> {
+    // Ctrl with a letter gives control characters, which the tty acts on
+    Keyboard::new(ScancodeSet1::new(), layout, HandleControl::MapLettersToUnicode)
+}
+
+fn modifier_bits(modifiers: &Modifiers) -> u16 {
+    let mut bits = 0;
+    if modifiers.is_shifted() {
+        bits |= input::MODIFIER_SHIFT;
+    }
+    if modifiers.is_ctrl() {
+        bits |= input::MODIFIER_CTRL;
+    }
+    if modifiers.lalt {
+        bits |= input::MODIFIER_ALT;
+    }
+    if modifiers.is_altgr() {
+        bits |= input::MODIFIER_ALTGR;
+    }
+    if modifiers.capslock {
+        bits |= input::MODIFIER_CAPSLOCK;
+    }
+    if modifiers.numlock {
+        bits |= input::MODIFIER_NUMLOCK;
+    }
+    bits
+}
 
 fn keyboard_handler() {
-    use x86_64::instructions::port::Port;
-
-    let mut keyboard = KEYBOARD.get().unwrap().lock();
-    let mut port = Port::new(0x60);
-
-    let scancode: u8 = unsafe { port.read() };
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            match key {
-                DecodedKey::Unicode(character) => print!("{}", character),
-                DecodedKey::RawKey(key) => print!("#({:?})", key),
-            }
-        }
+    let scancode = i8042::read_data();
+
+    let (code, state, key) = {
+        let mut keyboard = KEYBOARD.get().unwrap().lock();
+        let Ok(Some(key_event)) = keyboard.add_byte(scancode) else {
+            return;
+        };
+        let (code, state) = (key_event.code, key_event.state);
+        let key = keyboard.process_keyevent(key_event);
+        input::set_modifiers(modifier_bits(keyboard.get_modifiers()));
+        (code, state, key)
+    };
+
+    // Scrolling belongs to the kernel console
+    match key {
+        Some(DecodedKey::RawKey(KeyCode::PageUp)) => logger::scroll_fb(-SCROLL_LINES),
+        Some(DecodedKey::RawKey(KeyCode::PageDown)) => logger::scroll_fb(SCROLL_LINES),
+        _ => {}
     }
+
+    let ch = match key {
+        Some(DecodedKey::Unicode(ch)) => ch as u32,
+        _ => 0,
+    };
+    tty::input_event(SnInputEvent::key(code as u8, state != KeyState::Up, ch));
 }
 
-pub fn init() {
-    printk!("drivers::ps2_keyboard: initialize PS/2 keyboard controller");
-    KEYBOARD.init_once(move || {
-        Mutex::new(Keyboard::new(ScancodeSet1::new(),
-            layouts::Us104Key, HandleControl::Ignore)
-        )
+/// Switches the keyboard to another layout, returns false for an unknown
+/// name or without a keyboard
+pub fn set_layout(name: &str) -> bool {
+    let (Some(keyboard), Some(layout)) = (KEYBOARD.get(), layout_by_name(name)) else {
+        return false;
+    };
+    crate::hal::interface::interrupt::without_interrupts(|| {
+        *keyboard.lock() = new_keyboard(layout);
     });
+    printk!("keyboard layout {}", name);
+    true
+}
 
-    // Reset anything that might have been there
-    keyboard_handler();
-
-    let mut controller = INTERRUPT_CONTROLLER.get().unwrap().write();
-    controller.set_handler(KEYBOARD_IRQ as usize, keyboard_handler);
+/// Sets up the keyboard on the first PS/2 port, `i8042::init` must have found it
+pub fn init() {
+    printk!("initializing PS/2 keyboard");
+    let name = crate::cmdline::options().keymap.unwrap_or(DEFAULT_LAYOUT);
+    let layout = layout_by_name(name).unwrap_or_else(|| {
+        log::warn!("unknown keymap {}, using {}", name, DEFAULT_LAYOUT);
+        layout_by_name(DEFAULT_LAYOUT).unwrap()
+    });
+    KEYBOARD.init_once(move || Mutex::new(new_keyboard(layout)));
 
-    crate::hal::interface::interrupt::enable_irq(1);
-}
\ No newline at end of file
+    interrupt::request_isa_irq(KEYBOARD_IRQ, Box::new(|| {
+        keyboard_handler();
+        true
+    }))
+    .expect("cannot get the keyboard IRQ");
+}
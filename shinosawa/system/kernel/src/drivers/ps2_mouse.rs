@@ -0,0 +1,141 @@
+use alloc::boxed::Box;
+use conquer_once::spin::OnceCell;
+use spin::Mutex;
+
+use crate::{interrupt, printk};
+
+use super::{
+    i8042::{self, SnPs2Error, SnPs2Port},
+    input::SnInputEvent,
+    tty,
+};
+
+const MOUSE_IRQ: u8 = 12;
+
+const COMMAND_SET_SAMPLE_RATE: u8 = 0xf3;
+const COMMAND_GET_ID: u8 = 0xf2;
+const COMMAND_ENABLE_REPORTING: u8 = 0xf4;
+
+/// Device id of a mouse that sends wheel motion in a fourth byte
+const ID_WHEEL: u8 = 3;
+
+/// Samples per second once set up
+const SAMPLE_RATE: u8 = 100;
+
+// Bits of the first byte of a packet
+const PACKET_BUTTONS: u8 = 0b111;
+/// Always set, used to find the start of a packet again
+const PACKET_ALWAYS_ONE: u8 = 1 << 3;
+const PACKET_X_SIGN: u8 = 1 << 4;
+const PACKET_Y_SIGN: u8 = 1 << 5;
+const PACKET_X_OVERFLOW: u8 = 1 << 6;
+const PACKET_Y_OVERFLOW: u8 = 1 << 7;
+
+/// Assembles the bytes from the mouse into packets
+pub struct SnMousePackets {
+    packet: [u8; 4],
+    received: usize,
+    /// 3, or 4 with a wheel
+    size: usize,
+}
+
+impl SnMousePackets {
+    pub fn new(wheel: bool) -> SnMousePackets {
+        SnMousePackets {
+            packet: [0; 4],
+            received: 0,
+            size: if wheel { 4 } else { 3 },
+        }
+    }
+
+    /// Adds a byte, returning the event once a packet is complete
+    pub fn add_byte(&mut self, byte: u8) -> Option<SnInputEvent> {
+        if self.received == 0 && byte & PACKET_ALWAYS_ONE == 0 {
+            // Out of step, wait for the start of a packet
+            return None;
+        }
+        self.packet[self.received] = byte;
+        self.received += 1;
+        if self.received < self.size {
+            return None;
+        }
+        self.received = 0;
+
+        let [flags, x, y, z] = self.packet;
+        if flags & (PACKET_X_OVERFLOW | PACKET_Y_OVERFLOW) != 0 {
+            return None;
+        }
+        // 9 bit two's complement, the sign is in the first byte
+        let dx = x as i16 - if flags & PACKET_X_SIGN != 0 { 0x100 } else { 0 };
+        let dy = y as i16 - if flags & PACKET_Y_SIGN != 0 { 0x100 } else { 0 };
+        // Wheel steps are 4 bits, with down positive
+        let wheel = if self.size == 4 { -(((z << 4) as i8 >> 4) as i16) } else { 0 };
+
+        // The mouse counts y upwards
+        Some(SnInputEvent::mouse(flags & PACKET_BUTTONS, dx, -dy, wheel))
+    }
+}
+
+static MOUSE: OnceCell<Mutex<SnMousePackets>> = OnceCell::uninit();
+
+fn mouse_handler() {
+    let byte = i8042::read_data();
+    if let Some(event) = MOUSE.get().unwrap().lock().add_byte(byte) {
+        tty::input_event(event);
+    }
+}
+
+fn set_sample_rate(rate: u8) -> Result<(), SnPs2Error> {
+    i8042::send(SnPs2Port::Second, COMMAND_SET_SAMPLE_RATE)?;
+    i8042::send(SnPs2Port::Second, rate)
+}
+
+/// Turns the wheel on with the magic sequence of sample rates, returns
+/// whether the mouse has one
+fn enable_wheel() -> Result<bool, SnPs2Error> {
+    for rate in [200, 100, 80] {
+        set_sample_rate(rate)?;
+    }
+    Ok(i8042::query(SnPs2Port::Second, COMMAND_GET_ID)? == ID_WHEEL)
+}
+
+fn setup() -> Result<bool, SnPs2Error> {
+    let wheel = enable_wheel()?;
+    set_sample_rate(SAMPLE_RATE)?;
+    i8042::send(SnPs2Port::Second, COMMAND_ENABLE_REPORTING)?;
+    Ok(wheel)
+}
+
+/// Sets up the mouse on the second PS/2 port, `i8042::init` must have found it
+pub fn init() {
+    printk!("initializing PS/2 mouse");
+    let wheel = match setup() {
+        Ok(wheel) => wheel,
+        Err(err) => {
+            log::warn!("cannot set up the PS/2 mouse: {:?}", err);
+            return;
+        }
+    };
+    printk!("PS/2 mouse {} a wheel", if wheel { "with" } else { "without" });
+    MOUSE.init_once(|| Mutex::new(SnMousePackets::new(wheel)));
+
+    interrupt::request_isa_irq(MOUSE_IRQ, Box::new(|| {
+        mouse_handler();
+        true
+    }))
+    .expect("cannot get the mouse IRQ");
+}
+
+#[test_case]
+fn test_mouse_packets() {
+    let mut packets = SnMousePackets::new(true);
+    // A stray byte without the always one bit is skipped
+    assert_eq!(packets.add_byte(0x00), None);
+    assert_eq!(packets.add_byte(0x09 | PACKET_Y_SIGN), None);
+    assert_eq!(packets.add_byte(5), None);
+    assert_eq!(packets.add_byte(0xfe), None);
+
+    let event = packets.add_byte(0x0f).unwrap();
+    assert_eq!(event.buttons, 1);
+    assert_eq!((event.dx, event.dy, event.wheel), (5, 2, 1));
+}
@@ -0,0 +1,685 @@
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use pc_keyboard::KeyCode;
+use spin::{Mutex, RwLock};
+
+use crate::{
+    print, print_s, printk,
+    process::{
+        signal::{SnSignal, SnSignalTarget},
+        thread,
+    },
+};
+
+use super::input::{SnInputEvent, SnInputQueue, EVENT_KEY};
+
+/// Bytes of a line being edited, the rest of a longer line is ignored
+const MAX_LINE: usize = 256;
+/// Bytes of finished lines waiting to be read
+const MAX_INPUT: usize = 4096;
+/// Input events waiting to be read in raw mode
+const MAX_EVENTS: usize = 256;
+/// Bytes written to a pty waiting for the master side to read them
+const MAX_OUTPUT: usize = 4096;
+/// Pseudo-terminals open at once
+const MAX_PTYS: usize = 64;
+
+const BACKSPACE: char = '\x08';
+const DELETE: char = '\x7f';
+/// Ctrl-C, interrupts the foreground group
+const INTERRUPT: char = '\x03';
+/// Ctrl-D, ends the input when the line is empty
+const END_OF_FILE: char = '\x04';
+/// Ctrl-U, erases the line
+const KILL_LINE: char = '\x15';
+/// Ctrl-Z, stops the foreground group
+const SUSPEND: char = '\x1a';
+
+/// Bits of `SnTermios::flags`
+/// Lines are edited by the kernel and read whole
+pub const TERMIOS_CANONICAL: u32 = 1 << 0;
+/// What is typed is shown
+pub const TERMIOS_ECHO: u32 = 1 << 1;
+/// Ctrl-C and Ctrl-Z signal the foreground group
+pub const TERMIOS_SIGNALS: u32 = 1 << 2;
+/// Reads from the console return `SnInputEvent`s, without canonical mode
+pub const TERMIOS_EVENTS: u32 = 1 << 3;
+const TERMIOS_ALL: u32 = TERMIOS_CANONICAL | TERMIOS_ECHO | TERMIOS_SIGNALS | TERMIOS_EVENTS;
+
+/// How a tty treats its input, a small part of POSIX termios
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SnTermios {
+    pub flags: u32,
+}
+
+impl SnTermios {
+    pub const DEFAULT: SnTermios = SnTermios { flags: TERMIOS_CANONICAL | TERMIOS_ECHO | TERMIOS_SIGNALS };
+
+    pub fn has(&self, flag: u32) -> bool {
+        self.flags & flag != 0
+    }
+}
+
+impl TryFrom<u64> for SnTermios {
+    type Error = ();
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        match u32::try_from(value) {
+            Ok(flags) if flags & !TERMIOS_ALL == 0 => Ok(SnTermios { flags }),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SnTtyId {
+    /// Keyboard and mouse in, screen out
+    Console,
+    /// COM1
+    Serial,
+    /// Slave side of a pseudo-terminal, by number
+    Pty(u32),
+}
+
+impl SnTtyId {
+    pub fn as_u64(self) -> u64 {
+        match self {
+            SnTtyId::Console => 0,
+            SnTtyId::Serial => 1,
+            SnTtyId::Pty(number) => 2 + number as u64,
+        }
+    }
+}
+
+impl TryFrom<u64> for SnTtyId {
+    type Error = ();
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(SnTtyId::Console),
+            1 => Ok(SnTtyId::Serial),
+            _ => u32::try_from(value - 2).map(SnTtyId::Pty).map_err(|_| ()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnTtyError {
+    /// No such tty, or the pty was closed
+    NotFound = 1,
+    /// A signal was caught
+    Interrupted = 2,
+    /// Too many ptys, or the call needs a pty
+    Unsupported = 3,
+}
+
+/// Where a tty's output goes
+enum SnTtyOutput {
+    /// Written straight to a device
+    Device(fn(&str)),
+    /// Kept for the master side of a pty
+    Pty(SnInputQueue<u8>),
+}
+
+/// Terminal with a line discipline, reading keys or bytes
+pub struct SnTty {
+    termios: SnTermios,
+    output: SnTtyOutput,
+    /// Raw input events, for a terminal with a keyboard
+    events: Option<SnInputQueue<SnInputEvent>>,
+    line: Vec<u8>,
+    input: SnInputQueue<u8>,
+    /// Start of a UTF-8 character coming in byte by byte
+    partial: [u8; 4],
+    partial_len: usize,
+    /// Set by Ctrl-D on an empty line, the next read returns nothing
+    end_of_file: bool,
+    /// Session this is the controlling terminal of, zero if none
+    session_id: u64,
+    /// Process group that may read and gets the signals typed
+    foreground: u64,
+    /// Typed signal for the foreground group, sent once the tty is unlocked
+    signal: Option<SnSignal>,
+}
+
+impl SnTty {
+    fn new(output: SnTtyOutput, keyboard: bool) -> SnTty {
+        SnTty {
+            termios: SnTermios::DEFAULT,
+            output,
+            events: keyboard.then(|| SnInputQueue::new(MAX_EVENTS)),
+            line: Vec::with_capacity(MAX_LINE),
+            input: SnInputQueue::new(MAX_INPUT),
+            partial: [0; 4],
+            partial_len: 0,
+            end_of_file: false,
+            session_id: 0,
+            foreground: 0,
+            signal: None,
+        }
+    }
+
+    pub fn termios(&self) -> SnTermios {
+        self.termios
+    }
+
+    pub fn set_termios(&mut self, termios: SnTermios) {
+        if termios != self.termios {
+            if let Some(events) = &mut self.events {
+                events.clear();
+            }
+            // Half a line has nowhere to go
+            self.line.clear();
+            self.partial_len = 0;
+        }
+        self.termios = termios;
+    }
+
+    /// Whether reads return input events
+    fn reads_events(&self) -> bool {
+        self.events.is_some() && self.termios.has(TERMIOS_EVENTS) && !self.termios.has(TERMIOS_CANONICAL)
+    }
+
+    /// Shows input, which fills the master side of a pty without waiting
+    fn echo(&mut self, text: &str) {
+        if !self.termios.has(TERMIOS_ECHO) {
+            return;
+        }
+        match &mut self.output {
+            SnTtyOutput::Device(write) => write(text),
+            SnTtyOutput::Pty(output) => text.bytes().for_each(|byte| output.push(byte)),
+        }
+    }
+
+    /// Writes output, returns how many bytes fit
+    pub fn write(&mut self, bytes: &[u8]) -> usize {
+        match &mut self.output {
+            SnTtyOutput::Device(write) => {
+                for chunk in bytes.utf8_chunks() {
+                    write(chunk.valid());
+                }
+                bytes.len()
+            }
+            SnTtyOutput::Pty(output) => {
+                let len = bytes.len().min(output.space());
+                bytes[..len].iter().for_each(|byte| output.push(*byte));
+                len
+            }
+        }
+    }
+
+    /// Moves the edited line to the input, it is lost if there is no room
+    fn finish_line(&mut self) -> bool {
+        if self.input.space() < self.line.len() {
+            self.line.clear();
+            return false;
+        }
+        for byte in self.line.drain(..) {
+            self.input.push(byte);
+        }
+        true
+    }
+
+    /// Removes the last character of the edited line and from the screen
+    fn erase_char(&mut self) -> bool {
+        let Some(last) = self.line.iter().rposition(|byte| byte & 0xc0 != 0x80) else {
+            return false;
+        };
+        self.line.truncate(last);
+        self.echo("\x08 \x08");
+        true
+    }
+
+    /// Drops the input, and has the foreground group signalled
+    fn raise(&mut self, signal: SnSignal, echo: &str) -> bool {
+        self.line.clear();
+        self.input.clear();
+        self.echo(echo);
+        self.signal = Some(signal);
+        false
+    }
+
+    /// Adds a typed character, returns whether readers have something to
+    /// read now
+    fn receive_char(&mut self, ch: char) -> bool {
+        let signals = self.termios.has(TERMIOS_SIGNALS);
+        match ch {
+            INTERRUPT if signals => return self.raise(SnSignal::Interrupt, "^C\n"),
+            SUSPEND if signals => return self.raise(SnSignal::TerminalStop, "^Z\n"),
+            _ => {}
+        }
+
+        let mut bytes = [0; 4];
+        let text = ch.encode_utf8(&mut bytes);
+        if !self.termios.has(TERMIOS_CANONICAL) {
+            text.bytes().for_each(|byte| self.input.push(byte));
+            if !ch.is_control() || ch == '\n' {
+                self.echo(text);
+            }
+            return true;
+        }
+
+        match ch {
+            '\n' | '\r' => {
+                self.line.push(b'\n');
+                self.echo("\n");
+                self.finish_line()
+            }
+            BACKSPACE | DELETE => {
+                self.erase_char();
+                false
+            }
+            KILL_LINE => {
+                while self.erase_char() {}
+                false
+            }
+            END_OF_FILE if self.line.is_empty() => {
+                self.end_of_file = true;
+                true
+            }
+            END_OF_FILE => self.finish_line(),
+            ch if ch.is_control() && ch != '\t' => false,
+            _ => {
+                // Keep room for the newline
+                if self.line.len() + text.len() < MAX_LINE {
+                    self.line.extend_from_slice(text.as_bytes());
+                    self.echo(text);
+                }
+                false
+            }
+        }
+    }
+
+    /// Handles a byte from a serial line or the master side of a pty,
+    /// returns whether readers have something to read now
+    pub fn receive_byte(&mut self, byte: u8) -> bool {
+        if !self.termios.has(TERMIOS_CANONICAL) && byte.is_ascii() {
+            return self.receive_char(byte as char);
+        }
+
+        // Put UTF-8 sequences back together, dropping broken ones
+        if self.partial_len > 0 && byte & 0xc0 != 0x80 {
+            self.partial_len = 0;
+        }
+        self.partial[self.partial_len] = byte;
+        self.partial_len += 1;
+        let expected = (self.partial[0].leading_ones() as usize).clamp(1, 4);
+        if self.partial_len < expected {
+            return false;
+        }
+
+        let len = core::mem::take(&mut self.partial_len);
+        match str::from_utf8(&self.partial[..len]).ok().and_then(|text| text.chars().next()) {
+            Some(ch) => self.receive_char(ch),
+            None => false,
+        }
+    }
+
+    /// Handles a key or mouse event, returns whether readers have something
+    /// to read now
+    pub fn input_event(&mut self, event: SnInputEvent) -> bool {
+        if self.reads_events() {
+            if let Some(events) = &mut self.events {
+                events.push(event);
+            }
+            return true;
+        }
+
+        if event.kind != EVENT_KEY || event.pressed == 0 {
+            return false;
+        }
+        if let Some(ch) = char::from_u32(event.ch).filter(|ch| *ch != '\0') {
+            return self.receive_char(ch);
+        }
+        // Editing keys reach raw readers as a VT100 would send them
+        match key_sequence(event.code) {
+            Some(sequence) if !self.termios.has(TERMIOS_CANONICAL) => {
+                sequence.bytes().for_each(|byte| self.input.push(byte));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether a read would return right away
+    pub fn readable(&self) -> bool {
+        match &self.events {
+            Some(events) if self.reads_events() => !events.is_empty(),
+            _ if self.termios.has(TERMIOS_CANONICAL) => !self.input.is_empty() || self.end_of_file,
+            _ => !self.input.is_empty(),
+        }
+    }
+
+    /// Copies up to one line in canonical mode, or whole input events or
+    /// bytes otherwise.
+    ///
+    /// Returns the bytes written.
+    pub fn read(&mut self, out: &mut [u8]) -> usize {
+        let mut written = 0;
+        if self.reads_events() {
+            let events = self.events.as_mut().unwrap();
+            for chunk in out.chunks_exact_mut(SnInputEvent::SIZE) {
+                let Some(event) = events.pop() else {
+                    break;
+                };
+                chunk.copy_from_slice(&event.to_bytes());
+                written += SnInputEvent::SIZE;
+            }
+            return written;
+        }
+
+        let canonical = self.termios.has(TERMIOS_CANONICAL);
+        if self.input.is_empty() {
+            self.end_of_file = false;
+        }
+        while written < out.len() {
+            let Some(byte) = self.input.pop() else {
+                break;
+            };
+            out[written] = byte;
+            written += 1;
+            if canonical && byte == b'\n' {
+                break;
+            }
+        }
+        written
+    }
+
+    /// Whether the master side of a pty has output to read
+    pub fn has_output(&self) -> bool {
+        matches!(&self.output, SnTtyOutput::Pty(output) if !output.is_empty())
+    }
+
+    /// Copies output for the master side of a pty, returns the bytes written
+    pub fn read_output(&mut self, out: &mut [u8]) -> usize {
+        let SnTtyOutput::Pty(output) = &mut self.output else {
+            return 0;
+        };
+        let mut written = 0;
+        while written < out.len() {
+            let Some(byte) = output.pop() else {
+                break;
+            };
+            out[written] = byte;
+            written += 1;
+        }
+        written
+    }
+}
+
+/// Escape sequence for a key without a character
+fn key_sequence(code: u8) -> Option<&'static str> {
+    const SEQUENCES: [(KeyCode, &str); 7] = [
+        (KeyCode::ArrowUp, "\x1b[A"),
+        (KeyCode::ArrowDown, "\x1b[B"),
+        (KeyCode::ArrowRight, "\x1b[C"),
+        (KeyCode::ArrowLeft, "\x1b[D"),
+        (KeyCode::Home, "\x1b[H"),
+        (KeyCode::End, "\x1b[F"),
+        (KeyCode::Delete, "\x1b[3~"),
+    ];
+    SEQUENCES.iter().find(|(key, _)| *key as u8 == code).map(|(_, sequence)| *sequence)
+}
+
+/// Terminals, with the ptys until they are closed
+static TTYS: RwLock<BTreeMap<SnTtyId, Arc<Mutex<SnTty>>>> = RwLock::new(BTreeMap::new());
+/// Pty numbers are not reused, so a closed pty stays gone
+static NEXT_PTY: Mutex<u32> = Mutex::new(0);
+
+pub fn init() {
+    printk!("initializing ttys");
+    let mut ttys = TTYS.write();
+    let console = SnTty::new(SnTtyOutput::Device(|text| print!("{}", text)), true);
+    let serial = SnTty::new(SnTtyOutput::Device(|text| print_s!("{}", text)), false);
+    ttys.insert(SnTtyId::Console, Arc::new(Mutex::new(console)));
+    ttys.insert(SnTtyId::Serial, Arc::new(Mutex::new(serial)));
+}
+
+fn tty(id: SnTtyId) -> Result<Arc<Mutex<SnTty>>, SnTtyError> {
+    TTYS.read().get(&id).cloned().ok_or(SnTtyError::NotFound)
+}
+
+/// Channel readers of a tty wait on
+fn channel(tty: &Arc<Mutex<SnTty>>) -> u64 {
+    Arc::as_ptr(tty) as u64
+}
+
+/// Channel the master side of a pty and writers waiting for room wait on
+fn output_channel(tty: &Arc<Mutex<SnTty>>) -> u64 {
+    channel(tty) + 1
+}
+
+/// Passes input to a tty, then wakes whoever has something to do and
+/// signals the foreground group
+fn feed<F>(tty: &Arc<Mutex<SnTty>>, input: F) where F: FnOnce(&mut SnTty) -> bool {
+    let (readable, output, signal, foreground) = {
+        let mut tty = tty.lock();
+        let readable = input(&mut tty);
+        (readable, tty.has_output(), tty.signal.take(), tty.foreground)
+    };
+
+    if readable {
+        thread::wake(channel(tty));
+    }
+    if output {
+        thread::wake(output_channel(tty));
+    }
+    if let Some(signal) = signal.filter(|_| foreground != 0) {
+        thread::send_signal(SnSignalTarget::Group(foreground), signal);
+    }
+}
+
+/// Passes an input event to the console, called from interrupt handlers
+pub fn input_event(event: SnInputEvent) {
+    if let Ok(tty) = tty(SnTtyId::Console) {
+        feed(&tty, |tty| tty.input_event(event));
+    }
+}
+
+/// Passes a byte from a serial line to its tty, called from interrupt handlers
+pub fn receive_byte(id: SnTtyId, byte: u8) {
+    if let Ok(tty) = tty(id) {
+        feed(&tty, |tty| tty.receive_byte(byte));
+    }
+}
+
+/// Blocks until a read from the tty by process group `group_id` would return.
+///
+/// Groups in the background wait until they are brought to the foreground.
+/// Must be called with interrupts disabled, which must stay so until the read.
+pub fn wait_readable(id: SnTtyId, group_id: u64) -> Result<(), SnTtyError> {
+    loop {
+        let tty = tty(id)?;
+        {
+            let tty = tty.lock();
+            let foreground = tty.session_id == 0 || tty.foreground == group_id;
+            if foreground && tty.readable() {
+                return Ok(());
+            }
+        }
+        if thread::caught_signal() {
+            return Err(SnTtyError::Interrupted);
+        }
+        thread::wait(channel(&tty));
+    }
+}
+
+pub fn read(id: SnTtyId, out: &mut [u8]) -> Result<usize, SnTtyError> {
+    Ok(tty(id)?.lock().read(out))
+}
+
+/// Writes to the tty, waiting while the output of a pty is full.
+///
+/// Must be called with interrupts disabled. Returns the bytes written,
+/// fewer than given if a signal came in between.
+pub fn write(id: SnTtyId, bytes: &[u8]) -> Result<usize, SnTtyError> {
+    let mut written = 0;
+    loop {
+        let tty = tty(id)?;
+        written += tty.lock().write(&bytes[written..]);
+        thread::wake(output_channel(&tty));
+        if written == bytes.len() {
+            return Ok(written);
+        }
+        if thread::caught_signal() {
+            return if written > 0 { Ok(written) } else { Err(SnTtyError::Interrupted) };
+        }
+        thread::wait(output_channel(&tty));
+    }
+}
+
+pub fn termios(id: SnTtyId) -> Result<SnTermios, SnTtyError> {
+    Ok(tty(id)?.lock().termios())
+}
+
+pub fn set_termios(id: SnTtyId, termios: SnTermios) -> Result<(), SnTtyError> {
+    let tty = tty(id)?;
+    tty.lock().set_termios(termios);
+    // Readers may find something to read in the new mode
+    thread::wake(channel(&tty));
+    Ok(())
+}
+
+/// Makes the tty the controlling terminal of a session, with group
+/// `group_id` in the foreground
+pub fn attach(id: SnTtyId, session_id: u64, group_id: u64) -> Result<(), SnTtyError> {
+    let tty = tty(id)?;
+    let mut tty = tty.lock();
+    tty.session_id = session_id;
+    tty.foreground = group_id;
+    Ok(())
+}
+
+/// Puts group `group_id` in the foreground, if the tty controls `session_id`
+pub fn set_foreground(id: SnTtyId, session_id: u64, group_id: u64) -> Result<(), SnTtyError> {
+    let tty = tty(id)?;
+    {
+        let mut tty = tty.lock();
+        if tty.session_id != session_id {
+            return Err(SnTtyError::NotFound);
+        }
+        tty.foreground = group_id;
+    }
+    // Readers that waited in the background may go on
+    thread::wake(channel(&tty));
+    Ok(())
+}
+
+/// Opens a pseudo-terminal, returns the id of its slave side.
+///
+/// Whatever is written to the slave side is read from the master side,
+/// and what the master side writes is input to the slave side.
+pub fn open_pty() -> Result<SnTtyId, SnTtyError> {
+    let mut ttys = TTYS.write();
+    // Next to the console and the serial tty
+    if ttys.len() >= 2 + MAX_PTYS {
+        return Err(SnTtyError::Unsupported);
+    }
+
+    let id = {
+        let mut next_pty = NEXT_PTY.lock();
+        *next_pty += 1;
+        SnTtyId::Pty(*next_pty - 1)
+    };
+    let pty = SnTty::new(SnTtyOutput::Pty(SnInputQueue::new(MAX_OUTPUT)), false);
+    ttys.insert(id, Arc::new(Mutex::new(pty)));
+    printk!("opened pty {}", id.as_u64());
+    Ok(id)
+}
+
+/// Closes a pseudo-terminal, calls on either side fail from now on
+pub fn close_pty(id: SnTtyId) -> Result<(), SnTtyError> {
+    if !matches!(id, SnTtyId::Pty(_)) {
+        return Err(SnTtyError::Unsupported);
+    }
+    let tty = TTYS.write().remove(&id).ok_or(SnTtyError::NotFound)?;
+    thread::wake(channel(&tty));
+    thread::wake(output_channel(&tty));
+    Ok(())
+}
+
+/// Reads what was written to the slave side of a pty, waiting for some.
+///
+/// Must be called with interrupts disabled, which must stay so until the
+/// read, and `out` must be writable.
+pub fn read_master(id: SnTtyId, out: &mut [u8]) -> Result<usize, SnTtyError> {
+    if !matches!(id, SnTtyId::Pty(_)) {
+        return Err(SnTtyError::Unsupported);
+    }
+    loop {
+        let tty = tty(id)?;
+        let read = tty.lock().read_output(out);
+        if read > 0 {
+            // Writers may have waited for room
+            thread::wake(output_channel(&tty));
+            return Ok(read);
+        }
+        if thread::caught_signal() {
+            return Err(SnTtyError::Interrupted);
+        }
+        thread::wait(output_channel(&tty));
+    }
+}
+
+/// Passes bytes from the master side of a pty to its line discipline, as if typed
+pub fn write_master(id: SnTtyId, bytes: &[u8]) -> Result<usize, SnTtyError> {
+    if !matches!(id, SnTtyId::Pty(_)) {
+        return Err(SnTtyError::Unsupported);
+    }
+    let tty = tty(id)?;
+    for byte in bytes {
+        feed(&tty, |tty| tty.receive_byte(*byte));
+    }
+    Ok(bytes.len())
+}
+
+#[test_case]
+fn test_line_discipline() {
+    let key = |ch: char| SnInputEvent::key(0, true, ch as u32);
+    let mut tty = SnTty::new(SnTtyOutput::Device(|_| {}), true);
+
+    for ch in "hé".chars().chain([BACKSPACE, 'i', '\n']) {
+        tty.input_event(key(ch));
+    }
+    assert!(tty.readable());
+    let mut out = [0; 16];
+    assert_eq!(tty.read(&mut out), 3);
+    assert_eq!(&out[..3], b"hi\n");
+
+    assert!(tty.input_event(key(END_OF_FILE)));
+    assert_eq!(tty.read(&mut out), 0);
+    assert!(!tty.readable());
+
+    tty.input_event(key('x'));
+    tty.input_event(key(INTERRUPT));
+    assert_eq!(tty.signal.take(), Some(SnSignal::Interrupt));
+    assert!(!tty.readable());
+
+    tty.set_termios(SnTermios { flags: TERMIOS_EVENTS });
+    tty.input_event(key('a'));
+    tty.input_event(SnInputEvent::mouse(0, 1, 1, 0));
+    let mut out = [0; 2 * SnInputEvent::SIZE];
+    assert_eq!(tty.read(&mut out), 2 * SnInputEvent::SIZE);
+
+    // Without canonical mode keys come as bytes, Ctrl-C included
+    tty.set_termios(SnTermios { flags: 0 });
+    tty.input_event(key(INTERRUPT));
+    tty.input_event(SnInputEvent::key(KeyCode::ArrowUp as u8, true, 0));
+    assert_eq!(tty.read(&mut out), 4);
+    assert_eq!(&out[..4], b"\x03\x1b[A");
+
+    // Serial lines send UTF-8 a byte at a time, and DEL for backspace
+    let mut tty = SnTty::new(SnTtyOutput::Device(|_| {}), false);
+    for byte in "aé".bytes().chain([0x7f, b'\r']) {
+        tty.receive_byte(byte);
+    }
+    assert_eq!(tty.read(&mut out), 2);
+    assert_eq!(&out[..2], b"a\n");
+
+    // A pty echoes to its master side, which has room for so much output
+    let mut pty = SnTty::new(SnTtyOutput::Pty(SnInputQueue::new(4)), false);
+    pty.receive_byte(b'l');
+    assert_eq!(pty.write(b"abcdef"), 3);
+    assert_eq!(pty.read_output(&mut out), 4);
+    assert_eq!(&out[..4], b"labc");
+    assert!(!pty.has_output());
+}
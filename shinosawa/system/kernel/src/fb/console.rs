@@ -0,0 +1,575 @@
+use alloc::{vec, vec::Vec};
+
+use super::display::Color;
+
+/// Lines kept after they scroll off the top
+pub const SCROLLBACK_LINES: usize = 256;
+/// Parameters of a CSI sequence past this are dropped
+const MAX_PARAMS: usize = 8;
+const TAB_WIDTH: usize = 8;
+
+const fn rgb(value: u32) -> Color {
+    Color {
+        alpha: 0xff,
+        red: (value >> 16) as u8,
+        green: (value >> 8) as u8,
+        blue: value as u8,
+    }
+}
+
+/// A character on the screen with its colours
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnCell {
+    pub ch: char,
+    pub fg: Color,
+    pub bg: Color,
+}
+
+/// Draws the cells of a console
+pub trait SnCellRenderer {
+    fn draw_cell(&mut self, col: usize, row: usize, cell: SnCell);
+    /// Moves every row up by `lines`, filling the bottom with `background`
+    fn scroll_up(&mut self, lines: usize, background: Color);
+    /// Called after a batch of changes
+    fn flush(&mut self);
+}
+
+/// The 16 ANSI colours, and the colours of text without attributes
+#[derive(Debug, Clone, Copy)]
+pub struct SnPalette {
+    pub colors: [Color; 16],
+    pub foreground: Color,
+    /// Foreground of bold text without a colour
+    pub foreground_bright: Color,
+    pub background: Color,
+}
+
+impl SnPalette {
+    pub const fn new() -> SnPalette {
+        SnPalette {
+            colors: [
+                rgb(0x000000), rgb(0xaa0000), rgb(0x00aa00), rgb(0xaa5500),
+                rgb(0x0000aa), rgb(0xaa00aa), rgb(0x00aaaa), rgb(0xaaaaaa),
+                rgb(0x555555), rgb(0xff5555), rgb(0x55ff55), rgb(0xffff55),
+                rgb(0x5555ff), rgb(0xff55ff), rgb(0x55ffff), rgb(0xffffff),
+            ],
+            foreground: rgb(0xffffff),
+            foreground_bright: rgb(0xffffff),
+            background: rgb(0x00afcc),
+        }
+    }
+
+    /// Takes the `term_palette`, `term_palette_bright`, `term_foreground`,
+    /// `term_foreground_bright` and `term_background` options of limine.conf,
+    /// keeping the default for the ones missing
+    pub fn from_limine_conf(conf: &str) -> SnPalette {
+        // Colours are RRGGBB, or AARRGGBB with the alpha left out here
+        fn parse_color(value: &str) -> Option<Color> {
+            u32::from_str_radix(value.trim(), 16).ok().map(|value| rgb(value & 0xffffff))
+        }
+
+        let mut palette = SnPalette::new();
+        for line in conf.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+
+            match key.trim() {
+                "term_palette" | "term_palette_bright" => {
+                    let offset = if key.trim() == "term_palette" { 0 } else { 8 };
+                    for (index, color) in value.split(';').take(8).enumerate() {
+                        if let Some(color) = parse_color(color) {
+                            palette.colors[offset + index] = color;
+                        }
+                    }
+                }
+                "term_foreground" => palette.foreground = parse_color(value).unwrap_or(palette.foreground),
+                "term_foreground_bright" => {
+                    palette.foreground_bright = parse_color(value).unwrap_or(palette.foreground_bright)
+                }
+                "term_background" => palette.background = parse_color(value).unwrap_or(palette.background),
+                _ => {}
+            }
+        }
+        palette
+    }
+}
+
+/// A colour as set by SGR, resolved against the palette when drawing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SnAttrColor {
+    Default,
+    Palette(u8),
+    Rgb(Color),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SnParserState {
+    Ground,
+    Escape,
+    /// Inside `ESC [`
+    Csi,
+}
+
+/// A VT100-like terminal on a grid of cells, with scrollback
+pub struct SnConsole<R: SnCellRenderer> {
+    renderer: R,
+    palette: SnPalette,
+    cols: usize,
+    rows: usize,
+    cells: Vec<SnCell>,
+
+    /// Ring of the lines that scrolled off, allocated upfront so that
+    /// writing never allocates
+    history: Vec<SnCell>,
+    history_start: usize,
+    history_len: usize,
+    /// How many lines the view is scrolled back
+    view_offset: usize,
+
+    col: usize,
+    row: usize,
+    saved_cursor: (usize, usize),
+    cursor_visible: bool,
+
+    fg: SnAttrColor,
+    bg: SnAttrColor,
+    bold: bool,
+    reverse: bool,
+
+    state: SnParserState,
+    params: [u16; MAX_PARAMS],
+    param_count: usize,
+    private: bool,
+}
+
+impl<R: SnCellRenderer> SnConsole<R> {
+    pub fn new(renderer: R, cols: usize, rows: usize, palette: SnPalette) -> SnConsole<R> {
+        let blank = SnCell {
+            ch: ' ',
+            fg: palette.foreground,
+            bg: palette.background,
+        };
+
+        SnConsole {
+            renderer,
+            palette,
+            cols,
+            rows,
+            cells: vec![blank; cols * rows],
+            history: vec![blank; cols * SCROLLBACK_LINES],
+            history_start: 0,
+            history_len: 0,
+            view_offset: 0,
+            col: 0,
+            row: 0,
+            saved_cursor: (0, 0),
+            cursor_visible: true,
+            fg: SnAttrColor::Default,
+            bg: SnAttrColor::Default,
+            bold: false,
+            reverse: false,
+            state: SnParserState::Ground,
+            params: [0; MAX_PARAMS],
+            param_count: 0,
+            private: false,
+        }
+    }
+
+    pub fn renderer(&mut self) -> &mut R {
+        &mut self.renderer
+    }
+
+    pub fn palette(&self) -> &SnPalette {
+        &self.palette
+    }
+
+    /// Blanks the screen and moves the cursor home
+    pub fn clear(&mut self) {
+        self.col = 0;
+        self.row = 0;
+        self.erase(0, self.cells.len());
+        self.draw_cursor();
+        self.renderer.flush();
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        if self.view_offset != 0 {
+            self.view_offset = 0;
+            self.redraw();
+        }
+
+        self.hide_cursor();
+        for c in s.chars() {
+            self.put(c);
+        }
+        self.draw_cursor();
+        self.renderer.flush();
+    }
+
+    /// Moves the view through the scrollback, negative goes back
+    pub fn scroll_view(&mut self, lines: isize) {
+        let offset = (self.view_offset as isize - lines).clamp(0, self.history_len as isize) as usize;
+        if offset != self.view_offset {
+            self.view_offset = offset;
+            self.redraw();
+            self.renderer.flush();
+        }
+    }
+
+    /// Changes the size of the grid, keeping the lines up to the cursor that
+    /// still fit. The scrollback goes, its lines have the old width.
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        let blank = SnCell {
+            ch: ' ',
+            fg: self.palette.foreground,
+            bg: self.palette.background,
+        };
+        let mut cells = vec![blank; cols * rows];
+
+        let first = (self.row + 1).saturating_sub(rows);
+        let width = cols.min(self.cols);
+        for (new_row, old_row) in (first..self.rows).take(rows).enumerate() {
+            let old_start = old_row * self.cols;
+            cells[new_row * cols..new_row * cols + width].copy_from_slice(&self.cells[old_start..old_start + width]);
+        }
+
+        self.cells = cells;
+        self.history = vec![blank; cols * SCROLLBACK_LINES];
+        self.history_start = 0;
+        self.history_len = 0;
+        self.view_offset = 0;
+        self.row -= first;
+        self.col = self.col.min(cols);
+        self.saved_cursor = (self.saved_cursor.0.min(cols - 1), self.saved_cursor.1.min(rows - 1));
+        self.cols = cols;
+        self.rows = rows;
+    }
+
+    /// Draws the whole screen again
+    pub fn refresh(&mut self) {
+        self.redraw();
+        self.renderer.flush();
+    }
+
+    fn history_line(&self, index: usize) -> &[SnCell] {
+        let slot = (self.history_start + index) % SCROLLBACK_LINES;
+        &self.history[slot * self.cols..(slot + 1) * self.cols]
+    }
+
+    /// Draws every cell, taking the view offset into account
+    fn redraw(&mut self) {
+        for row in 0..self.rows {
+            let line = self.history_len - self.view_offset + row;
+            for col in 0..self.cols {
+                let cell = if line < self.history_len {
+                    self.history_line(line)[col]
+                } else {
+                    self.cells[(line - self.history_len) * self.cols + col]
+                };
+                self.renderer.draw_cell(col, row, cell);
+            }
+        }
+        self.draw_cursor();
+    }
+
+    fn draw_cursor(&mut self) {
+        if !self.cursor_visible || self.view_offset != 0 {
+            return;
+        }
+        let col = self.col.min(self.cols - 1);
+        let mut cell = self.cells[self.row * self.cols + col];
+        core::mem::swap(&mut cell.fg, &mut cell.bg);
+        self.renderer.draw_cell(col, self.row, cell);
+    }
+
+    fn hide_cursor(&mut self) {
+        let col = self.col.min(self.cols - 1);
+        self.renderer.draw_cell(col, self.row, self.cells[self.row * self.cols + col]);
+    }
+
+    fn resolve(&self, color: SnAttrColor, default: Color) -> Color {
+        match color {
+            SnAttrColor::Default => default,
+            SnAttrColor::Palette(index) => self.palette.colors[index as usize],
+            SnAttrColor::Rgb(color) => color,
+        }
+    }
+
+    /// Colours of new cells, from the current attributes
+    fn current_colors(&self) -> (Color, Color) {
+        let fg = match self.fg {
+            SnAttrColor::Default if self.bold => self.palette.foreground_bright,
+            SnAttrColor::Palette(index) if self.bold && index < 8 => self.palette.colors[index as usize + 8],
+            fg => self.resolve(fg, self.palette.foreground),
+        };
+        let bg = self.resolve(self.bg, self.palette.background);
+
+        if self.reverse { (bg, fg) } else { (fg, bg) }
+    }
+
+    fn set_cell(&mut self, col: usize, row: usize, cell: SnCell) {
+        self.cells[row * self.cols + col] = cell;
+        self.renderer.draw_cell(col, row, cell);
+    }
+
+    /// Blanks the cells `start..end` with the current background
+    fn erase(&mut self, start: usize, end: usize) {
+        let (_, bg) = self.current_colors();
+        let blank = SnCell {
+            ch: ' ',
+            fg: self.palette.foreground,
+            bg,
+        };
+        for index in start..end.min(self.cells.len()) {
+            self.set_cell(index % self.cols, index / self.cols, blank);
+        }
+    }
+
+    fn scroll(&mut self) {
+        let slot = if self.history_len < SCROLLBACK_LINES {
+            self.history_len += 1;
+            (self.history_start + self.history_len - 1) % SCROLLBACK_LINES
+        } else {
+            let slot = self.history_start;
+            self.history_start = (self.history_start + 1) % SCROLLBACK_LINES;
+            slot
+        };
+        self.history[slot * self.cols..(slot + 1) * self.cols].copy_from_slice(&self.cells[..self.cols]);
+
+        self.cells.copy_within(self.cols.., 0);
+        let (_, bg) = self.current_colors();
+        let blank = SnCell {
+            ch: ' ',
+            fg: self.palette.foreground,
+            bg,
+        };
+        let last_line = (self.rows - 1) * self.cols;
+        self.cells[last_line..].fill(blank);
+        self.renderer.scroll_up(1, bg);
+    }
+
+    fn line_feed(&mut self) {
+        if self.row + 1 == self.rows {
+            self.scroll();
+        } else {
+            self.row += 1;
+        }
+    }
+
+    fn put(&mut self, c: char) {
+        match self.state {
+            SnParserState::Ground => self.put_ground(c),
+            SnParserState::Escape => {
+                self.state = SnParserState::Ground;
+                match c {
+                    '[' => {
+                        self.state = SnParserState::Csi;
+                        self.params = [0; MAX_PARAMS];
+                        self.param_count = 0;
+                        self.private = false;
+                    }
+                    '7' => self.saved_cursor = (self.col, self.row),
+                    '8' => (self.col, self.row) = self.saved_cursor,
+                    'c' => {
+                        self.reset_attributes();
+                        self.clear();
+                    }
+                    _ => {}
+                }
+            }
+            SnParserState::Csi => match c {
+                '0'..='9' => {
+                    if self.param_count == 0 {
+                        self.param_count = 1;
+                    }
+                    if let Some(param) = self.params.get_mut(self.param_count - 1) {
+                        *param = param.saturating_mul(10).saturating_add(c as u16 - '0' as u16);
+                    }
+                }
+                ';' => self.param_count = (self.param_count.max(1) + 1).min(MAX_PARAMS + 1),
+                '?' => self.private = true,
+                '\x40'..='\x7e' => {
+                    self.state = SnParserState::Ground;
+                    self.execute_csi(c);
+                }
+                _ => {}
+            },
+        }
+    }
+
+    fn put_ground(&mut self, c: char) {
+        match c {
+            '\x1b' => self.state = SnParserState::Escape,
+            // The kernel and userland write bare newlines
+            '\n' => {
+                self.col = 0;
+                self.line_feed();
+            }
+            '\r' => self.col = 0,
+            '\x08' => self.col = self.col.min(self.cols - 1).saturating_sub(1),
+            '\t' => self.col = ((self.col / TAB_WIDTH + 1) * TAB_WIDTH).min(self.cols - 1),
+            c if c < ' ' => {}
+            c => {
+                // Wrap only when the next character comes, so a full line
+                // followed by a newline doesn't leave an empty one
+                if self.col >= self.cols {
+                    self.col = 0;
+                    self.line_feed();
+                }
+                let (fg, bg) = self.current_colors();
+                self.set_cell(self.col, self.row, SnCell { ch: c, fg, bg });
+                self.col += 1;
+            }
+        }
+    }
+
+    /// Parameter `index`, or `default` if it is missing or zero
+    fn param(&self, index: usize, default: u16) -> usize {
+        match self.params[..self.param_count.min(MAX_PARAMS)].get(index) {
+            Some(&value) if value != 0 => value as usize,
+            _ => default as usize,
+        }
+    }
+
+    fn execute_csi(&mut self, command: char) {
+        let n = self.param(0, 1);
+        match command {
+            'A' => self.row = self.row.saturating_sub(n),
+            'B' => self.row = (self.row + n).min(self.rows - 1),
+            'C' => self.col = (self.col + n).min(self.cols - 1),
+            'D' => self.col = self.col.min(self.cols - 1).saturating_sub(n),
+            'G' => self.col = (n - 1).min(self.cols - 1),
+            'H' | 'f' => {
+                self.row = (n - 1).min(self.rows - 1);
+                self.col = (self.param(1, 1) - 1).min(self.cols - 1);
+            }
+            'J' => {
+                let cursor = self.row * self.cols + self.col.min(self.cols - 1);
+                match self.param(0, 0) {
+                    0 => self.erase(cursor, self.cells.len()),
+                    1 => self.erase(0, cursor + 1),
+                    _ => self.erase(0, self.cells.len()),
+                }
+            }
+            'K' => {
+                let line = self.row * self.cols;
+                let cursor = line + self.col.min(self.cols - 1);
+                match self.param(0, 0) {
+                    0 => self.erase(cursor, line + self.cols),
+                    1 => self.erase(line, cursor + 1),
+                    _ => self.erase(line, line + self.cols),
+                }
+            }
+            'm' => self.select_graphic_rendition(),
+            's' => self.saved_cursor = (self.col, self.row),
+            'u' => (self.col, self.row) = self.saved_cursor,
+            'h' | 'l' if self.private && self.param(0, 0) == 25 => self.cursor_visible = command == 'h',
+            _ => {}
+        }
+    }
+
+    fn reset_attributes(&mut self) {
+        self.fg = SnAttrColor::Default;
+        self.bg = SnAttrColor::Default;
+        self.bold = false;
+        self.reverse = false;
+    }
+
+    fn select_graphic_rendition(&mut self) {
+        let count = self.param_count.clamp(1, MAX_PARAMS);
+        let mut index = 0;
+
+        while index < count {
+            let param = self.params[index];
+            match param {
+                0 => self.reset_attributes(),
+                1 => self.bold = true,
+                22 => self.bold = false,
+                7 => self.reverse = true,
+                27 => self.reverse = false,
+                30..=37 => self.fg = SnAttrColor::Palette((param - 30) as u8),
+                39 => self.fg = SnAttrColor::Default,
+                40..=47 => self.bg = SnAttrColor::Palette((param - 40) as u8),
+                49 => self.bg = SnAttrColor::Default,
+                90..=97 => self.fg = SnAttrColor::Palette((param - 90 + 8) as u8),
+                100..=107 => self.bg = SnAttrColor::Palette((param - 100 + 8) as u8),
+                38 | 48 => {
+                    let (color, used) = self.extended_color(index + 1, count);
+                    index += used;
+                    if let Some(color) = color {
+                        if param == 38 {
+                            self.fg = color;
+                        } else {
+                            self.bg = color;
+                        }
+                    }
+                }
+                _ => {}
+            }
+            index += 1;
+        }
+    }
+
+    /// Parses the `5;n` or `2;r;g;b` after a 38 or 48, returning the colour
+    /// and how many parameters it took
+    fn extended_color(&self, index: usize, count: usize) -> (Option<SnAttrColor>, usize) {
+        let params = &self.params[index.min(count)..count];
+        match params {
+            [5, n, ..] => {
+                let color = match *n {
+                    0..=15 => SnAttrColor::Palette(*n as u8),
+                    // 6x6x6 cube
+                    16..=231 => {
+                        let level = |v: u16| if v == 0 { 0 } else { 55 + v as u32 * 40 };
+                        let n = *n - 16;
+                        SnAttrColor::Rgb(rgb(level(n / 36) << 16 | level(n / 6 % 6) << 8 | level(n % 6)))
+                    }
+                    // Greyscale ramp
+                    n => {
+                        let grey = 8 + (n.min(255) - 232) as u32 * 10;
+                        SnAttrColor::Rgb(rgb(grey << 16 | grey << 8 | grey))
+                    }
+                };
+                (Some(color), 2)
+            }
+            [2, r, g, b, ..] => {
+                let color = rgb((*r as u32 & 0xff) << 16 | (*g as u32 & 0xff) << 8 | (*b as u32 & 0xff));
+                (Some(SnAttrColor::Rgb(color)), 4)
+            }
+            _ => (None, params.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+struct SnNullRenderer;
+
+#[cfg(test)]
+impl SnCellRenderer for SnNullRenderer {
+    fn draw_cell(&mut self, _col: usize, _row: usize, _cell: SnCell) {}
+    fn scroll_up(&mut self, _lines: usize, _background: Color) {}
+    fn flush(&mut self) {}
+}
+
+#[test_case]
+fn test_console_escapes() {
+    let palette = SnPalette::from_limine_conf("term_palette: 000000;d20f39;40a02b\nterm_background: ffeff1f5");
+    assert_eq!(palette.colors[1], rgb(0xd20f39));
+    assert_eq!(palette.background, rgb(0xeff1f5));
+
+    let mut console = SnConsole::new(SnNullRenderer, 10, 3, palette);
+    console.write_str("a\x1b[31mb\x1b[0m\x1b[3;5Hc");
+    assert_eq!(console.cells[1].fg, rgb(0xd20f39));
+    assert_eq!(console.cells[2 * 10 + 4].ch, 'c');
+
+    // Two lines go into the scrollback, the view can go back to them
+    console.write_str("\n\n");
+    assert_eq!(console.history_len, 2);
+    assert_eq!(console.history_line(0)[0].ch, 'a');
+    console.scroll_view(-5);
+    assert_eq!(console.view_offset, 2);
+    console.write_str("\x1b[2J");
+    assert_eq!(console.view_offset, 0);
+    assert!(console.cells.iter().all(|cell| cell.ch == ' '));
+}
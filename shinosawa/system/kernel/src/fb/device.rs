@@ -0,0 +1,116 @@
+use conquer_once::spin::OnceCell;
+use limine::framebuffer::Framebuffer;
+use spin::Mutex;
+
+use crate::{
+    hal::interface::paging,
+    logger,
+    memory::{SnPhysAddr, SnVirtAddr},
+    process::thread::USER_FRAMEBUFFER_START,
+};
+
+/// Mode of the display, as handed to userland
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SnDisplayMode {
+    pub width: u32,
+    pub height: u32,
+    /// Bytes from one row to the next
+    pub pitch: u32,
+    pub bpp: u16,
+    pub red_size: u8,
+    pub red_shift: u8,
+    pub green_size: u8,
+    pub green_shift: u8,
+    pub blue_size: u8,
+    pub blue_shift: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnDisplayError {
+    NoDisplay = 1,
+    /// Another process owns the display
+    Busy = 2,
+    MapFailed = 3,
+}
+
+struct SnDisplayDevice {
+    phys_addr: SnPhysAddr,
+    size: u64,
+    mode: SnDisplayMode,
+}
+
+static DISPLAY: OnceCell<SnDisplayDevice> = OnceCell::uninit();
+
+/// Process drawing to the screen, the kernel console is hidden meanwhile
+static OWNER: Mutex<Option<u64>> = Mutex::new(None);
+
+pub fn init(framebuffer: &Framebuffer) {
+    let hhdm_offset = crate::limine::HHDM_REQUEST.get_response().unwrap().offset();
+
+    DISPLAY.init_once(|| SnDisplayDevice {
+        phys_addr: SnPhysAddr::new(framebuffer.addr() as u64 - hhdm_offset),
+        size: framebuffer.pitch() * framebuffer.height(),
+        mode: SnDisplayMode {
+            width: framebuffer.width() as u32,
+            height: framebuffer.height() as u32,
+            pitch: framebuffer.pitch() as u32,
+            bpp: framebuffer.bpp(),
+            red_size: framebuffer.red_mask_size(),
+            red_shift: framebuffer.red_mask_shift(),
+            green_size: framebuffer.green_mask_size(),
+            green_shift: framebuffer.green_mask_shift(),
+            blue_size: framebuffer.blue_mask_size(),
+            blue_shift: framebuffer.blue_mask_shift(),
+        },
+    });
+}
+
+pub fn mode() -> Option<SnDisplayMode> {
+    DISPLAY.get().map(|display| display.mode)
+}
+
+/// Maps the framebuffer into the current address space of `process_id`,
+/// write-combining, and hands the screen over to it.
+///
+/// Returns where the framebuffer was mapped.
+pub fn map(process_id: u64) -> Result<SnVirtAddr, SnDisplayError> {
+    let display = DISPLAY.get().ok_or(SnDisplayError::NoDisplay)?;
+    let address = SnVirtAddr::new(USER_FRAMEBUFFER_START);
+
+    let mut owner = OWNER.lock();
+    match *owner {
+        Some(id) if id == process_id => return Ok(address),
+        Some(_) => return Err(SnDisplayError::Busy),
+        None => {}
+    }
+
+    if paging::map_user_device_memory(display.phys_addr, address, display.size, true).is_err() {
+        paging::unmap_user_device_memory(address, display.size);
+        return Err(SnDisplayError::MapFailed);
+    }
+
+    log::info!("process {} owns the display", process_id);
+    *owner = Some(process_id);
+    logger::pause_fb(true);
+    Ok(address)
+}
+
+/// Gives the screen back to the kernel console if `process_id` owns it.
+///
+/// The mapping is removed from the current address space if `unmap` is set,
+/// a process that ended has no address space left.
+pub fn release(process_id: u64, unmap: bool) {
+    let mut owner = OWNER.lock();
+    if *owner != Some(process_id) {
+        return;
+    }
+
+    if let (true, Some(display)) = (unmap, DISPLAY.get()) {
+        paging::unmap_user_device_memory(SnVirtAddr::new(USER_FRAMEBUFFER_START), display.size);
+    }
+
+    *owner = None;
+    logger::pause_fb(false);
+    log::info!("process {} released the display", process_id);
+}
@@ -1,8 +1,8 @@
+use alloc::{vec, vec::Vec};
 use core::slice;
 
-use embedded_graphics::{pixelcolor::Rgb888, prelude::*};
+use embedded_graphics::{pixelcolor::Rgb888, prelude::*, primitives::Rectangle};
 use limine::framebuffer::Framebuffer;
-use spin::Mutex;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Position {
@@ -18,9 +18,45 @@ pub struct Color {
     pub blue: u8,
 }
 
+/// Where the channels sit in a pixel, as given by Limine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnPixelFormat {
+    /// 2, 3 or 4
+    pub bytes_per_pixel: usize,
+    pub red_size: u8,
+    pub red_shift: u8,
+    pub green_size: u8,
+    pub green_shift: u8,
+    pub blue_size: u8,
+    pub blue_shift: u8,
+}
+
+impl SnPixelFormat {
+    /// Packs a colour into the value of a pixel, the low `bytes_per_pixel` bytes are used
+    pub fn pack(&self, color: Color) -> u32 {
+        let channel = |value: u8, size: u8, shift: u8| (value as u32 >> (8 - size.min(8))) << shift;
+
+        channel(color.red, self.red_size, self.red_shift)
+            | channel(color.green, self.green_size, self.green_shift)
+            | channel(color.blue, self.blue_size, self.blue_shift)
+    }
+}
+
+/// Area touched since the last flush, as `x0..x1` and `y0..y1`
+#[derive(Debug, Clone, Copy)]
+struct SnDirtyRect {
+    x0: usize,
+    y0: usize,
+    x1: usize,
+    y1: usize,
+}
+
 pub struct SnFramebufferDisplay {
-    buffer: Mutex<&'static mut [u8]> ,
-    bpp: u16,
+    front: &'static mut [u8],
+    /// Drawing goes here when set, and reaches the screen on `flush`
+    back: Option<Vec<u8>>,
+    dirty: Option<SnDirtyRect>,
+    format: SnPixelFormat,
     pitch: usize,
     pub width: usize,
     pub height: usize,
@@ -32,45 +68,157 @@ impl SnFramebufferDisplay {
         let buffer_slice = unsafe { slice::from_raw_parts_mut(framebuffer.addr(), fb_size) };
 
         SnFramebufferDisplay {
-            buffer: Mutex::new(buffer_slice),
+            front: buffer_slice,
+            back: None,
+            dirty: None,
+            format: SnPixelFormat {
+                bytes_per_pixel: (framebuffer.bpp() as usize).div_ceil(8),
+                red_size: framebuffer.red_mask_size(),
+                red_shift: framebuffer.red_mask_shift(),
+                green_size: framebuffer.green_mask_size(),
+                green_shift: framebuffer.green_mask_shift(),
+                blue_size: framebuffer.blue_mask_size(),
+                blue_shift: framebuffer.blue_mask_shift(),
+            },
             pitch: framebuffer.pitch() as usize,
-            bpp: framebuffer.bpp(),
             height: framebuffer.height() as usize,
             width: framebuffer.width() as usize,
         }
     }
 
-    fn set_pixel_in(&mut self, position: Position, color: Color) {
-        let pixel_offset = position.y * self.pitch as usize + position.x * (self.bpp / 8) as usize;
+    /// Draws into memory from now on, only copying what changed to the screen on `flush`.
+    ///
+    /// Reading the framebuffer is slow, this makes scrolling cheap too.
+    pub fn enable_back_buffer(&mut self) {
+        if self.back.is_none() {
+            let mut back = vec![0; self.front.len()];
+            back.copy_from_slice(self.front);
+            self.back = Some(back);
+        }
+    }
 
+    /// Copies the rows changed since the last flush to the screen
+    pub fn flush(&mut self) {
+        let (Some(back), Some(dirty)) = (&self.back, self.dirty.take()) else {
+            return;
+        };
 
-        let pixel_buffer = &mut self.buffer.lock()[pixel_offset..];
+        let bytes_per_pixel = self.format.bytes_per_pixel;
+        for y in dirty.y0..dirty.y1 {
+            let start = y * self.pitch + dirty.x0 * bytes_per_pixel;
+            let end = y * self.pitch + dirty.x1 * bytes_per_pixel;
+            self.front[start..end].copy_from_slice(&back[start..end]);
+        }
+    }
 
-        pixel_buffer[3] = color.alpha;
-        pixel_buffer[2] = color.red;
-        pixel_buffer[1] = color.green;
-        pixel_buffer[0] = color.blue;
+    fn target(&mut self) -> &mut [u8] {
+        match &mut self.back {
+            Some(back) => back,
+            None => &mut *self.front,
+        }
     }
 
-    pub fn draw_pixel(&mut self, Pixel(coordinates, color): Pixel<Rgb888>) {
-        // ignore any out of bounds pixels
-        let (width, height) = { (self.width as usize, self.height as usize) };
+    fn mark_dirty(&mut self, x0: usize, y0: usize, x1: usize, y1: usize) {
+        if self.back.is_none() || x0 >= x1 || y0 >= y1 {
+            return;
+        }
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => SnDirtyRect {
+                x0: dirty.x0.min(x0),
+                y0: dirty.y0.min(y0),
+                x1: dirty.x1.max(x1),
+                y1: dirty.y1.max(y1),
+            },
+            None => SnDirtyRect { x0, y0, x1, y1 },
+        });
+    }
 
-        let (x, y) = {
-            let c: (i32, i32) = coordinates.into();
-            (c.0 as usize, c.1 as usize)
-        };
+    /// Writes a packed pixel at the start of `pixel`
+    fn write_pixel(pixel: &mut [u8], value: u32, bytes_per_pixel: usize) {
+        pixel[..bytes_per_pixel].copy_from_slice(&value.to_le_bytes()[..bytes_per_pixel]);
+    }
+
+    /// Fills a rectangle, clipped to the screen
+    pub fn fill_rect(&mut self, position: Position, width: usize, height: usize, color: Color) {
+        let x_end = (position.x + width).min(self.width);
+        let y_end = (position.y + height).min(self.height);
+        if position.x >= x_end || position.y >= y_end {
+            return;
+        }
+
+        let bytes_per_pixel = self.format.bytes_per_pixel;
+        let value = self.format.pack(color);
+        let pitch = self.pitch;
+        let row_start = position.x * bytes_per_pixel;
+        let row_end = x_end * bytes_per_pixel;
+        let target = self.target();
+
+        // Fill the first row pixel by pixel, then copy it to the others
+        let first = position.y * pitch;
+        for pixel in target[first + row_start..first + row_end].chunks_exact_mut(bytes_per_pixel) {
+            Self::write_pixel(pixel, value, bytes_per_pixel);
+        }
+        for y in position.y + 1..y_end {
+            target.copy_within(first + row_start..first + row_end, y * pitch + row_start);
+        }
+
+        self.mark_dirty(position.x, position.y, x_end, y_end);
+    }
+
+    /// Draws `width` by `height` pixels from `pixels`, row after row, clipped to the screen
+    pub fn blit(&mut self, position: Position, width: usize, height: usize, pixels: &[Color]) {
+        let x_end = (position.x + width).min(self.width);
+        let y_end = (position.y + height).min(self.height).min(position.y + pixels.len() / width.max(1));
+        if position.x >= x_end || position.y >= y_end {
+            return;
+        }
 
-        if (0..width).contains(&x) && (0..height).contains(&y) {
-            let color = Color {
-                red: color.r(),
-                green: color.g(),
-                blue: color.b(),
-                alpha: 0xFF,
-            };
+        let format = self.format;
+        let bytes_per_pixel = format.bytes_per_pixel;
+        let pitch = self.pitch;
+        let target = self.target();
 
-            self.set_pixel_in(Position { x, y }, color);
+        for (y, source) in (position.y..y_end).zip(pixels.chunks_exact(width)) {
+            let row = &mut target[y * pitch + position.x * bytes_per_pixel..y * pitch + x_end * bytes_per_pixel];
+            for (pixel, color) in row.chunks_exact_mut(bytes_per_pixel).zip(source) {
+                Self::write_pixel(pixel, format.pack(*color), bytes_per_pixel);
+            }
         }
+
+        self.mark_dirty(position.x, position.y, x_end, y_end);
+    }
+
+    /// Moves the picture up by `lines` rows of pixels, filling the bottom with `color`
+    pub fn scroll_up(&mut self, lines: usize, color: Color) {
+        let lines = lines.min(self.height);
+        let end = self.height * self.pitch;
+        let offset = lines * self.pitch;
+        self.target().copy_within(offset..end, 0);
+
+        self.mark_dirty(0, 0, self.width, self.height);
+        self.fill_rect(Position { x: 0, y: self.height - lines }, self.width, lines, color);
+    }
+
+    pub fn draw_pixel(&mut self, Pixel(coordinates, color): Pixel<Rgb888>) {
+        // ignore any out of bounds pixels
+        let (x, y) = (coordinates.x as usize, coordinates.y as usize);
+
+        if coordinates.x >= 0 && coordinates.y >= 0 && x < self.width && y < self.height {
+            let bytes_per_pixel = self.format.bytes_per_pixel;
+            let value = self.format.pack(to_color(color));
+            let offset = y * self.pitch + x * bytes_per_pixel;
+            Self::write_pixel(&mut self.target()[offset..], value, bytes_per_pixel);
+            self.mark_dirty(x, y, x + 1, y + 1);
+        }
+    }
+}
+
+fn to_color(color: Rgb888) -> Color {
+    Color {
+        red: color.r(),
+        green: color.g(),
+        blue: color.b(),
+        alpha: 0xFF,
     }
 }
 
@@ -90,6 +238,51 @@ impl DrawTarget for SnFramebufferDisplay {
 
         Ok(())
     }
+
+    /// Text with a background comes through here, a row at a time
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        let width = area.size.width as usize;
+        if area.top_left.x < 0 || area.top_left.y < 0 || width == 0 {
+            return self.draw_iter(area.points().zip(colors).map(|(point, color)| Pixel(point, color)));
+        }
+
+        let position = Position {
+            x: area.top_left.x as usize,
+            y: area.top_left.y as usize,
+        };
+        let mut row = [to_color(Rgb888::BLACK); 64];
+        let mut colors = colors.into_iter();
+        for y in 0..area.size.height as usize {
+            // Wider areas go in chunks
+            for x in (0..width).step_by(row.len()) {
+                let chunk = (width - x).min(row.len());
+                for (pixel, color) in row[..chunk].iter_mut().zip(colors.by_ref()) {
+                    *pixel = to_color(color);
+                }
+                self.blit(Position { x: position.x + x, y: position.y + y }, chunk, 1, &row[..chunk]);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let area = area.intersection(&self.bounding_box());
+        self.fill_rect(
+            Position {
+                x: area.top_left.x as usize,
+                y: area.top_left.y as usize,
+            },
+            area.size.width as usize,
+            area.size.height as usize,
+            to_color(color),
+        );
+
+        Ok(())
+    }
 }
 
 impl OriginDimensions for SnFramebufferDisplay {
@@ -97,3 +290,30 @@ impl OriginDimensions for SnFramebufferDisplay {
         Size::new(self.width as u32, self.height as u32)
     }
 }
+
+#[test_case]
+fn test_pixel_format() {
+    let color = Color { alpha: 0xff, red: 0xff, green: 0x80, blue: 0x08 };
+
+    let bgra = SnPixelFormat {
+        bytes_per_pixel: 4,
+        red_size: 8,
+        red_shift: 16,
+        green_size: 8,
+        green_shift: 8,
+        blue_size: 8,
+        blue_shift: 0,
+    };
+    assert_eq!(bgra.pack(color), 0xff8008);
+
+    let rgb565 = SnPixelFormat {
+        bytes_per_pixel: 2,
+        red_size: 5,
+        red_shift: 11,
+        green_size: 6,
+        green_shift: 5,
+        blue_size: 5,
+        blue_shift: 0,
+    };
+    assert_eq!(rgb565.pack(color), 0b11111_100000_00001);
+}
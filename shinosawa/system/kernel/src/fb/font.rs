@@ -0,0 +1,253 @@
+use alloc::{vec, vec::Vec};
+
+use crate::cmdline;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_HAS_TABLE: u8 = 0x02;
+const PSF1_MODE_SEQUENCES: u8 = 0x04;
+const PSF1_SEPARATOR: u16 = 0xffff;
+const PSF1_START_SEQUENCE: u16 = 0xfffe;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xff;
+const PSF2_START_SEQUENCE: u8 = 0xfe;
+
+/// Glyphs wider than this are not supported
+pub const MAX_GLYPH_WIDTH: usize = 64;
+
+/// Characters 0x80 to 0xff of code page 437, the encoding of raw VGA fonts
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»\
+░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀\
+αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+/// A bitmap font, one bit per pixel with rows padded to whole bytes
+pub struct SnBitmapFont {
+    glyphs: &'static [u8],
+    glyph_count: usize,
+    pub width: usize,
+    pub height: usize,
+    bytes_per_row: usize,
+    /// Glyph of each character sorted by character, empty if glyphs are
+    /// indexed by code point
+    unicode: Vec<(char, u32)>,
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().unwrap()))
+}
+
+impl SnBitmapFont {
+    /// Loads a PSF1 or PSF2 font, or a raw one for files named `.F08` to `.F32`
+    /// holding 256 glyphs 8 pixels wide
+    pub fn load(name: &str, bytes: &'static [u8]) -> Option<SnBitmapFont> {
+        if bytes.starts_with(&PSF1_MAGIC) {
+            Self::load_psf1(bytes)
+        } else if bytes.starts_with(&PSF2_MAGIC) {
+            Self::load_psf2(bytes)
+        } else if Self::is_raw_font_name(name) {
+            Self::load_raw(bytes)
+        } else {
+            None
+        }
+    }
+
+    /// Whether `name` looks like a font file this can load
+    pub fn is_font_name(name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        name.ends_with(".psf") || name.ends_with(".psfu") || Self::is_raw_font_name(&name)
+    }
+
+    fn is_raw_font_name(name: &str) -> bool {
+        name.rsplit_once('.')
+            .and_then(|(_, extension)| extension.strip_prefix(['f', 'F']))
+            .and_then(|height| height.parse::<usize>().ok())
+            .is_some_and(|height| (8..=32).contains(&height))
+    }
+
+    fn new(glyphs: &'static [u8], glyph_count: usize, width: usize, height: usize) -> Option<SnBitmapFont> {
+        let bytes_per_row = width.div_ceil(8);
+        if width == 0 || width > MAX_GLYPH_WIDTH || height == 0 || glyphs.len() < glyph_count * bytes_per_row * height {
+            return None;
+        }
+
+        Some(SnBitmapFont {
+            glyphs,
+            glyph_count,
+            width,
+            height,
+            bytes_per_row,
+            unicode: Vec::new(),
+        })
+    }
+
+    fn load_psf1(bytes: &'static [u8]) -> Option<SnBitmapFont> {
+        let mode = *bytes.get(2)?;
+        let height = *bytes.get(3)? as usize;
+        let glyph_count = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+        let glyphs_end = 4 + glyph_count * height;
+
+        let mut font = Self::new(bytes.get(4..glyphs_end)?, glyph_count, 8, height)?;
+
+        if mode & (PSF1_MODE_HAS_TABLE | PSF1_MODE_SEQUENCES) != 0 {
+            let mut glyph = 0;
+            let mut in_sequence = false;
+            for value in bytes[glyphs_end..].chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])) {
+                match value {
+                    PSF1_SEPARATOR => {
+                        glyph += 1;
+                        in_sequence = false;
+                    }
+                    PSF1_START_SEQUENCE => in_sequence = true,
+                    value if !in_sequence => {
+                        if let Some(ch) = char::from_u32(value as u32) {
+                            font.unicode.push((ch, glyph));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            font.sort_unicode();
+        }
+
+        Some(font)
+    }
+
+    fn load_psf2(bytes: &'static [u8]) -> Option<SnBitmapFont> {
+        let header_size = u32_at(bytes, 8)? as usize;
+        let flags = u32_at(bytes, 12)?;
+        let glyph_count = u32_at(bytes, 16)? as usize;
+        let glyph_size = u32_at(bytes, 20)? as usize;
+        let height = u32_at(bytes, 24)? as usize;
+        let width = u32_at(bytes, 28)? as usize;
+        if glyph_size != width.div_ceil(8) * height {
+            return None;
+        }
+        let glyphs_end = header_size.checked_add(glyph_count.checked_mul(glyph_size)?)?;
+
+        let mut font = Self::new(bytes.get(header_size..glyphs_end)?, glyph_count, width, height)?;
+
+        if flags & PSF2_HAS_UNICODE_TABLE != 0 {
+            // UTF-8 strings for each glyph in turn
+            for (glyph, entry) in bytes[glyphs_end..].split(|byte| *byte == PSF2_SEPARATOR).enumerate() {
+                // Sequences of several characters come last, only single ones are used
+                let singles = entry.split(|byte| *byte == PSF2_START_SEQUENCE).next().unwrap_or(&[]);
+                if let Ok(chars) = str::from_utf8(singles) {
+                    font.unicode.extend(chars.chars().map(|ch| (ch, glyph as u32)));
+                }
+            }
+            font.sort_unicode();
+        }
+
+        Some(font)
+    }
+
+    fn load_raw(bytes: &'static [u8]) -> Option<SnBitmapFont> {
+        if bytes.len() % 256 != 0 {
+            return None;
+        }
+        let mut font = Self::new(bytes, 256, 8, bytes.len() / 256)?;
+
+        font.unicode.extend((0x20..0x7f).map(|code| (code as u8 as char, code as u32)));
+        font.unicode.extend(CP437_HIGH.chars().zip(0x80..));
+        font.sort_unicode();
+        Some(font)
+    }
+
+    fn sort_unicode(&mut self) {
+        self.unicode.sort_unstable_by_key(|(ch, _)| *ch);
+        self.unicode.dedup_by_key(|(ch, _)| *ch);
+    }
+
+    fn glyph_index(&self, ch: char) -> Option<usize> {
+        if self.unicode.is_empty() {
+            return Some(ch as usize).filter(|index| *index < self.glyph_count);
+        }
+        let position = self.unicode.binary_search_by_key(&ch, |(ch, _)| *ch).ok()?;
+        Some(self.unicode[position].1 as usize).filter(|index| *index < self.glyph_count)
+    }
+
+    /// Returns the rows of the glyph for `ch`, falling back to the replacement
+    /// character and then `?`
+    pub fn glyph(&self, ch: char) -> Option<&[u8]> {
+        let index = self
+            .glyph_index(ch)
+            .or_else(|| self.glyph_index('\u{fffd}'))
+            .or_else(|| self.glyph_index('?'))?;
+        let size = self.bytes_per_row * self.height;
+        Some(&self.glyphs[index * size..(index + 1) * size])
+    }
+
+    /// Whether the pixel at `x` is set in a row returned by `glyph`
+    pub fn is_set(&self, glyph: &[u8], x: usize, y: usize) -> bool {
+        glyph[y * self.bytes_per_row + x / 8] & (0x80 >> (x % 8)) != 0
+    }
+}
+
+/// Whether the `font=` option names a file on the VFS rather than a Limine module
+fn is_vfs_path(name: &str) -> bool {
+    name.contains(":/")
+}
+
+/// Finds the console font among the Limine modules: the one the `font=` option
+/// names, or else the first font file
+pub fn from_modules() -> Option<SnBitmapFont> {
+    let font = match cmdline::options().font {
+        Some(name) if is_vfs_path(name) => return None,
+        Some(name) => crate::limine::modules().find(|(path, _)| path.ends_with(name)),
+        None => crate::limine::modules().find(|(path, _)| SnBitmapFont::is_font_name(path)),
+    };
+
+    let (path, contents) = font?;
+    let font = SnBitmapFont::load(path, contents);
+    if font.is_none() {
+        log::warn!("cannot load font {}", path);
+    }
+    font
+}
+
+/// Loads the font the `font=` option names if it is on the VFS, which only
+/// comes up after the console
+pub fn from_vfs() -> Option<SnBitmapFont> {
+    let path = cmdline::options().font.filter(|name| is_vfs_path(name))?;
+    let Ok(file) = crate::fs::vfs::find(path) else {
+        log::warn!("cannot find font {}", path);
+        return None;
+    };
+
+    let mut contents = vec![0; file.len()];
+    if file.read(&mut contents).is_err() {
+        log::warn!("cannot read font {}", path);
+        return None;
+    }
+
+    // The console keeps using the font until the next one
+    let font = SnBitmapFont::load(path, contents.leak());
+    if font.is_none() {
+        log::warn!("cannot load font {}", path);
+    }
+    font
+}
+
+#[test_case]
+fn test_font_loading() {
+    // PSF2 with two 8x2 glyphs, the second one mapped to 'é' and 'e'
+    static PSF2: [u8; 32 + 4 + 5] = [
+        0x72, 0xb5, 0x4a, 0x86, 0, 0, 0, 0, 32, 0, 0, 0, 1, 0, 0, 0,
+        2, 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 8, 0, 0, 0,
+        0x00, 0x00, 0x80, 0x01,
+        0xff, 0xc3, 0xa9, b'e', 0xff,
+    ];
+    let font = SnBitmapFont::load("test.psf", &PSF2).unwrap();
+    assert_eq!((font.width, font.height), (8, 2));
+    let glyph = font.glyph('é').unwrap();
+    assert!(font.is_set(glyph, 0, 0) && font.is_set(glyph, 7, 1));
+    assert_eq!(font.glyph('e'), Some(glyph));
+
+    static RAW: [u8; 256 * 8] = [0; 256 * 8];
+    let font = SnBitmapFont::load("VGA.F08", &RAW).unwrap();
+    assert_eq!(font.height, 8);
+    assert_eq!(font.glyph_index('░'), Some(0xb0));
+    assert!(SnBitmapFont::load("VGA.bin", &RAW).is_none());
+}
@@ -1,13 +1,18 @@
 
 use display::SnFramebufferDisplay;
 
+pub mod console;
+pub mod device;
 pub mod display;
+pub mod font;
 pub mod writer;
 
 pub fn init() -> Option<SnFramebufferDisplay> {
     if let Some(framebuffer_response) = crate::limine::FRAMEBUFFER_REQUEST.get_response() {
         if let Some(mut framebuffer) = framebuffer_response.framebuffers().next() {
-            let display = SnFramebufferDisplay::new(&mut framebuffer);
+            device::init(&framebuffer);
+            let mut display = SnFramebufferDisplay::new(&mut framebuffer);
+            display.enable_back_buffer();
 
             return Some(display);
         }
@@ -1,88 +1,161 @@
 use core::fmt;
 
 use embedded_graphics::{
-    mono_font::{MonoFont, MonoTextStyle}, pixelcolor::Rgb888, prelude::*, primitives::{PrimitiveStyleBuilder, Rectangle}, text::Text, Drawable
+    mono_font::{MonoFont, MonoTextStyleBuilder}, pixelcolor::Rgb888, prelude::*, text::{Baseline, Text}, Drawable
 };
 
 const FONT: MonoFont<'_> = embedded_graphics::mono_font::ascii::FONT_9X18;
 
-use super::display::SnFramebufferDisplay;
+use super::{
+    console::{SnCell, SnCellRenderer, SnConsole, SnPalette},
+    display::{Color, Position, SnFramebufferDisplay},
+    font::{self, SnBitmapFont, MAX_GLYPH_WIDTH},
+};
 
-const LINE_SPACING: usize = 5;
-const BORDER_PADDING: usize = 5;
+const CELL_WIDTH: usize = FONT.character_size.width as usize + FONT.character_spacing as usize;
+const CELL_HEIGHT: usize = FONT.character_size.height as usize;
 
-pub struct SnFramebufferWriter {
+/// Draws console cells with a loaded bitmap font, or the built-in one
+pub struct SnFramebufferRenderer {
     display: SnFramebufferDisplay,
-    x_pos: usize,
-    y_pos: usize,
-    x_char: usize,
-    y_char: usize,
+    font: Option<SnBitmapFont>,
+    /// Set while a process owns the screen
+    paused: bool,
 }
 
-impl SnFramebufferWriter {
-    pub fn new(display: SnFramebufferDisplay) -> SnFramebufferWriter {
-        let logger = Self {
-            display: display,
-            x_pos: BORDER_PADDING,
-            y_pos: FONT.character_size.height as usize,
-            x_char: FONT.character_size.width as usize + FONT.character_spacing as usize,
-            y_char: FONT.character_size.height as usize,
+impl SnFramebufferRenderer {
+    fn cell_width(&self) -> usize {
+        self.font.as_ref().map_or(CELL_WIDTH, |font| font.width)
+    }
+
+    fn cell_height(&self) -> usize {
+        self.font.as_ref().map_or(CELL_HEIGHT, |font| font.height)
+    }
+
+    fn draw_glyph(&mut self, col: usize, row: usize, cell: SnCell) {
+        let Some(font) = &self.font else {
+            return;
         };
+        let (width, height) = (font.width, font.height);
+        let x = col * width;
+        let glyph = font.glyph(cell.ch);
 
-        logger
+        let mut pixels = [cell.bg; MAX_GLYPH_WIDTH];
+        for y in 0..height {
+            for (px, pixel) in pixels[..width].iter_mut().enumerate() {
+                let set = glyph.is_some_and(|glyph| font.is_set(glyph, px, y));
+                *pixel = if set { cell.fg } else { cell.bg };
+            }
+            let position = Position { x, y: row * height + y };
+            self.display.blit(position, width, 1, &pixels[..width]);
+        }
     }
+}
 
-    fn newline(&mut self) {
-        self.y_pos += self.x_char + LINE_SPACING;
-        if self.y_pos >= self.height() {
-            self.y_pos = FONT.character_size.height as usize;
-            self.clear();
+fn to_rgb(color: Color) -> Rgb888 {
+    Rgb888::new(color.red, color.green, color.blue)
+}
+
+impl SnCellRenderer for SnFramebufferRenderer {
+    fn draw_cell(&mut self, col: usize, row: usize, cell: SnCell) {
+        if self.paused {
+            return;
+        }
+        if self.font.is_some() {
+            self.draw_glyph(col, row, cell);
+            return;
         }
-        self.carriage_return();
+        let style = MonoTextStyleBuilder::new()
+            .font(&FONT)
+            .text_color(to_rgb(cell.fg))
+            .background_color(to_rgb(cell.bg))
+            .build();
+        let str = &mut [0u8; 4];
+        let position = Point::new((col * CELL_WIDTH) as i32, (row * CELL_HEIGHT) as i32);
+        Text::with_baseline(cell.ch.encode_utf8(str), position, style, Baseline::Top)
+            .draw(&mut self.display)
+            .unwrap();
     }
 
-    fn carriage_return(&mut self) {
-        self.x_pos = BORDER_PADDING;
+    fn scroll_up(&mut self, lines: usize, background: Color) {
+        if self.paused {
+            return;
+        }
+        self.display.scroll_up(lines * self.cell_height(), background);
     }
 
-    fn width(&self) -> usize {
-        self.display.width
+    fn flush(&mut self) {
+        if self.paused {
+            return;
+        }
+        self.display.flush();
     }
+}
+
+/// Text console on the framebuffer
+pub struct SnFramebufferWriter {
+    console: SnConsole<SnFramebufferRenderer>,
+}
 
-    fn height(&self) -> usize {
-        self.display.height
+impl SnFramebufferWriter {
+    pub fn new(display: SnFramebufferDisplay) -> SnFramebufferWriter {
+        let palette = crate::limine::module("limine.conf")
+            .and_then(|conf| str::from_utf8(conf).ok())
+            .map_or(SnPalette::new(), SnPalette::from_limine_conf);
+        let renderer = SnFramebufferRenderer {
+            display,
+            font: font::from_modules(),
+            paused: false,
+        };
+        let cols = renderer.display.width / renderer.cell_width();
+        let rows = renderer.display.height / renderer.cell_height();
+
+        SnFramebufferWriter {
+            console: SnConsole::new(renderer, cols, rows, palette),
+        }
     }
-    /// Writes a single char to the framebuffer. Takes care of special control characters, such as
-    /// newlines and carriage returns.
-    fn write_char(&mut self, c: char) {
-        match c {
-            '\n' => self.newline(),
-            '\r' => self.carriage_return(),
-            c => {
-                let character_style =
-                    MonoTextStyle::new(&FONT, Rgb888::new(0xFF, 0xFF, 0xFF));
-                let str = &mut [0u8; 4];
-                let new_str = c.encode_utf8(str);
-                Text::new(new_str, Point::new(self.x_pos as i32, self.y_pos as i32), character_style)
-                    .draw(&mut self.display)
-                    .unwrap();
-                self.x_pos += self.x_char;
-                
-                if self.x_pos > self.display.width {
-                    self.newline();
-                    self.carriage_return();
-                }
-            }
+
+    /// Switches to another font, the grid is resized to fit and redrawn
+    pub fn set_font(&mut self, font: SnBitmapFont) {
+        let renderer = self.console.renderer();
+        renderer.font = Some(font);
+        let cols = renderer.display.width / renderer.cell_width();
+        let rows = renderer.display.height / renderer.cell_height();
+
+        self.console.resize(cols, rows);
+        if !self.console.renderer().paused {
+            self.fill_background();
+            self.console.refresh();
         }
     }
 
+    /// Fills the whole screen with the background, including the margin
+    /// right and below the cells
+    fn fill_background(&mut self) {
+        let background = self.console.palette().background;
+        let display = &mut self.console.renderer().display;
+        let (width, height) = (display.width, display.height);
+        display.fill_rect(Position { x: 0, y: 0 }, width, height, background);
+    }
+
     pub fn clear(&mut self) {
-        let style = PrimitiveStyleBuilder::new()
-                .fill_color(Rgb888::new(0x00, 0xaf, 0xcc))
-                .build();
-        Rectangle::new(Point::new(0, 0), Size::new(self.width() as u32, self.height() as u32))
-        .into_styled(style)
-        .draw(&mut self.display).unwrap();
+        self.fill_background();
+        self.console.clear();
+    }
+
+    /// Stops drawing while a process owns the screen, the text keeps being
+    /// kept and shows up again on resume
+    pub fn set_paused(&mut self, paused: bool) {
+        self.console.renderer().paused = paused;
+        if !paused {
+            self.fill_background();
+            self.console.refresh();
+        }
+    }
+
+    /// Moves the view through the lines that scrolled off, negative goes back
+    pub fn scroll_view(&mut self, lines: isize) {
+        self.console.scroll_view(lines);
     }
 }
 
@@ -91,10 +164,7 @@ unsafe impl Sync for SnFramebufferWriter {}
 
 impl fmt::Write for SnFramebufferWriter {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.chars() {
-            self.write_char(c);
-        }
-        
+        self.console.write_str(s);
         Ok(())
     }
 }
@@ -62,7 +62,7 @@ impl SnVfsNode for SnDummyNode {
         self.name
     }
 
-    fn read_dir(&self, entries: &mut [super::vfs::SnDirEntry]) {
+    fn read_dir(&self, entries: &mut [super::vfs::SnDirEntry]) -> usize {
         let mut ent = Vec::<SnDirEntry>::new();
         if let Some(dir) = &self.children {
             let dirs = dir.read();
@@ -80,8 +80,9 @@ impl SnVfsNode for SnDummyNode {
                 }
             }
         }
-        let vec = ent.as_slice();
-        entries[0..vec.len()].copy_from_slice(&vec);
+        let len = ent.len().min(entries.len());
+        entries[..len].copy_from_slice(&ent[..len]);
+        len
     }
     
     fn node_type(&self) -> SnVfsType {
@@ -93,7 +94,7 @@ impl SnVfsNode for SnDummyNode {
 
         let node = match name {
             "" | "." => Ok(self as SnVfsNodeRef),
-            _ => self.children.as_ref().unwrap().read().get(name).cloned().ok_or(SnVfsError::NotFound),
+            _ => self.children.as_ref().ok_or(SnVfsError::NotFound)?.read().get(name).cloned().ok_or(SnVfsError::NotFound),
         }?;
         
         if let Some(sub)= sub {
@@ -116,7 +117,7 @@ impl SnVfsFilesystem for SnDummyFilesystem {
 }
 
 pub fn new_example_filesystem() -> SnDummyFilesystem {
-    printk!("fs::dummy: creating a sample rootfs");
+    printk!("creating a sample rootfs");
     SnDummyFilesystem {
         root: Arc::new(
             SnDummyNode {
@@ -143,9 +144,21 @@ pub fn new_example_filesystem() -> SnDummyFilesystem {
                                                     node_type: SnVfsType::File,
                                                     contents: Some(include_bytes!("../../../../../target/x86_64-shinosawa/debug/kotono")),
                                                     children: None,
+                                                }) as SnVfsNodeRef),
+                                                ("shell", Arc::new(SnDummyNode {
+                                                    name: "shell",
+                                                    node_type: SnVfsType::File,
+                                                    contents: Some(include_bytes!("../../../../../target/x86_64-shinosawa/debug/shell")),
+                                                    children: None,
                                                 }) as SnVfsNodeRef)
                                             ])
                                         )),
+                                    }) as SnVfsNodeRef),
+                                    ("config", Arc::new(SnDummyNode {
+                                        name: "config",
+                                        node_type: SnVfsType::File,
+                                        contents: Some(include_bytes!("../../../../config")),
+                                        children: None,
                                     }) as SnVfsNodeRef)
                                 ]),
                             )),
@@ -2,3 +2,6 @@
 pub mod vfs;
 /// Dummy filesystem
 pub mod dummy;
+
+/// Filesystem in memory
+pub mod ramfs;
@@ -0,0 +1,156 @@
+use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use spin::RwLock;
+
+use crate::printk;
+
+use super::vfs::{split_path, SnDirEntry, SnVfsError, SnVfsFilesystem, SnVfsNode, SnVfsNodeRef, SnVfsResult, SnVfsType};
+
+/// File or directory kept in memory, gone on reboot
+struct SnRamNode {
+    name: &'static str,
+    node_type: SnVfsType,
+    contents: RwLock<Vec<u8>>,
+    children: Option<RwLock<BTreeMap<&'static str, SnVfsNodeRef>>>,
+}
+
+impl SnRamNode {
+    fn new(name: &'static str, node_type: SnVfsType) -> SnRamNode {
+        SnRamNode {
+            name,
+            node_type,
+            contents: RwLock::new(Vec::new()),
+            children: matches!(node_type, SnVfsType::Dir).then(|| RwLock::new(BTreeMap::new())),
+        }
+    }
+}
+
+impl SnVfsNode for SnRamNode {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn is_file(&self) -> bool {
+        matches!(self.node_type, SnVfsType::File)
+    }
+
+    fn is_dir(&self) -> bool {
+        matches!(self.node_type, SnVfsType::Dir)
+    }
+
+    fn len(&self) -> usize {
+        self.contents.read().len()
+    }
+
+    fn node_type(&self) -> SnVfsType {
+        self.node_type
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize, SnVfsError> {
+        if !self.is_file() {
+            return Err(SnVfsError::ReadError);
+        }
+        let contents = self.contents.read();
+        let len = buf.len().min(contents.len());
+        buf[..len].copy_from_slice(&contents[..len]);
+        Ok(len)
+    }
+
+    fn read_dir(&self, entries: &mut [SnDirEntry]) -> usize {
+        let Some(children) = &self.children else {
+            return 0;
+        };
+        let children = children.read();
+        for (entry, child) in entries.iter_mut().zip(children.values()) {
+            *entry = SnDirEntry {
+                name: child.name(),
+                dir_type: child.node_type(),
+            };
+        }
+        children.len().min(entries.len())
+    }
+
+    fn find(self: Arc<Self>, path: &str) -> SnVfsResult {
+        let (name, sub) = split_path(path);
+
+        let node = match name {
+            "" | "." => self as SnVfsNodeRef,
+            _ => {
+                let children = self.children.as_ref().ok_or(SnVfsError::NotFound)?;
+                children.read().get(name).cloned().ok_or(SnVfsError::NotFound)?
+            }
+        };
+
+        match sub {
+            Some(sub) => node.find(sub),
+            None => Ok(node),
+        }
+    }
+
+    fn create(&self, name: &str) -> SnVfsResult {
+        let children = self.children.as_ref().ok_or(SnVfsError::NotFound)?;
+        let mut children = children.write();
+        if let Some(node) = children.get(name) {
+            return Ok(node.clone());
+        }
+
+        // Entries hand out their names for as long as the kernel runs
+        let name: &'static str = Box::leak(String::from(name).into_boxed_str());
+        let node = Arc::new(SnRamNode::new(name, SnVfsType::File)) as SnVfsNodeRef;
+        children.insert(name, node.clone());
+        Ok(node)
+    }
+
+    fn truncate(&self) -> Result<(), SnVfsError> {
+        if !self.is_file() {
+            return Err(SnVfsError::ReadError);
+        }
+        self.contents.write().clear();
+        Ok(())
+    }
+
+    fn append(&self, data: &[u8]) -> Result<usize, SnVfsError> {
+        if !self.is_file() {
+            return Err(SnVfsError::ReadError);
+        }
+        self.contents.write().extend_from_slice(data);
+        Ok(data.len())
+    }
+}
+
+/// Filesystem in memory, the place to write files to
+pub struct SnRamFilesystem {
+    root: SnVfsNodeRef,
+}
+
+impl SnVfsFilesystem for SnRamFilesystem {
+    fn startup(&self) {}
+
+    fn root(&self) -> SnVfsNodeRef {
+        self.root.clone()
+    }
+}
+
+pub fn new_ram_filesystem() -> SnRamFilesystem {
+    printk!("creating a filesystem in memory");
+    SnRamFilesystem {
+        root: Arc::new(SnRamNode::new("", SnVfsType::Dir)),
+    }
+}
+
+#[test_case]
+fn test_ram_filesystem() {
+    let root = new_ram_filesystem().root();
+    let file = root.create("notes").unwrap();
+    file.append(b"hello ").unwrap();
+    file.append(b"world").unwrap();
+    assert_eq!(root.create("notes").unwrap().len(), 11);
+
+    let mut buf = [0; 16];
+    assert_eq!(root.clone().find("notes").unwrap().read(&mut buf).unwrap(), 11);
+    assert_eq!(&buf[..11], b"hello world");
+
+    let mut entries = [SnDirEntry { name: "", dir_type: SnVfsType::Dir }; 4];
+    assert_eq!(root.read_dir(&mut entries), 1);
+    assert_eq!(entries[0].name, "notes");
+    assert!(root.find("notes/more").is_err());
+}
@@ -12,6 +12,8 @@ pub type SnVfsResult = Result<super::vfs::SnVfsNodeRef, SnVfsError> ;
 pub enum SnVfsError {
     ReadError,
     NotFound,
+    /// The filesystem cannot be changed
+    ReadOnly,
 }
 
 #[derive(Clone)]
@@ -45,9 +47,26 @@ pub trait SnVfsNode: Send + Sync {
     fn node_type(&self) -> SnVfsType;
 
     fn read(&self, buf: &mut [u8]) -> Result<usize, SnVfsError>;
-    fn read_dir(&self, entries: &mut [SnDirEntry]);
+    /// Fills `entries` with the first entries of a directory, returns how many
+    fn read_dir(&self, entries: &mut [SnDirEntry]) -> usize;
 
     fn find(self: Arc<Self>, path: &str) -> SnVfsResult;
+
+    /// Creates an empty file named `name` in a directory, or returns the
+    /// file already there
+    fn create(&self, _name: &str) -> SnVfsResult {
+        Err(SnVfsError::ReadOnly)
+    }
+
+    /// Empties a file
+    fn truncate(&self) -> Result<(), SnVfsError> {
+        Err(SnVfsError::ReadOnly)
+    }
+
+    /// Adds to the end of a file, returns the bytes written
+    fn append(&self, _data: &[u8]) -> Result<usize, SnVfsError> {
+        Err(SnVfsError::ReadOnly)
+    }
 }
 
 pub trait SnVfsFilesystem: Send + Sync {
@@ -69,7 +88,7 @@ impl SnVfs {
 pub static VFS: OnceCell<RwLock<SnVfs>> = OnceCell::uninit();
 
 pub fn init() {
-    printk!("fs::vfs: initializing VFS interface");
+    printk!("initializing VFS interface");
     VFS.init_once(move || {
         RwLock::new(SnVfs {
             filesystem: BTreeMap::new(),
@@ -78,7 +97,7 @@ pub fn init() {
 }
 
 pub fn attach(drive: &'static str, fs: impl SnVfsFilesystem + 'static) {
-    printk!("fs::vfs: attaching a filesystem");
+    printk!("attaching a filesystem");
     let mut vfs = VFS.get().unwrap().write();
 
     vfs.attach(drive, fs);
@@ -104,4 +123,15 @@ pub fn find(path: &str) -> Result<SnVfsNodeRef, SnVfsError> {
     } else {
         Err(SnVfsError::NotFound)
     }
-}
\ No newline at end of file
+}
+
+/// Creates the file at `path` in an existing directory, or finds the file
+/// already there
+pub fn create(path: &str) -> Result<SnVfsNodeRef, SnVfsError> {
+    let (parent, name) = path.rsplit_once('/').ok_or(SnVfsError::NotFound)?;
+    if name.is_empty() {
+        return Err(SnVfsError::NotFound);
+    }
+    // Keep the slash, `SNSW:/` is the root while `SNSW:` is nothing
+    find(&path[..parent.len() + 1])?.create(name)
+}
@@ -1,10 +1,11 @@
-use acpi::{platform::interrupt::InterruptSourceOverride, InterruptModel};
+use acpi::{platform::interrupt::{Polarity, TriggerMode}, InterruptModel};
+use alloc::vec::Vec;
 use conquer_once::spin::OnceCell;
 use spin::Mutex;
-use x2apic::{ioapic::IoApic, lapic::{LocalApic, LocalApicBuilder}};
+use x2apic::{ioapic::{IoApic, IrqFlags, IrqMode, RedirectionTableEntry}, lapic::{LocalApic, LocalApicBuilder}};
 use x86_64::PhysAddr;
 
-use crate::{acpi::HARDWARE_INFO, hal::x86_64::{interrupt::InterruptIndex, paging::{self, map_phys_page}}, interrupt::FREE_VECTORS_START, memory::{SnPhysAddr, SnVirtAddr}, printk};
+use crate::{acpi::HARDWARE_INFO, hal::x86_64::{interrupt::InterruptIndex, paging::{self, map_phys_page}}, memory::{SnPhysAddr, SnVirtAddr}, printk};
 use core::{ops::{Deref, DerefMut}};
 
 // FIXME: this is not sound
@@ -28,14 +29,42 @@ impl DerefMut for UnsafeLocalApic {
 }
 
 pub static LOCAL_APIC: OnceCell<Mutex<UnsafeLocalApic>> = OnceCell::uninit();
-pub static IOAPIC: OnceCell<Mutex<IoApic>> = OnceCell::uninit();
+/// Number of legacy ISA IRQs
+const ISA_IRQS: usize = 16;
+
+pub struct SnIoApic {
+    /// First GSI handled by this IO APIC
+    gsi_base: u32,
+    /// Number of redirection table entries
+    lines: u32,
+    io_apic: IoApic,
+}
 
-pub fn init() {
-    printk!("x86_64::apic: initializing");
+/// How an interrupt line is wired
+#[derive(Clone, Copy, Debug)]
+struct SnIrqRoute {
+    gsi: u32,
+    active_low: bool,
+    level_triggered: bool,
+}
 
-    let hw_info = HARDWARE_INFO.get().unwrap();
+pub static IOAPICS: OnceCell<Mutex<Vec<SnIoApic>>> = OnceCell::uninit();
+/// ISA IRQs after applying the interrupt source overrides
+static ISA_ROUTES: OnceCell<[SnIrqRoute; ISA_IRQS]> = OnceCell::uninit();
+static BSP_LAPIC_ID: OnceCell<u8> = OnceCell::uninit();
+
+/// Sets up the local APIC and the IO APICs.
+///
+/// Returns false if the system has no APIC.
+pub fn init() -> bool {
+    printk!("initializing");
+
+    let Some(hw_info) = HARDWARE_INFO.get() else {
+        log::warn!("no ACPI tables, so no APIC either");
+        return false;
+    };
     if let InterruptModel::Apic(apic) = &hw_info.interrupt_model {
-        printk!("x86_64::apic: this system has APIC");
+        printk!("this system has APIC");
         let apic_physical_address: u64 = apic.local_apic_address ;
         let apic_virtual_address: u64 = paging::phys_to_virt_addr(PhysAddr::new(apic_physical_address)).as_u64();
     
@@ -55,39 +84,167 @@ pub fn init() {
 
         let mut lapic = lapic.lock();;
 
-        printk!("x86_64::apic: local APIC yeeee");
+        printk!("local APIC yeeee");
         unsafe {
             lapic.enable();
         }
 
-        let lapic_id = unsafe { lapic.id() };
-
-        for f in 0..apic.interrupt_source_overrides.len() {
-            let iso: InterruptSourceOverride = apic.interrupt_source_overrides[f];
-            printk!("x86_64::apic: interrupt source override {} to {}", iso.isa_source, iso.global_system_interrupt );
+        let bsp_lapic_id = hw_info
+            .processor_info
+            .as_ref()
+            .map(|info| info.boot_processor.local_apic_id)
+            .unwrap_or(0);
+        BSP_LAPIC_ID.init_once(|| bsp_lapic_id as u8);
+
+        let mut isa_routes: [SnIrqRoute; ISA_IRQS] = core::array::from_fn(|irq| SnIrqRoute {
+            gsi: irq as u32,
+            active_low: false,
+            level_triggered: false,
+        });
+        for iso in apic.interrupt_source_overrides.iter() {
+            printk!("interrupt source override {} to {}", iso.isa_source, iso.global_system_interrupt );
+            if let Some(route) = isa_routes.get_mut(iso.isa_source as usize) {
+                // ISA defaults are active high and edge triggered
+                *route = SnIrqRoute {
+                    gsi: iso.global_system_interrupt,
+                    active_low: matches!(iso.polarity, Polarity::ActiveLow),
+                    level_triggered: matches!(iso.trigger_mode, TriggerMode::Level),
+                };
+            }
+        }
+        ISA_ROUTES.init_once(move || isa_routes);
+
+        printk!("unleashing IO APIC");
+        let mut io_apics = Vec::new();
+        for io_apic_obj in apic.io_apics.iter() {
+            let io_apic_phys_address = io_apic_obj.address;
+            let io_apic_virt_address = paging::phys_to_virt_addr(PhysAddr::new(io_apic_phys_address as u64));
+
+            map_phys_page(SnPhysAddr::new(io_apic_phys_address as u64),SnVirtAddr::new(io_apic_virt_address.as_u64()));
+            let mut io_apic = unsafe { IoApic::new(io_apic_virt_address.as_u64()) };
+
+            // Lines get a vector once a driver asks for them
+            let lines = unsafe { io_apic.max_table_entry() } as u32 + 1;
+            for pin in 0..lines {
+                let mut entry = RedirectionTableEntry::default();
+                entry.set_flags(IrqFlags::MASKED);
+                unsafe { io_apic.set_table_entry(pin as u8, entry) };
+            }
+
+            printk!(
+                "IO APIC {} handles GSI {} to {}",
+                io_apic_obj.id,
+                io_apic_obj.global_system_interrupt_base,
+                io_apic_obj.global_system_interrupt_base + lines - 1
+            );
+            io_apics.push(SnIoApic {
+                gsi_base: io_apic_obj.global_system_interrupt_base,
+                lines,
+                io_apic,
+            });
         }
 
-        printk!("x86_64::apic: unleashing IO APIC");
-        let io_apic_obj: acpi::platform::interrupt::IoApic = apic.io_apics[0];
-        let io_apic_phys_address = io_apic_obj.address;
-        let io_apic_virt_address = paging::phys_to_virt_addr(PhysAddr::new(io_apic_phys_address as u64));
-        
-        map_phys_page(SnPhysAddr::new(io_apic_phys_address as u64),SnVirtAddr::new(io_apic_virt_address.as_u64()));
-        let mut io_apic = unsafe { IoApic::new(io_apic_virt_address.as_u64()) };
-
-        unsafe { io_apic.init(FREE_VECTORS_START) };
-        
-        IOAPIC.init_once(move || Mutex::new(io_apic));
+        IOAPICS.init_once(move || Mutex::new(io_apics));
 
-        // enable_irq(1);
+        true
     } else {
-        printk!("x86_64::apic: this system does not use APIC, apparently");
+        printk!("this system does not use APIC, apparently");
+
+        false
+    }
+
+}
+
+/// Runs a closure on the IO APIC handling a GSI, with the pin of the GSI on it
+fn with_io_apic<F, R>(gsi: u32, func: F) -> Option<R> where F: FnOnce(&mut IoApic, u8) -> R {
+    let mut io_apics = IOAPICS.get()?.lock();
+    let io_apic = io_apics
+        .iter_mut()
+        .find(|io_apic| gsi >= io_apic.gsi_base && gsi < io_apic.gsi_base + io_apic.lines)?;
+    let pin = (gsi - io_apic.gsi_base) as u8;
+
+    Some(func(&mut io_apic.io_apic, pin))
+}
+
+/// Returns the GSI an ISA IRQ is wired to
+pub fn isa_irq_to_gsi(irq: u8) -> u32 {
+    isa_route(irq).gsi
+}
+
+fn isa_route(irq: u8) -> SnIrqRoute {
+    ISA_ROUTES
+        .get()
+        .and_then(|routes| routes.get(irq as usize))
+        .copied()
+        .unwrap_or(SnIrqRoute { gsi: irq as u32, active_low: false, level_triggered: false })
+}
+
+/// Returns how a GSI is signalled
+fn gsi_route(gsi: u32) -> SnIrqRoute {
+    let routes = ISA_ROUTES.get();
+    if let Some(route) = routes.and_then(|routes| routes.iter().find(|route| route.gsi == gsi)) {
+        return *route;
+    }
+    if gsi < ISA_IRQS as u32 {
+        // Identity mapped ISA IRQ
+        return isa_route(gsi as u8);
+    }
+
+    // PCI interrupts are active low and level triggered
+    SnIrqRoute { gsi, active_low: true, level_triggered: true }
+}
+
+/// Points a GSI at a vector on a local APIC, leaving it masked.
+///
+/// Without `lapic_id` the interrupt goes to the bootstrap processor.
+/// Returns false if no IO APIC handles the GSI.
+pub fn route_irq(gsi: u32, vector: u8, lapic_id: Option<u8>) -> bool {
+    let route = gsi_route(gsi);
+    let dest = lapic_id.unwrap_or_else(|| *BSP_LAPIC_ID.get().unwrap_or(&0));
+
+    let mut flags = IrqFlags::MASKED;
+    if route.active_low {
+        flags |= IrqFlags::LOW_ACTIVE;
     }
+    if route.level_triggered {
+        flags |= IrqFlags::LEVEL_TRIGGERED;
+    }
+
+    let mut entry = RedirectionTableEntry::default();
+    entry.set_vector(vector);
+    entry.set_mode(IrqMode::Fixed);
+    entry.set_flags(flags);
+    entry.set_dest(dest);
 
+    with_io_apic(gsi, |io_apic, pin| unsafe { io_apic.set_table_entry(pin, entry) }).is_some()
 }
 
-pub fn enable_irq(irq: u8) {
-    let mut io_apic = IOAPIC.get().unwrap().lock();
+/// Sends a GSI to another local APIC, keeping its vector and mask
+pub fn set_irq_target(gsi: u32, lapic_id: u8) {
+    with_io_apic(gsi, |io_apic, pin| unsafe {
+        let mut entry = io_apic.table_entry(pin);
+        entry.set_dest(lapic_id);
+        io_apic.set_table_entry(pin, entry);
+    });
+}
+
+/// Base of the MSI message address, see the Intel SDM "Message Signalled Interrupts"
+const MSI_ADDRESS_BASE: u64 = 0xfee0_0000;
+
+/// Returns the address and data a device writes to raise `vector`
+/// on a local APIC, as an edge triggered fixed interrupt.
+///
+/// Without `lapic_id` the interrupt goes to the bootstrap processor.
+pub fn msi_message(vector: u8, lapic_id: Option<u8>) -> (u64, u32) {
+    let dest = lapic_id.unwrap_or_else(|| *BSP_LAPIC_ID.get().unwrap_or(&0));
+
+    (MSI_ADDRESS_BASE | (dest as u64) << 12, vector as u32)
+}
+
+pub fn enable_irq(gsi: u32) {
+    with_io_apic(gsi, |io_apic, pin| unsafe { io_apic.enable_irq(pin) });
+}
 
-    unsafe { io_apic.enable_irq(irq) };
-}
\ No newline at end of file
+pub fn disable_irq(gsi: u32) {
+    with_io_apic(gsi, |io_apic, pin| unsafe { io_apic.disable_irq(pin) });
+}
@@ -0,0 +1,42 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use crate::printk;
+
+use super::pic;
+
+/// How long the TSC is measured against the PIT
+const CALIBRATION_US: u32 = 10_000;
+
+/// TSC ticks per microsecond, zero until calibrated
+static TSC_PER_US: AtomicU64 = AtomicU64::new(0);
+/// TSC value at calibration, the start of the kernel clock
+static TSC_START: AtomicU64 = AtomicU64::new(0);
+
+fn read_tsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Measures the TSC frequency, starting the kernel clock
+pub fn init() {
+    let start = read_tsc();
+    pic::pit_wait(CALIBRATION_US);
+    let end = read_tsc();
+
+    let tsc_per_us = ((end - start) / CALIBRATION_US as u64).max(1);
+    TSC_START.store(start, Ordering::Relaxed);
+    TSC_PER_US.store(tsc_per_us, Ordering::Relaxed);
+
+    printk!("TSC runs at {} MHz", tsc_per_us);
+}
+
+/// Time since the clock started, zero before
+pub fn uptime() -> Duration {
+    let tsc_per_us = TSC_PER_US.load(Ordering::Relaxed);
+    if tsc_per_us == 0 {
+        return Duration::ZERO;
+    }
+
+    let ticks = read_tsc().saturating_sub(TSC_START.load(Ordering::Relaxed));
+    Duration::from_micros(ticks / tsc_per_us)
+}
@@ -1,15 +1,37 @@
+use x86_64::{VirtAddr, instructions::tlb, registers::model_specific::{FsBase, Msr}};
+
 use crate::printk;
 
-use super::{gdt, interrupt, apic};
+use super::{clock, gdt, interrupt};
 
 pub fn init() {
-    printk!("x86_64: initializing APIC timer");
-    apic::init();
-    printk!("x86_64: initialing CPU tables");
+    printk!("starting the clock");
+    clock::init();
+    init_pat();
+    printk!("initializing interrupt controller");
+    interrupt::init_controller();
+    printk!("initialing CPU tables");
     gdt::init();
     interrupt::init();
 }
 
+const IA32_PAT: u32 = 0x277;
+
+/// Memory types of the PAT entries: write-back, write-combining, uncached-minus,
+/// uncached, write-protect, write-combining, uncached-minus, uncached.
+///
+/// This is the layout Limine sets up, with entry 1 turned from write-through into
+/// write-combining so that `PageTableFlags::WRITE_THROUGH` alone selects it.
+const PAT_LAYOUT: u64 = 0x0007_0105_0007_0106;
+
+fn init_pat() {
+    unsafe {
+        Msr::new(IA32_PAT).write(PAT_LAYOUT);
+        core::arch::asm!("wbinvd", options(nostack));
+    }
+    tlb::flush_all();
+}
+
 #[derive(Clone, Copy, Debug)]
 #[repr(packed)]
 pub struct SnCpuContext {
@@ -46,6 +68,11 @@ impl SnCpuContext {
         self.rip
     }
 
+    /// Whether the context was saved in user mode
+    pub fn in_user_mode(&self) -> bool {
+        self.cs & 3 == 3
+    }
+
     pub fn set_stack_pointer(&mut self, rsp: usize) {
         self.rsp = rsp;
     }
@@ -61,7 +88,97 @@ impl SnCpuContext {
     pub fn set_arg_val_1(&mut self, rdi: usize) {
         self.rdi = rdi;
     }
+
+    pub fn set_arg_val_2(&mut self, rsi: usize) {
+        self._rsi = rsi;
+    }
+
+    #[cfg(feature = "gdb")]
+    pub fn set_instruction_pointer(&mut self, rip: usize) {
+        self.rip = rip;
+    }
+
+    /// Raises a debug exception after the next instruction when returning to this context
+    #[cfg(feature = "gdb")]
+    pub fn set_single_step(&mut self, enabled: bool) {
+        if enabled {
+            self.rflags |= RFLAGS_TRAP;
+        } else {
+            self.rflags &= !RFLAGS_TRAP;
+        }
+    }
+
+    /// Reads a register in the order of GDB's amd64 `g` packet,
+    /// returning its value and size in bytes
+    #[cfg(feature = "gdb")]
+    pub fn gdb_register(&self, idx: usize) -> Option<(u64, usize)> {
+        let value = match idx {
+            0 => self.rax,
+            1 => self._rbx,
+            2 => self.rcx,
+            3 => self._rdx,
+            4 => self._rsi,
+            5 => self.rdi,
+            6 => self._rbp,
+            7 => self.rsp,
+            8 => self._r8,
+            9 => self._r9,
+            10 => self._r10,
+            11 => self._r11,
+            12 => self._r12,
+            13 => self._r13,
+            14 => self._r14,
+            15 => self._r15,
+            16 => self.rip,
+            17 => self.rflags,
+            18 => self.cs,
+            // ss, then ds and es which are the same in long mode
+            19..=21 => self.ss,
+            // fs and gs, their base is what matters
+            22 | 23 => 0,
+            _ => return None,
+        };
+        Some((value as u64, if idx < 17 { 8 } else { 4 }))
+    }
+
+    /// Writes a register, see `gdb_register`. Segment registers are left alone,
+    /// as returning to the context relies on them.
+    #[cfg(feature = "gdb")]
+    pub fn set_gdb_register(&mut self, idx: usize, value: u64) -> bool {
+        let value = value as usize;
+        match idx {
+            0 => self.rax = value,
+            1 => self._rbx = value,
+            2 => self.rcx = value,
+            3 => self._rdx = value,
+            4 => self._rsi = value,
+            5 => self.rdi = value,
+            6 => self._rbp = value,
+            7 => self.rsp = value,
+            8 => self._r8 = value,
+            9 => self._r9 = value,
+            10 => self._r10 = value,
+            11 => self._r11 = value,
+            12 => self._r12 = value,
+            13 => self._r13 = value,
+            14 => self._r14 = value,
+            15 => self._r15 = value,
+            16 => self.rip = value,
+            17 => self.rflags = value,
+            18..GDB_REGISTER_COUNT => {}
+            _ => return false,
+        }
+        true
+    }
 }
+
+/// Number of registers in GDB's amd64 `g` packet that we know of
+#[cfg(feature = "gdb")]
+pub const GDB_REGISTER_COUNT: usize = 24;
+
+/// Trap flag in RFLAGS, for single-stepping
+#[cfg(feature = "gdb")]
+const RFLAGS_TRAP: usize = 1 << 8;
 pub unsafe fn set_context(context_addr: u64, function: u64, user_stack_end: u64, user: bool) {
     // Set context registers
     let context = unsafe {&mut *(context_addr as *mut SnCpuContext)};
@@ -78,4 +195,34 @@ pub unsafe fn set_context(context_addr: u64, function: u64, user_stack_end: u64,
         context.cs = code_selector.0 as usize;
         context.ss = data_selector.0 as usize;
     }
-}
\ No newline at end of file
+}
+/// Reads the thread pointer (FS base) of the running thread
+pub fn thread_pointer() -> u64 {
+    FsBase::read().as_u64()
+}
+
+/// Sets the thread pointer (FS base) of the running thread
+pub fn set_thread_pointer(addr: u64) {
+    FsBase::write(VirtAddr::new(addr));
+}
+
+/// Reads the frame pointer (RBP) of the calling function
+#[inline(always)]
+pub fn frame_pointer() -> u64 {
+    let rbp: u64;
+    unsafe { core::arch::asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+    rbp
+}
+
+/// Runs `func` with the write protection of read-only pages turned off,
+/// to patch kernel code
+#[cfg(feature = "gdb")]
+pub fn without_write_protect<F, R>(func: F) -> R where F: FnOnce() -> R {
+    use x86_64::registers::control::{Cr0, Cr0Flags};
+
+    let flags = Cr0::read();
+    unsafe { Cr0::write(flags - Cr0Flags::WRITE_PROTECT) };
+    let ret = func();
+    unsafe { Cr0::write(flags) };
+    ret
+}
@@ -1,32 +1,25 @@
-use core::arch::asm;
+use core::slice;
 
 use limine::memory_map::{self, EntryType};
 use x86_64::{structures::paging::{FrameAllocator, PhysFrame, Size4KiB}, PhysAddr, VirtAddr};
 
-use crate::printk;
+use shinosawa_system_frame_bitmap::{words_for_frames, SnFrameBitmap};
 
-pub struct SnLimineFrameAllocator {
-    memory_map: &'static [&'static memory_map::Entry],
-    level_3_virt_addr: VirtAddr,
-    level_2_virt_addr: VirtAddr,
-    level_1_virt_addr: VirtAddr,
-    frame_phys_addr: PhysAddr,
-}
+const FRAME_SIZE: u64 = 4096;
 
-fn nonzero_bit_index(bitmap: u64) -> u64 {
-    let index: u64;
-    unsafe {
-        asm!("bsf rax, rcx",
-             in("rcx") bitmap,
-             lateout("rax") index,
-             options(pure, nomem, nostack));
-    }
-    index
+/// Frames below this address can be used by devices limited to 32-bit DMA
+pub const DMA32_LIMIT: PhysAddr = PhysAddr::new_truncate(0x1_0000_0000);
+
+pub struct SnLimineFrameAllocator {
+    bitmap: SnFrameBitmap<'static>,
 }
 
 impl SnLimineFrameAllocator {
     /// Create a FrameAllocator from the passed memory map.
     ///
+    /// A single bitmap spans all `USABLE` regions, and is stored at the start
+    /// of the first usable region big enough to hold it.
+    ///
     /// This function is unsafe because the caller must guarantee that the passed
     /// memory map is valid. The main requirement is that all frames that are marked
     /// as `USABLE` in it are really unused.
@@ -34,138 +27,86 @@ impl SnLimineFrameAllocator {
         memory_map: &'static [&'static memory_map::Entry],
         physical_memory_offset: VirtAddr
     ) -> SnLimineFrameAllocator {
-        let mut usable_regions = memory_map
+        let usable_regions = || memory_map
             .iter()
             .filter(|r| r.entry_type == EntryType::USABLE);
 
-        let region = usable_regions.next().unwrap();
-
-        let start_addr = region.base;
-        let end_addr = region.base + region.length;
-        let nframes = (end_addr - start_addr) / 4096 ;
-
-        let level_3_virt_addr = physical_memory_offset + start_addr;
-        let level_2_virt_addr = level_3_virt_addr + 8u64;
-        let level_1_virt_addr = level_2_virt_addr + 8u64 * 8u64;
-        
-        let level_3_ptr = level_3_virt_addr.as_mut_ptr() as *mut u64;
-        let level_2_ptr = level_2_virt_addr.as_mut_ptr() as *mut u64;
-        let level_1_ptr = level_1_virt_addr.as_mut_ptr() as *mut u64;
-        
-        unsafe {
-            *level_3_ptr = 0xFFFF_FFFF_FFFF_FFFF;
-            *level_2_ptr = 0xFFFF_FFFF_FFFF_FFFF;
-            *level_1_ptr = 0xFFFF_FFFF_FFFF_FFFE;
-            
-            for i in 0..63 {
-                *(level_2_ptr.offset(i)) = 0xFFFF_FFFF_FFFF_FFFF;
-                *level_1_ptr.offset(i * 64) = 0xFFFF_FFFF_FFFF_FFFE;
-
-                for j in 1..63 {
-                    *(level_1_ptr.offset(i * 64 + j)) = 0xFFFF_FFFF_FFFF_FFFF;
-                }
-            }
+        let first_frame = usable_regions().map(|r| r.base / FRAME_SIZE).min().unwrap();
+        let end_frame = usable_regions().map(|r| (r.base + r.length) / FRAME_SIZE).max().unwrap();
+        let frame_count = end_frame - first_frame;
+
+        let bitmap_words = words_for_frames(frame_count);
+        let bitmap_frames = (bitmap_words as u64 * 8).div_ceil(FRAME_SIZE);
+
+        let bitmap_region = usable_regions()
+            .find(|r| r.length / FRAME_SIZE >= bitmap_frames)
+            .expect("no usable region can hold the frame bitmap");
+
+        let words = unsafe {
+            slice::from_raw_parts_mut(
+                (physical_memory_offset + bitmap_region.base).as_mut_ptr::<u64>(),
+                bitmap_words,
+            )
+        };
+
+        let mut bitmap = SnFrameBitmap::new(words, first_frame, frame_count);
+        for region in usable_regions() {
+            bitmap.add_usable(region.base.div_ceil(FRAME_SIZE), region.length / FRAME_SIZE);
         }
+        bitmap.reserve(bitmap_region.base / FRAME_SIZE, bitmap_frames);
+        // Keep the null frame out of circulation
+        bitmap.reserve(0, 1);
 
         SnLimineFrameAllocator {
-            level_3_virt_addr,
-            level_2_virt_addr,
-            level_1_virt_addr,
-            frame_phys_addr: PhysAddr::new(start_addr),
-            memory_map,
+            bitmap,
         }
     }
-    
-    /// Returns an iterator over the usable frames specified in the memory map.
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        // get usable regions from memory map
-        let regions = self.memory_map.iter().skip(1);
-        let usable_regions = regions.filter(|r| r.entry_type == EntryType::USABLE);
-        // map each region to its address range
-        let addr_ranges = usable_regions.map(|r| r.base..(r.base + r.length));
-        // transform to an iterator of frame start addresses
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
-
-        // create `PhysFrame` types from the start addresses
-        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+
+    pub fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.bitmap.free(frame.start_address().as_u64() / FRAME_SIZE);
+    }
+
+    /// Allocates a frame below a physical address, for devices that cannot address all memory
+    pub fn allocate_frame_below(&mut self, limit: PhysAddr) -> Option<PhysFrame> {
+        self.bitmap
+            .allocate_below(limit.as_u64() / FRAME_SIZE)
+            .map(|frame| PhysFrame::containing_address(PhysAddr::new(frame * FRAME_SIZE)))
     }
 
-    fn fetch_frame(&mut self) -> u64 {
-        let l3_ptr = self.level_3_virt_addr.as_mut_ptr() as *mut u64;
-        let mut l3_bitmap = unsafe{*l3_ptr};
-        let l3_index = nonzero_bit_index(l3_bitmap);
+    /// Allocates `count` physically contiguous frames below `limit`, e.g. for DMA buffers.
+    ///
+    /// The first frame is aligned to `align` frames. Returns the first frame.
+    pub fn allocate_contiguous(&mut self, count: u64, align: u64, limit: PhysAddr) -> Option<PhysFrame> {
+        self.bitmap
+            .allocate_contiguous(count, align, limit.as_u64() / FRAME_SIZE)
+            .map(|frame| PhysFrame::containing_address(PhysAddr::new(frame * FRAME_SIZE)))
+    }
 
-        let l2_ptr = unsafe {
-            (self.level_2_virt_addr.as_mut_ptr() as *mut u64)
-            .offset(l3_index as isize)
-        };
-        let mut l2_bitmap = unsafe{*l2_ptr};
-        let l2_index = nonzero_bit_index(l2_bitmap);
-
-        let l1_ptr = unsafe{(self.level_1_virt_addr.as_mut_ptr() as *mut u64)
-            .offset(l3_index as isize * 64 + l2_index as isize)};
-        let mut l1_bitmap = unsafe{*l1_ptr};
-        let l1_index = nonzero_bit_index(l1_bitmap);
-        // printk_s!("l3: {:#} {:#} {:#}", l3_index, l2_index, l1_index);
-
-        let frame_number =
-            (l3_index as u64) * 64u64 * 64u64 +
-            (l2_index as u64) * 64u64
-            + (l1_index as u64);
-        
-        l1_bitmap ^= 1 << l1_index;
-        unsafe{*l1_ptr = l1_bitmap;}
-
-        if l1_bitmap == 0 {
-            l2_bitmap ^= 1 << l2_index;
-            unsafe{*l2_ptr = l2_bitmap;}
-
-            if l2_bitmap == 0 {
-
-                l3_bitmap ^= 1 << l3_index;
-                unsafe{*l3_ptr = l3_bitmap;}
-            }
-        }
+    /// Frees frames allocated with `allocate_contiguous`
+    pub fn deallocate_contiguous(&mut self, first: PhysFrame, count: u64) {
+        self.bitmap.free_contiguous(first.start_address().as_u64() / FRAME_SIZE, count);
+    }
 
-        frame_number
+    /// Number of usable frames
+    pub fn total_frames(&self) -> u64 {
+        self.bitmap.total_frames()
     }
 
-    fn return_frame(&mut self, frame_number: u64) {
-        // Calculate indices
-        let l1_index = frame_number % 64;
-        let l2_index = frame_number / 64;
-        let l3_index = frame_number / (64*64);
-
-        let l1_ptr = unsafe{(self.level_1_virt_addr.as_mut_ptr() as *mut u64)
-            .offset(l3_index as isize * 64 +l2_index as isize)};
-        unsafe{*l1_ptr |= 1 << l1_index;}
-
-        if(unsafe { *l1_ptr } == 0) {
-            // set level 2 bit
-            let l2_ptr = self.level_2_virt_addr.as_mut_ptr() as *mut u64;
-            unsafe{*l2_ptr |= 1 << l2_index};
-
-            if(unsafe { *l2_ptr} == 0) {
-                // set level 3 bit
-                let l3_ptr = self.level_3_virt_addr.as_mut_ptr() as *mut u64;
-                unsafe{*l3_ptr |= 1 << l3_index};
-            }
-        }
+    /// Number of free frames
+    pub fn free_frames(&self) -> u64 {
+        self.bitmap.free_frames()
     }
 
-    pub fn deallocate_frame(&mut self, frame: PhysFrame) {
-        let frame_number = (frame.start_address() - self.frame_phys_addr) / 4096;
-        self.return_frame(frame_number);
+    /// Number of allocated frames
+    pub fn used_frames(&self) -> u64 {
+        self.bitmap.used_frames()
     }
 }
 
 unsafe impl FrameAllocator<Size4KiB> for SnLimineFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        let frame = self.fetch_frame();
-        
-        let frame = self.usable_frames().nth(frame as usize);
-
-        frame
+        self.bitmap
+            .allocate()
+            .map(|frame| PhysFrame::containing_address(PhysAddr::new(frame * FRAME_SIZE)))
     }
-    
-}
\ No newline at end of file
+}
@@ -8,12 +8,19 @@ use crate::hal::x86_64::interrupt::InterruptStackIndex;
 use crate::memory::SnVirtAddr;
 use crate::printk;
 
+/// Stack shared by the CPU exceptions
+pub const EXCEPTION_IST_INDEX: u16 = 0;
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 pub const GENERAL_PROTECTION_FAULT_IST_INDEX: u16 = 0;
 pub const TIMER_IST_INDEX: u16 = 1;
 pub const PAGE_FAULT_IST_INDEX: u16 = 0;
-pub const PLATFORM_HANDLER_IST_INDEX: u16 = 0;
 pub const SYSCALL_IST_INDEX: u16 = 2;
+/// Breakpoints and debug exceptions, which can happen while another
+/// handler is using the exception stack
+pub const DEBUG_IST_INDEX: u16 = 3;
+/// Device IRQs, so that an exception in a handler does not overwrite the
+/// frame of the IRQ
+pub const PLATFORM_HANDLER_IST_INDEX: u16 = 4;
 
 static TSS: OnceCell<Mutex<TaskStateSegment>> = OnceCell::uninit();
 static GDT: OnceCell<(GlobalDescriptorTable, Selectors)> = OnceCell::uninit();
@@ -33,6 +40,10 @@ pub fn set_interrupt_stack_table(index: usize, stack_end: SnVirtAddr) {
     TSS.get().unwrap().lock().interrupt_stack_table[index] = stack_end;
 }
 
+pub fn interrupt_stack_end(index: usize) -> SnVirtAddr {
+    SnVirtAddr::new(TSS.get().unwrap().lock().interrupt_stack_table[index].as_u64())
+}
+
 struct Selectors {
     code_selector: SegmentSelector,
     tss_selector: SegmentSelector,
@@ -44,7 +55,7 @@ struct Selectors {
 pub fn init() {
     use x86_64::instructions::segmentation::{CS, SS, Segment};
     use x86_64::instructions::tables::load_tss;
-    printk!("x86_64: initializing TSS");
+    printk!("initializing TSS");
     TSS.init_once(move || {
         let mut tss = TaskStateSegment::new();
         tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
@@ -56,13 +67,29 @@ pub fn init() {
             stack_end
         };
 
+        tss.interrupt_stack_table[DEBUG_IST_INDEX as usize] = {
+            const STACK_SIZE: usize = 4096 * 5;
+            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+            let stack_start = VirtAddr::from_ptr(&raw const STACK);
+            stack_start + STACK_SIZE as u64
+        };
+
+        tss.interrupt_stack_table[PLATFORM_HANDLER_IST_INDEX as usize] = {
+            const STACK_SIZE: usize = 4096 * 5;
+            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+            let stack_start = VirtAddr::from_ptr(&raw const STACK);
+            stack_start + STACK_SIZE as u64
+        };
+
         tss.interrupt_stack_table[TIMER_IST_INDEX as usize] =
             tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize]; // New
 
         Mutex::new(tss)
     });
 
-    printk!("x86_64: initializing GDT");
+    printk!("initializing GDT");
     GDT.init_once(move || {
         let mut gdt = GlobalDescriptorTable::new();
         // The GDT has to be in this exact order to support syscalls using syscall and sysret
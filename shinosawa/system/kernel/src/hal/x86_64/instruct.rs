@@ -6,8 +6,24 @@ pub fn hcf() -> ! {
     }
 }
 
+/// Traps into the breakpoint handler
+#[cfg(feature = "gdb")]
+pub fn breakpoint() {
+    x86_64::instructions::interrupts::int3();
+}
+
 pub fn halt() {
     unsafe {
         asm!("hlt");
     }
-}
\ No newline at end of file
+}
+/// Resets the CPU with a triple fault, the last resort to reboot
+pub fn triple_fault() -> ! {
+    use x86_64::{VirtAddr, instructions::tables::lidt, structures::DescriptorTablePointer};
+
+    let empty = DescriptorTablePointer { limit: 0, base: VirtAddr::zero() };
+    unsafe {
+        lidt(&empty);
+        asm!("int3", options(noreturn));
+    }
+}
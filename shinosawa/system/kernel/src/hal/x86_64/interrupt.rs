@@ -1,21 +1,41 @@
 use core::arch::naked_asm;
 
 use crate::{
-    hal::x86_64::{apic::LOCAL_APIC, gdt}, interrupt::{FREE_VECTORS_START, INTERRUPT_CONTROLLER}, memory::SnVirtAddr, print, printk
+    hal::x86_64::{apic::LOCAL_APIC, gdt}, interrupt::{FREE_VECTORS, FREE_VECTORS_START, INTERRUPT_CONTROLLER}, memory::SnVirtAddr, print, printk
 };
 use conquer_once::spin::OnceCell;
 use x86_64::{
+    PrivilegeLevel,
     instructions::interrupts,
+    registers::control::{Cr2, Cr3},
     structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
 };
 
-use super::{apic, cpu::SnCpuContext};
+use super::{apic, cpu::{self, SnCpuContext}, pic};
 
 
 static IDT: OnceCell<InterruptDescriptorTable> = OnceCell::uninit();
 
 pub static SCHEDULE: OnceCell<fn(usize) -> usize> = OnceCell::uninit();
 
+/// Called when a user process causes an exception, to kill it
+pub static USER_FAULT: OnceCell<fn(SnFault) -> !> = OnceCell::uninit();
+
+/// Called on breakpoints and debug exceptions with the interrupted
+/// registers and the vector, when a debugger is set up
+pub static DEBUG_TRAP: OnceCell<fn(&mut SnCpuContext, u8)> = OnceCell::uninit();
+
+/// A CPU exception caused by a user process
+#[derive(Clone, Copy, Debug)]
+pub struct SnFault {
+    pub name: &'static str,
+    pub vector: u8,
+    pub instruction_pointer: u64,
+    pub error_code: Option<u64>,
+    /// Accessed address, for page faults
+    pub address: Option<u64>,
+}
+
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum InterruptIndex {
@@ -23,6 +43,16 @@ pub enum InterruptIndex {
     ApicTimer = 0xfe,
     ApicSpurious = 0xff,
 }
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnInterruptBackend {
+    /// Local APIC timer, IO APICs for device interrupts
+    Apic,
+    /// 8259 PICs, with the PIT as timer
+    Pic,
+}
+
+static BACKEND: OnceCell<SnInterruptBackend> = OnceCell::uninit();
+
 pub enum InterruptStackIndex {
     Timer = 0x01,
 }
@@ -37,19 +67,39 @@ impl InterruptIndex {
     }
 }
 
-// TODO: this is only initialized once for now
-// Find a way to dynamically load interrupt handlers?
 #[allow(static_mut_refs)]
 pub fn init() {
-    printk!("x86_64: initializing handlers");
+    printk!("initializing handlers");
     IDT.init_once(move || {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
+        // Exceptions can happen in ring 3, and RSP0 is not set
+        unsafe {
+            idt.divide_error.set_handler_fn(divide_error_handler).set_stack_index(gdt::EXCEPTION_IST_INDEX);
+            idt.debug.set_handler_fn(debug_handler).set_stack_index(gdt::DEBUG_IST_INDEX);
+            idt.non_maskable_interrupt.set_handler_fn(non_maskable_interrupt_handler).set_stack_index(gdt::EXCEPTION_IST_INDEX);
+            idt.breakpoint.set_handler_fn(breakpoint_handler).set_stack_index(gdt::DEBUG_IST_INDEX);
+            idt.overflow.set_handler_fn(overflow_handler).set_stack_index(gdt::EXCEPTION_IST_INDEX);
+            idt.bound_range_exceeded.set_handler_fn(bound_range_exceeded_handler).set_stack_index(gdt::EXCEPTION_IST_INDEX);
+            idt.invalid_opcode.set_handler_fn(invalid_opcode_handler).set_stack_index(gdt::EXCEPTION_IST_INDEX);
+            idt.device_not_available.set_handler_fn(device_not_available_handler).set_stack_index(gdt::EXCEPTION_IST_INDEX);
+            idt.invalid_tss.set_handler_fn(invalid_tss_handler).set_stack_index(gdt::EXCEPTION_IST_INDEX);
+            idt.segment_not_present.set_handler_fn(segment_not_present_handler).set_stack_index(gdt::EXCEPTION_IST_INDEX);
+            idt.stack_segment_fault.set_handler_fn(stack_segment_fault_handler).set_stack_index(gdt::EXCEPTION_IST_INDEX);
+            idt.x87_floating_point.set_handler_fn(x87_floating_point_handler).set_stack_index(gdt::EXCEPTION_IST_INDEX);
+            idt.alignment_check.set_handler_fn(alignment_check_handler).set_stack_index(gdt::EXCEPTION_IST_INDEX);
+            idt.machine_check.set_handler_fn(machine_check_handler).set_stack_index(gdt::EXCEPTION_IST_INDEX);
+            idt.simd_floating_point.set_handler_fn(simd_floating_point_handler).set_stack_index(gdt::EXCEPTION_IST_INDEX);
+            idt.virtualization.set_handler_fn(virtualization_handler).set_stack_index(gdt::EXCEPTION_IST_INDEX);
+            idt.cp_protection_exception.set_handler_fn(cp_protection_handler).set_stack_index(gdt::EXCEPTION_IST_INDEX);
+            idt.hv_injection_exception.set_handler_fn(hv_injection_handler).set_stack_index(gdt::EXCEPTION_IST_INDEX);
+            idt.vmm_communication_exception.set_handler_fn(vmm_communication_handler).set_stack_index(gdt::EXCEPTION_IST_INDEX);
+            idt.security_exception.set_handler_fn(security_exception_handler).set_stack_index(gdt::EXCEPTION_IST_INDEX);
+        }
         unsafe {
             idt.page_fault
                 .set_handler_fn(page_fault_handler)
-                .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);; 
-            }
+                .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
+        }
         unsafe {
             idt.double_fault
                 .set_handler_fn(double_fault_handler)
@@ -65,50 +115,219 @@ pub fn init() {
                 .set_handler_fn(general_protection_fault_handler)
                 .set_stack_index(gdt::GENERAL_PROTECTION_FAULT_IST_INDEX);
         }
-        unsafe { idt[FREE_VECTORS_START + 0x01].set_handler_fn(platform_handler_01).set_stack_index(gdt::PLATFORM_HANDLER_IST_INDEX) };
-        unsafe { idt[FREE_VECTORS_START + 0x02].set_handler_fn(platform_handler_02).set_stack_index(gdt::PLATFORM_HANDLER_IST_INDEX) };
-        idt[FREE_VECTORS_START + 0x03].set_handler_fn(platform_handler_03);
-        idt[FREE_VECTORS_START + 0x04].set_handler_fn(platform_handler_04);
-        idt[FREE_VECTORS_START + 0x05].set_handler_fn(platform_handler_05);
-        idt[FREE_VECTORS_START + 0x06].set_handler_fn(platform_handler_06);
-        idt[FREE_VECTORS_START + 0x07].set_handler_fn(platform_handler_07);
-        idt[FREE_VECTORS_START + 0x08].set_handler_fn(platform_handler_08);
-        idt[FREE_VECTORS_START + 0x09].set_handler_fn(platform_handler_09);
-        idt[FREE_VECTORS_START + 0x0a].set_handler_fn(platform_handler_0a);
-        idt[FREE_VECTORS_START + 0x0b].set_handler_fn(platform_handler_0b);
-        idt[FREE_VECTORS_START + 0x0c].set_handler_fn(platform_handler_0c);
-        idt[FREE_VECTORS_START + 0x0d].set_handler_fn(platform_handler_0d);
-        idt[FREE_VECTORS_START + 0x0e].set_handler_fn(platform_handler_0e);
-        idt[FREE_VECTORS_START + 0x0f].set_handler_fn(platform_handler_0f);
-        idt[FREE_VECTORS_START + 0x10].set_handler_fn(platform_handler_10);
-        idt[FREE_VECTORS_START + 0x11].set_handler_fn(platform_handler_11);
-        idt[FREE_VECTORS_START + 0x12].set_handler_fn(platform_handler_12);
-        idt[FREE_VECTORS_START + 0x13].set_handler_fn(platform_handler_13);
-        idt[FREE_VECTORS_START + 0x14].set_handler_fn(platform_handler_14);
-        idt[FREE_VECTORS_START + 0x15].set_handler_fn(platform_handler_15);
-        idt[FREE_VECTORS_START + 0x16].set_handler_fn(platform_handler_16);
-        idt[FREE_VECTORS_START + 0x17].set_handler_fn(platform_handler_17);
-        idt[FREE_VECTORS_START + 0x18].set_handler_fn(platform_handler_18);
-        idt[FREE_VECTORS_START + 0x19].set_handler_fn(platform_handler_19);
-        idt[FREE_VECTORS_START + 0x1a].set_handler_fn(platform_handler_1a);
-        idt[FREE_VECTORS_START + 0x1b].set_handler_fn(platform_handler_1b);
-        idt[FREE_VECTORS_START + 0x1c].set_handler_fn(platform_handler_1c);
-        idt[FREE_VECTORS_START + 0x1d].set_handler_fn(platform_handler_1d);
-        idt[FREE_VECTORS_START + 0x1e].set_handler_fn(platform_handler_1e);
-        idt[FREE_VECTORS_START + 0x1f].set_handler_fn(platform_handler_1f);
+        unsafe {
+            idt[pic::PIC_1_OFFSET + pic::TIMER_IRQ]
+                .set_handler_fn(timer_interrupt_handler_preempt)
+                .set_stack_index(gdt::TIMER_IST_INDEX);
+        }
+        for (idx, handler) in LEGACY_HANDLERS.iter().enumerate() {
+            unsafe {
+                idt[pic::PIC_1_OFFSET + 1 + idx as u8]
+                    .set_handler_fn(*handler)
+                    .set_stack_index(gdt::PLATFORM_HANDLER_IST_INDEX)
+            };
+        }
+        for (idx, handler) in PLATFORM_HANDLERS.iter().enumerate() {
+            // Interrupts can arrive in ring 3, and RSP0 is not set
+            unsafe {
+                idt[FREE_VECTORS_START + idx as u8]
+                    .set_handler_fn(*handler)
+                    .set_stack_index(gdt::PLATFORM_HANDLER_IST_INDEX)
+            };
+        }
 
         idt
     });
 
-    printk!("x86_64: loading interrupts");
+    printk!("loading interrupts");
     IDT.get().unwrap().load();
 
-    printk!("x86_64: we will start receiving interrupts!");
+    printk!("we will start receiving interrupts!");
     x86_64::instructions::interrupts::enable(); // new
 }
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    printk!("x86_64: breakpoint\n{:#?}", stack_frame);
+/// Kills the current process if the exception came from ring 3,
+/// otherwise the kernel is broken and panics with everything we know.
+///
+/// Must be called straight from the handler, see `interrupted_frame_pointer`.
+#[inline(never)]
+fn handle_exception(
+    stack_frame: &InterruptStackFrame,
+    name: &'static str,
+    vector: u8,
+    error_code: Option<u64>,
+    address: Option<u64>,
+) {
+    let fault = SnFault {
+        name,
+        vector,
+        instruction_pointer: stack_frame.instruction_pointer.as_u64(),
+        error_code,
+        address,
+    };
+
+    if stack_frame.code_segment.rpl() == PrivilegeLevel::Ring3 {
+        if let Some(kill) = USER_FAULT.get() {
+            log::warn!("{} in user mode at {:#x}", name, fault.instruction_pointer);
+            crate::debug::backtrace::print_backtrace(
+                Some(fault.instruction_pointer),
+                interrupted_frame_pointer(),
+            );
+            // The kernel stack of the thread is freed with it, so the
+            // timer has to use this stack until the next context switch
+            gdt::set_interrupt_stack_table(
+                InterruptStackIndex::Timer as usize,
+                gdt::interrupt_stack_end(gdt::EXCEPTION_IST_INDEX as usize),
+            );
+            kill(fault);
+        }
+    }
+
+    log::error!("{} (vector {})", name, vector);
+    if let Some(error_code) = error_code {
+        log::error!("error code: {:#x}", error_code);
+    }
+    if let Some(address) = address {
+        log::error!("accessed address: {:#x}", address);
+    }
+    log::error!("page table: {:?}", Cr3::read().0.start_address());
+    log::error!("{:#?}", stack_frame);
+
+    panic!("x86_64: {}", name);
+}
+
+/// Frame pointer of the code an exception interrupted.
+///
+/// The handler saves it at the start of its own frame, which the frame
+/// of `handle_exception` points to.
+#[inline(always)]
+fn interrupted_frame_pointer() -> u64 {
+    let handle_exception_frame = cpu::frame_pointer() as *const u64;
+    unsafe {
+        let handler_frame = *handle_exception_frame as *const u64;
+        *handler_frame
+    }
+}
+
+macro_rules! exception_handler {
+    ($handler:ident, $name:literal, $vector:literal) => {
+        extern "x86-interrupt" fn $handler(stack_frame: InterruptStackFrame) {
+            handle_exception(&stack_frame, $name, $vector, None, None);
+        }
+    };
+    ($handler:ident, $name:literal, $vector:literal, error_code) => {
+        extern "x86-interrupt" fn $handler(stack_frame: InterruptStackFrame, error_code: u64) {
+            handle_exception(&stack_frame, $name, $vector, Some(error_code), None);
+        }
+    };
+}
+
+exception_handler!(divide_error_handler, "divide error", 0);
+exception_handler!(overflow_handler, "overflow", 4);
+exception_handler!(bound_range_exceeded_handler, "bound range exceeded", 5);
+exception_handler!(invalid_opcode_handler, "invalid opcode", 6);
+exception_handler!(device_not_available_handler, "device not available", 7);
+exception_handler!(invalid_tss_handler, "invalid TSS", 10, error_code);
+exception_handler!(segment_not_present_handler, "segment not present", 11, error_code);
+exception_handler!(stack_segment_fault_handler, "stack segment fault", 12, error_code);
+exception_handler!(general_protection_fault_handler, "general protection fault", 13, error_code);
+exception_handler!(x87_floating_point_handler, "x87 floating point exception", 16);
+exception_handler!(alignment_check_handler, "alignment check", 17, error_code);
+exception_handler!(simd_floating_point_handler, "SIMD floating point exception", 19);
+exception_handler!(virtualization_handler, "virtualization exception", 20);
+exception_handler!(cp_protection_handler, "control protection exception", 21, error_code);
+exception_handler!(hv_injection_handler, "hypervisor injection exception", 28);
+exception_handler!(vmm_communication_handler, "VMM communication exception", 29, error_code);
+exception_handler!(security_exception_handler, "security exception", 30, error_code);
+
+fn debug_trap(context_addr: usize, vector: u8) {
+    let context = unsafe { &mut *(context_addr as *mut SnCpuContext) };
+
+    match DEBUG_TRAP.get() {
+        Some(trap) => trap(context, vector),
+        None if vector == BREAKPOINT_VECTOR => printk!("breakpoint\n{:#?}", context),
+        None => printk!("debug exception\n{:#?}", context),
+    }
+}
+
+pub const DEBUG_VECTOR: u8 = 1;
+pub const BREAKPOINT_VECTOR: u8 = 3;
+
+extern "C" fn breakpoint_trap(context_addr: usize) {
+    debug_trap(context_addr, BREAKPOINT_VECTOR);
+}
+
+extern "C" fn debug_exception_trap(context_addr: usize) {
+    debug_trap(context_addr, DEBUG_VECTOR);
+}
+
+/// Entry point saving every register in a `SnCpuContext`, like the timer,
+/// so the debugger can see and change them
+macro_rules! trap_entry {
+    ($entry:ident, $handler:ident) => {
+        #[naked]
+        extern "x86-interrupt" fn $entry(_stack_frame: InterruptStackFrame) {
+            unsafe {
+                naked_asm!(
+                    "push rax",
+                    "push rbx",
+                    "push rcx",
+                    "push rdx",
+
+                    "push rdi",
+                    "push rsi",
+                    "push rbp",
+                    "push r8",
+
+                    "push r9",
+                    "push r10",
+                    "push r11",
+                    "push r12",
+
+                    "push r13",
+                    "push r14",
+                    "push r15",
+
+                    // The context is the first argument
+                    "mov rdi, rsp",
+                    "call {handler}",
+
+                    "pop r15",
+                    "pop r14",
+                    "pop r13",
+
+                    "pop r12",
+                    "pop r11",
+                    "pop r10",
+                    "pop r9",
+
+                    "pop r8",
+                    "pop rbp",
+                    "pop rsi",
+                    "pop rdi",
+
+                    "pop rdx",
+                    "pop rcx",
+                    "pop rbx",
+                    "pop rax",
+                    "iretq",
+                    handler = sym $handler,
+                );
+            }
+        }
+    };
+}
+
+trap_entry!(breakpoint_handler, breakpoint_trap);
+trap_entry!(debug_handler, debug_exception_trap);
+
+extern "x86-interrupt" fn non_maskable_interrupt_handler(stack_frame: InterruptStackFrame) {
+    log::warn!("non-maskable interrupt\n{:#?}", stack_frame);
+}
+
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    // The hardware is in trouble, no process to blame
+    panic!("x86_64: machine check\n{:#?}", stack_frame);
 }
 
 // new
@@ -123,35 +342,28 @@ extern "x86-interrupt" fn page_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
-    use x86_64::registers::control::Cr2;
     let accessed_virtaddr = Cr2::read().expect("Cannot read accessed address");
 
     if error_code == (PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE | PageFaultErrorCode::USER_MODE) {
-        if let Err(msg) = crate::hal::interface::paging::map_missing_user_page(SnVirtAddr::new(accessed_virtaddr.as_u64())) {
-            printk!("Page fault error:\n{:#?}", stack_frame);
-            
-            crate::hal::interface::instruct::hcf();
+        let fault_addr = SnVirtAddr::new(accessed_virtaddr.as_u64());
+        let result = match crate::hal::interface::paging::handle_copy_on_write(fault_addr) {
+            Ok(false) => crate::hal::interface::paging::map_missing_user_page(fault_addr),
+            result => result,
+        };
+        match result {
+            Ok(true) => return,
+            Ok(false) => {}
+            Err(msg) => log::warn!("page fault not resolved: {:?}", msg),
         }
-    } else {
-
-        printk!("x86_64: page fault");
-        printk!("you tried to access address: {:?}", accessed_virtaddr);
-        printk!("error code: {:?}", error_code);
-        printk!("{:#?}", stack_frame);
-
-        panic!("page fault");
     }
-}
 
-extern "x86-interrupt" fn general_protection_fault_handler(
-    stack_frame: InterruptStackFrame,
-    _error_code: u64,
-) {
-    printk!("x86_64: general protection fault: {:x}", _error_code);
-
-    printk!("{:#?}", stack_frame);
-
-    panic!("general protection fault");
+    handle_exception(
+        &stack_frame,
+        "page fault",
+        14,
+        Some(error_code.bits()),
+        Some(accessed_virtaddr.as_u64()),
+    );
 }
 
 fn platform_handler(idx: u8) {
@@ -165,20 +377,28 @@ extern "C" fn timer_interrupt_handler(context_addr: usize) -> usize{
         let next_stack = (SCHEDULE.get().unwrap())(context_addr);
         let ctx = unsafe { *(context_addr as *const SnCpuContext).clone() };
         if ctx.ss == 0 {
-            printk!("something weird is happening");
+            log::warn!("something weird is happening");
         }
-        let mut lapic = LOCAL_APIC.get().unwrap().lock();
-        unsafe { lapic.end_of_interrupt() };
+        end_of_timer_interrupt();
 
         return next_stack;
     } else {
-        let mut lapic = LOCAL_APIC.get().unwrap().lock();
-        unsafe { lapic.end_of_interrupt() };
+        end_of_timer_interrupt();
 
         return 0;
     }
 }
 
+fn end_of_timer_interrupt() {
+    match backend() {
+        SnInterruptBackend::Apic => {
+            let mut lapic = LOCAL_APIC.get().unwrap().lock();
+            unsafe { lapic.end_of_interrupt() };
+        }
+        SnInterruptBackend::Pic => pic::end_of_interrupt(pic::TIMER_IRQ),
+    }
+}
+
 #[naked]
 pub extern "x86-interrupt" fn timer_interrupt_handler_preempt(_stack_frame: InterruptStackFrame) {
     unsafe {
@@ -248,58 +468,130 @@ pub extern "x86-interrupt" fn timer_interrupt_handler_preempt(_stack_frame: Inte
     }
 }
 
-macro_rules! platform_handler {
-    ($name:ident, $number:literal) => {
-        extern "x86-interrupt" fn $name(
-            stack_frame: InterruptStackFrame,
-        ) {
-            platform_handler($number);
+macro_rules! platform_handlers {
+    ($($number:literal),* $(,)?) => {
+        [$({
+            extern "x86-interrupt" fn handler(_stack_frame: InterruptStackFrame) {
+                platform_handler($number);
 
-            let mut lapic = LOCAL_APIC.get().unwrap().lock();
-            unsafe { lapic.end_of_interrupt() };
-        }
+                let mut lapic = LOCAL_APIC.get().unwrap().lock();
+                unsafe { lapic.end_of_interrupt() };
+            }
+            handler
+        }),*]
     };
 }
 
-platform_handler!(platform_handler_00, 0x00);
-platform_handler!(platform_handler_01, 0x01);
-platform_handler!(platform_handler_02, 0x02);
-platform_handler!(platform_handler_03, 0x03);
-platform_handler!(platform_handler_04, 0x04);
-platform_handler!(platform_handler_05, 0x05);
-platform_handler!(platform_handler_06, 0x06);
-platform_handler!(platform_handler_07, 0x07);
-platform_handler!(platform_handler_08, 0x08);
-platform_handler!(platform_handler_09, 0x09);
-platform_handler!(platform_handler_0a, 0x0a);
-platform_handler!(platform_handler_0b, 0x0b);
-platform_handler!(platform_handler_0c, 0x0c);
-platform_handler!(platform_handler_0d, 0x0d);
-platform_handler!(platform_handler_0e, 0x0e);
-platform_handler!(platform_handler_0f, 0x0f);
-platform_handler!(platform_handler_10, 0x10);
-platform_handler!(platform_handler_11, 0x11);
-platform_handler!(platform_handler_12, 0x12);
-platform_handler!(platform_handler_13, 0x13);
-platform_handler!(platform_handler_14, 0x14);
-platform_handler!(platform_handler_15, 0x15);
-platform_handler!(platform_handler_16, 0x16);
-platform_handler!(platform_handler_17, 0x17);
-platform_handler!(platform_handler_18, 0x18);
-platform_handler!(platform_handler_19, 0x19);
-platform_handler!(platform_handler_1a, 0x1a);
-platform_handler!(platform_handler_1b, 0x1b);
-platform_handler!(platform_handler_1c, 0x1c);
-platform_handler!(platform_handler_1d, 0x1d);
-platform_handler!(platform_handler_1e, 0x1e);
-platform_handler!(platform_handler_1f, 0x1f);
+/// Entry points for the free vectors, all ending up in `platform_handler`
+static PLATFORM_HANDLERS: [extern "x86-interrupt" fn(InterruptStackFrame); FREE_VECTORS] = platform_handlers!(
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
+    0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+    0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17,
+    0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f,
+    0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27,
+    0x28, 0x29, 0x2a, 0x2b, 0x2c, 0x2d, 0x2e, 0x2f,
+    0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37,
+    0x38, 0x39, 0x3a, 0x3b, 0x3c, 0x3d, 0x3e, 0x3f,
+);
+
+
+fn legacy_handler(irq: u8) {
+    if let Some(vector) = pic::routed_vector(irq) {
+        platform_handler(vector - FREE_VECTORS_START);
+    }
+    pic::end_of_interrupt(irq);
+}
+
+macro_rules! legacy_handlers {
+    ($($irq:literal),* $(,)?) => {
+        [$({
+            extern "x86-interrupt" fn handler(_stack_frame: InterruptStackFrame) {
+                legacy_handler($irq);
+            }
+            handler
+        }),*]
+    };
+}
 
+/// Entry points for the PIC vectors, IRQ 0 is the timer
+static LEGACY_HANDLERS: [extern "x86-interrupt" fn(InterruptStackFrame); pic::PIC_IRQS - 1] = legacy_handlers!(
+    0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
+    0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+);
 
 /// Number of bytes needed to store a Context struct
 pub const INTERRUPT_CONTEXT_SIZE: usize = 20 * 8;
 
-pub fn enable_irq(irq: u8) {
-    apic::enable_irq(1);
+/// Sets up the interrupt controller: the APIC if ACPI reports one,
+/// otherwise the legacy PIC with the PIT as the preemption timer.
+pub fn init_controller() {
+    let backend = if apic::init() {
+        // Keep the PICs quiet, they may still be wired up
+        pic::disable();
+        SnInterruptBackend::Apic
+    } else {
+        pic::init();
+        SnInterruptBackend::Pic
+    };
+
+    BACKEND.init_once(|| backend);
+}
+
+/// Returns the interrupt controller in use
+pub fn backend() -> SnInterruptBackend {
+    *BACKEND.get().unwrap_or(&SnInterruptBackend::Apic)
+}
+
+/// Returns the GSI an ISA IRQ is wired to, following the ACPI interrupt source overrides
+pub fn isa_irq_to_gsi(irq: u8) -> u32 {
+    match backend() {
+        SnInterruptBackend::Apic => apic::isa_irq_to_gsi(irq),
+        SnInterruptBackend::Pic => irq as u32,
+    }
+}
+
+/// Delivers a GSI to a vector, masked until `enable_irq`.
+///
+/// Without `cpu`, the interrupt goes to the bootstrap processor.
+pub fn route_irq(gsi: u32, vector: u8, cpu: Option<u8>) -> bool {
+    match backend() {
+        SnInterruptBackend::Apic => apic::route_irq(gsi, vector, cpu),
+        SnInterruptBackend::Pic => {
+            pic::disable_irq(gsi);
+            pic::route_irq(gsi, vector)
+        }
+    }
+}
+
+/// Delivers a GSI to another CPU, identified by its local APIC id
+pub fn set_irq_target(gsi: u32, cpu: u8) {
+    if backend() == SnInterruptBackend::Apic {
+        apic::set_irq_target(gsi, cpu);
+    }
+}
+
+/// Returns the message address and data for an MSI to `vector`.
+///
+/// MSIs go to a local APIC, so there is none without one.
+pub fn msi_message(vector: u8, cpu: Option<u8>) -> Option<(u64, u32)> {
+    match backend() {
+        SnInterruptBackend::Apic => Some(apic::msi_message(vector, cpu)),
+        SnInterruptBackend::Pic => None,
+    }
+}
+
+pub fn enable_irq(gsi: u32) {
+    match backend() {
+        SnInterruptBackend::Apic => apic::enable_irq(gsi),
+        SnInterruptBackend::Pic => pic::enable_irq(gsi),
+    }
+}
+
+pub fn disable_irq(gsi: u32) {
+    match backend() {
+        SnInterruptBackend::Apic => apic::disable_irq(gsi),
+        SnInterruptBackend::Pic => pic::disable_irq(gsi),
+    }
 }
 
 #[test_case]
@@ -5,6 +5,8 @@ pub mod instruct;
 pub mod interrupt;
 /// CPU-related functions
 pub mod cpu;
+/// Kernel clock
+pub mod clock;
 /// Memory paging
 pub mod paging;
 /// x86 system call
@@ -14,5 +16,7 @@ pub mod syscall;
 mod gdt;
 /// Intel APIC
 mod apic;
+/// Legacy 8259 PIC and PIT
+mod pic;
 /// Frame Allocator
 mod frame_alloc;
\ No newline at end of file
@@ -2,25 +2,28 @@
 #![allow(static_mut_refs)]
 
 use core::ops::Range;
+use core::sync::atomic::{AtomicU64, Ordering};
 
+use alloc::collections::BTreeMap;
 use conquer_once::spin::OnceCell;
-use spin::RwLock;
+use spin::{Mutex, RwLock};
 use x86_64::{
     PhysAddr, VirtAddr,
+    instructions::tlb,
     registers::control::Cr3Flags,
     structures::paging::{
         FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame,
-        Size4KiB, mapper::MapToError, page,
+        Size4KiB, mapper::MapToError, page, page_table::PageTableEntry,
     },
 };
 
 use crate::{
     limine::MEMORY_MAP_REQUEST,
-    memory::{SnPhysAddr, SnVirtAddr, USER_HEAP_SIZE, USER_STACK_SIZE},
+    memory::{alloc::HEAP_START, SnPhysAddr, SnVirtAddr, USER_HEAP_SIZE, USER_STACK_SIZE},
     printk,
 };
 
-use super::frame_alloc::SnLimineFrameAllocator;
+use super::frame_alloc::{DMA32_LIMIT, SnLimineFrameAllocator};
 
 /// Returns a mutable reference to the active level 4 table.
 ///
@@ -66,6 +69,64 @@ pub struct MemoryInfo {
 
 pub static mut MEMORY_INFO: Option<MemoryInfo> = None;
 
+/// Marks a user page that is shared with another address space after a fork.
+/// The page is mapped read-only, and the first write to it copies the frame.
+const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_9;
+
+/// Marks a user page mapping memory the frame allocator doesn't own, like the
+/// framebuffer. Such pages are never freed, counted or copied on write.
+const DEVICE_MEMORY: PageTableFlags = PageTableFlags::BIT_10;
+
+/// Selects PAT entry 1, which `cpu::init_pat` makes write-combining
+const WRITE_COMBINING: PageTableFlags = PageTableFlags::WRITE_THROUGH;
+
+/// Number of address spaces referencing a user frame.
+/// Frames that are not in this map are owned by a single address space.
+static SHARED_FRAMES: Mutex<BTreeMap<u64, u64>> = Mutex::new(BTreeMap::new());
+
+/// Number of frames backing user pages, over all address spaces
+static USER_FRAMES: AtomicU64 = AtomicU64::new(0);
+/// Number of frames used as page tables by user address spaces
+static PAGE_TABLE_FRAMES: AtomicU64 = AtomicU64::new(0);
+
+/// Adds another address space reference to a user frame
+fn share_user_frame(frame_addr: PhysAddr) {
+    let mut shared_frames = SHARED_FRAMES.lock();
+    *shared_frames.entry(frame_addr.as_u64()).or_insert(1) += 1;
+}
+
+/// Drops an address space reference to a user frame,
+/// freeing the frame once nobody else references it.
+fn release_user_frame(frame_allocator: &mut SnLimineFrameAllocator, frame_addr: PhysAddr) {
+    let mut shared_frames = SHARED_FRAMES.lock();
+    if let Some(count) = shared_frames.get_mut(&frame_addr.as_u64()) {
+        *count -= 1;
+        if *count <= 1 {
+            shared_frames.remove(&frame_addr.as_u64());
+        }
+    } else {
+        frame_allocator.deallocate_frame(PhysFrame::containing_address(frame_addr));
+        USER_FRAMES.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Hands out frames for the page tables `Mapper::map_to` creates in user
+/// address spaces, keeping `PAGE_TABLE_FRAMES` in sync with `free_user_pagetables`.
+struct SnPageTableFrames<'a>(&'a mut SnLimineFrameAllocator);
+
+unsafe impl FrameAllocator<Size4KiB> for SnPageTableFrames<'_> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let frame = self.0.allocate_frame()?;
+        PAGE_TABLE_FRAMES.fetch_add(1, Ordering::Relaxed);
+        Some(frame)
+    }
+}
+
+/// Returns true if the frame is referenced by more than one address space
+fn is_shared_user_frame(frame_addr: PhysAddr) -> bool {
+    SHARED_FRAMES.lock().contains_key(&frame_addr.as_u64())
+}
+
 pub unsafe fn init_page_table(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
     unsafe {
         let level_4_table = active_level_4_table(physical_memory_offset);
@@ -102,6 +163,106 @@ pub fn phys_to_virt_addr(phys: PhysAddr) -> VirtAddr {
     return memory_info.physical_memory_offset + phys.as_u64();
 }
 
+/// Allocates physically contiguous frames for DMA.
+///
+/// Set `below_4gib` for devices that can only address 32 bits.
+/// Returns the physical address of the first frame.
+pub fn allocate_dma_frames(count: u64, below_4gib: bool) -> Option<SnPhysAddr> {
+    let memory_info = unsafe { MEMORY_INFO.as_mut().unwrap() };
+    let limit = if below_4gib {
+        DMA32_LIMIT
+    } else {
+        PhysAddr::new_truncate(u64::MAX)
+    };
+
+    memory_info
+        .frame_allocator
+        .allocate_contiguous(count, 1, limit)
+        .map(|frame| SnPhysAddr::new(frame.start_address().as_u64()))
+}
+
+/// Frees frames allocated with `allocate_dma_frames`
+pub fn free_dma_frames(start: SnPhysAddr, count: u64) {
+    let memory_info = unsafe { MEMORY_INFO.as_mut().unwrap() };
+    memory_info.frame_allocator.deallocate_contiguous(
+        PhysFrame::containing_address(PhysAddr::new(start.as_u64())),
+        count,
+    );
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SnFrameUsage {
+    /// Usable physical frames
+    pub total_frames: u64,
+    pub free_frames: u64,
+    /// Frames backing user pages
+    pub user_frames: u64,
+    /// Frames holding user page tables
+    pub page_table_frames: u64,
+}
+
+/// Returns the physical frame counters
+pub fn frame_usage() -> SnFrameUsage {
+    let memory_info = unsafe { MEMORY_INFO.as_mut().unwrap() };
+
+    SnFrameUsage {
+        total_frames: memory_info.frame_allocator.total_frames(),
+        free_frames: memory_info.frame_allocator.free_frames(),
+        user_frames: USER_FRAMES.load(Ordering::Relaxed),
+        page_table_frames: PAGE_TABLE_FRAMES.load(Ordering::Relaxed),
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SnAddressSpaceUsage {
+    /// User pages currently mapped, including copy-on-write pages
+    pub resident_pages: u64,
+    /// Resident pages that are shared with another address space
+    pub shared_pages: u64,
+    /// Page tables of this address space, not counting the shared kernel ones
+    pub page_tables: u64,
+}
+
+/// Counts the pages mapped by a user address space
+pub fn address_space_usage(page_table_phys_addr: u64) -> SnAddressSpaceUsage {
+    fn count_pages_rec(
+        physical_memory_offset: VirtAddr,
+        table: &PageTable,
+        level: u16,
+        usage: &mut SnAddressSpaceUsage,
+    ) {
+        usage.page_tables += 1;
+        for (i, entry) in table.iter().enumerate() {
+            if entry.is_unused() || is_shared_kernel_entry(level, i) {
+                continue;
+            }
+            if (level == 1) || entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+                if entry.flags().contains(PageTableFlags::USER_ACCESSIBLE)
+                    && !entry.flags().contains(DEVICE_MEMORY)
+                {
+                    usage.resident_pages += 1;
+                    if is_shared_user_frame(entry.addr()) {
+                        usage.shared_pages += 1;
+                    }
+                }
+            } else {
+                let table_m1 = unsafe {
+                    &*(physical_memory_offset + entry.addr().as_u64()).as_ptr()
+                };
+                count_pages_rec(physical_memory_offset, table_m1, level - 1, usage);
+            }
+        }
+    }
+
+    let memory_info = unsafe { MEMORY_INFO.as_mut().unwrap() };
+    let physical_memory_offset = memory_info.physical_memory_offset;
+    let table = unsafe { get_page_table_from_address(physical_memory_offset, page_table_phys_addr) };
+
+    let mut usage = SnAddressSpaceUsage::default();
+    count_pages_rec(physical_memory_offset, table, 4, &mut usage);
+    usage
+}
+
 fn create_empty_pagetable() -> (*mut PageTable, u64) {
     // Need to borrow as mutable so that we can allocate new frames
     // and so modify the frame allocator
@@ -109,6 +270,7 @@ fn create_empty_pagetable() -> (*mut PageTable, u64) {
 
     // Get a frame to store the level 4 table
     let level_4_table_frame = memory_info.frame_allocator.allocate_frame().unwrap();
+    PAGE_TABLE_FRAMES.fetch_add(1, Ordering::Relaxed);
     let phys = level_4_table_frame.start_address(); // Physical address
     let virt = memory_info.physical_memory_offset + phys.as_u64(); // Kernel virtual address
     let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
@@ -121,13 +283,27 @@ fn create_empty_pagetable() -> (*mut PageTable, u64) {
     (page_table_ptr, phys.as_u64())
 }
 
+/// Level 4 index covering the kernel heap. Every address space points at the
+/// kernel's own level 3 table for it, so memory mapped when the heap grows
+/// shows up in all of them.
+const KERNEL_HEAP_L4_INDEX: usize = (HEAP_START >> 39) & 0x1ff;
+
+/// Level 4 indices holding the user stacks of threads
+const USER_STACK_L4_INDICES: Range<u64> = 3..6;
+/// Level 4 indices holding the user heaps of processes
+const USER_HEAP_L4_INDICES: Range<u64> = 7..11;
+
+fn is_shared_kernel_entry(level: u16, index: usize) -> bool {
+    level == 4 && index == KERNEL_HEAP_L4_INDEX
+}
+
 pub fn create_new_user_pagetable() -> (SnVirtAddr, SnPhysAddr) {
     let (page_table_ptr, page_table_phys_addr) = create_empty_pagetable();
     let table = unsafe { &mut *page_table_ptr };
 
     // Investigate why removing this causes physical address errors
     printk!(
-        "paging::user_pagetable: {:x} {:x}",
+        "{:x} {:x}",
         page_table_ptr as u64,
         page_table_phys_addr
     );
@@ -140,8 +316,11 @@ pub fn create_new_user_pagetable() -> (SnVirtAddr, SnPhysAddr) {
     ) {
         for (i, entry) in from_table.iter().enumerate() {
             if !entry.is_unused() {
-                if (level == 1) || entry.flags().contains(PageTableFlags::HUGE_PAGE) {
-                    // Maps a frame, not a page table
+                if (level == 1)
+                    || entry.flags().contains(PageTableFlags::HUGE_PAGE)
+                    || is_shared_kernel_entry(level, i)
+                {
+                    // Maps a frame or a shared table, not a page table to copy
                     to_table[i].set_addr(entry.addr(), entry.flags());
                 } else {
                     // Create a new table at level - 1
@@ -181,6 +360,207 @@ pub fn create_new_user_pagetable() -> (SnVirtAddr, SnPhysAddr) {
     )
 }
 
+/// Duplicates a user address space for a forked process.
+///
+/// Page tables are copied, but user frames are shared between both address spaces.
+/// Writable user pages are made read-only and marked copy-on-write in both tables,
+/// so the frame gets copied by `handle_copy_on_write` on the first write.
+/// Device memory is left out of the new address space.
+pub fn fork_user_pagetable(page_table_phys_addr: u64) -> (SnVirtAddr, SnPhysAddr) {
+    let (new_table_ptr, new_table_phys_addr) = create_empty_pagetable();
+    let to_table = unsafe { &mut *new_table_ptr };
+
+    fn fork_pages_rec(
+        physical_memory_offset: VirtAddr,
+        from_table: &mut PageTable,
+        to_table: &mut PageTable,
+        level: u16,
+    ) {
+        for (i, entry) in from_table.iter_mut().enumerate() {
+            if entry.is_unused() {
+                continue;
+            }
+
+            if is_shared_kernel_entry(level, i) {
+                to_table[i].set_addr(entry.addr(), entry.flags());
+                continue;
+            }
+
+            if (level == 1) || entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+                // Maps a frame, not a page table
+                let mut flags = entry.flags();
+                if flags.contains(DEVICE_MEMORY) {
+                    // The device stays with the process that owns it, the
+                    // child would keep it after the owner lets go
+                    continue;
+                } else if flags.contains(PageTableFlags::USER_ACCESSIBLE) {
+                    if flags.contains(PageTableFlags::WRITABLE) {
+                        flags.remove(PageTableFlags::WRITABLE);
+                        flags.insert(COPY_ON_WRITE);
+                        entry.set_flags(flags);
+                    }
+                    share_user_frame(entry.addr());
+                }
+                to_table[i].set_addr(entry.addr(), flags);
+            } else {
+                // Create a new table at level - 1
+                let (new_table_ptr, new_table_physaddr) = create_empty_pagetable();
+                let to_table_m1 = unsafe { &mut *new_table_ptr };
+
+                // Point the entry to the new table
+                to_table[i].set_addr(PhysAddr::new(new_table_physaddr), entry.flags());
+
+                let from_table_m1 = {
+                    let virt = physical_memory_offset + entry.addr().as_u64();
+                    unsafe { &mut *virt.as_mut_ptr() }
+                };
+
+                fork_pages_rec(physical_memory_offset, from_table_m1, to_table_m1, level - 1);
+            }
+        }
+    }
+
+    let memory_info = unsafe { MEMORY_INFO.as_mut().unwrap() };
+    let physical_memory_offset = memory_info.physical_memory_offset;
+    let from_table =
+        unsafe { get_page_table_from_address(physical_memory_offset, page_table_phys_addr) };
+
+    fork_pages_rec(physical_memory_offset, from_table, to_table, 4);
+
+    // The parent lost write access to its pages
+    tlb::flush_all();
+
+    (
+        SnVirtAddr::from_ptr(new_table_ptr),
+        SnPhysAddr::new(new_table_phys_addr),
+    )
+}
+
+/// Finds the level 1 entry of a user page in the active page table. Every
+/// level has to be present and user accessible, and the kernel heap, mapped
+/// in every address space, never holds user pages.
+fn active_user_level_1_entry(addr: VirtAddr) -> Option<&'static mut PageTableEntry> {
+    if usize::from(addr.p4_index()) == KERNEL_HEAP_L4_INDEX {
+        return None;
+    }
+    let memory_info = unsafe { MEMORY_INFO.as_mut().unwrap() };
+    let user = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    let current_page_table = get_current_page_table_phys_addr();
+    let mut table = unsafe {
+        get_page_table_from_address(memory_info.physical_memory_offset, current_page_table)
+    };
+
+    for index in [addr.p4_index(), addr.p3_index(), addr.p2_index()] {
+        let flags = table[index].flags();
+        if !flags.contains(user) || flags.contains(PageTableFlags::HUGE_PAGE) {
+            return None;
+        }
+        table = unsafe {
+            &mut *(memory_info.physical_memory_offset + table[index].addr().as_u64()).as_mut_ptr()
+        };
+    }
+
+    let entry = &mut table[addr.p1_index()];
+    entry.flags().contains(user).then_some(entry)
+}
+
+/// Returns true if user code can read `addr` through the active page table
+pub fn is_user_page(addr: SnVirtAddr) -> bool {
+    active_user_level_1_entry(VirtAddr::new_truncate(addr.as_u64())).is_some()
+}
+
+/// Returns true if `addr` can be read through the active page table
+pub fn is_mapped(addr: SnVirtAddr) -> bool {
+    // Also used by the panic handler, which can run before paging is set up
+    let Some(memory_info) = (unsafe { MEMORY_INFO.as_ref() }) else {
+        return false;
+    };
+    let addr = VirtAddr::new_truncate(addr.as_u64());
+    let current_page_table = get_current_page_table_phys_addr();
+    let mut table: &PageTable = unsafe {
+        get_page_table_from_address(memory_info.physical_memory_offset, current_page_table)
+    };
+
+    for index in [addr.p4_index(), addr.p3_index(), addr.p2_index()] {
+        let entry = &table[index];
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            return false;
+        }
+        if entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return true;
+        }
+        table = unsafe {
+            &*(memory_info.physical_memory_offset + entry.addr().as_u64()).as_ptr()
+        };
+    }
+
+    table[addr.p1_index()].flags().contains(PageTableFlags::PRESENT)
+}
+
+/// Resolves a write fault on a copy-on-write page in the active page table.
+///
+/// Returns `Ok(false)` if the page is not copy-on-write, so the fault needs
+/// to be handled elsewhere.
+pub fn handle_copy_on_write(addr: SnVirtAddr) -> Result<bool, MapToError<Size4KiB>> {
+    let addr = VirtAddr::new(addr.as_u64());
+    let Some(entry) = active_user_level_1_entry(addr) else {
+        return Ok(false);
+    };
+
+    let mut flags = entry.flags();
+    if !flags.contains(COPY_ON_WRITE) {
+        return Ok(false);
+    }
+    flags.remove(COPY_ON_WRITE);
+    flags.insert(PageTableFlags::WRITABLE);
+
+    let memory_info = unsafe { MEMORY_INFO.as_mut().unwrap() };
+    let old_frame_addr = entry.addr();
+
+    if is_shared_user_frame(old_frame_addr) {
+        // Someone else still uses this frame, so take a private copy
+        let new_frame = memory_info
+            .frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        unsafe {
+            let src = (memory_info.physical_memory_offset + old_frame_addr.as_u64()).as_ptr::<u8>();
+            let dst = (memory_info.physical_memory_offset + new_frame.start_address().as_u64())
+                .as_mut_ptr::<u8>();
+            core::ptr::copy_nonoverlapping(src, dst, 4096);
+        }
+        USER_FRAMES.fetch_add(1, Ordering::Relaxed);
+        entry.set_addr(new_frame.start_address(), flags);
+        release_user_frame(&mut memory_info.frame_allocator, old_frame_addr);
+    } else {
+        // Last owner of the frame, just take it back
+        entry.set_flags(flags);
+    }
+
+    tlb::flush(Page::<Size4KiB>::containing_address(addr).start_address());
+
+    Ok(true)
+}
+
+/// Makes sure the kernel can write to a user page in the active page table,
+/// resolving copy-on-write and lazily allocated pages up front.
+///
+/// Returns false if `addr` is not in a user page or no frame is left for it.
+pub fn prepare_user_page_for_write(addr: SnVirtAddr) -> bool {
+    let Some(entry) = active_user_level_1_entry(VirtAddr::new(addr.as_u64())) else {
+        return false;
+    };
+    if entry.flags().contains(PageTableFlags::WRITABLE) {
+        return true;
+    }
+
+    match handle_copy_on_write(addr) {
+        Ok(true) => true,
+        Ok(false) => map_missing_user_page(addr).unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
 pub fn with_page_table<T: FnOnce()>(page_table_phys_addr: SnPhysAddr, func: T) {
     use x86_64::registers::control::Cr3;
 
@@ -237,7 +617,85 @@ fn map_phys_page_inner(
     Ok(())
 }
 
-pub fn map_new_memory(start_addr: SnVirtAddr, end_addr: SnVirtAddr) {
+/// Maps device memory into the higher half direct map, uncached.
+///
+/// Pages that are already mapped are left alone. Returns the virtual address of `phys_addr`.
+pub fn map_mmio(phys_addr: SnPhysAddr, size: u64) -> SnVirtAddr {
+    let memory_info = unsafe { MEMORY_INFO.as_mut().unwrap() };
+    let physical_memory_offset = memory_info.physical_memory_offset;
+
+    let mut mapper: OffsetPageTable<'_> = unsafe { init_page_table(physical_memory_offset) };
+
+    let start = PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(phys_addr.as_u64()));
+    let end = PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(phys_addr.as_u64() + size.max(1) - 1));
+    for frame in PhysFrame::range_inclusive(start, end) {
+        let virt = physical_memory_offset + frame.start_address().as_u64();
+        if mapper.translate_page(Page::<Size4KiB>::containing_address(virt)).is_ok() {
+            continue;
+        }
+        map_phys_page_inner(
+            &mut mapper,
+            &mut memory_info.frame_allocator,
+            frame.start_address(),
+            virt,
+        )
+        .expect("cannot map memory");
+    }
+
+    SnVirtAddr::new((physical_memory_offset + phys_addr.as_u64()).as_u64())
+}
+
+/// Maps device memory into the active user address space at `virt_addr`,
+/// write-combining if asked. The frames stay with the device.
+pub fn map_user_device_memory(
+    phys_addr: SnPhysAddr,
+    virt_addr: SnVirtAddr,
+    size: u64,
+    write_combining: bool,
+) -> Result<(), MapToError<Size4KiB>> {
+    let memory_info = unsafe { MEMORY_INFO.as_mut().unwrap() };
+    let mut mapper = unsafe { init_page_table(memory_info.physical_memory_offset) };
+
+    let mut flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::USER_ACCESSIBLE
+        | DEVICE_MEMORY;
+    if write_combining {
+        flags |= WRITE_COMBINING;
+    } else {
+        flags |= PageTableFlags::NO_CACHE;
+    }
+
+    for offset in (0..size).step_by(4096) {
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(virt_addr.as_u64() + offset));
+        let frame = PhysFrame::containing_address(PhysAddr::new(phys_addr.as_u64() + offset));
+        unsafe {
+            mapper
+                .map_to(page, frame, flags, &mut SnPageTableFrames(&mut memory_info.frame_allocator))?
+                .flush()
+        };
+    }
+
+    Ok(())
+}
+
+/// Removes a mapping made by `map_user_device_memory` from the active address space
+pub fn unmap_user_device_memory(virt_addr: SnVirtAddr, size: u64) {
+    let memory_info = unsafe { MEMORY_INFO.as_mut().unwrap() };
+    let mut mapper = unsafe { init_page_table(memory_info.physical_memory_offset) };
+
+    for offset in (0..size).step_by(4096) {
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(virt_addr.as_u64() + offset));
+        if let Ok((_, flush)) = mapper.unmap(page) {
+            flush.flush();
+        }
+    }
+}
+
+/// Maps fresh frames at `start_addr..=end_addr` in the active page table.
+///
+/// Fails when frames run out, with nothing of the range left mapped.
+pub fn map_new_memory(start_addr: SnVirtAddr, end_addr: SnVirtAddr) -> Result<(), MapToError<Size4KiB>> {
     let memory_info = unsafe { MEMORY_INFO.as_mut().unwrap() };
     let physical_memory_offset = memory_info.physical_memory_offset;
 
@@ -252,13 +710,12 @@ pub fn map_new_memory(start_addr: SnVirtAddr, end_addr: SnVirtAddr) {
         start_addr_x86,
         end_addr_x86,
     )
-    .expect("cannot map memory")
 }
 
 /// Create heap
 fn map_new_memory_inner(
     mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    frame_allocator: &mut SnLimineFrameAllocator,
     start_addr: VirtAddr,
     end_addr: VirtAddr,
 ) -> Result<(), MapToError<Size4KiB>> {
@@ -271,11 +728,27 @@ fn map_new_memory_inner(
     };
 
     for page in page_range {
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+        let mapped = frame_allocator.allocate_frame().ok_or(MapToError::FrameAllocationFailed).and_then(|frame| {
+            let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+            match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+                Ok(flush) => Ok(flush.flush()),
+                Err(err) => {
+                    frame_allocator.deallocate_frame(frame);
+                    Err(err)
+                }
+            }
+        });
+
+        if let Err(err) = mapped {
+            // Give back the pages mapped so far
+            for mapped_page in Page::range(page_range.start, page) {
+                if let Ok((frame, flush)) = mapper.unmap(mapped_page) {
+                    flush.flush();
+                    frame_allocator.deallocate_frame(frame);
+                }
+            }
+            return Err(err);
+        }
     }
 
     Ok(())
@@ -357,10 +830,11 @@ pub fn map_user_executable_memory(start_addr: SnVirtAddr, end_addr: SnVirtAddr)
     let mut mapper: OffsetPageTable<'_> = unsafe { init_page_table(physical_memory_offset) };
     let start_addr_x86 = VirtAddr::new(start_addr.as_u64());
     let end_addr_x86 = VirtAddr::new(end_addr.as_u64());
-    printk!("x86_paging: {:X} {:X}", start_addr_x86, end_addr_x86);
+    log::debug!("{:X} {:X}", start_addr_x86, end_addr_x86);
     map_user_memory_inner(
         &mut mapper,
         &mut memory_info.frame_allocator,
+        physical_memory_offset,
         start_addr_x86,
         end_addr_x86,
         PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE,
@@ -368,10 +842,18 @@ pub fn map_user_executable_memory(start_addr: SnVirtAddr, end_addr: SnVirtAddr)
     .expect("cannot map memory")
 }
 
+/// Clears a frame about to be mapped into user space, so that nothing its
+/// last owner left shows through
+fn zero_frame(physical_memory_offset: VirtAddr, frame: PhysFrame) {
+    let ptr = (physical_memory_offset + frame.start_address().as_u64()).as_mut_ptr::<u8>();
+    unsafe { core::ptr::write_bytes(ptr, 0, 4096) };
+}
+
 /// Create heap
 fn map_user_memory_inner(
     mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    frame_allocator: &mut SnLimineFrameAllocator,
+    physical_memory_offset: VirtAddr,
     start_addr: VirtAddr,
     end_addr: VirtAddr,
     page_table_flags: PageTableFlags,
@@ -385,16 +867,18 @@ fn map_user_memory_inner(
     };
 
     for page in page_range {
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
         if mapper.translate_page(page).is_ok() {
             continue;
         }
-        
+        let frame = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        USER_FRAMES.fetch_add(1, Ordering::Relaxed);
+        zero_frame(physical_memory_offset, frame);
+
         unsafe {
             mapper
-                .map_to(page, frame, page_table_flags, frame_allocator)?
+                .map_to(page, frame, page_table_flags, &mut SnPageTableFrames(frame_allocator))?
                 .flush()
         };
     }
@@ -416,6 +900,7 @@ pub fn map_user_allocate_mem(start_addr: SnVirtAddr, end_addr: SnVirtAddr) {
     map_user_memory_inner(
         &mut mapper,
         &mut memory_info.frame_allocator,
+        physical_memory_offset,
         start_addr_x86,
         end_addr_x86,
         PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE,
@@ -425,6 +910,7 @@ pub fn map_user_allocate_mem(start_addr: SnVirtAddr, end_addr: SnVirtAddr) {
     map_user_memory_inner(
         &mut mapper,
         &mut memory_info.frame_allocator,
+        physical_memory_offset,
         start_ro_addr_x86,
         end_ro_addr_x86,
         PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE,
@@ -462,7 +948,7 @@ pub fn get_user_thread_stack(page_table_phys_addr: u64) -> Result<(u64, u64), &'
     let mut table =
         unsafe { get_page_table_from_address(physical_memory_offset, page_table_phys_addr) };
     let mut thread_stack_index: [u64; 3] = [0, 0, 0];
-    'all: for idx_1 in 3..6 {
+    'all: for idx_1 in USER_STACK_L4_INDICES {
         for idx_2 in 0..511 {
             let page_table = unsafe { &mut *(find_empty_stack_entry(table, idx_1, idx_2)) };
             for idx_3 in 0..512 {
@@ -493,7 +979,7 @@ pub fn get_user_heap(page_table_phys_addr: u64) -> Result<(u64, u64), &'static s
     let mut table =
         unsafe { get_page_table_from_address(physical_memory_offset, page_table_phys_addr) };
     let mut thread_stack_index: [u64; 3] = [0, 0, 0];
-    'all: for idx_1 in 7..11 {
+    'all: for idx_1 in USER_HEAP_L4_INDICES {
         for idx_2 in 0..511 {
             let page_table = unsafe { &mut *(find_empty_stack_entry(table, idx_1, idx_2)) };
             for idx_3 in 0..256 {
@@ -517,26 +1003,38 @@ pub fn get_user_heap(page_table_phys_addr: u64) -> Result<(u64, u64), &'static s
     Ok((slot_address + 4096, slot_address + USER_HEAP_SIZE))
 }
 
-/// Maps user accessible memory
-pub fn map_missing_user_page(start_addr: SnVirtAddr) -> Result<(), MapToError<Size4KiB>> {
+/// Gives a read-only page of a user stack or heap its own writable frame,
+/// the first time it is written.
+///
+/// Returns `Ok(false)` for any other page, which is never mapped lazily.
+pub fn map_missing_user_page(start_addr: SnVirtAddr) -> Result<bool, MapToError<Size4KiB>> {
+    let start_addr_x86 = VirtAddr::new(start_addr.as_u64());
+    let l4_index = u64::from(start_addr_x86.p4_index());
+    let lazy = USER_STACK_L4_INDICES.contains(&l4_index) || USER_HEAP_L4_INDICES.contains(&l4_index);
+    if !lazy || active_user_level_1_entry(start_addr_x86).is_none() {
+        return Ok(false);
+    }
+
     let memory_info = unsafe { MEMORY_INFO.as_mut().unwrap() };
     let physical_memory_offset = memory_info.physical_memory_offset;
 
     let mut mapper: OffsetPageTable<'_> = unsafe { init_page_table(physical_memory_offset) };
-    let start_addr_x86 = VirtAddr::new(start_addr.as_u64());
 
     map_missing_user_page_inner(
         &mut mapper,
         &mut memory_info.frame_allocator,
+        physical_memory_offset,
         start_addr_x86,
         PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE,
-    )
+    )?;
+    Ok(true)
 }
 
 /// Create heap
 fn map_missing_user_page_inner(
     mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    frame_allocator: &mut SnLimineFrameAllocator,
+    physical_memory_offset: VirtAddr,
     start_addr: VirtAddr,
     page_table_flags: PageTableFlags,
 ) -> Result<(), MapToError<Size4KiB>> {
@@ -544,10 +1042,15 @@ fn map_missing_user_page_inner(
     let frame = frame_allocator
         .allocate_frame()
         .ok_or(MapToError::FrameAllocationFailed)?;
-    let _ = mapper.unmap(page);
+    USER_FRAMES.fetch_add(1, Ordering::Relaxed);
+    zero_frame(physical_memory_offset, frame);
+    if let Ok((old_frame, flush)) = mapper.unmap(page) {
+        flush.ignore();
+        release_user_frame(frame_allocator, old_frame.start_address());
+    }
     unsafe {
         mapper
-            .map_to(page, frame, page_table_flags, frame_allocator)?
+            .map_to(page, frame, page_table_flags, &mut SnPageTableFrames(frame_allocator))?
             .flush()
     };
 
@@ -617,6 +1120,7 @@ pub fn free_user_stack(stack_end: SnVirtAddr) -> Result<(), &'static str> {
             memory_info
                 .frame_allocator
                 .deallocate_frame(entry.frame().unwrap());
+            USER_FRAMES.fetch_sub(1, Ordering::Relaxed);
         }
         entry.set_flags(PageTableFlags::empty());
     }
@@ -624,6 +1128,15 @@ pub fn free_user_stack(stack_end: SnVirtAddr) -> Result<(), &'static str> {
     Ok(())
 }
 
+/// Returns the physical address of the kernel's level 4 table
+pub fn kernel_page_table_phys_addr() -> SnPhysAddr {
+    let memory_info = unsafe { MEMORY_INFO.as_mut().unwrap() };
+    SnPhysAddr::new(
+        (memory_info.kernel_l4_table as *mut PageTable as u64)
+            - memory_info.physical_memory_offset.as_u64(),
+    )
+}
+
 pub fn free_user_pagetables(page_table_phys_addr: u64) {
     let memory_info = unsafe { MEMORY_INFO.as_mut().unwrap() };
 
@@ -636,13 +1149,19 @@ pub fn free_user_pagetables(page_table_phys_addr: u64) {
         let table = unsafe {
             &mut *(physical_memory_offset + table_physaddr.as_u64()).as_mut_ptr() as &mut PageTable
         };
-        for entry in table.iter() {
+        for (i, entry) in table.iter().enumerate() {
+            if is_shared_kernel_entry(level, i) {
+                // Owned by the kernel page table
+                continue;
+            }
             if !entry.is_unused() {
                 if (level == 1) || entry.flags().contains(PageTableFlags::HUGE_PAGE) {
                     // Maps a frame, not a page table
-                    if entry.flags().contains(PageTableFlags::USER_ACCESSIBLE) {
-                        // A user frame => deallocate
-                        frame_allocator.deallocate_frame(entry.frame().unwrap());
+                    if entry.flags().contains(PageTableFlags::USER_ACCESSIBLE)
+                        && !entry.flags().contains(DEVICE_MEMORY)
+                    {
+                        // A user frame => deallocate, unless a forked process still uses it
+                        release_user_frame(frame_allocator, entry.addr());
                     }
                 } else {
                     // A page table
@@ -657,6 +1176,7 @@ pub fn free_user_pagetables(page_table_phys_addr: u64) {
         }
         // Free page table
         frame_allocator.deallocate_frame(PhysFrame::from_start_address(table_physaddr).unwrap());
+        PAGE_TABLE_FRAMES.fetch_sub(1, Ordering::Relaxed);
     }
 
     let memory_info = unsafe { MEMORY_INFO.as_mut().unwrap() };
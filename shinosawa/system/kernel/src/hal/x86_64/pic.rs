@@ -0,0 +1,140 @@
+use pic8259::ChainedPics;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use crate::printk;
+
+pub const PIC_1_OFFSET: u8 = 0x20;
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+pub const PIC_IRQS: usize = 16;
+
+/// IRQ of the PIT, used for preemption
+pub const TIMER_IRQ: u8 = 0;
+/// IRQ the second PIC is chained to
+const CASCADE_IRQ: u8 = 2;
+
+/// Rate of the PIT timer interrupt
+pub const PIT_FREQUENCY_HZ: u32 = 100;
+const PIT_BASE_FREQUENCY_HZ: u32 = 1_193_182;
+const PIT_CHANNEL_0: u16 = 0x40;
+const PIT_CHANNEL_2: u16 = 0x42;
+const PIT_COMMAND: u16 = 0x43;
+/// Channel 0, low byte then high byte, mode 2 (rate generator)
+const PIT_CHANNEL_0_RATE_GENERATOR: u8 = 0b0011_0100;
+/// Channel 2, low byte then high byte, mode 0 (interrupt on terminal count)
+const PIT_CHANNEL_2_ONE_SHOT: u8 = 0b1011_0000;
+/// Gate of channel 2 and PC speaker control
+const PIT_CHANNEL_2_GATE: u16 = 0x61;
+const GATE_ENABLE: u8 = 1 << 0;
+const GATE_SPEAKER: u8 = 1 << 1;
+const GATE_OUTPUT: u8 = 1 << 5;
+
+static PICS: Mutex<ChainedPics> =
+    Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+
+/// Free vector each IRQ is dispatched to, see `route_irq`
+static ROUTES: Mutex<[Option<u8>; PIC_IRQS]> = Mutex::new([None; PIC_IRQS]);
+
+/// Sets up the PICs with every line masked except the cascade and the timer,
+/// and starts the PIT.
+pub fn init() {
+    printk!("using the legacy PIC and PIT");
+
+    let mut pics = PICS.lock();
+    unsafe {
+        pics.initialize();
+        pics.write_masks(!(1 << CASCADE_IRQ | 1 << TIMER_IRQ), 0xff);
+    }
+
+    set_pit_frequency(PIT_FREQUENCY_HZ);
+}
+
+/// Moves the PICs away from the exception vectors and masks them,
+/// for when the APIC delivers interrupts
+pub fn disable() {
+    let mut pics = PICS.lock();
+    unsafe {
+        pics.initialize();
+        pics.disable();
+    }
+}
+
+fn set_pit_frequency(hz: u32) {
+    let divisor = (PIT_BASE_FREQUENCY_HZ / hz).clamp(1, u16::MAX as u32) as u16;
+
+    unsafe {
+        Port::<u8>::new(PIT_COMMAND).write(PIT_CHANNEL_0_RATE_GENERATOR);
+        let mut channel_0 = Port::<u8>::new(PIT_CHANNEL_0);
+        channel_0.write(divisor as u8);
+        channel_0.write((divisor >> 8) as u8);
+    }
+}
+
+/// Busy-waits using PIT channel 2, which doesn't interrupt and is
+/// free whichever timer is used for preemption. At most 54 ms.
+pub fn pit_wait(us: u32) {
+    let count = (PIT_BASE_FREQUENCY_HZ as u64 * us as u64 / 1_000_000).clamp(1, u16::MAX as u64) as u16;
+
+    unsafe {
+        let mut gate = Port::<u8>::new(PIT_CHANNEL_2_GATE);
+        // Keep the speaker off, and the gate low while programming
+        let control = gate.read() & !(GATE_SPEAKER | GATE_ENABLE);
+        gate.write(control);
+
+        Port::<u8>::new(PIT_COMMAND).write(PIT_CHANNEL_2_ONE_SHOT);
+        let mut channel_2 = Port::<u8>::new(PIT_CHANNEL_2);
+        channel_2.write(count as u8);
+        channel_2.write((count >> 8) as u8);
+
+        // Raising the gate starts counting down, the output goes high at zero
+        gate.write(control | GATE_ENABLE);
+        while gate.read() & GATE_OUTPUT == 0 {
+            core::hint::spin_loop();
+        }
+        gate.write(control);
+    }
+}
+
+/// Dispatches an IRQ to a free vector. The PIC vectors are fixed,
+/// so this is remembered and looked up in the PIC handlers.
+pub fn route_irq(irq: u32, vector: u8) -> bool {
+    if irq as usize >= PIC_IRQS || irq as u8 == CASCADE_IRQ || irq as u8 == TIMER_IRQ {
+        return false;
+    }
+
+    ROUTES.lock()[irq as usize] = Some(vector);
+    true
+}
+
+/// Returns the free vector an IRQ is dispatched to
+pub fn routed_vector(irq: u8) -> Option<u8> {
+    ROUTES.lock().get(irq as usize).copied().flatten()
+}
+
+fn set_masked(irq: u32, masked: bool) {
+    if irq as usize >= PIC_IRQS {
+        return;
+    }
+
+    let mut pics = PICS.lock();
+    let mut masks = unsafe { pics.read_masks() };
+    let (pic, bit) = ((irq / 8) as usize, irq % 8);
+    if masked {
+        masks[pic] |= 1 << bit;
+    } else {
+        masks[pic] &= !(1 << bit);
+    }
+    unsafe { pics.write_masks(masks[0], masks[1]) };
+}
+
+pub fn enable_irq(irq: u32) {
+    set_masked(irq, false);
+}
+
+pub fn disable_irq(irq: u32) {
+    set_masked(irq, true);
+}
+
+pub fn end_of_interrupt(irq: u8) {
+    unsafe { PICS.lock().notify_end_of_interrupt(PIC_1_OFFSET + irq) };
+}
@@ -1,4 +1,4 @@
-use crate::{hal::x86_64::gdt, printk, process::thread, syscall::SYSCALL_CONTROLLER};
+use crate::{hal::x86_64::gdt, memory::TIMER_STACK_SIZE, printk, process::thread, syscall::SYSCALL_CONTROLLER};
 use core::arch::{asm, naked_asm};
 
 use super::cpu::SnCpuContext;
@@ -8,7 +8,8 @@ const MSR_LSTAR: usize = 0xc0000082;
 const MSR_FMASK: usize = 0xc0000084;
 const MSR_KERNEL_GS_BASE: usize = 0xC0000102;
 
-const SYSCALL_KERNEL_STACK_OFFSET: u64 = 512 * 2;
+/// Syscalls start below the part of the kernel stack the timer uses
+const SYSCALL_KERNEL_STACK_OFFSET: u64 = TIMER_STACK_SIZE;
 
 extern "C" fn dispatch_syscall(
     context_addr: u64,
@@ -37,7 +38,7 @@ extern "C" fn handle_syscall() {
             "swapgs",
             "mov gs:{tss_syscall}, rsp", // save user RSP
             "mov rsp, gs:{tss_timer}", // load kernel RSP
-            // Move stack pointer by two pages
+            // Move below the timer's part of the stack
             "sub rsp, {ks_offset}",
 
             "sub rsp, 8", // To be replaced with SS
@@ -122,7 +123,7 @@ extern "C" fn handle_syscall() {
 }
 
 pub fn init() {
-    printk!("x86_64::syscall: initializing syscall interface");
+    printk!("initializing syscall interface");
     let handler_addr = handle_syscall as *const () as u64;
     unsafe {
         // Enable syscall and sysret ops
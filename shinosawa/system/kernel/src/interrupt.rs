@@ -1,40 +1,318 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use alloc::{boxed::Box, vec::Vec};
 use conquer_once::spin::OnceCell;
 use spin::RwLock;
 
-use crate::printk;
+use crate::{hal::interface::interrupt::without_interrupts, printk};
 
 
 pub const FREE_VECTORS_START: u8 = 0x40;
-pub const FREE_VECTORS: usize = 0x20;
+pub const FREE_VECTORS: usize = 0x40;
+
+/// An interrupt handler. Context is passed by capturing it in the closure.
+///
+/// Returns true if the interrupt came from the handler's device,
+/// so handlers can share a line.
+pub type SnInterruptHandler = dyn Fn() -> bool + Send + Sync;
 
-pub struct InterruptHandler {
-    handler: fn(),
+/// Identifies a registered handler, to remove it later
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SnInterruptHandle {
+    pub vector: usize,
+    id: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SnInterruptStats {
+    /// Number of times the vector fired
+    pub count: u64,
+    /// Number of times no handler claimed the interrupt
+    pub unhandled: u64,
+    pub handlers: usize,
+    /// Interrupt line delivered to this vector, if any
+    pub gsi: Option<u32>,
+    pub masked: bool,
+}
+
+struct InterruptVector {
+    handlers: Vec<(u64, Box<SnInterruptHandler>)>,
+    /// Handed out by `allocate_vector`
+    allocated: bool,
+    /// Interrupt line routed to this vector
+    gsi: Option<u32>,
+    masked: bool,
+    count: AtomicU64,
+    unhandled: AtomicU64,
+}
+
+impl InterruptVector {
+    const fn new() -> InterruptVector {
+        InterruptVector {
+            handlers: Vec::new(),
+            allocated: false,
+            gsi: None,
+            masked: true,
+            count: AtomicU64::new(0),
+            unhandled: AtomicU64::new(0),
+        }
+    }
 }
 
 pub struct InterruptController {
-    handlers: [Option<InterruptHandler>; FREE_VECTORS],
+    vectors: [InterruptVector; FREE_VECTORS],
+    next_handler_id: u64,
 }
 
 impl InterruptController {
     pub fn new() -> InterruptController{
         InterruptController {
-            handlers: [const { None }; FREE_VECTORS],
+            vectors: [const { InterruptVector::new() }; FREE_VECTORS],
+            next_handler_id: 0,
         }
     }
 
-    pub fn set_handler(&mut self, idx: usize,  handler: fn()) {
-        self.handlers[idx] = Some(InterruptHandler { handler: handler });
+    /// Reserves a free vector.
+    ///
+    /// Returns the index of the vector, the CPU vector is `FREE_VECTORS_START + index`.
+    pub fn allocate_vector(&mut self) -> Option<usize> {
+        let idx = (0..FREE_VECTORS).find(|&idx| !self.vectors[idx].allocated)?;
+        self.vectors[idx].allocated = true;
+        Some(idx)
+    }
+
+    /// Returns a vector from `allocate_vector`, dropping its handlers
+    pub fn free_vector(&mut self, idx: usize) {
+        let vector = &mut self.vectors[idx];
+        vector.allocated = false;
+        vector.gsi = None;
+        vector.masked = true;
+        vector.handlers.clear();
+    }
+
+    /// Returns the vector an interrupt line is routed to
+    pub fn vector_for_gsi(&self, gsi: u32) -> Option<usize> {
+        self.vectors.iter().position(|vector| vector.gsi == Some(gsi))
+    }
+
+    /// Adds a handler to a vector, after any handlers already there
+    pub fn add_handler(&mut self, idx: usize, handler: Box<SnInterruptHandler>) -> SnInterruptHandle {
+        let id = self.next_handler_id;
+        self.next_handler_id += 1;
+        self.vectors[idx].handlers.push((id, handler));
+
+        SnInterruptHandle { vector: idx, id }
+    }
+
+    /// Removes a handler. Returns the number of handlers left on the vector.
+    pub fn remove_handler(&mut self, handle: SnInterruptHandle) -> usize {
+        let handlers = &mut self.vectors[handle.vector].handlers;
+        handlers.retain(|(id, _)| *id != handle.id);
+        handlers.len()
     }
 
+    /// Runs every handler of a vector, as more than one device on a shared
+    /// line can have something pending
     pub fn run_handler(&self, idx: usize) {
-        if let Some(handler) = &self.handlers[idx] {
-            (handler.handler)();
+        let vector = &self.vectors[idx];
+        vector.count.fetch_add(1, Ordering::Relaxed);
+
+        let handled = vector.handlers.iter().fold(false, |handled, (_, handler)| handler() | handled);
+        if !handled {
+            vector.unhandled.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn stats(&self, idx: usize) -> SnInterruptStats {
+        let vector = &self.vectors[idx];
+        SnInterruptStats {
+            count: vector.count.load(Ordering::Relaxed),
+            unhandled: vector.unhandled.load(Ordering::Relaxed),
+            handlers: vector.handlers.len(),
+            gsi: vector.gsi,
+            masked: vector.masked,
         }
     }
 }
 
 pub static INTERRUPT_CONTROLLER: OnceCell<RwLock<InterruptController>> = OnceCell::uninit();
 pub fn init() {
-    printk!("interrupt: initializing general interrupt controller");
+    printk!("initializing general interrupt controller");
     INTERRUPT_CONTROLLER.init_once(move || RwLock::new(InterruptController::new()) );
-}
\ No newline at end of file
+}
+
+/// Runs a closure with the interrupt controller locked for writing.
+///
+/// Interrupts are disabled meanwhile, as handlers take the lock for reading.
+fn with_controller<F, R>(func: F) -> R where F: FnOnce(&mut InterruptController) -> R {
+    without_interrupts(|| func(&mut INTERRUPT_CONTROLLER.get().unwrap().write()))
+}
+
+/// Adds a handler to an interrupt line (GSI) and unmasks the line.
+///
+/// Other handlers already on the line keep running. Returns None if
+/// the line does not exist or no vector is left for it.
+pub fn request_irq(gsi: u32, handler: Box<SnInterruptHandler>) -> Option<SnInterruptHandle> {
+    let handle = with_controller(|controller| {
+        let idx = match controller.vector_for_gsi(gsi) {
+            Some(idx) => idx,
+            None => {
+                let idx = controller.allocate_vector()?;
+                let vector = FREE_VECTORS_START + idx as u8;
+                if !crate::hal::interface::interrupt::route_irq(gsi, vector, None) {
+                    controller.free_vector(idx);
+                    return None;
+                }
+                controller.vectors[idx].gsi = Some(gsi);
+                idx
+            }
+        };
+        Some(controller.add_handler(idx, handler))
+    })?;
+
+    unmask_irq(gsi);
+    Some(handle)
+}
+
+/// Adds a handler to a legacy ISA IRQ, see `request_irq`
+pub fn request_isa_irq(irq: u8, handler: Box<SnInterruptHandler>) -> Option<SnInterruptHandle> {
+    request_irq(crate::hal::interface::interrupt::isa_irq_to_gsi(irq), handler)
+}
+
+/// Removes a handler, releasing its vector if it was the last one
+pub fn free_irq(handle: SnInterruptHandle) {
+    let gsi = with_controller(|controller| {
+        if controller.remove_handler(handle) > 0 {
+            return None;
+        }
+        let gsi = controller.vectors[handle.vector].gsi;
+        controller.free_vector(handle.vector);
+        gsi
+    });
+
+    if let Some(gsi) = gsi {
+        crate::hal::interface::interrupt::disable_irq(gsi);
+    }
+}
+
+/// Reserves a vector with a handler, for interrupts that don't come
+/// through an interrupt line. Returns the CPU vector number.
+pub fn allocate_vector(handler: Box<SnInterruptHandler>) -> Option<(u8, SnInterruptHandle)> {
+    with_controller(|controller| {
+        let idx = controller.allocate_vector()?;
+        let handle = controller.add_handler(idx, handler);
+        Some((FREE_VECTORS_START + idx as u8, handle))
+    })
+}
+
+/// Releases a vector from `allocate_vector`
+pub fn free_vector(handle: SnInterruptHandle) {
+    with_controller(|controller| controller.free_vector(handle.vector));
+}
+
+fn set_masked(gsi: u32, masked: bool) {
+    with_controller(|controller| {
+        if let Some(idx) = controller.vector_for_gsi(gsi) {
+            controller.vectors[idx].masked = masked;
+        }
+    });
+}
+
+pub fn mask_irq(gsi: u32) {
+    set_masked(gsi, true);
+    crate::hal::interface::interrupt::disable_irq(gsi);
+}
+
+pub fn unmask_irq(gsi: u32) {
+    set_masked(gsi, false);
+    crate::hal::interface::interrupt::enable_irq(gsi);
+}
+
+/// Delivers an interrupt line to another CPU, identified by its local APIC id
+pub fn set_irq_affinity(gsi: u32, cpu: u8) {
+    crate::hal::interface::interrupt::set_irq_target(gsi, cpu);
+}
+
+pub fn stats(idx: usize) -> SnInterruptStats {
+    without_interrupts(|| INTERRUPT_CONTROLLER.get().unwrap().read().stats(idx))
+}
+
+/// Prints the statistics of every vector that has fired or has handlers
+pub fn dump_stats() {
+    for idx in 0..FREE_VECTORS {
+        let stats = stats(idx);
+        if stats.count == 0 && stats.handlers == 0 {
+            continue;
+        }
+        printk!(
+            "vector {:#x} (GSI {:?}): {} interrupts, {} unhandled, {} handlers{}",
+            FREE_VECTORS_START as usize + idx,
+            stats.gsi,
+            stats.count,
+            stats.unhandled,
+            stats.handlers,
+            if stats.masked { ", masked" } else { "" }
+        );
+    }
+}
+
+#[test_case]
+fn test_shared_handlers() {
+    use alloc::sync::Arc;
+
+    let mut controller = InterruptController::new();
+    let hits = Arc::new(AtomicU64::new(0));
+
+    // The first handler does not claim the interrupt, so the second one runs
+    let first_hits = hits.clone();
+    controller.add_handler(3, Box::new(move || {
+        first_hits.fetch_add(1, Ordering::Relaxed);
+        false
+    }));
+    let second_hits = hits.clone();
+    let second = controller.add_handler(3, Box::new(move || {
+        second_hits.fetch_add(10, Ordering::Relaxed);
+        true
+    }));
+
+    controller.run_handler(3);
+    assert_eq!(hits.load(Ordering::Relaxed), 11);
+
+    assert_eq!(controller.remove_handler(second), 1);
+    controller.run_handler(3);
+    assert_eq!(controller.stats(3).count, 2);
+    assert_eq!(controller.stats(3).unhandled, 1);
+}
+
+#[test_case]
+fn test_allocate_vector() {
+    let mut controller = InterruptController::new();
+
+    let first = controller.allocate_vector().unwrap();
+    assert_eq!(first, 0);
+    assert_eq!(controller.allocate_vector(), Some(1));
+
+    controller.free_vector(first);
+    assert_eq!(controller.allocate_vector(), Some(first));
+}
+
+#[test_case]
+fn test_shared_handlers_all_run() {
+    use alloc::sync::Arc;
+
+    let mut controller = InterruptController::new();
+    let hits = Arc::new(AtomicU64::new(0));
+
+    // Both devices on the line have something pending
+    for _ in 0..2 {
+        let hits = hits.clone();
+        controller.add_handler(3, Box::new(move || {
+            hits.fetch_add(1, Ordering::Relaxed);
+            true
+        }));
+    }
+
+    controller.run_handler(3);
+    assert_eq!(hits.load(Ordering::Relaxed), 2);
+    assert_eq!(controller.stats(3).unhandled, 0);
+}
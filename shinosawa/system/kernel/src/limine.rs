@@ -1,6 +1,6 @@
 
 use limine::BaseRevision;
-use limine::request::{FramebufferRequest, HhdmRequest, MemoryMapRequest, RequestsEndMarker, RequestsStartMarker, RsdpRequest};
+use limine::request::{ExecutableCmdlineRequest, ExecutableFileRequest, FramebufferRequest, HhdmRequest, MemoryMapRequest, ModuleRequest, RequestsEndMarker, RequestsStartMarker, RsdpRequest};
 
 use crate::init;
 
@@ -28,6 +28,47 @@ pub static HHDM_REQUEST: HhdmRequest = HhdmRequest::new();
 #[unsafe(link_section = ".requests")]
 pub static RSDP_REQUEST: RsdpRequest = RsdpRequest::new();
 
+/// The kernel ELF, for the symbols in backtraces
+#[used]
+#[unsafe(link_section = ".requests")]
+pub static EXECUTABLE_FILE_REQUEST: ExecutableFileRequest = ExecutableFileRequest::new();
+
+/// The kernel command line, set with `cmdline` in limine.conf
+#[used]
+#[unsafe(link_section = ".requests")]
+pub static EXECUTABLE_CMDLINE_REQUEST: ExecutableCmdlineRequest = ExecutableCmdlineRequest::new();
+
+/// Returns the kernel command line, empty if there is none
+pub fn cmdline() -> &'static str {
+    EXECUTABLE_CMDLINE_REQUEST
+        .get_response()
+        .and_then(|response| response.cmdline().to_str().ok())
+        .unwrap_or("")
+}
+
+/// Files loaded next to the kernel, set with `module_path` in limine.conf
+#[used]
+#[unsafe(link_section = ".requests")]
+pub static MODULE_REQUEST: ModuleRequest = ModuleRequest::new();
+
+/// Returns the path and contents of every module
+pub fn modules() -> impl Iterator<Item = (&'static str, &'static [u8])> {
+    MODULE_REQUEST
+        .get_response()
+        .map_or(&[][..], |response| response.modules())
+        .iter()
+        .map(|file| {
+            let path = file.path().to_str().unwrap_or("");
+            let contents = unsafe { core::slice::from_raw_parts(file.addr(), file.size() as usize) };
+            (path, contents)
+        })
+}
+
+/// Returns the contents of the module whose path ends with `name`
+pub fn module(name: &str) -> Option<&'static [u8]> {
+    modules().find(|(path, _)| path.ends_with(name)).map(|(_, contents)| contents)
+}
+
 /// Define the stand and end markers for Limine requests.
 #[used]
 #[unsafe(link_section = ".requests_start_marker")]
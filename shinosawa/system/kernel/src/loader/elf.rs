@@ -1,4 +1,8 @@
-use object::{Object, ObjectSegment};
+use object::{
+    Object, ObjectSegment,
+    elf::PT_TLS,
+    read::elf::ProgramHeader,
+};
 
 use crate::{
     hal::x86_64::paging::{self, switch_page_table, with_page_table},
@@ -6,13 +10,14 @@ use crate::{
     printk,
 };
 
-use super::SnExecutable;
+use super::{SnExecutable, SnTlsTemplate};
 
 #[derive(Clone)]
 pub struct SnElfExecutable {
     entry_point: SnVirtAddr,
     user_page_table_virt_addr: SnVirtAddr,
     user_page_table_phys_addr: SnPhysAddr,
+    tls_template: Option<SnTlsTemplate>,
 }
 
 impl SnExecutable for SnElfExecutable {
@@ -29,28 +34,59 @@ impl SnExecutable for SnElfExecutable {
         self.user_page_table_phys_addr
         // todo!()
     }
+
+    fn tls_template(&self) -> Option<SnTlsTemplate> {
+        self.tls_template
+    }
+}
+
+/// Finds the PT_TLS segment of an ELF file
+fn find_tls_template(obj: &object::File) -> Option<SnTlsTemplate> {
+    let object::File::Elf64(elf) = obj else {
+        return None;
+    };
+    let endian = elf.endian();
+
+    elf.elf_program_headers()
+        .iter()
+        .find(|header| header.p_type(endian) == PT_TLS)
+        .map(|header| SnTlsTemplate {
+            start: SnVirtAddr::new(header.p_vaddr(endian)),
+            file_size: header.p_filesz(endian),
+            mem_size: header.p_memsz(endian),
+            align: header.p_align(endian).max(1),
+        })
 }
 
 pub fn load_elf(bin: &[u8]) -> Result<SnElfExecutable, &'static str> {
     // Check the header
     const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
 
-    let (user_page_table_virt_addr, user_page_table_physaddr) = paging::create_new_user_pagetable();
-
-    if bin[0..4] != ELF_MAGIC {
+    if !bin.starts_with(&ELF_MAGIC) {
         return Err("Expected ELF binary");
     }
+
+    let (user_page_table_virt_addr, user_page_table_physaddr) = paging::create_new_user_pagetable();
     // Use the object crate to parse the ELF file
     // https://crates.io/crates/object
     if let Ok(obj) = object::File::parse(bin) {
         let entry_point = obj.entry();
-        printk!("loader::elf: Entry point: {:#016X}", entry_point);
+        printk!("Entry point: {:#016X}", entry_point);
+
+        let tls_template = find_tls_template(&obj);
+        if let Some(tls) = &tls_template {
+            printk!(
+                "TLS template at {:#016X}, {} bytes",
+                tls.start.as_u64(),
+                tls.mem_size
+            );
+        }
 
         crate::hal::interface::interrupt::without_interrupts(|| {
             with_page_table(user_page_table_physaddr, || {
                 for segment in obj.segments() {
                     printk!(
-                        "loader::elf: Section {:?} : {:#016X}",
+                        "Section {:?} : {:#016X}",
                         segment.name(),
                         segment.address()
                     );
@@ -82,6 +118,7 @@ pub fn load_elf(bin: &[u8]) -> Result<SnElfExecutable, &'static str> {
             entry_point: SnVirtAddr::new(entry_point),
             user_page_table_virt_addr,
             user_page_table_phys_addr: user_page_table_physaddr,
+            tls_template,
         });
     }
     Err("Could not parse ELF")
@@ -3,8 +3,22 @@ use crate::memory::{SnPhysAddr, SnVirtAddr};
 /// ELF loader
 pub mod elf;
 
+/// Initial image of an executable's thread-local storage
+#[derive(Clone, Copy, Debug)]
+pub struct SnTlsTemplate {
+    /// Address of the initialized TLS data in the process
+    pub start: SnVirtAddr,
+    /// Size of the initialized TLS data
+    pub file_size: u64,
+    /// Size of the whole TLS block, including zero-initialized data
+    pub mem_size: u64,
+    /// Alignment of the TLS block
+    pub align: u64,
+}
+
 pub trait SnExecutable {
     fn entry_point(&self) -> SnVirtAddr;
     fn page_table_virt(&self) -> SnVirtAddr;
     fn page_table_phys(&self) -> SnPhysAddr;
+    fn tls_template(&self) -> Option<SnTlsTemplate>;
 }
\ No newline at end of file
@@ -0,0 +1,70 @@
+use alloc::{string::String, vec::Vec};
+use log::LevelFilter;
+
+/// Which messages are logged, per target.
+///
+/// Parsed from a spec like `info,x86_64::apic=trace,fs=off`: a default level,
+/// then levels for targets and everything under them.
+pub struct SnLogFilter {
+    default: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl SnLogFilter {
+    pub const fn new(default: LevelFilter) -> SnLogFilter {
+        SnLogFilter {
+            default,
+            targets: Vec::new(),
+        }
+    }
+
+    /// Parses a spec, ignoring the parts that don't make sense
+    pub fn parse(spec: &str) -> SnLogFilter {
+        let mut filter = SnLogFilter::new(LevelFilter::Info);
+
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    if let Ok(level) = level.parse() {
+                        filter.targets.push((String::from(target), level));
+                    }
+                }
+                None => {
+                    if let Ok(level) = directive.parse() {
+                        filter.default = level;
+                    }
+                }
+            }
+        }
+
+        // Longest targets first, so the most specific one matches
+        filter.targets.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        filter
+    }
+
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .find(|(prefix, _)| {
+                target.strip_prefix(prefix.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    /// Most verbose level any target can log at
+    pub fn max_level(&self) -> LevelFilter {
+        self.targets.iter().map(|(_, level)| *level).fold(self.default, Ord::max)
+    }
+}
+
+#[test_case]
+fn test_log_filter() {
+    let filter = SnLogFilter::parse("warn,x86_64=info,x86_64::apic=trace,bogus=loud");
+
+    assert_eq!(filter.level_for("fs::vfs"), LevelFilter::Warn);
+    assert_eq!(filter.level_for("x86_64::pic"), LevelFilter::Info);
+    assert_eq!(filter.level_for("x86_64::apic"), LevelFilter::Trace);
+    // Prefixes only match whole path segments
+    assert_eq!(filter.level_for("x86_64_extra"), LevelFilter::Warn);
+    assert_eq!(filter.max_level(), LevelFilter::Trace);
+}
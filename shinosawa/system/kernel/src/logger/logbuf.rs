@@ -1,31 +1,230 @@
-use core::fmt;
+use alloc::collections::VecDeque;
+use core::{fmt, str, time::Duration};
 
-use ringbuffer::{AllocRingBuffer, RingBuffer};
+use log::Level;
 
-const BUF_SIZE: usize = 16 * 1024; // 16 KiB
+/// Bytes of records kept, the oldest records are dropped to make room
+pub const BUF_SIZE: usize = 16 * 1024; // 16 KiB
 
+/// A record starts with its sequence number and timestamp in microseconds (u64),
+/// the level (u8), the target length (u8) and the message length (u16), all little
+/// endian, followed by the target and the message.
+///
+/// Records are stored and handed to userland in this format.
+pub const RECORD_HEADER_SIZE: usize = 20;
+/// Longer targets are cut
+pub const MAX_TARGET_LEN: usize = u8::MAX as usize;
+/// Longer messages are cut
+pub const MAX_MESSAGE_LEN: usize = 512;
+/// A buffer this large always fits the next record
+pub const MAX_RECORD_SIZE: usize = RECORD_HEADER_SIZE + MAX_TARGET_LEN + MAX_MESSAGE_LEN;
+
+/// A log record, borrowed from the buffer
+pub struct SnLogRecord<'a> {
+    pub seq: u64,
+    /// Uptime when the record was logged
+    pub timestamp: Duration,
+    pub level: Level,
+    pub target: &'a str,
+    pub message: &'a str,
+}
+
+impl<'a> SnLogRecord<'a> {
+    /// Decodes the record at the start of `bytes`, returning it with its size
+    pub fn decode(bytes: &'a [u8]) -> Option<(SnLogRecord<'a>, usize)> {
+        let header = bytes.get(..RECORD_HEADER_SIZE)?;
+        let target_len = header[17] as usize;
+        let message_len = u16::from_le_bytes([header[18], header[19]]) as usize;
+        let size = RECORD_HEADER_SIZE + target_len + message_len;
+        let body = bytes.get(RECORD_HEADER_SIZE..size)?;
+
+        let level = match header[16] {
+            1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            5 => Level::Trace,
+            _ => return None,
+        };
+
+        let record = SnLogRecord {
+            seq: u64::from_le_bytes(header[0..8].try_into().unwrap()),
+            timestamp: Duration::from_micros(u64::from_le_bytes(header[8..16].try_into().unwrap())),
+            level,
+            target: str::from_utf8(&body[..target_len]).ok()?,
+            message: str::from_utf8(&body[target_len..]).ok()?,
+        };
+        Some((record, size))
+    }
+
+    fn header(&self) -> [u8; RECORD_HEADER_SIZE] {
+        let mut header = [0; RECORD_HEADER_SIZE];
+        header[0..8].copy_from_slice(&self.seq.to_le_bytes());
+        header[8..16].copy_from_slice(&(self.timestamp.as_micros() as u64).to_le_bytes());
+        header[16] = self.level as u8;
+        header[17] = self.target.len() as u8;
+        header[18..20].copy_from_slice(&(self.message.len() as u16).to_le_bytes());
+        header
+    }
+
+    fn size(&self) -> usize {
+        RECORD_HEADER_SIZE + self.target.len() + self.message.len()
+    }
+}
+
+/// Cuts a string to at most `len` bytes, on a char boundary
+fn truncate(s: &str, len: usize) -> &str {
+    if s.len() <= len {
+        return s;
+    }
+    let mut end = len;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Formats a message on the stack, so logging never allocates
+pub struct SnMessageBuffer {
+    buf: [u8; MAX_MESSAGE_LEN],
+    len: usize,
+}
+
+impl SnMessageBuffer {
+    pub const fn new() -> SnMessageBuffer {
+        SnMessageBuffer {
+            buf: [0; MAX_MESSAGE_LEN],
+            len: 0,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only whole chars are copied in
+        unsafe { str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+}
+
+impl fmt::Write for SnMessageBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let s = truncate(s, MAX_MESSAGE_LEN - self.len);
+        self.buf[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+        self.len += s.len();
+        Ok(())
+    }
+}
+
+/// Ring of log records, numbered in the order they were logged
 pub struct SnLogBuffer {
-    buf: AllocRingBuffer<char>,
+    buf: VecDeque<u8>,
+    /// Sequence number of the oldest record kept
+    first_seq: u64,
+    next_seq: u64,
 }
 
 impl SnLogBuffer {
     pub fn new() -> SnLogBuffer {
         SnLogBuffer {
-            buf: AllocRingBuffer::new(BUF_SIZE),
+            buf: VecDeque::with_capacity(BUF_SIZE),
+            first_seq: 0,
+            next_seq: 0,
         }
     }
 
-    pub fn iter(&mut self) -> impl Iterator<Item = &char> {
-        self.buf.iter()
+    /// Adds a record, dropping old ones if needed, and returns its sequence number
+    pub fn push(&mut self, timestamp: Duration, level: Level, target: &str, message: &str) -> u64 {
+        let record = SnLogRecord {
+            seq: self.next_seq,
+            timestamp,
+            level,
+            target: truncate(target, MAX_TARGET_LEN),
+            message: truncate(message, MAX_MESSAGE_LEN),
+        };
+
+        while BUF_SIZE - self.buf.len() < record.size() {
+            self.drop_oldest();
+        }
+
+        self.buf.extend(record.header());
+        self.buf.extend(record.target.bytes());
+        self.buf.extend(record.message.bytes());
+        self.next_seq += 1;
+        record.seq
     }
-}
 
-impl fmt::Write for SnLogBuffer {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.chars() {
-            self.buf.push(c);
+    /// Size of the record starting at `offset`
+    fn record_size(&self, offset: usize) -> usize {
+        let target_len = self.buf[offset + 17] as usize;
+        let message_len = u16::from_le_bytes([self.buf[offset + 18], self.buf[offset + 19]]) as usize;
+        RECORD_HEADER_SIZE + target_len + message_len
+    }
+
+    fn drop_oldest(&mut self) {
+        let size = self.record_size(0);
+        self.buf.drain(..size);
+        self.first_seq += 1;
+    }
+
+    /// Copies the records numbered `cursor` and later into `out`, as many whole
+    /// records as fit.
+    ///
+    /// Returns the bytes written and the cursor to continue from. Records that
+    /// were dropped already are skipped, which shows as a gap in the numbers.
+    pub fn read(&self, cursor: u64, out: &mut [u8]) -> (usize, u64) {
+        let mut seq = self.first_seq;
+        let mut offset = 0;
+        while seq < cursor && offset < self.buf.len() {
+            offset += self.record_size(offset);
+            seq += 1;
         }
-        
-        Ok(())
+
+        let mut written = 0;
+        while offset < self.buf.len() {
+            let size = self.record_size(offset);
+            let Some(dest) = out.get_mut(written..written + size) else {
+                break;
+            };
+            for (dest, byte) in dest.iter_mut().zip(self.buf.range(offset..offset + size)) {
+                *dest = *byte;
+            }
+            written += size;
+            offset += size;
+            seq += 1;
+        }
+
+        (written, seq)
+    }
+
+    pub fn records(&mut self) -> impl Iterator<Item = SnLogRecord<'_>> {
+        let mut bytes = &*self.buf.make_contiguous();
+        core::iter::from_fn(move || {
+            let (record, size) = SnLogRecord::decode(bytes)?;
+            bytes = &bytes[size..];
+            Some(record)
+        })
+    }
+}
+
+#[test_case]
+fn test_log_buffer_cursor() {
+    let mut buffer = SnLogBuffer::new();
+    for i in 0..4 {
+        buffer.push(Duration::from_micros(i), Level::Info, "test", "message");
+    }
+
+    let mut out = [0; MAX_RECORD_SIZE];
+    let (written, next) = buffer.read(2, &mut out);
+    assert_eq!(next, 4);
+    let (record, size) = SnLogRecord::decode(&out).unwrap();
+    assert_eq!((record.seq, record.target, record.message), (2, "test", "message"));
+    assert_eq!(written, size * 2);
+
+    // Fill the buffer past its size, the oldest records go
+    let message = [b'x'; MAX_MESSAGE_LEN];
+    for _ in 0..BUF_SIZE / MAX_MESSAGE_LEN {
+        buffer.push(Duration::ZERO, Level::Warn, "test", str::from_utf8(&message).unwrap());
     }
+    let (_, next) = buffer.read(0, &mut out);
+    assert!(buffer.first_seq > 0);
+    assert_eq!(next, buffer.first_seq + 1);
+    assert_eq!(buffer.records().count() as u64, buffer.next_seq - buffer.first_seq);
 }
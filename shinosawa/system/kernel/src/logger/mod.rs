@@ -1,23 +1,40 @@
-use core::fmt::{self, Write};
+use core::{
+    fmt::{self, Write},
+    time::Duration,
+};
 
 use conquer_once::spin::OnceCell;
-use logbuf::SnLogBuffer;
+use filter::SnLogFilter;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use logbuf::{SnLogBuffer, SnMessageBuffer};
 use spin::{Mutex, RwLock};
 
 use crate::{
-    fb::{display::SnFramebufferDisplay, writer::SnFramebufferWriter}, hal, serial::SnSerialWriter
+    cmdline::SnBootOptions,
+    fb::{display::SnFramebufferDisplay, font::SnBitmapFont, writer::SnFramebufferWriter}, hal, serial::SnSerialWriter
 };
 
+pub mod filter;
 pub mod logbuf;
 
 /// The global logger instance used for the `log` crate.
 pub static LOGGER: OnceCell<RwLock<SnLogger>> = OnceCell::uninit();
 
+/// Which messages are logged at all, before the sink levels apply
+static FILTER: RwLock<SnLogFilter> = RwLock::new(SnLogFilter::new(LevelFilter::Info));
+
+/// Prefix of the module paths, left out of targets
+const CRATE_PREFIX: &str = "shinosawa_system_kernel";
+
 /// A logger instance protected by a spinlock.
 pub struct SnLogger {
     pub fb: Option<Mutex<SnFramebufferWriter>>,
     pub serial: Option<Mutex<SnSerialWriter>>,
     pub buf: Option<Mutex<SnLogBuffer>>,
+    /// Levels each sink shows, the serial port is verbose and the screen quiet
+    pub fb_level: LevelFilter,
+    pub serial_level: LevelFilter,
+    pub buf_level: LevelFilter,
 }
 
 impl SnLogger {
@@ -26,6 +43,9 @@ impl SnLogger {
             fb: None,
             serial: None,
             buf: None,
+            fb_level: LevelFilter::Warn,
+            serial_level: LevelFilter::Trace,
+            buf_level: LevelFilter::Trace,
         }
     }
 
@@ -60,11 +80,6 @@ pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
     let logger = LOGGER.get().unwrap().read();
     hal::interface::interrupt::without_interrupts(|| {
-        if let Some(log_buffer) = &logger.buf {
-            let mut buffer = log_buffer.lock();
-            buffer.write_fmt(args).unwrap();
-        }
-
         if let Some(logger_writer) = &logger.fb {
             let mut writer = logger_writer.lock();
             writer.write_fmt(args).unwrap();
@@ -76,19 +91,116 @@ pub fn _print(args: fmt::Arguments) {
     });
 }
 
+/// Writes to the serial port only
+#[doc(hidden)]
+pub fn _print_serial(args: fmt::Arguments) {
+    if !LOGGER.is_initialized() { return }
+
+    use core::fmt::Write;
+    let logger = LOGGER.get().unwrap().read();
+    if let Some(logger_serial) = &logger.serial {
+        hal::interface::interrupt::without_interrupts(|| {
+            logger_serial.lock().write_fmt(args).unwrap();
+        });
+    }
+}
+
 #[macro_export]
 macro_rules! print_s {
     ($($arg:tt)*) => ($crate::logger::_print_serial(format_args!($($arg)*)));
 }
 
+/// Logs at the info level, with the module as target
 #[macro_export]
 macro_rules! printk {
     () => ($crate::print!("\n"));
-    ($($arg:tt)*) => ($crate::println!("shinosawa::system::kernel: {}", format_args!($($arg)*)));
+    ($($arg:tt)*) => (::log::info!($($arg)*));
+}
+
+/// Backend of the `log` crate, writing to the sinks of `LOGGER`
+struct SnKernelLog;
+
+static KERNEL_LOG: SnKernelLog = SnKernelLog;
+
+/// Turns a module path into a shorter target, `shinosawa_system_kernel::hal::x86_64::apic`
+/// becomes `x86_64::apic`
+fn short_target(target: &str) -> &str {
+    let Some(rest) = target.strip_prefix(CRATE_PREFIX) else {
+        return target;
+    };
+    let rest = rest.strip_prefix("::").unwrap_or(rest);
+    let rest = rest.strip_prefix("hal::").unwrap_or(rest);
+    if rest.is_empty() { "kernel" } else { rest }
+}
+
+/// Writes a record as a line of text
+fn write_line(writer: &mut impl Write, uptime: Duration, level: Level, target: &str, message: impl fmt::Display) {
+    let _ = writeln!(
+        writer,
+        "[{:>5}.{:06}] {:<5} {}: {}",
+        uptime.as_secs(),
+        uptime.subsec_micros(),
+        level,
+        target,
+        message
+    );
+}
+
+impl Log for SnKernelLog {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= FILTER.read().level_for(short_target(metadata.target()))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) || !LOGGER.is_initialized() {
+            return;
+        }
+
+        let uptime = hal::interface::clock::uptime();
+        let level = record.level();
+        let target = short_target(record.target());
+
+        let logger = LOGGER.get().unwrap().read();
+        hal::interface::interrupt::without_interrupts(|| {
+            if let Some(log_buffer) = logger.buf.as_ref().filter(|_| level <= logger.buf_level) {
+                let mut message = SnMessageBuffer::new();
+                let _ = message.write_fmt(*record.args());
+                log_buffer.lock().push(uptime, level, target, message.as_str());
+            }
+            if let Some(logger_writer) = logger.fb.as_ref().filter(|_| level <= logger.fb_level) {
+                write_line(&mut *logger_writer.lock(), uptime, level, target, record.args());
+            }
+            if let Some(logger_serial) = logger.serial.as_ref().filter(|_| level <= logger.serial_level) {
+                write_line(&mut *logger_serial.lock(), uptime, level, target, record.args());
+            }
+        });
+    }
+
+    fn flush(&self) {}
 }
 
 pub fn init() {
     LOGGER.init_once(move || RwLock::new(SnLogger::new()));
+
+    log::set_logger(&KERNEL_LOG).unwrap();
+    log::set_max_level(FILTER.read().max_level());
+}
+
+/// Applies the logging options of the kernel command line
+pub fn configure(options: &SnBootOptions) {
+    if let Some(spec) = options.log {
+        let filter = SnLogFilter::parse(spec);
+        log::set_max_level(filter.max_level());
+        *FILTER.write() = filter;
+    }
+
+    let mut logger = LOGGER.get().unwrap().write();
+    if let Some(level) = options.log_serial {
+        logger.serial_level = level;
+    }
+    if let Some(level) = options.log_fb {
+        logger.fb_level = level;
+    }
 }
 
 pub fn set_buffer(buffer: SnLogBuffer) {
@@ -106,29 +218,62 @@ pub fn set_fb(display: SnFramebufferDisplay) {
     logger.add_fb(writer);
 }
 
+/// Draws the log on the screen with another font
+pub fn set_fb_font(font: SnBitmapFont) {
+    let logger = LOGGER.get().unwrap().read();
+    if let Some(writer) = &logger.fb {
+        hal::interface::interrupt::without_interrupts(|| writer.lock().set_font(font));
+    }
+}
+
+/// Scrolls the log on the screen through the lines that went off, negative goes back
+pub fn scroll_fb(lines: isize) {
+    let logger = LOGGER.get().unwrap().read();
+    if let Some(writer) = &logger.fb {
+        hal::interface::interrupt::without_interrupts(|| writer.lock().scroll_view(lines));
+    }
+}
+
+/// Stops drawing the log on the screen while a process owns it, and draws it
+/// again once the screen comes back
+pub fn pause_fb(paused: bool) {
+    let logger = LOGGER.get().unwrap().read();
+    if let Some(writer) = &logger.fb {
+        hal::interface::interrupt::without_interrupts(|| writer.lock().set_paused(paused));
+    }
+}
+
 pub fn set_serial(serial: SnSerialWriter) {
     let mut logger = LOGGER.get().unwrap().write();
 
     logger.add_serial(serial);
 }
 
+/// Shows the records logged before the screen and serial port were set up
 pub fn clean_buffer() {
     let logger = LOGGER.get().unwrap().write();
 
     if let Some(buf) = &logger.buf {
         let mut buffer = buf.lock();
 
-        buffer.iter().for_each(|c| {
+        buffer.records().for_each(|record| {
             hal::interface::interrupt::without_interrupts(|| {
-                if let Some(logger_writer) = &logger.fb {
+                if let Some(logger_writer) = logger.fb.as_ref().filter(|_| record.level <= logger.fb_level) {
                     let mut writer = logger_writer.lock();
-                    writer.write_char(*c).unwrap();
+                    write_line(&mut *writer, record.timestamp, record.level, record.target, record.message);
                 }
-                if let Some(logger_serial) = &logger.serial {
+                if let Some(logger_serial) = logger.serial.as_ref().filter(|_| record.level <= logger.serial_level) {
                     let mut serial = logger_serial.lock();
-                    serial.write_char(*c).unwrap();
+                    write_line(&mut *serial, record.timestamp, record.level, record.target, record.message);
                 }
             });
         });
     }
 }
+
+/// Copies log records numbered `cursor` and later into `out`, see `SnLogBuffer::read`
+pub fn read_records(cursor: u64, out: &mut [u8]) -> Option<(usize, u64)> {
+    let logger = LOGGER.get()?.read();
+    let buf = logger.buf.as_ref()?;
+    Some(hal::interface::interrupt::without_interrupts(|| buf.lock().read(cursor, out)))
+}
@@ -10,7 +10,7 @@
 #![feature(allocator_api)]
 #![feature(naked_functions)]
 
-use alloc::vec::Vec;
+use alloc::{string::String, vec::Vec};
 use hal::x86_64::instruct::hcf;
 use logger::{clean_buffer, logbuf::SnLogBuffer};
 
@@ -20,6 +20,8 @@ extern crate alloc;
 mod fb;
 /// Limine intrinsics
 mod limine;
+/// Kernel command line
+mod cmdline;
 /// Logger module
 mod logger;
 /// Panic handler
@@ -46,6 +48,8 @@ mod drivers;
 mod fs;
 /// Executable loaders
 mod loader;
+/// Backtraces and kernel symbols
+mod debug;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -56,6 +60,8 @@ pub fn init() {
 
     let buffer = SnLogBuffer::new();
     logger::set_buffer(buffer);
+    crate::cmdline::init();
+    logger::configure(crate::cmdline::options());
 
     {
         printk!("shinosawa::system::kernel {}", VERSION);
@@ -68,11 +74,14 @@ pub fn init() {
     let display = fb::init().unwrap();
     logger::set_fb(display);
     clean_buffer();
-    
+
+    crate::debug::init();
     crate::acpi::init();
 
     crate::hal::interface::cpu::init();
     crate::interrupt::init();
+    #[cfg(feature = "gdb")]
+    crate::debug::gdb::init();
 
     crate::process::thread::init();
 
@@ -83,21 +92,41 @@ pub fn init() {
 }
 
 pub fn kernel_main() {
-    // PS/2 driver
-    crate::drivers::ps2_keyboard::init();
+    // Ttys, fed by the keyboard and COM1
+    crate::drivers::tty::init();
+    crate::serial::init_receive();
+    // PS/2 controller, then the keyboard and mouse on it
+    let ps2_ports = crate::drivers::i8042::init();
+    if ps2_ports.first {
+        crate::drivers::ps2_keyboard::init();
+    }
+    if ps2_ports.second {
+        crate::drivers::ps2_mouse::init();
+    }
+    crate::drivers::i8042::enable_irqs(ps2_ports);
+    // PCI devices
+    crate::drivers::pci::init();
+    crate::drivers::edu::init();
 
     // VFS system
     crate::fs::vfs::init();
     // Init dummy example filesystem
     let example_fs = crate::fs::dummy::new_example_filesystem();
     crate::fs::vfs::attach("SNSW:", example_fs);
+    // Writable scratch space
+    crate::fs::vfs::attach("TMP:", crate::fs::ramfs::new_ram_filesystem());
+    // Console font given as a VFS path
+    if let Some(font) = crate::fb::font::from_vfs() {
+        logger::set_fb_font(font);
+    }
 
     // Initialize syscall for CPU
     crate::hal::interface::syscall::init();
     // Initialize syscall controller
     crate::syscall::init();
 
-    let file = crate::fs::vfs::find("SNSW:/shinosawa/system/kotono").unwrap();
+    let init = crate::cmdline::options().init;
+    let file = crate::fs::vfs::find(init).unwrap_or_else(|err| panic!("cannot open init {}: {:?}", init, err));
     let len = file.len();
 
     let mut buf: Vec<u8> = Vec::with_capacity(len);
@@ -106,7 +135,13 @@ pub fn kernel_main() {
     let kotono = crate::loader::elf::load_elf(buf.as_slice()).unwrap();
 
     // We can *actually* start a user process now.
-    crate::process::thread::new_user_thread(kotono);
+    crate::process::thread::new_user_thread(kotono, crate::process::thread::SnProcessSetup {
+        parent_id: 0,
+        name: String::from(init.rsplit('/').next().unwrap_or(init)),
+        args: Vec::new(),
+        job: None,
+        files: None,
+    });
     #[cfg(test)]
     {
         printk!("tests has been enabled. running them now.");
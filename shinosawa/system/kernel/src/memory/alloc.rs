@@ -1,27 +1,226 @@
-// in src/allocator.rs
+use core::{alloc::{GlobalAlloc, Layout}, ptr};
 
+use crate::{hal::interface::interrupt::without_interrupts, printk};
 
-use crate::printk;
-
-use super::{linked_list::LinkedListAllocator, Locked, SnVirtAddr};
+use super::{
+    linked_list::LinkedListAllocator,
+    slab::{size_class, SnSlabCache, SnSlabStats, SLAB_CHUNK_ALIGN, SLAB_CHUNK_SIZE, SLAB_SIZES},
+    Locked, SnVirtAddr,
+};
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
-pub const HEAP_SIZE: usize = 10 * 1024 * 1024; // 10 KiB
+pub const HEAP_SIZE: usize = 10 * 1024 * 1024; // 10 MiB to start with
+pub const HEAP_MAX_SIZE: usize = 256 * 1024 * 1024; // 256 MiB
+/// The heap grows by at least this much at a time
+pub const HEAP_GROW_SIZE: usize = 1024 * 1024; // 1 MiB
 
 pub const ACPI_START: usize = 0x_4444_0000_0000;
 
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SnHeapStats {
+    /// Bytes of mapped heap memory
+    pub heap_size: usize,
+    /// Bytes handed out to callers, as requested
+    pub used_bytes: usize,
+    pub allocations: u64,
+    pub deallocations: u64,
+    pub failed_allocations: u64,
+    /// Number of times the heap was grown
+    pub grow_count: u64,
+    pub slabs: [SnSlabStats; SLAB_SIZES.len()],
+}
+
+/// The kernel heap: slab caches for small objects
+/// in front of a linked list allocator for everything else.
+pub struct SnKernelHeap {
+    list: LinkedListAllocator,
+    slabs: [SnSlabCache; SLAB_SIZES.len()],
+    heap_end: usize,
+    stats: SnHeapStats,
+}
+
+impl SnKernelHeap {
+    pub const fn new() -> Self {
+        SnKernelHeap {
+            list: LinkedListAllocator::new(),
+            slabs: [
+                SnSlabCache::new(SLAB_SIZES[0]),
+                SnSlabCache::new(SLAB_SIZES[1]),
+                SnSlabCache::new(SLAB_SIZES[2]),
+                SnSlabCache::new(SLAB_SIZES[3]),
+                SnSlabCache::new(SLAB_SIZES[4]),
+                SnSlabCache::new(SLAB_SIZES[5]),
+                SnSlabCache::new(SLAB_SIZES[6]),
+                SnSlabCache::new(SLAB_SIZES[7]),
+            ],
+            heap_end: HEAP_START,
+            stats: SnHeapStats {
+                heap_size: 0,
+                used_bytes: 0,
+                allocations: 0,
+                deallocations: 0,
+                failed_allocations: 0,
+                grow_count: 0,
+                slabs: [SnSlabStats { block_size: 0, total_blocks: 0, used_blocks: 0 }; SLAB_SIZES.len()],
+            },
+        }
+    }
+
+    /// Maps at least `min_size` more bytes at the end of the heap.
+    ///
+    /// Returns false if the heap cannot grow any further.
+    fn grow(&mut self, min_size: usize) -> bool {
+        let grow_size = min_size.max(HEAP_GROW_SIZE).next_multiple_of(4096);
+        if self.heap_end + grow_size > HEAP_START + HEAP_MAX_SIZE {
+            return false;
+        }
+
+        let start_addr = SnVirtAddr::new(self.heap_end as u64);
+        let end_addr = SnVirtAddr::new((self.heap_end + grow_size - 1) as u64);
+        if crate::hal::interface::paging::map_new_memory(start_addr, end_addr).is_err() {
+            return false;
+        }
+
+        unsafe { self.list.extend(self.heap_end, grow_size) };
+        self.heap_end += grow_size;
+        self.stats.heap_size += grow_size;
+        self.stats.grow_count += 1;
+
+        true
+    }
+
+    /// Allocates from the linked list, growing the heap if nothing fits
+    fn allocate_from_list(&mut self, layout: Layout) -> *mut u8 {
+        let ptr = self.list.allocate(layout);
+        if !ptr.is_null() {
+            return ptr;
+        }
+
+        // Worst case the alignment padding wastes almost a whole `align`
+        if self.grow(layout.size() + layout.align()) {
+            self.list.allocate(layout)
+        } else {
+            ptr::null_mut()
+        }
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let ptr = match size_class(&layout) {
+            Some(class) => {
+                if self.slabs[class].stats().used_blocks == self.slabs[class].stats().total_blocks {
+                    let chunk_layout =
+                        Layout::from_size_align(SLAB_CHUNK_SIZE, SLAB_CHUNK_ALIGN).unwrap();
+                    let chunk = self.allocate_from_list(chunk_layout);
+                    if !chunk.is_null() {
+                        unsafe { self.slabs[class].add_chunk(chunk, SLAB_CHUNK_SIZE) };
+                    }
+                }
+                self.slabs[class].allocate()
+            }
+            None => self.allocate_from_list(layout),
+        };
+
+        if ptr.is_null() {
+            self.stats.failed_allocations += 1;
+        } else {
+            self.stats.allocations += 1;
+            self.stats.used_bytes += layout.size();
+        }
+
+        ptr
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        match size_class(&layout) {
+            Some(class) => unsafe { self.slabs[class].deallocate(ptr) },
+            None => unsafe { self.list.deallocate(ptr, layout) },
+        }
+
+        self.stats.deallocations += 1;
+        self.stats.used_bytes -= layout.size();
+    }
+
+    fn stats(&self) -> SnHeapStats {
+        let mut stats = self.stats;
+        for (i, slab) in self.slabs.iter().enumerate() {
+            stats.slabs[i] = slab.stats();
+        }
+        stats
+    }
+}
+
+// Interrupt handlers allocate too, so an interrupt must not come while the
+// lock is held
+unsafe impl GlobalAlloc for Locked<SnKernelHeap> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        without_interrupts(|| self.lock().allocate(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        without_interrupts(|| unsafe { self.lock().deallocate(ptr, layout) })
+    }
+}
+
 #[global_allocator]
-static ALLOCATOR: Locked<LinkedListAllocator> =
-    Locked::new(LinkedListAllocator::new());
+static ALLOCATOR: Locked<SnKernelHeap> =
+    Locked::new(SnKernelHeap::new());
 
 /// Create heap for allocator
 pub fn init()  {
-    let start_addr = SnVirtAddr::new(HEAP_START as u64);
-    let end_addr = start_addr + HEAP_SIZE as u64 - 1u64;
+    if !without_interrupts(|| ALLOCATOR.lock().grow(HEAP_SIZE)) {
+        panic!("cannot map the kernel heap");
+    }
+}
+
+/// Returns a snapshot of the kernel heap statistics
+pub fn stats() -> SnHeapStats {
+    without_interrupts(|| ALLOCATOR.lock().stats())
+}
 
-    crate::hal::interface::paging::map_new_memory(start_addr, end_addr);
+/// Prints the kernel heap statistics, for debugging
+pub fn dump_stats() {
+    let stats = stats();
 
-    unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+    printk!(
+        "heap {} KiB, {} bytes in use, {} allocs, {} frees, {} failed, grown {} times",
+        stats.heap_size / 1024,
+        stats.used_bytes,
+        stats.allocations,
+        stats.deallocations,
+        stats.failed_allocations,
+        stats.grow_count
+    );
+    for slab in stats.slabs.iter() {
+        printk!(
+            "slab {:>4}: {}/{} blocks in use",
+            slab.block_size,
+            slab.used_blocks,
+            slab.total_blocks
+        );
     }
-}
\ No newline at end of file
+}
+
+#[test_case]
+fn test_heap_growth() {
+    use ::alloc::vec::Vec;
+
+    let before = stats();
+
+    // Bigger than the free heap space left, so the heap has to grow
+    let big: Vec<u8> = Vec::with_capacity(HEAP_SIZE + HEAP_GROW_SIZE);
+    assert!(stats().heap_size > before.heap_size);
+    drop(big);
+}
+
+#[test_case]
+fn test_slab_reuse() {
+    use ::alloc::boxed::Box;
+
+    let first = Box::new(0u64);
+    let first_addr = &*first as *const u64 as usize;
+    drop(first);
+
+    // The freed block is at the head of its cache's free list
+    let second = Box::new(0u64);
+    assert_eq!(&*second as *const u64 as usize, first_addr);
+}
@@ -0,0 +1,60 @@
+use crate::hal::interface::{interrupt::without_interrupts, paging};
+
+/// Memory usage returned by the meminfo syscall.
+///
+/// Shared with user space, so the layout must match sysface.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SnMemInfo {
+    /// Usable physical frames
+    pub total_frames: u64,
+    pub free_frames: u64,
+    /// Frames backing user pages, over all processes
+    pub user_frames: u64,
+    /// Frames holding user page tables, over all processes
+    pub page_table_frames: u64,
+
+    /// Bytes of mapped kernel heap
+    pub heap_size: u64,
+    /// Bytes of kernel heap handed out
+    pub heap_used: u64,
+    pub heap_allocations: u64,
+    pub heap_deallocations: u64,
+
+    /// User pages mapped by the calling process
+    pub process_resident_pages: u64,
+    /// Resident pages shared with another process after a fork
+    pub process_shared_pages: u64,
+    /// Page tables of the calling process
+    pub process_page_tables: u64,
+}
+
+/// Collects the memory counters, with per-process counts for an address space.
+///
+/// Pass 0 as `page_table_phys_addr` to leave the per-process counts empty.
+pub fn collect(page_table_phys_addr: u64) -> SnMemInfo {
+    let heap = super::alloc::stats();
+
+    without_interrupts(|| {
+        let frames = paging::frame_usage();
+        let process = if page_table_phys_addr != 0 {
+            paging::address_space_usage(page_table_phys_addr)
+        } else {
+            paging::SnAddressSpaceUsage::default()
+        };
+
+        SnMemInfo {
+            total_frames: frames.total_frames,
+            free_frames: frames.free_frames,
+            user_frames: frames.user_frames,
+            page_table_frames: frames.page_table_frames,
+            heap_size: heap.heap_size as u64,
+            heap_used: heap.used_bytes as u64,
+            heap_allocations: heap.allocations,
+            heap_deallocations: heap.deallocations,
+            process_resident_pages: process.resident_pages,
+            process_shared_pages: process.shared_pages,
+            process_page_tables: process.page_tables,
+        }
+    })
+}
@@ -138,18 +138,20 @@ fn align_up(addr: usize, align: usize) -> usize {
         addr - remainder + align
     }
 }
-unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+impl LinkedListAllocator {
+    /// Allocates a region fitting the given layout.
+    ///
+    /// Returns a null pointer if no free region is big enough.
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
         // perform layout adjustments
         let (size, align) = LinkedListAllocator::size_align(layout);
-        let mut allocator = self.lock();
 
-        if let Some((region, alloc_start)) = allocator.find_region(size, align) {
+        if let Some((region, alloc_start)) = self.find_region(size, align) {
             let alloc_end = alloc_start.checked_add(size).expect("overflow");
             let excess_size = region.end_addr() - alloc_end;
             if excess_size > 0 {
                 unsafe {
-                    allocator.add_free_region(alloc_end, excess_size);
+                    self.add_free_region(alloc_end, excess_size);
                 }
             }
             alloc_start as *mut u8
@@ -158,10 +160,32 @@ unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
         }
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    /// Returns a region allocated with `allocate` to the free list.
+    ///
+    /// This function is unsafe because the caller must guarantee that the region
+    /// was allocated from this allocator with the same layout.
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
         // perform layout adjustments
         let (size, _) = LinkedListAllocator::size_align(layout);
 
-        unsafe { self.lock().add_free_region(ptr as usize, size) }
+        unsafe { self.add_free_region(ptr as usize, size) }
+    }
+
+    /// Adds newly mapped memory to the heap.
+    ///
+    /// This function is unsafe because the caller must guarantee that the
+    /// memory is mapped and unused.
+    pub unsafe fn extend(&mut self, addr: usize, size: usize) {
+        unsafe { self.add_free_region(addr, size) }
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.lock().allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.lock().deallocate(ptr, layout) }
     }
 }
@@ -4,8 +4,16 @@ use core::{fmt::{self, Debug}, ops::{Add, Sub}};
 pub mod alloc;
 // Linked list allocator
 pub mod linked_list;
-
-pub const KERNEL_STACK_SIZE: u64 = 4096 * 2; // 8 KiB stack
+// Slab caches for small allocations
+pub mod slab;
+// Memory usage counters
+pub mod info;
+
+pub const KERNEL_STACK_SIZE: u64 = 4096 * 4; // 16 KiB stack
+/// Top of each kernel stack, where the timer interrupt runs. Syscalls run
+/// below it, so a tick while a syscall blocks leaves the syscall's frames
+/// alone; the timer handler and the scheduler must fit in here.
+pub const TIMER_STACK_SIZE: u64 = 4096 * 2;
 pub const USER_STACK_SIZE: u64 = 4096 * 512; // 2 MiB stack (one page empty for guard)
 pub const USER_HEAP_SIZE: u64 = 4096 * 1024; // 4 MiB stack (one page empty for guard)
 
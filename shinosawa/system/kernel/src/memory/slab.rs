@@ -0,0 +1,105 @@
+use core::{alloc::Layout, ptr};
+
+/// Block sizes of the slab caches. Allocations up to the largest size are
+/// served from the smallest cache that fits them.
+pub const SLAB_SIZES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// Size of the chunks carved into blocks when a cache runs empty
+pub const SLAB_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Alignment of the chunks. Since every block size divides it,
+/// each block is aligned to its own size.
+pub const SLAB_CHUNK_ALIGN: usize = 4096;
+
+struct FreeBlock {
+    next: Option<&'static mut FreeBlock>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SnSlabStats {
+    /// Size of each block in this cache
+    pub block_size: usize,
+    /// Number of blocks carved from chunks so far
+    pub total_blocks: usize,
+    /// Number of blocks handed out right now
+    pub used_blocks: usize,
+}
+
+/// A free list of fixed size blocks
+pub struct SnSlabCache {
+    block_size: usize,
+    free: Option<&'static mut FreeBlock>,
+    total_blocks: usize,
+    used_blocks: usize,
+}
+
+impl SnSlabCache {
+    pub const fn new(block_size: usize) -> Self {
+        SnSlabCache {
+            block_size,
+            free: None,
+            total_blocks: 0,
+            used_blocks: 0,
+        }
+    }
+
+    /// Takes a block from the free list, if there is any
+    pub fn allocate(&mut self) -> *mut u8 {
+        match self.free.take() {
+            Some(block) => {
+                self.free = block.next.take();
+                self.used_blocks += 1;
+                block as *mut FreeBlock as *mut u8
+            }
+            None => ptr::null_mut(),
+        }
+    }
+
+    /// Puts a block back on the free list.
+    ///
+    /// This function is unsafe because the caller must guarantee that the
+    /// block was allocated from this cache.
+    pub unsafe fn deallocate(&mut self, block: *mut u8) {
+        unsafe { self.push(block) };
+        self.used_blocks -= 1;
+    }
+
+    /// Carves a chunk of memory into blocks.
+    ///
+    /// This function is unsafe because the caller must guarantee that the
+    /// chunk is unused and aligned to the block size.
+    pub unsafe fn add_chunk(&mut self, chunk: *mut u8, size: usize) {
+        let block_size = self.block_size;
+        for i in 0..size / block_size {
+            unsafe { self.push(chunk.add(i * block_size)) };
+            self.total_blocks += 1;
+        }
+    }
+
+    unsafe fn push(&mut self, block: *mut u8) {
+        let block = block as *mut FreeBlock;
+        unsafe {
+            block.write(FreeBlock {
+                next: self.free.take(),
+            });
+            self.free = Some(&mut *block);
+        }
+    }
+
+    pub fn stats(&self) -> SnSlabStats {
+        SnSlabStats {
+            block_size: self.block_size,
+            total_blocks: self.total_blocks,
+            used_blocks: self.used_blocks,
+        }
+    }
+}
+
+// The free list only ever points into the kernel heap
+unsafe impl Send for SnSlabCache {}
+
+/// Returns the index of the smallest cache that can hold the layout
+pub fn size_class(layout: &Layout) -> Option<usize> {
+    let required = layout.size().max(layout.align());
+    SLAB_SIZES.iter().position(|&size| size >= required)
+}
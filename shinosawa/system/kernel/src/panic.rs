@@ -1,8 +1,7 @@
-use crate::printk;
-
 #[panic_handler]
 fn rust_panic(info: &core::panic::PanicInfo) -> ! {
-    printk!("PANIC: {}\n{:#?}", info.message().as_str().unwrap_or("See info below"), info);
+    log::error!("PANIC: {}\n{:#?}", info.message().as_str().unwrap_or("See info below"), info);
+    crate::debug::backtrace::print_current();
     use crate::hal::interface::instruct::hcf;
     hcf();
 }
@@ -0,0 +1,302 @@
+use alloc::{collections::vec_deque::VecDeque, sync::Arc, vec, vec::Vec};
+use spin::Mutex;
+
+use crate::{
+    drivers::tty::{self, SnTtyError, SnTtyId},
+    fs::vfs::{SnDirEntry, SnVfsError, SnVfsNodeRef, SnVfsType},
+};
+
+use super::thread;
+
+/// Open files a process can have
+pub const MAX_FILES: usize = 32;
+/// Bytes a pipe holds before writers wait
+const PIPE_SIZE: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnFileError {
+    /// No such file, or not open for this
+    Invalid = 1,
+    /// A signal was caught
+    Interrupted = 2,
+    Unsupported = 3,
+    /// Written to a pipe nobody reads
+    BrokenPipe = 4,
+}
+
+impl From<SnTtyError> for SnFileError {
+    fn from(err: SnTtyError) -> SnFileError {
+        match err {
+            SnTtyError::NotFound => SnFileError::Invalid,
+            SnTtyError::Interrupted => SnFileError::Interrupted,
+            SnTtyError::Unsupported => SnFileError::Unsupported,
+        }
+    }
+}
+
+impl From<SnVfsError> for SnFileError {
+    fn from(err: SnVfsError) -> SnFileError {
+        match err {
+            SnVfsError::ReadOnly => SnFileError::Unsupported,
+            SnVfsError::ReadError | SnVfsError::NotFound => SnFileError::Invalid,
+        }
+    }
+}
+
+struct SnPipeState {
+    buffer: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+}
+
+/// Bytes passed from one process to another
+struct SnPipe {
+    state: Mutex<SnPipeState>,
+}
+
+impl SnPipe {
+    /// Channel readers wait on, writers wait on the next one
+    fn channel(self: &Arc<Self>) -> u64 {
+        Arc::as_ptr(self) as u64
+    }
+}
+
+/// One side of a pipe, the pipe breaks when all of a side are dropped
+pub struct SnPipeEnd {
+    pipe: Arc<SnPipe>,
+    write: bool,
+}
+
+impl Drop for SnPipeEnd {
+    fn drop(&mut self) {
+        let mut state = self.pipe.state.lock();
+        if self.write {
+            state.writers -= 1;
+        } else {
+            state.readers -= 1;
+        }
+        drop(state);
+        // Readers see the end, writers the broken pipe
+        thread::wake(self.pipe.channel());
+        thread::wake(self.pipe.channel() + 1);
+    }
+}
+
+/// Creates a pipe, returns its read and write ends
+pub fn pipe() -> (SnPipeEnd, SnPipeEnd) {
+    let pipe = Arc::new(SnPipe {
+        state: Mutex::new(SnPipeState {
+            buffer: VecDeque::with_capacity(PIPE_SIZE),
+            readers: 1,
+            writers: 1,
+        }),
+    });
+    (SnPipeEnd { pipe: pipe.clone(), write: false }, SnPipeEnd { pipe, write: true })
+}
+
+/// A file or directory opened from the VFS
+pub struct SnNodeFile {
+    node: SnVfsNodeRef,
+    /// Contents when opened, for reading
+    contents: Option<Vec<u8>>,
+    /// Next byte to read, or next entry of a directory
+    offset: Mutex<usize>,
+    writable: bool,
+}
+
+impl SnNodeFile {
+    /// Opens a node, files opened for writing are only appended to
+    pub fn open(node: SnVfsNodeRef, writable: bool) -> Result<SnNodeFile, SnFileError> {
+        let contents = match node.node_type() {
+            SnVfsType::File if !writable => {
+                let mut contents = vec![0; node.len()];
+                let len = node.read(&mut contents)?;
+                contents.truncate(len);
+                Some(contents)
+            }
+            SnVfsType::Dir if writable => return Err(SnFileError::Unsupported),
+            _ => None,
+        };
+        Ok(SnNodeFile { node, contents, offset: Mutex::new(0), writable })
+    }
+}
+
+pub enum SnFile {
+    Tty(SnTtyId),
+    Pipe(SnPipeEnd),
+    Node(SnNodeFile),
+}
+
+impl SnFile {
+    /// Blocks until a read by process group `group_id` would not block.
+    ///
+    /// Must be called with interrupts disabled, which must stay so until the read.
+    pub fn wait_readable(&self, group_id: u64) -> Result<(), SnFileError> {
+        match self {
+            SnFile::Tty(id) => Ok(tty::wait_readable(*id, group_id)?),
+            SnFile::Pipe(end) if !end.write => loop {
+                {
+                    let state = end.pipe.state.lock();
+                    if !state.buffer.is_empty() || state.writers == 0 {
+                        return Ok(());
+                    }
+                }
+                if thread::caught_signal() {
+                    return Err(SnFileError::Interrupted);
+                }
+                thread::wait(end.pipe.channel());
+            },
+            SnFile::Node(file) if file.contents.is_some() => Ok(()),
+            _ => Err(SnFileError::Invalid),
+        }
+    }
+
+    /// Reads what is there without waiting, nothing at the end of a file
+    pub fn read(&self, out: &mut [u8]) -> Result<usize, SnFileError> {
+        match self {
+            SnFile::Tty(id) => Ok(tty::read(*id, out)?),
+            SnFile::Pipe(end) if !end.write => {
+                let mut state = end.pipe.state.lock();
+                let len = out.len().min(state.buffer.len());
+                for (byte, value) in out.iter_mut().zip(state.buffer.drain(..len)) {
+                    *byte = value;
+                }
+                drop(state);
+                thread::wake(end.pipe.channel() + 1);
+                Ok(len)
+            }
+            SnFile::Node(SnNodeFile { contents: Some(contents), offset, .. }) => {
+                let mut offset = offset.lock();
+                let rest = &contents[*offset..];
+                let len = out.len().min(rest.len());
+                out[..len].copy_from_slice(&rest[..len]);
+                *offset += len;
+                Ok(len)
+            }
+            _ => Err(SnFileError::Invalid),
+        }
+    }
+
+    /// Writes all of `bytes`, waiting for room in a pipe.
+    ///
+    /// Must be called with interrupts disabled. Returns fewer bytes than
+    /// given if a signal came in between.
+    pub fn write(&self, bytes: &[u8]) -> Result<usize, SnFileError> {
+        match self {
+            SnFile::Tty(id) => Ok(tty::write(*id, bytes)?),
+            SnFile::Pipe(end) if end.write => {
+                let mut written = 0;
+                loop {
+                    {
+                        let mut state = end.pipe.state.lock();
+                        if state.readers == 0 {
+                            return Err(SnFileError::BrokenPipe);
+                        }
+                        let len = (PIPE_SIZE - state.buffer.len()).min(bytes.len() - written);
+                        state.buffer.extend(&bytes[written..written + len]);
+                        written += len;
+                    }
+                    thread::wake(end.pipe.channel());
+                    if written == bytes.len() {
+                        return Ok(written);
+                    }
+                    if thread::caught_signal() {
+                        return if written > 0 { Ok(written) } else { Err(SnFileError::Interrupted) };
+                    }
+                    thread::wait(end.pipe.channel() + 1);
+                }
+            }
+            SnFile::Node(file) if file.writable => Ok(file.node.append(bytes)?),
+            _ => Err(SnFileError::Invalid),
+        }
+    }
+
+    /// Fills `entries` with the next entries of a directory, returns how many
+    pub fn read_dir(&self, entries: &mut [SnDirEntry]) -> Result<usize, SnFileError> {
+        let SnFile::Node(file) = self else {
+            return Err(SnFileError::Invalid);
+        };
+        if !file.node.is_dir() {
+            return Err(SnFileError::Invalid);
+        }
+
+        // Nodes only give out the first entries
+        let mut offset = file.offset.lock();
+        let mut all = vec![SnDirEntry { name: "", dir_type: SnVfsType::File }; *offset + entries.len()];
+        let count = file.node.read_dir(&mut all).saturating_sub(*offset);
+        entries[..count].copy_from_slice(&all[*offset..*offset + count]);
+        *offset += count;
+        Ok(count)
+    }
+}
+
+/// Open files of a process, by number
+#[derive(Clone)]
+pub struct SnFiles {
+    files: Vec<Option<Arc<SnFile>>>,
+}
+
+impl SnFiles {
+    pub fn new() -> SnFiles {
+        SnFiles { files: vec![None; MAX_FILES] }
+    }
+
+    /// Files with the tty open as input, output and error
+    pub fn with_tty(id: SnTtyId) -> SnFiles {
+        let mut files = SnFiles::new();
+        let tty = Arc::new(SnFile::Tty(id));
+        for fd in 0..3 {
+            files.files[fd] = Some(tty.clone());
+        }
+        files
+    }
+
+    pub fn get(&self, fd: u64) -> Option<Arc<SnFile>> {
+        self.files.get(fd as usize).cloned().flatten()
+    }
+
+    /// Puts a file at the lowest free number and returns it
+    pub fn insert(&mut self, file: Arc<SnFile>) -> Option<u64> {
+        let fd = self.files.iter().position(Option::is_none)?;
+        self.files[fd] = Some(file);
+        Some(fd as u64)
+    }
+
+    /// Puts a file at number `fd`, closing the one there
+    pub fn set(&mut self, fd: u64, file: Arc<SnFile>) -> bool {
+        match self.files.get_mut(fd as usize) {
+            Some(slot) => {
+                *slot = Some(file);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns false if `fd` was not open
+    pub fn close(&mut self, fd: u64) -> bool {
+        self.files.get_mut(fd as usize).and_then(Option::take).is_some()
+    }
+}
+
+#[test_case]
+fn test_pipe_files() {
+    let (reader, writer) = pipe();
+    let mut files = SnFiles::new();
+    assert_eq!(files.insert(Arc::new(SnFile::Pipe(reader))), Some(0));
+    assert_eq!(files.insert(Arc::new(SnFile::Pipe(writer))), Some(1));
+
+    let writer = files.get(1).unwrap();
+    assert_eq!(writer.write(b"hello").unwrap(), 5);
+    assert_eq!(writer.read(&mut [0; 4]).unwrap_err(), SnFileError::Invalid);
+
+    let reader = files.get(0).unwrap();
+    let mut buf = [0; 8];
+    assert_eq!(reader.read(&mut buf).unwrap(), 5);
+    assert_eq!(&buf[..5], b"hello");
+
+    drop(reader);
+    assert!(files.close(0));
+    assert!(!files.close(0));
+    assert_eq!(writer.write(b"lost").unwrap_err(), SnFileError::BrokenPipe);
+}
@@ -1,3 +1,7 @@
 pub mod thread;
 
-pub mod process;
\ No newline at end of file
+pub mod process;
+
+pub mod signal;
+
+pub mod file;
@@ -1,17 +1,130 @@
-use spin::RwLock;
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use spin::{Mutex, RwLock};
 use x86_64::structures::paging::page;
 
-use crate::{hal::x86_64::paging, printk};
+use crate::{
+    drivers::tty::SnTtyId,
+    hal::{interface::interrupt::SnFault, x86_64::paging},
+    loader::SnTlsTemplate,
+    printk,
+};
+
+use super::{
+    file::SnFiles,
+    signal::{SnSignal, SnSignals},
+};
+
+/// How a process ended
+#[derive(Clone, Copy, Debug)]
+pub enum SnExitStatus {
+    /// The last thread called exit
+    Exited(u64),
+    /// Killed because of a CPU exception in user mode
+    Faulted(SnFault),
+    /// Killed by a signal
+    Signaled(SnSignal),
+}
+
+/// Job control state of a process, inherited on fork
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SnJob {
+    /// Id of the process that started the session, zero for kernel threads
+    pub session_id: u64,
+    /// Process group, signalled together from the terminal
+    pub group_id: u64,
+    /// Controlling terminal, read from and written to
+    pub tty: Option<SnTtyId>,
+}
+
+/// A process, as listed by the process list syscall
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SnProcessInfo {
+    pub id: u64,
+    pub parent_id: u64,
+    pub group_id: u64,
+    pub session_id: u64,
+    pub threads: u32,
+    /// One of the `PROCESS_` states
+    pub state: u32,
+    /// Start of the name, padded with zeroes
+    pub name: [u8; 32],
+}
+
+pub const PROCESS_RUNNING: u32 = 0;
+/// Every thread is waiting
+pub const PROCESS_SLEEPING: u32 = 1;
+pub const PROCESS_STOPPED: u32 = 2;
+
+/// Exit status of processes that ended, keyed by process id,
+/// with the id of the parent process.
+static EXIT_STATUSES: Mutex<BTreeMap<u64, (u64, SnExitStatus)>> = Mutex::new(BTreeMap::new());
 
 pub struct Process {
     pub id: u64,
+    /// Process that created this one, zero if the kernel did
+    pub parent_id: u64,
     pub page_table_phys_addr: u64,
+    /// Thread-local storage image copied into every new thread
+    pub tls_template: Option<SnTlsTemplate>,
+    /// Set when the process is killed, otherwise it exited normally
+    pub exit_status: Mutex<Option<SnExitStatus>>,
+    pub job: Mutex<SnJob>,
+    pub signals: SnSignals,
+    /// Name of the executable
+    pub name: String,
+    /// Arguments given when spawned, separated by NUL bytes
+    pub args: Vec<u8>,
+    pub files: Mutex<SnFiles>,
 }
 impl Drop for Process {
     fn drop(&mut self) {
-        printk!("process::process: dropping process {}", self.id);
+        printk!("dropping process {}", self.id);
         if self.page_table_phys_addr != 0 {
+            let usage = paging::address_space_usage(self.page_table_phys_addr);
+            let free_before = paging::frame_usage().free_frames;
+
             paging::free_user_pagetables(self.page_table_phys_addr);
+
+            // Everything but the shared pages should come back
+            let freed = paging::frame_usage().free_frames - free_before;
+            let expected = usage.resident_pages - usage.shared_pages + usage.page_tables;
+            printk!(
+                "process {} had {} pages ({} shared) and {} page tables, freed {} of {} frames",
+                self.id,
+                usage.resident_pages,
+                usage.shared_pages,
+                usage.page_tables,
+                freed,
+                expected
+            );
+        }
+
+        crate::fb::device::release(self.id, false);
+
+        let status = self.exit_status.lock().take().unwrap_or(SnExitStatus::Exited(0));
+        // Nobody waits for what the kernel started
+        if self.parent_id != 0 {
+            EXIT_STATUSES.lock().insert(self.id, (self.parent_id, status));
+            crate::process::thread::wake(exit_channel());
         }
     }
 }
+
+/// Channel parents wait on for their children to end or stop
+pub fn exit_channel() -> u64 {
+    &EXIT_STATUSES as *const _ as u64
+}
+
+/// Takes the exit status of child process `id` of `parent_id` that ended, or
+/// of any of its children for None.
+///
+/// Returns the id of the process with the status, None if none ended.
+pub fn take_exit_status(parent_id: u64, id: Option<u64>) -> Option<(u64, SnExitStatus)> {
+    let mut exit_statuses = EXIT_STATUSES.lock();
+    let id = exit_statuses
+        .iter()
+        .find(|(child, (parent, _))| *parent == parent_id && id.is_none_or(|id| id == **child))
+        .map(|(child, _)| *child)?;
+    exit_statuses.remove(&id).map(|(_, status)| (id, status))
+}
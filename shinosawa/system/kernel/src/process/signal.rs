@@ -0,0 +1,194 @@
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use spin::Mutex;
+
+/// Signals a process can get, numbered as on Linux
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnSignal {
+    /// Ctrl-C
+    Interrupt = 2,
+    /// Cannot be caught or ignored
+    Kill = 9,
+    Terminate = 15,
+    /// Resumes a stopped process
+    Continue = 18,
+    /// Ctrl-Z
+    TerminalStop = 20,
+}
+
+impl TryFrom<u64> for SnSignal {
+    type Error = ();
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        match value {
+            2 => Ok(SnSignal::Interrupt),
+            9 => Ok(SnSignal::Kill),
+            15 => Ok(SnSignal::Terminate),
+            18 => Ok(SnSignal::Continue),
+            20 => Ok(SnSignal::TerminalStop),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnSignalAction {
+    /// Continue resumes, TerminalStop stops, the others kill
+    Default = 0,
+    Ignore = 1,
+    /// Kept pending until taken, blocking tty calls fail meanwhile
+    Catch = 2,
+}
+
+impl TryFrom<u64> for SnSignalAction {
+    type Error = ();
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(SnSignalAction::Default),
+            1 => Ok(SnSignalAction::Ignore),
+            2 => Ok(SnSignalAction::Catch),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Who a signal is sent to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnSignalTarget {
+    Process(u64),
+    Group(u64),
+}
+
+/// What delivering a signal did to a process
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnSignalEffect {
+    None,
+    Caught,
+    Stopped,
+    Continued,
+    Killed,
+}
+
+/// Signal numbers are below this
+const SIGNAL_SLOTS: usize = 32;
+
+/// Signal state of a process.
+///
+/// The flags are atomics as the scheduler and interrupt handlers read them.
+pub struct SnSignals {
+    actions: Mutex<[SnSignalAction; SIGNAL_SLOTS]>,
+    /// Bits of caught signals that were not taken yet
+    caught: AtomicU32,
+    stopped: AtomicBool,
+    /// Set when stopped, until the parent learns of it
+    stop_report: AtomicBool,
+    /// Set once, the threads exit as they get scheduled
+    killed: AtomicBool,
+}
+
+impl SnSignals {
+    pub fn new() -> SnSignals {
+        SnSignals {
+            actions: Mutex::new([SnSignalAction::Default; SIGNAL_SLOTS]),
+            caught: AtomicU32::new(0),
+            stopped: AtomicBool::new(false),
+            stop_report: AtomicBool::new(false),
+            killed: AtomicBool::new(false),
+        }
+    }
+
+    /// Signal state of a forked process: the same actions, nothing pending
+    pub fn inherit(&self) -> SnSignals {
+        SnSignals {
+            actions: Mutex::new(*self.actions.lock()),
+            ..SnSignals::new()
+        }
+    }
+
+    /// Returns false for Kill, which always kills
+    pub fn set_action(&self, signal: SnSignal, action: SnSignalAction) -> bool {
+        if signal == SnSignal::Kill {
+            return false;
+        }
+        self.actions.lock()[signal as usize] = action;
+        true
+    }
+
+    pub fn deliver(&self, signal: SnSignal) -> SnSignalEffect {
+        if self.killed() {
+            return SnSignalEffect::None;
+        }
+
+        let action = self.actions.lock()[signal as usize];
+        match (signal, action) {
+            (SnSignal::Kill, _) | (SnSignal::Interrupt | SnSignal::Terminate, SnSignalAction::Default) => {
+                self.stopped.store(false, Ordering::Relaxed);
+                self.killed.store(true, Ordering::Relaxed);
+                SnSignalEffect::Killed
+            }
+            (SnSignal::Continue, _) => {
+                if action == SnSignalAction::Catch {
+                    self.caught.fetch_or(1 << signal as u32, Ordering::Relaxed);
+                }
+                self.stopped.store(false, Ordering::Relaxed);
+                self.stop_report.store(false, Ordering::Relaxed);
+                SnSignalEffect::Continued
+            }
+            (SnSignal::TerminalStop, SnSignalAction::Default) => {
+                self.stopped.store(true, Ordering::Relaxed);
+                self.stop_report.store(true, Ordering::Relaxed);
+                SnSignalEffect::Stopped
+            }
+            (_, SnSignalAction::Catch) => {
+                self.caught.fetch_or(1 << signal as u32, Ordering::Relaxed);
+                SnSignalEffect::Caught
+            }
+            _ => SnSignalEffect::None,
+        }
+    }
+
+    /// Whether a caught signal is waiting to be taken
+    pub fn has_caught(&self) -> bool {
+        self.caught.load(Ordering::Relaxed) != 0
+    }
+
+    /// Returns the bits of the caught signals, by signal number, and clears them
+    pub fn take_caught(&self) -> u32 {
+        self.caught.swap(0, Ordering::Relaxed)
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    /// Whether the process stopped since this was last called
+    pub fn take_stop_report(&self) -> bool {
+        self.stopped() && self.stop_report.swap(false, Ordering::Relaxed)
+    }
+
+    pub fn killed(&self) -> bool {
+        self.killed.load(Ordering::Relaxed)
+    }
+}
+
+#[test_case]
+fn test_signal_delivery() {
+    let signals = SnSignals::new();
+    assert_eq!(signals.deliver(SnSignal::TerminalStop), SnSignalEffect::Stopped);
+    assert!(signals.stopped());
+    assert!(signals.take_stop_report());
+    assert!(!signals.take_stop_report());
+    assert_eq!(signals.deliver(SnSignal::Continue), SnSignalEffect::Continued);
+    assert!(!signals.stopped());
+
+    assert!(signals.set_action(SnSignal::Interrupt, SnSignalAction::Catch));
+    assert!(!signals.set_action(SnSignal::Kill, SnSignalAction::Ignore));
+    let child = signals.inherit();
+    assert_eq!(child.deliver(SnSignal::Interrupt), SnSignalEffect::Caught);
+    assert_eq!(child.take_caught(), 1 << SnSignal::Interrupt as u32);
+    assert!(!child.has_caught());
+
+    assert_eq!(signals.deliver(SnSignal::Terminate), SnSignalEffect::Killed);
+    assert_eq!(signals.deliver(SnSignal::Kill), SnSignalEffect::None);
+}
@@ -1,27 +1,33 @@
 extern crate alloc;
 
 use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
 
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
 use alloc::{boxed::Box, collections::vec_deque::VecDeque};
 use conquer_once::spin::OnceCell;
-use spin::rwlock::RwLock;
+use spin::{Mutex, rwlock::RwLock};
 
 use crate::hal::interface::cpu::SnCpuContext;
 use crate::memory::{KERNEL_STACK_SIZE, USER_STACK_SIZE};
 use crate::{
     hal::interface::{
-        interrupt::{INTERRUPT_CONTEXT_SIZE, InterruptStackIndex, SCHEDULE},
+        clock, cpu,
+        interrupt::{INTERRUPT_CONTEXT_SIZE, InterruptStackIndex, SCHEDULE, SnFault, USER_FAULT},
         paging,
     },
-    loader::SnExecutable,
+    loader::{SnExecutable, SnTlsTemplate},
     memory::{SnPhysAddr, SnVirtAddr},
     printk,
 };
 
-use super::process::Process;
+use super::file::SnFiles;
+use super::process::{self, Process, SnExitStatus, SnJob, SnProcessInfo, PROCESS_RUNNING, PROCESS_SLEEPING, PROCESS_STOPPED};
+use super::signal::{SnSignal, SnSignalEffect, SnSignalTarget, SnSignals};
 
 // Allocate pages for the user stack
 const USER_STACK_START: u64 = 0x5002000;
@@ -30,15 +36,34 @@ const USER_STACK_START: u64 = 0x5002000;
 pub const USER_CODE_START: u64 = 0x20_0000;
 /// Exclusive upper limit for user code or data
 pub const USER_CODE_END: u64 = 0x5000_0000;
+/// Where the framebuffer is mapped into the process that owns the display
+pub const USER_FRAMEBUFFER_START: u64 = 0x0000_0600_0000_0000;
+/// Exclusive upper limit of the user half of the address space
+pub const USER_SPACE_END: u64 = 0x0000_8000_0000_0000;
 
 static RUNNING_QUEUE: OnceCell<RwLock<VecDeque<Box<Thread>>>> =
     OnceCell::new(RwLock::new(VecDeque::new()));
 
 static CURRENT_THREAD: RwLock<Option<Box<Thread>>> = RwLock::new(None);
 
+/// Threads blocked in `wait`, off the running queue until woken
+static WAITING_THREADS: RwLock<Vec<Box<Thread>>> = RwLock::new(Vec::new());
+
+/// Kernel stacks of exited threads. A thread is still on its stack when it
+/// exits, so the stack is freed on a later context switch.
+static DEAD_KERNEL_STACKS: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+
+/// Runs when nothing else can, kept off the running queue
+static IDLE_THREAD: RwLock<Option<Box<Thread>>> = RwLock::new(None);
+static IDLE_THREAD_ID: AtomicU64 = AtomicU64::new(0);
+
 static THREAD_COUNTER: OnceCell<RwLock<u64>> = OnceCell::new(RwLock::new(0));
 static PROCESS_COUNTER: OnceCell<RwLock<u64>> = OnceCell::new(RwLock::new(0));
 
+/// Channel of sleeping threads, woken on every timer tick to check the time.
+/// Never the address of anything.
+const SLEEP_CHANNEL: u64 = u64::MAX;
+
 struct Thread {
     id: u64,
     process: Arc<Process>,
@@ -46,6 +71,9 @@ struct Thread {
     kernel_stack_end: u64, // This address goes in the TSS
     user_stack_end: u64,
     context: u64, // Address of Context on kernel stack
+    fs_base: u64, // Thread pointer, restored on context switch
+    waiting_on: Option<u64>, // Channel the thread is blocked on
+    exiting: bool, // Sent to exit_killed_thread
 
     page_table_addr: u64,
 }
@@ -56,6 +84,48 @@ impl Drop for Thread {
     }
 }
 
+/// A thread, as seen by the debugger
+#[derive(Clone, Copy, Debug)]
+pub struct SnThreadInfo {
+    pub id: u64,
+    pub process_id: u64,
+    /// Kernel threads share the kernel page table
+    pub kernel: bool,
+    /// Address of the saved registers, stale for the running thread
+    pub context: u64,
+    pub running: bool,
+}
+
+/// Calls `func` for the running thread, then for every thread waiting to run.
+///
+/// This neither allocates nor waits for locks, so exception handlers can use it.
+/// Threads behind a lock that is held are skipped.
+pub fn for_each_thread<F>(mut func: F) where F: FnMut(SnThreadInfo) {
+    let info = |thread: &Thread, running: bool| SnThreadInfo {
+        id: thread.id,
+        process_id: thread.process.id,
+        kernel: thread.process.page_table_phys_addr == 0,
+        context: thread.context,
+        running,
+    };
+
+    if let Some(current_thread) = CURRENT_THREAD.try_read() {
+        if let Some(thread) = current_thread.as_ref() {
+            func(info(thread, true));
+        }
+    }
+    if let Some(running_queue) = RUNNING_QUEUE.get().and_then(|queue| queue.try_read()) {
+        for thread in running_queue.iter() {
+            func(info(thread, false));
+        }
+    }
+    if let Some(waiting_threads) = WAITING_THREADS.try_read() {
+        for thread in waiting_threads.iter() {
+            func(info(thread, false));
+        }
+    }
+}
+
 pub fn new_thread_id() -> u64 {
     crate::hal::interface::interrupt::without_interrupts(|| {
         let mut counter = THREAD_COUNTER.get().unwrap().write();
@@ -72,8 +142,62 @@ pub fn new_process_id() -> u64 {
     })
 }
 
-pub fn new_kernel_thread(function: fn() -> ()) {
-    printk!("process: spawning new kernel thread {:x}", function as u64);
+/// Places the TLS block and the thread pointer below `user_stack_end`.
+///
+/// The linker finds thread-local variables at `-round_up(mem_size, align)`
+/// from the thread pointer, so only the thread pointer gets the extra
+/// 16-byte alignment of the ABI.
+///
+/// Returns the thread pointer and the start of the TLS block.
+fn thread_local_layout(template: &SnTlsTemplate, user_stack_end: u64) -> (u64, u64) {
+    let align = template.align.max(1);
+    let block_size = template.mem_size.div_ceil(align) * align;
+
+    // Leave room for the self pointer at the thread pointer
+    let thread_pointer = (user_stack_end - 16) & !(align.max(16) - 1);
+    (thread_pointer, thread_pointer - block_size)
+}
+
+/// Copies a TLS template below the end of a new user stack.
+///
+/// This follows the x86_64 variant II layout: the TLS block sits right
+/// below the thread pointer, and the thread pointer points to a word
+/// containing its own address.
+/// Must be called with the page table of the thread's process active.
+///
+/// Returns the thread pointer and the new end of the user stack.
+fn setup_thread_local_storage(template: &SnTlsTemplate, user_stack_end: u64) -> (u64, u64) {
+    let (thread_pointer, block_start) = thread_local_layout(template, user_stack_end);
+    let block_size = thread_pointer - block_start;
+
+    let mut page = block_start & !0xfff;
+    while page < thread_pointer + 8 {
+        assert!(
+            paging::prepare_user_page_for_write(SnVirtAddr::new(page)),
+            "cannot map thread-local storage"
+        );
+        page += 4096;
+    }
+
+    unsafe {
+        let block = block_start as *mut u8;
+        core::ptr::copy_nonoverlapping(
+            template.start.as_ptr::<u8>(),
+            block,
+            template.file_size as usize,
+        );
+        core::ptr::write_bytes(
+            block.add(template.file_size as usize),
+            0,
+            (block_size - template.file_size) as usize,
+        );
+        *(thread_pointer as *mut u64) = thread_pointer;
+    }
+
+    (thread_pointer, block_start & !0xf)
+}
+
+fn kernel_thread(function: fn() -> ()) -> Box<Thread> {
     let new_thread = {
         let thread_id = new_thread_id();
         let kernel_stack =
@@ -88,12 +212,23 @@ pub fn new_kernel_thread(function: fn() -> ()) {
             id: thread_id,
             process: Arc::new(Process {
                 id: new_process_id(),
+                parent_id: 0,
                 page_table_phys_addr: 0,
+                tls_template: None,
+                exit_status: Mutex::new(None),
+                job: Mutex::new(SnJob { session_id: 0, group_id: 0, tty: None }),
+                signals: SnSignals::new(),
+                name: String::from("kernel"),
+                args: Vec::new(),
+                files: Mutex::new(SnFiles::new()),
             }),
             kernel_stack,
             kernel_stack_end,
             user_stack_end,
             context,
+            fs_base: 0,
+            waiting_on: None,
+            exiting: false,
             page_table_addr: 0,
         })
     };
@@ -107,19 +242,41 @@ pub fn new_kernel_thread(function: fn() -> ()) {
         )
     };
 
+    new_thread
+}
+
+pub fn new_kernel_thread(function: fn() -> ()) {
+    printk!("spawning new kernel thread {:x}", function as u64);
+    let new_thread = kernel_thread(function);
+
     crate::hal::interface::interrupt::without_interrupts(|| {
         RUNNING_QUEUE.get().unwrap().write().push_back(new_thread);
     });
 }
 
-pub fn new_user_thread<T: SnExecutable>(executable: T) {
+/// What a new user process starts with
+pub struct SnProcessSetup {
+    /// Zero if the kernel starts the process
+    pub parent_id: u64,
+    pub name: String,
+    pub args: Vec<u8>,
+    /// Job of the parent, with a group id of zero for a new group led by
+    /// the process. None leads a new session on the boot tty.
+    pub job: Option<SnJob>,
+    /// None opens the boot tty as input, output and error
+    pub files: Option<SnFiles>,
+}
+
+/// Starts a process running `executable`, returns its id
+pub fn new_user_thread<T: SnExecutable>(executable: T, setup: SnProcessSetup) -> u64 {
     printk!(
-        "process: spawning new user thread {:x}",
+        "spawning new user thread {:x}",
         executable.entry_point().as_u64()
     );
 
-    let (user_stack, user_stack_end) = paging::get_user_thread_stack(executable.page_table_phys().as_u64()).unwrap();
+    let (user_stack, mut user_stack_end) = paging::get_user_thread_stack(executable.page_table_phys().as_u64()).unwrap();
     let (user_heap, user_heap_end) = paging::get_user_heap(executable.page_table_phys().as_u64()).unwrap();
+    let mut fs_base = 0;
 
     let new_thread = {
         let thread_id = new_thread_id();
@@ -139,20 +296,49 @@ pub fn new_user_thread<T: SnExecutable>(executable: T) {
                     SnVirtAddr::new(user_heap),
                     SnVirtAddr::new(user_heap_end),
                 );
+
+                if let Some(template) = executable.tls_template() {
+                    (fs_base, user_stack_end) =
+                        setup_thread_local_storage(&template, user_stack_end);
+                }
             })
         });
 
+        let process_id = new_process_id();
+        let tty = crate::cmdline::options().tty;
+        let job = match setup.job {
+            Some(job) if job.group_id == 0 => SnJob { group_id: process_id, ..job },
+            Some(job) => job,
+            None => {
+                if let Err(err) = crate::drivers::tty::attach(tty, process_id, process_id) {
+                    log::warn!("cannot attach {:?} to process {}: {:?}", tty, process_id, err);
+                }
+                SnJob { session_id: process_id, group_id: process_id, tty: Some(tty) }
+            }
+        };
+
         Box::new(Thread {
             id: new_thread_id(),
             process: Arc::new(Process {
-                id: new_process_id(),
+                id: process_id,
+                parent_id: setup.parent_id,
                 page_table_phys_addr: executable.page_table_phys().as_u64(),
+                tls_template: executable.tls_template(),
+                exit_status: Mutex::new(None),
+                job: Mutex::new(job),
+                signals: SnSignals::new(),
+                name: setup.name,
+                args: setup.args,
+                files: Mutex::new(setup.files.unwrap_or_else(|| SnFiles::with_tty(tty))),
             }
             ),
             kernel_stack,
             kernel_stack_end,
             user_stack_end,
             context,
+            fs_base,
+            waiting_on: None,
+            exiting: false,
             page_table_addr: executable.page_table_phys().as_u64(),
         })
     };
@@ -171,9 +357,11 @@ pub fn new_user_thread<T: SnExecutable>(executable: T) {
         context.set_heap_addrs(user_heap as usize, user_heap_end as usize);
     };
 
+    let process_id = new_thread.process.id;
     crate::hal::interface::interrupt::without_interrupts(|| {
         RUNNING_QUEUE.get().unwrap().write().push_back(new_thread);
     });
+    process_id
 }
 
 /// Adds a thread to the front of the running queue
@@ -185,9 +373,87 @@ pub fn schedule_thread(thread: Box<Thread>) {
     });
 }
 
+/// Channel the threads of a stopped process wait on
+fn stop_channel(process: &Arc<Process>) -> u64 {
+    Arc::as_ptr(process) as u64
+}
+
+/// Takes the next thread to run off the queue, parking the threads of
+/// stopped processes and sending those of killed processes to exit
+fn next_thread(running_queue: &mut VecDeque<Box<Thread>>) -> Option<Box<Thread>> {
+    while let Some(mut thread) = running_queue.pop_front() {
+        // Threads waiting in a syscall give up and get here from user mode,
+        // dropping what they hold
+        let in_user_mode = unsafe { (*(thread.context as *const SnCpuContext)).in_user_mode() };
+        if thread.process.signals.killed() {
+            if !thread.exiting && in_user_mode {
+                thread.exiting = true;
+                // Runs in kernel mode below the saved registers, whatever
+                // the thread was doing is dropped
+                unsafe {
+                    cpu::set_context(thread.context, exit_killed_thread as fn() -> ! as u64, thread.context & !0xf, false);
+                }
+            }
+            return Some(thread);
+        }
+        if thread.process.signals.stopped() {
+            thread.waiting_on = Some(stop_channel(&thread.process));
+            WAITING_THREADS.write().push(thread);
+            continue;
+        }
+        return Some(thread);
+    }
+    None
+}
+
+/// Makes the sleeping threads run again, to see whether they slept enough
+fn wake_sleeping(running_queue: &mut VecDeque<Box<Thread>>, current_thread: &mut Option<Box<Thread>>) {
+    if let Some(thread) = current_thread.as_mut().filter(|thread| thread.waiting_on == Some(SLEEP_CHANNEL)) {
+        thread.waiting_on = None;
+    }
+
+    // Tried again on the next tick if the timer hit while it was read
+    let Some(mut waiting_threads) = WAITING_THREADS.try_write() else {
+        return;
+    };
+    let mut index = 0;
+    while index < waiting_threads.len() {
+        if waiting_threads[index].waiting_on == Some(SLEEP_CHANNEL) {
+            let mut thread = waiting_threads.swap_remove(index);
+            thread.waiting_on = None;
+            running_queue.push_back(thread);
+        } else {
+            index += 1;
+        }
+    }
+}
+
+/// Drops the thread that is running, keeping its kernel stack for
+/// `free_dead_kernel_stacks`
+fn drop_running_thread(mut thread: Box<Thread>) {
+    DEAD_KERNEL_STACKS.lock().push(core::mem::take(&mut thread.kernel_stack));
+    drop(thread);
+}
+
+/// Frees the kernel stacks of exited threads, but the one holding
+/// `context_addr`, which the timer handler still runs on
+fn free_dead_kernel_stacks(context_addr: usize) {
+    if let Some(mut stacks) = DEAD_KERNEL_STACKS.try_lock() {
+        stacks.retain(|stack| {
+            let start = stack.as_ptr() as usize;
+            (start..start + stack.capacity()).contains(&context_addr)
+        });
+    }
+}
+
 fn schedule_next(context_addr: usize) -> usize {
+    free_dead_kernel_stacks(context_addr);
     let mut running_queue = RUNNING_QUEUE.get().unwrap().write();
     let mut current_thread = CURRENT_THREAD.write();
+    wake_sleeping(&mut running_queue, &mut current_thread);
+    // Otherwise the kernel is still starting up or the thread exited, and
+    // carries on from where the timer hit
+    let switching = current_thread.is_some();
 
     if let Some(mut thread) = current_thread.take() {
         // // Save the location of the Context struct
@@ -196,12 +462,25 @@ fn schedule_next(context_addr: usize) -> usize {
         // switching during functions which manipulate page tables
         // for example new_user_thread
         thread.page_table_addr = crate::hal::interface::paging::get_current_page_table_phys_addr();
-
-        // Put to the back of the queue
-        running_queue.push_back(thread);
+        thread.fs_base = cpu::thread_pointer();
+
+        if thread.id == IDLE_THREAD_ID.load(Ordering::Relaxed) {
+            *IDLE_THREAD.write() = Some(thread);
+        } else if thread.waiting_on.is_none() {
+            // Put to the back of the queue
+            running_queue.push_back(thread);
+        } else if running_queue.is_empty() {
+            // Nothing else to run, keep halting in wait
+            *current_thread = Some(thread);
+            return 0;
+        } else {
+            WAITING_THREADS.write().push(thread);
+        }
     }
 
-    *current_thread = running_queue.pop_front();
+    *current_thread = next_thread(&mut running_queue).or_else(|| {
+        if switching { IDLE_THREAD.write().take() } else { None }
+    });
     match current_thread.as_ref() {
         Some(thread) => {
             // Set the kernel stack for the next interrupt
@@ -215,6 +494,7 @@ fn schedule_next(context_addr: usize) -> usize {
                 // Note: zero for kernel thread
                 paging::switch_page_table(SnPhysAddr::new(thread.page_table_addr));
             }
+            cpu::set_thread_pointer(thread.fs_base);
 
             // Point the stack to the new context
             thread.context as usize
@@ -223,10 +503,12 @@ fn schedule_next(context_addr: usize) -> usize {
     }
 }
 
-pub fn fork_current_thread(current_context: &mut SnCpuContext) {
+/// Creates a new thread in the current process, which continues
+/// from the same instruction with its own stack.
+pub fn new_thread_in_current_process(current_context: &mut SnCpuContext) {
     if let Some(current_thread) = CURRENT_THREAD.read().as_ref() {
         printk!(
-            "process: forking thread {:x}",
+            "creating thread {:x}",
             current_context.instruction_pointer(),
         );
 
@@ -242,7 +524,8 @@ pub fn fork_current_thread(current_context: &mut SnCpuContext) {
             let context = kernel_stack_end - INTERRUPT_CONTEXT_SIZE as u64;
             // The 4096 (1 page) offset is a guard page
             
-        let (user_stack, user_stack_end) = paging::get_user_thread_stack(page_table_phys_addr).unwrap();
+        let (user_stack, mut user_stack_end) = paging::get_user_thread_stack(page_table_phys_addr).unwrap();
+            let mut fs_base = 0;
 
             crate::hal::interface::interrupt::without_interrupts(|| {
                 crate::hal::interface::paging::with_page_table(
@@ -252,6 +535,11 @@ pub fn fork_current_thread(current_context: &mut SnCpuContext) {
                             SnVirtAddr::new(user_stack),
                             SnVirtAddr::new(user_stack_end),
                         );
+
+                        if let Some(template) = &current_thread.process.tls_template {
+                            (fs_base, user_stack_end) =
+                                setup_thread_local_storage(template, user_stack_end);
+                        }
                     },
                 )
             });
@@ -263,6 +551,9 @@ pub fn fork_current_thread(current_context: &mut SnCpuContext) {
                 kernel_stack_end,
                 user_stack_end,
                 context,
+                fs_base,
+                waiting_on: None,
+            exiting: false,
                 page_table_addr: page_table_phys_addr,
             })
         };
@@ -278,6 +569,7 @@ pub fn fork_current_thread(current_context: &mut SnCpuContext) {
 
         let new_context = unsafe { &mut *(new_thread.context as *mut SnCpuContext) };
         *new_context = current_context.clone(); // Copy of caller
+        new_context.set_stack_pointer(new_thread.user_stack_end as usize); // But on its own stack
 
         new_context.set_ret_val_1(0); // No error
         new_context.set_arg_val_1(0); // Indicates that this is the new thread
@@ -292,14 +584,361 @@ pub fn fork_current_thread(current_context: &mut SnCpuContext) {
     }
 }
 
-pub fn exit_current_thread(_current_context: &mut SnCpuContext) {
+/// Forks the current process. The new process gets a copy-on-write
+/// duplicate of the address space and a copy of the calling thread.
+pub fn fork_current_process(current_context: &mut SnCpuContext) {
+    if let Some(current_thread) = CURRENT_THREAD.read().as_ref() {
+        let page_table_phys_addr =
+            crate::hal::interface::paging::get_current_page_table_phys_addr();
+
+        if current_thread.process.page_table_phys_addr == 0 {
+            // Kernel threads share the kernel address space
+            current_context.set_ret_val_1(1);
+            return;
+        }
+
+        printk!("forking process {}", current_thread.process.id);
+
+        let (_, new_page_table_phys_addr) = paging::fork_user_pagetable(page_table_phys_addr);
+
+        let process = Arc::new(Process {
+            id: new_process_id(),
+            parent_id: current_thread.process.id,
+            page_table_phys_addr: new_page_table_phys_addr.as_u64(),
+            tls_template: current_thread.process.tls_template,
+            exit_status: Mutex::new(None),
+            job: Mutex::new(*current_thread.process.job.lock()),
+            signals: current_thread.process.signals.inherit(),
+            name: current_thread.process.name.clone(),
+            args: current_thread.process.args.clone(),
+            files: Mutex::new(current_thread.process.files.lock().clone()),
+        });
+
+        let new_thread = {
+            let kernel_stack = Vec::with_capacity(KERNEL_STACK_SIZE as usize);
+            let kernel_stack_end =
+                (SnVirtAddr::from_ptr(kernel_stack.as_ptr()) + KERNEL_STACK_SIZE).as_u64();
+
+            let context = kernel_stack_end - INTERRUPT_CONTEXT_SIZE as u64;
+
+            Box::new(Thread {
+                id: new_thread_id(),
+                process: process.clone(),
+                kernel_stack,
+                kernel_stack_end,
+                // Same stack address, the contents are copied on write
+                user_stack_end: current_thread.user_stack_end,
+                context,
+                // The TLS block is copied along with the address space
+                fs_base: cpu::thread_pointer(),
+                waiting_on: None,
+            exiting: false,
+                page_table_addr: new_page_table_phys_addr.as_u64(),
+            })
+        };
+
+        let new_context = unsafe { &mut *(new_thread.context as *mut SnCpuContext) };
+        *new_context = current_context.clone(); // Copy of caller
+
+        new_context.set_ret_val_1(0); // No error
+        new_context.set_arg_val_1(0); // Indicates that this is the new process
+        current_context.set_ret_val_1(0); // Also success
+        current_context.set_arg_val_1(process.id as usize);
+
+        crate::hal::interface::interrupt::without_interrupts(|| {
+            RUNNING_QUEUE.get().unwrap().write().push_back(new_thread);
+        });
+    } else {
+        current_context.set_ret_val_1(1);
+    }
+}
+
+/// Returns the id of the process running on this CPU, if a thread is running
+pub fn current_process_id() -> Option<u64> {
+    CURRENT_THREAD.read().as_ref().map(|thread| thread.process.id)
+}
+
+/// Returns the process running on this CPU, if a thread is running
+pub fn current_process() -> Option<Arc<Process>> {
+    CURRENT_THREAD.read().as_ref().map(|thread| thread.process.clone())
+}
+
+/// Whether the process of the current thread caught a signal that is
+/// waiting, or was killed. Blocking syscalls give up then.
+pub fn caught_signal() -> bool {
+    current_process().is_some_and(|process| process.signals.has_caught() || process.signals.killed())
+}
+
+/// Calls `func` with every thread of a user process until it returns true.
+///
+/// Kernel threads are left out. This does not allocate, so interrupt
+/// handlers can use it.
+fn find_user_thread<F>(mut func: F) -> bool where F: FnMut(&Thread) -> bool {
+    let mut check = |thread: &Thread| thread.process.page_table_phys_addr != 0 && func(thread);
+
+    crate::hal::interface::interrupt::without_interrupts(|| {
+        // Taken while waiting, during the wait syscall
+        let found_current = match CURRENT_THREAD.try_read() {
+            Some(current_thread) => current_thread.as_deref().is_some_and(&mut check),
+            None => false,
+        };
+        found_current
+            || RUNNING_QUEUE.get().unwrap().read().iter().any(|thread| check(thread))
+            || WAITING_THREADS.read().iter().any(|thread| check(thread))
+    })
+}
+
+/// Calls `func` with the process of every thread, once per thread, until it
+/// returns true
+fn find_user_process<F>(mut func: F) -> bool where F: FnMut(&Arc<Process>) -> bool {
+    find_user_thread(|thread| func(&thread.process))
+}
+
+/// Fills `out` with the user processes, returns how many there are
+pub fn process_list(out: &mut [SnProcessInfo]) -> usize {
+    let len = out.len();
+    let mut count = 0;
+    find_user_thread(|thread| {
+        let process = &thread.process;
+        let runnable = thread.waiting_on.is_none();
+        if let Some(info) = out[..count.min(len)].iter_mut().find(|info| info.id == process.id) {
+            info.threads += 1;
+            if runnable && info.state == PROCESS_SLEEPING {
+                info.state = PROCESS_RUNNING;
+            }
+            return false;
+        }
+
+        if count < len {
+            let job = *process.job.lock();
+            let mut name = [0; 32];
+            let len = process.name.len().min(name.len());
+            name[..len].copy_from_slice(&process.name.as_bytes()[..len]);
+            out[count] = SnProcessInfo {
+                id: process.id,
+                parent_id: process.parent_id,
+                group_id: job.group_id,
+                session_id: job.session_id,
+                threads: 1,
+                state: match () {
+                    _ if process.signals.stopped() => PROCESS_STOPPED,
+                    _ if runnable => PROCESS_RUNNING,
+                    _ => PROCESS_SLEEPING,
+                },
+                name,
+            };
+        }
+        count += 1;
+        false
+    });
+    count
+}
+
+/// Whether `parent_id` has a child process `id` running, or any child for None
+pub fn has_child(parent_id: u64, id: Option<u64>) -> bool {
+    find_user_process(|process| process.parent_id == parent_id && id.is_none_or(|id| id == process.id))
+}
+
+/// Finds a child process like `has_child` that stopped since last asked,
+/// returns its id
+pub fn take_stopped_child(parent_id: u64, id: Option<u64>) -> Option<u64> {
+    let mut found = None;
+    find_user_process(|process| {
+        if process.parent_id == parent_id && id.is_none_or(|id| id == process.id) && process.signals.take_stop_report() {
+            found = Some(process.id);
+        }
+        found.is_some()
+    });
+    found
+}
+
+/// Finds a user process by id
+pub fn find_process(id: u64) -> Option<Arc<Process>> {
+    let mut found = None;
+    find_user_process(|process| {
+        if process.id == id {
+            found = Some(process.clone());
+        }
+        found.is_some()
+    });
+    found
+}
+
+/// Whether a process is in group `group_id` of session `session_id`
+pub fn group_exists(session_id: u64, group_id: u64) -> bool {
+    find_user_process(|process| {
+        let job = process.job.lock();
+        job.session_id == session_id && job.group_id == group_id
+    })
+}
+
+/// Sends `signal` to the user processes of `target`.
+///
+/// Threads of processes that were killed, continued or caught the signal
+/// are woken to act on it. Returns false if no process matched.
+pub fn send_signal(target: SnSignalTarget, signal: SnSignal) -> bool {
+    let matches = |process: &Process| match target {
+        SnSignalTarget::Process(id) => process.id == id,
+        SnSignalTarget::Group(id) => process.job.lock().group_id == id,
+    };
+
+    let mut found = false;
+    let mut stopped = false;
+    find_user_process(|process| {
+        if matches(process) {
+            found = true;
+            match process.signals.deliver(signal) {
+                SnSignalEffect::Killed => *process.exit_status.lock() = Some(SnExitStatus::Signaled(signal)),
+                // Reported to the parent like an exit
+                SnSignalEffect::Stopped => stopped = true,
+                _ => {}
+            }
+        }
+        false
+    });
+    if !found {
+        return false;
+    }
+    if stopped {
+        wake(process::exit_channel());
+    }
+
+    // Waits are retried in a loop, so waking too many is harmless
+    let woken = |thread: &Thread| matches(&thread.process) && !thread.process.signals.stopped();
+    crate::hal::interface::interrupt::without_interrupts(|| {
+        if let Some(mut current_thread) = CURRENT_THREAD.try_write() {
+            if let Some(thread) = current_thread.as_mut().filter(|thread| woken(thread)) {
+                thread.waiting_on = None;
+            }
+        }
+
+        let mut running_queue = RUNNING_QUEUE.get().unwrap().write();
+        let mut waiting_threads = WAITING_THREADS.write();
+        let mut index = 0;
+        while index < waiting_threads.len() {
+            if woken(&waiting_threads[index]) {
+                let mut thread = waiting_threads.swap_remove(index);
+                thread.waiting_on = None;
+                running_queue.push_back(thread);
+            } else {
+                index += 1;
+            }
+        }
+    });
+    true
+}
+
+/// Sets the thread pointer of the current thread.
+///
+/// Returns false if the address is not in user space.
+pub fn set_current_thread_pointer(addr: u64) -> bool {
+    if addr >= USER_SPACE_END {
+        return false;
+    }
+
+    if let Some(thread) = CURRENT_THREAD.write().as_mut() {
+        thread.fs_base = addr;
+        cpu::set_thread_pointer(addr);
+        return true;
+    }
+
+    false
+}
+
+/// Blocks the current thread until `wake` is called with the same channel,
+/// which is the address of whatever the thread waits for.
+///
+/// Must be called with interrupts disabled, right after finding there is
+/// nothing to do, so that a wake up in between is not missed.
+pub fn wait(channel: u64) {
+    match CURRENT_THREAD.write().as_mut() {
+        Some(thread) => thread.waiting_on = Some(channel),
+        None => return,
+    }
+
+    // The timer moves the thread to the waiting threads, and runs it again
+    // from here once woken
+    while CURRENT_THREAD.read().as_ref().is_some_and(|thread| thread.waiting_on.is_some()) {
+        unsafe {
+            asm!("sti", "hlt", "cli");
+        }
+    }
+}
+
+/// Blocks the current thread for `duration`.
+///
+/// Returns false if it woke up early for a caught signal. Must be called
+/// with interrupts disabled, like `wait`.
+pub fn sleep(duration: Duration) -> bool {
+    let deadline = clock::uptime() + duration;
+    while clock::uptime() < deadline {
+        if caught_signal() {
+            return false;
+        }
+        wait(SLEEP_CHANNEL);
+    }
+    true
+}
+
+/// Makes the threads waiting on `channel` run again
+pub fn wake(channel: u64) {
+    crate::hal::interface::interrupt::without_interrupts(|| {
+        // Still running if it waits since the last timer tick
+        if let Some(mut current_thread) = CURRENT_THREAD.try_write() {
+            if let Some(thread) = current_thread.as_mut().filter(|thread| thread.waiting_on == Some(channel)) {
+                thread.waiting_on = None;
+            }
+        }
+
+        let mut running_queue = RUNNING_QUEUE.get().unwrap().write();
+        let mut waiting_threads = WAITING_THREADS.write();
+        let mut index = 0;
+        while index < waiting_threads.len() {
+            if waiting_threads[index].waiting_on == Some(channel) {
+                let mut thread = waiting_threads.swap_remove(index);
+                thread.waiting_on = None;
+                running_queue.push_back(thread);
+            } else {
+                index += 1;
+            }
+        }
+    });
+}
+
+/// Where the threads of a killed process continue, in kernel mode
+fn exit_killed_thread() -> ! {
+    crate::hal::interface::interrupt::without_interrupts(|| {
+        if let Some(thread) = CURRENT_THREAD.write().take() {
+            // The page tables go away with the last thread
+            paging::switch_page_table(paging::kernel_page_table_phys_addr());
+            drop_running_thread(thread);
+        }
+    });
+
+    // Wait for the timer to switch to another thread
+    unsafe {
+        asm!("sti", "2:", "hlt", "jmp 2b", options(noreturn));
+    }
+}
+
+/// Ends the current thread, the last one to exit sets the exit code of the
+/// process
+pub fn exit_current_thread(_current_context: &mut SnCpuContext, code: u64) {
     {
         let mut current_thread = CURRENT_THREAD.write();
 
-        if let Some(_thread) = current_thread.take() {
-            // Drop thread, freeing stacks. If this is the last thread
-            // in this process, memory and page tables will be freed
-            // in the Process drop() function
+        if let Some(thread) = current_thread.take() {
+            let mut exit_status = thread.process.exit_status.lock();
+            if !matches!(*exit_status, Some(SnExitStatus::Faulted(_) | SnExitStatus::Signaled(_))) {
+                *exit_status = Some(SnExitStatus::Exited(code));
+            }
+            drop(exit_status);
+            // The page tables go away with the last thread
+            paging::switch_page_table(paging::kernel_page_table_phys_addr());
+
+            // If this is the last thread in this process, memory and page
+            // tables will be freed in the Process drop() function
+            drop_running_thread(thread);
         }
     }
     // Can't return from this syscall, so this thread now waits for a
@@ -309,7 +948,67 @@ pub fn exit_current_thread(_current_context: &mut SnCpuContext) {
     }
 }
 
+/// Kills the process of the current thread after a fault in user mode.
+///
+/// All threads of the process are descheduled, and the fault is kept
+/// as the exit status for the parent.
+fn kill_current_process(fault: SnFault) -> ! {
+    crate::hal::interface::interrupt::without_interrupts(|| {
+        let Some(thread) = CURRENT_THREAD.write().take() else {
+            return;
+        };
+
+        log::warn!(
+            "killing process {}: {} at {:#x}",
+            thread.process.id,
+            fault.name,
+            fault.instruction_pointer
+        );
+        *thread.process.exit_status.lock() = Some(SnExitStatus::Faulted(fault));
+
+        RUNNING_QUEUE
+            .get()
+            .unwrap()
+            .write()
+            .retain(|other| !Arc::ptr_eq(&other.process, &thread.process));
+        WAITING_THREADS
+            .write()
+            .retain(|other| !Arc::ptr_eq(&other.process, &thread.process));
+
+        // The page tables go away with the process
+        paging::switch_page_table(paging::kernel_page_table_phys_addr());
+        drop(thread);
+    });
+
+    // Wait for the timer to switch to another thread
+    unsafe {
+        asm!("sti", "2:", "hlt", "jmp 2b", options(noreturn));
+    }
+}
+
+/// Body of the idle thread
+fn idle() {
+    crate::hal::interface::instruct::hcf();
+}
+
 pub fn init() {
-    printk!("process: setting the scheduler");
+    printk!("setting the scheduler");
+    let idle_thread = kernel_thread(idle);
+    IDLE_THREAD_ID.store(idle_thread.id, Ordering::Relaxed);
+    *IDLE_THREAD.write() = Some(idle_thread);
     SCHEDULE.init_once(move || schedule_next);
+    USER_FAULT.init_once(move || kill_current_process);
+}
+
+#[test_case]
+fn test_thread_local_layout() {
+    let template = SnTlsTemplate { start: SnVirtAddr::new(0), file_size: 4, mem_size: 8, align: 8 };
+    let (thread_pointer, block_start) = thread_local_layout(&template, 0x5000_1008);
+    assert_eq!(thread_pointer % 16, 0);
+    assert_eq!(block_start, thread_pointer - 8);
+
+    let template = SnTlsTemplate { start: SnVirtAddr::new(0), file_size: 0, mem_size: 40, align: 64 };
+    let (thread_pointer, block_start) = thread_local_layout(&template, 0x5000_1008);
+    assert_eq!(thread_pointer % 64, 0);
+    assert_eq!(block_start, thread_pointer - 64);
 }
@@ -1,5 +1,20 @@
 use core::fmt;
 
+use alloc::boxed::Box;
+
+use crate::{
+    drivers::tty::{self, SnTtyId},
+    interrupt, printk,
+};
+
+/// First serial port, used for the kernel log
+pub const COM1: u16 = 0x3F8;
+/// Second serial port
+#[cfg(feature = "gdb")]
+pub const COM2: u16 = 0x2F8;
+
+const COM1_IRQ: u8 = 4;
+
 pub struct SnSerialWriter {
     port: uart_16550::SerialPort,
 }
@@ -7,16 +22,61 @@ pub struct SnSerialWriter {
 impl SnSerialWriter {
     /// # Safety
     ///
-    /// unsafe because this function must only be called once
-    unsafe fn init() -> Self {
-        let mut port = unsafe { uart_16550::SerialPort::new(0x3F8) };
+    /// unsafe because this function must only be called once per port
+    unsafe fn init(base: u16) -> Self {
+        let mut port = unsafe { uart_16550::SerialPort::new(base) };
         port.init();
         Self { port }
     }
+
+    /// Sends a byte as is, without the translation done for text
+    #[cfg(feature = "gdb")]
+    pub fn send_byte(&mut self, byte: u8) {
+        self.port.send_raw(byte);
+    }
+
+    /// Waits for a byte
+    #[cfg(feature = "gdb")]
+    pub fn receive_byte(&mut self) -> u8 {
+        self.port.receive()
+    }
+
+    #[cfg(feature = "gdb")]
+    pub fn try_receive_byte(&mut self) -> Option<u8> {
+        self.port.try_receive().ok()
+    }
 }
 
 pub unsafe fn init() -> SnSerialWriter {
-    unsafe { SnSerialWriter::init() }
+    unsafe { SnSerialWriter::init(COM1) }
+}
+
+/// Hands what arrives on COM1 to the serial tty.
+///
+/// The logger keeps its `SnSerialWriter` for COM1, this only touches the
+/// receive side of the port.
+pub fn init_receive() {
+    printk!("receiving on COM1");
+    interrupt::request_isa_irq(COM1_IRQ, Box::new(|| {
+        let mut port = unsafe { uart_16550::SerialPort::new(COM1) };
+        let mut received = false;
+        while let Ok(byte) = port.try_receive() {
+            tty::receive_byte(SnTtyId::Serial, byte);
+            received = true;
+        }
+        received
+    }))
+    .expect("cannot get the COM1 IRQ");
+}
+
+/// Initializes another serial port
+///
+/// # Safety
+///
+/// Must only be called once per port, and not for COM1
+#[cfg(feature = "gdb")]
+pub unsafe fn init_port(base: u16) -> SnSerialWriter {
+    unsafe { SnSerialWriter::init(base) }
 }
 
 impl fmt::Write for SnSerialWriter {
@@ -1,11 +1,64 @@
-use core::slice;
+use core::{slice, time::Duration};
 
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
 use conquer_once::spin::OnceCell;
 use spin::RwLock;
 
-use crate::{hal::interface::cpu::SnCpuContext, print, printk, println, process};
+use crate::{
+    drivers::{i8042, ps2_keyboard, tty::{self, SnTermios, SnTtyId}},
+    fb::device::{self, SnDisplayMode},
+    fs::vfs::{self, SnDirEntry, SnVfsError, SnVfsType},
+    hal::interface::{clock, cpu::SnCpuContext, instruct, paging},
+    logger::{self, logbuf},
+    memory::{info::SnMemInfo, SnVirtAddr},
+    printk,
+    process::{
+        self,
+        file::{self, SnFile, SnFileError, SnFiles, SnNodeFile},
+        process::{Process, SnExitStatus, SnProcessInfo},
+        signal::{SnSignal, SnSignalAction, SnSignalTarget},
+        thread::SnProcessSetup,
+    },
+};
 
-pub const SYSCALL_INDEXES: usize = 32;
+pub const SYSCALL_INDEXES: usize = 64;
+
+/// arch_prctl codes, same values as Linux
+const ARCH_SET_FS: u64 = 0x1002;
+const ARCH_GET_FS: u64 = 0x1003;
+
+/// Longest keyboard layout name accepted by set_keymap
+const MAX_KEYMAP_NAME: u64 = 32;
+/// Longest path accepted
+const MAX_PATH: u64 = 256;
+/// Longest arguments accepted by spawn
+const MAX_ARGS: u64 = 4096;
+/// Most entries returned by one call
+const MAX_LIST: u64 = 256;
+/// Most bytes read by one call, the buffer is mapped in before reading
+const MAX_READ: u64 = 64 * 1024;
+
+/// Flags of open
+const OPEN_WRITE: u64 = 1;
+/// Creates the file if it is missing
+const OPEN_CREATE: u64 = 2;
+/// Empties the file, when opened for writing
+const OPEN_TRUNCATE: u64 = 4;
+
+/// Flag of wait, to also return children that stopped
+const WAIT_STOPPED: u64 = 1;
+/// Flag of wait, to return at once when no child has ended
+const WAIT_NO_HANG: u64 = 2;
+/// How a child ended, in the upper half of the status returned by wait
+const STATUS_EXITED: u64 = 0;
+const STATUS_SIGNALED: u64 = 1;
+const STATUS_FAULTED: u64 = 2;
+const STATUS_STOPPED: u64 = 3;
+
+/// Leaves a file of a spawned process closed
+const SPAWN_NO_FILE: u64 = u64::MAX;
+/// Puts a spawned process in a new group it leads
+const SPAWN_NEW_GROUP: u64 = u64::MAX;
 
 // Currently registered syscalls:
 // 0: writer
@@ -15,6 +68,42 @@ pub enum Syscall {
     Write = 1,
     Fork = 10,
     Exit = 11,
+    ThreadCreate = 12,
+    ArchPrctl = 13,
+    MemInfo = 14,
+    ReadKlog = 15,
+    DisplayInfo = 16,
+    MapDisplay = 17,
+    ReleaseDisplay = 18,
+    SetTermios = 19,
+    SetKeymap = 20,
+    SetTty = 21,
+    GetTermios = 22,
+    OpenPty = 23,
+    ClosePty = 24,
+    ReadPty = 25,
+    WritePty = 26,
+    SetSid = 27,
+    SetPgid = 28,
+    ProcessIds = 29,
+    SetForeground = 30,
+    Kill = 31,
+    SetSignalAction = 32,
+    TakeSignals = 33,
+    Open = 34,
+    Close = 35,
+    ReadFile = 36,
+    WriteFile = 37,
+    Pipe = 38,
+    Dup2 = 39,
+    ReadDir = 40,
+    Spawn = 41,
+    Wait = 42,
+    Args = 43,
+    ProcessList = 44,
+    Reboot = 45,
+    Sleep = 46,
+    Uptime = 47,
     Max = 255,
 }
 pub struct SyscallHandler {
@@ -45,32 +134,869 @@ impl SyscallController {
 
 pub static SYSCALL_CONTROLLER: OnceCell<RwLock<SyscallController>> = OnceCell::uninit();
 pub fn init() {
-    printk!("syscall: initializing general syscall handler");
+    printk!("initializing general syscall handler");
     SYSCALL_CONTROLLER.init_once(move || RwLock::new(SyscallController::new()) );
 
     let mut controller = SYSCALL_CONTROLLER.get().unwrap().write();
+    controller.set_handler(Syscall::Read as u64, read);
     controller.set_handler(Syscall::Write as u64, write);
     controller.set_handler(Syscall::Fork as u64, fork);
     controller.set_handler(Syscall::Exit as u64, exit);
+    controller.set_handler(Syscall::ThreadCreate as u64, thread_create);
+    controller.set_handler(Syscall::ArchPrctl as u64, arch_prctl);
+    controller.set_handler(Syscall::MemInfo as u64, meminfo);
+    controller.set_handler(Syscall::ReadKlog as u64, read_klog);
+    controller.set_handler(Syscall::DisplayInfo as u64, display_info);
+    controller.set_handler(Syscall::MapDisplay as u64, map_display);
+    controller.set_handler(Syscall::ReleaseDisplay as u64, release_display);
+    controller.set_handler(Syscall::SetTermios as u64, set_termios);
+    controller.set_handler(Syscall::SetKeymap as u64, set_keymap);
+    controller.set_handler(Syscall::SetTty as u64, set_tty);
+    controller.set_handler(Syscall::GetTermios as u64, get_termios);
+    controller.set_handler(Syscall::OpenPty as u64, open_pty);
+    controller.set_handler(Syscall::ClosePty as u64, close_pty);
+    controller.set_handler(Syscall::ReadPty as u64, read_pty);
+    controller.set_handler(Syscall::WritePty as u64, write_pty);
+    controller.set_handler(Syscall::SetSid as u64, set_sid);
+    controller.set_handler(Syscall::SetPgid as u64, set_pgid);
+    controller.set_handler(Syscall::ProcessIds as u64, process_ids);
+    controller.set_handler(Syscall::SetForeground as u64, set_foreground);
+    controller.set_handler(Syscall::Kill as u64, kill);
+    controller.set_handler(Syscall::SetSignalAction as u64, set_signal_action);
+    controller.set_handler(Syscall::TakeSignals as u64, take_signals);
+    controller.set_handler(Syscall::Open as u64, open);
+    controller.set_handler(Syscall::Close as u64, close);
+    controller.set_handler(Syscall::ReadFile as u64, read_file);
+    controller.set_handler(Syscall::WriteFile as u64, write_file);
+    controller.set_handler(Syscall::Pipe as u64, pipe);
+    controller.set_handler(Syscall::Dup2 as u64, dup2);
+    controller.set_handler(Syscall::ReadDir as u64, read_dir);
+    controller.set_handler(Syscall::Spawn as u64, spawn);
+    controller.set_handler(Syscall::Wait as u64, wait);
+    controller.set_handler(Syscall::Args as u64, args);
+    controller.set_handler(Syscall::ProcessList as u64, process_list);
+    controller.set_handler(Syscall::Reboot as u64, reboot);
+    controller.set_handler(Syscall::Sleep as u64, sleep);
+    controller.set_handler(Syscall::Uptime as u64, uptime);
+}
+
+/// Sets rax to 0 and rdi to `value`, or rax to the error code
+fn set_result<E>(ctx: &mut SnCpuContext, result: Result<usize, E>) where E: Into<usize> {
+    match result {
+        Ok(value) => {
+            ctx.set_ret_val_1(0);
+            ctx.set_arg_val_1(value);
+        }
+        Err(err) => ctx.set_ret_val_1(err.into()),
+    }
+}
+
+impl From<tty::SnTtyError> for usize {
+    fn from(err: tty::SnTtyError) -> usize {
+        err as usize
+    }
+}
+
+impl From<SnFileError> for usize {
+    fn from(err: SnFileError) -> usize {
+        err as usize
+    }
+}
+
+/// Returns the calling process with its controlling tty
+fn controlling_tty() -> Option<(alloc::sync::Arc<Process>, SnTtyId)> {
+    let process = process::thread::current_process()?;
+    let tty = process.job.lock().tty?;
+    Some((process, tty))
+}
+
+/// Returns the calling process with its open file `fd`
+fn open_file(fd: u64) -> Option<(Arc<Process>, Arc<SnFile>)> {
+    let process = process::thread::current_process()?;
+    let file = process.files.lock().get(fd)?;
+    Some((process, file))
+}
+
+/// Copies a path out of user space
+fn user_path(ptr: u64, len: u64) -> Option<String> {
+    if len == 0 || len > MAX_PATH || !in_user_space(ptr, len) {
+        return None;
+    }
+    let path = unsafe { slice::from_raw_parts(ptr as *const u8, len as usize) };
+    str::from_utf8(path).ok().map(String::from)
+}
+
+/// Reads from file 0 of the process, as `read_file`
+fn read(ctx: &mut SnCpuContext, ptr: u64, len: u64, _arg3: u64) {
+    read_file(ctx, 0, ptr, len);
+}
+
+/// Writes to file 1 of the process, as `write_file`
+fn write(ctx: &mut SnCpuContext, ptr: u64, len: u64, _arg3: u64) {
+    write_file(ctx, 1, ptr, len);
+}
+
+/// Reads from file `fd` into the buffer at `ptr`, waiting for input, and
+/// on a tty for the group of the process to be in the foreground.
+///
+/// A tty in canonical mode gives up to one line, and with `TERMIOS_EVENTS`
+/// the console gives whole `SnInputEvent`s. Nothing is read at the end of a
+/// file or pipe. Fails with 2 while a caught signal is pending.
+/// Returns the bytes read in rdi, at most `MAX_READ`.
+fn read_file(ctx: &mut SnCpuContext, fd: u64, ptr: u64, len: u64) {
+    let Some((process, file)) = open_file(fd) else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+    if len == 0 {
+        ctx.set_ret_val_1(0);
+        ctx.set_arg_val_1(0);
+        return;
+    }
+
+    let len = len.min(MAX_READ);
+
+    // Interrupts stay off from here to the read, as for all syscalls
+    let group_id = process.job.lock().group_id;
+    if let Err(err) = file.wait_readable(group_id) {
+        ctx.set_ret_val_1(err as usize);
+        return;
+    }
+    if !prepare_user_buffer(ptr, len) {
+        ctx.set_ret_val_1(1);
+        return;
+    }
+
+    let out = unsafe { slice::from_raw_parts_mut(ptr as *mut u8, len as usize) };
+    set_result(ctx, file.read(out));
+}
+
+/// Writes the buffer at `ptr` to file `fd`, files from the VFS are
+/// appended to. Fails with 4 for a pipe nobody reads.
+///
+/// Returns the bytes written in rdi.
+fn write_file(ctx: &mut SnCpuContext, fd: u64, ptr: u64, len: u64) {
+    let Some((_, file)) = open_file(fd) else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+    if len == 0 {
+        ctx.set_ret_val_1(0);
+        ctx.set_arg_val_1(0);
+        return;
+    }
+    if !in_user_space(ptr, len) {
+        ctx.set_ret_val_1(1);
+        return;
+    }
+
+    let bytes = unsafe { slice::from_raw_parts(ptr as *const u8, len as usize) };
+    set_result(ctx, file.write(bytes));
+}
+
+/// Opens the file or directory at the path at `ptr` with the `OPEN_` flags.
+///
+/// Returns the number of the file in rdi.
+fn open(ctx: &mut SnCpuContext, ptr: u64, len: u64, flags: u64) {
+    let (Some(process), Some(path)) = (process::thread::current_process(), user_path(ptr, len)) else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+    if flags & !(OPEN_WRITE | OPEN_CREATE | OPEN_TRUNCATE) != 0 {
+        ctx.set_ret_val_1(1);
+        return;
+    }
+
+    let writable = flags & OPEN_WRITE != 0;
+    let node = match vfs::find(&path) {
+        Err(SnVfsError::NotFound) if flags & OPEN_CREATE != 0 => vfs::create(&path),
+        node => node,
+    };
+    let result = node
+        .map_err(SnFileError::from)
+        .and_then(|node| {
+            if writable && flags & OPEN_TRUNCATE != 0 {
+                node.truncate()?;
+            }
+            SnNodeFile::open(node, writable)
+        })
+        .and_then(|file| {
+            let fd = process.files.lock().insert(Arc::new(SnFile::Node(file)));
+            fd.ok_or(SnFileError::Invalid)
+        });
+    set_result(ctx, result.map(|fd| fd as usize));
+}
+
+fn close(ctx: &mut SnCpuContext, fd: u64, _arg2: u64, _arg3: u64) {
+    match process::thread::current_process() {
+        Some(process) if process.files.lock().close(fd) => ctx.set_ret_val_1(0),
+        _ => ctx.set_ret_val_1(1),
+    }
+}
+
+/// Opens a pipe, returns the number of its read end in rdi and of its
+/// write end in rsi
+fn pipe(ctx: &mut SnCpuContext, _arg1: u64, _arg2: u64, _arg3: u64) {
+    let Some(process) = process::thread::current_process() else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+
+    let (reader, writer) = file::pipe();
+    let mut files = process.files.lock();
+    let Some(read_fd) = files.insert(Arc::new(SnFile::Pipe(reader))) else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+    let Some(write_fd) = files.insert(Arc::new(SnFile::Pipe(writer))) else {
+        files.close(read_fd);
+        ctx.set_ret_val_1(1);
+        return;
+    };
+    ctx.set_ret_val_1(0);
+    ctx.set_arg_val_1(read_fd as usize);
+    ctx.set_arg_val_2(write_fd as usize);
+}
+
+/// Makes `new_fd` refer to the same file as `fd`, closing what was there
+fn dup2(ctx: &mut SnCpuContext, fd: u64, new_fd: u64, _arg3: u64) {
+    let Some((process, file)) = open_file(fd) else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+    if process.files.lock().set(new_fd, file) {
+        ctx.set_ret_val_1(0);
+    } else {
+        ctx.set_ret_val_1(1);
+    }
+}
+
+/// A directory entry, as returned by the read_dir syscall
+#[repr(C)]
+struct SnDirInfo {
+    /// 0 for a file, 1 for a directory
+    kind: u32,
+    name_len: u32,
+    /// Start of the name, padded with zeroes
+    name: [u8; 56],
+}
+
+/// Fills the `count` entries at `ptr` with the next entries of directory `fd`.
+///
+/// Returns how many were filled in rdi, zero at the end.
+fn read_dir(ctx: &mut SnCpuContext, fd: u64, ptr: u64, count: u64) {
+    let Some((_, file)) = open_file(fd) else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+    let count = count.min(MAX_LIST);
+    if ptr % 4 != 0 || !prepare_user_buffer(ptr, count * size_of::<SnDirInfo>() as u64) {
+        ctx.set_ret_val_1(1);
+        return;
+    }
+
+    let mut entries = vec![SnDirEntry { name: "", dir_type: SnVfsType::File }; count as usize];
+    let count = match file.read_dir(&mut entries) {
+        Ok(count) => count,
+        Err(err) => return ctx.set_ret_val_1(err as usize),
+    };
+
+    let out = ptr as *mut SnDirInfo;
+    for (index, entry) in entries[..count].iter().enumerate() {
+        let mut name = [0; 56];
+        let len = entry.name.len().min(name.len());
+        name[..len].copy_from_slice(&entry.name.as_bytes()[..len]);
+        let info = SnDirInfo {
+            kind: matches!(entry.dir_type, SnVfsType::Dir) as u32,
+            name_len: len as u32,
+            name,
+        };
+        unsafe { out.add(index).write(info) };
+    }
+    set_result::<usize>(ctx, Ok(count));
+}
+
+/// What to start, passed to the spawn syscall
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SnSpawnRequest {
+    path: u64,
+    path_len: u64,
+    /// Separated by NUL bytes
+    args: u64,
+    args_len: u64,
+    /// Files of the caller to open as input, output and error, or
+    /// `SPAWN_NO_FILE`
+    files: [u64; 3],
+    /// 0 for the group of the caller, `SPAWN_NEW_GROUP`, or a group of its
+    /// session
+    group: u64,
+}
+
+/// Starts the executable described by the `SnSpawnRequest` at `ptr` as a
+/// child process.
+///
+/// Returns the id of the process in rdi.
+fn spawn(ctx: &mut SnCpuContext, ptr: u64, _arg2: u64, _arg3: u64) {
+    let Some(caller) = process::thread::current_process() else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+    if ptr % 8 != 0 || !in_user_space(ptr, size_of::<SnSpawnRequest>() as u64) {
+        ctx.set_ret_val_1(1);
+        return;
+    }
+    let request = unsafe { (ptr as *const SnSpawnRequest).read() };
+
+    let Some(path) = user_path(request.path, request.path_len) else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+    let args = match request.args_len {
+        0 => Vec::new(),
+        len if len <= MAX_ARGS && in_user_space(request.args, len) => {
+            unsafe { slice::from_raw_parts(request.args as *const u8, len as usize) }.to_vec()
+        }
+        _ => {
+            ctx.set_ret_val_1(1);
+            return;
+        }
+    };
+
+    let mut files = SnFiles::new();
+    {
+        let caller_files = caller.files.lock();
+        for (fd, from) in request.files.into_iter().enumerate() {
+            if from == SPAWN_NO_FILE {
+                continue;
+            }
+            let Some(file) = caller_files.get(from) else {
+                ctx.set_ret_val_1(1);
+                return;
+            };
+            files.set(fd as u64, file);
+        }
+    }
+
+    let mut job = *caller.job.lock();
+    match request.group {
+        0 => {}
+        SPAWN_NEW_GROUP => job.group_id = 0,
+        group_id if process::thread::group_exists(job.session_id, group_id) => job.group_id = group_id,
+        _ => {
+            ctx.set_ret_val_1(1);
+            return;
+        }
+    }
+
+    let node = match vfs::find(&path) {
+        Ok(node) if node.is_file() => node,
+        _ => {
+            ctx.set_ret_val_1(1);
+            return;
+        }
+    };
+    let mut bin = vec![0; node.len()];
+    let executable = match node.read(&mut bin).map(|len| crate::loader::elf::load_elf(&bin[..len])) {
+        Ok(Ok(executable)) => executable,
+        _ => {
+            ctx.set_ret_val_1(1);
+            return;
+        }
+    };
+
+    let id = process::thread::new_user_thread(executable, SnProcessSetup {
+        parent_id: caller.id,
+        name: String::from(path.rsplit('/').next().unwrap_or(&path)),
+        args,
+        job: Some(job),
+        files: Some(files),
+    });
+    set_result::<usize>(ctx, Ok(id as usize));
+}
+
+/// Waits for child process `id` to end, or any child for 0, and with
+/// `WAIT_STOPPED` also for one to stop.
+///
+/// Returns the id of the child in rdi, and in rsi a `STATUS_` kind in the
+/// upper half with the exit code, signal or exception vector in the lower.
+/// With `WAIT_NO_HANG` the id is 0 if none is there yet.
+/// Fails with 1 without such children, and with 2 while a caught signal
+/// is pending.
+fn wait(ctx: &mut SnCpuContext, id: u64, flags: u64, _arg3: u64) {
+    let Some(parent_id) = process::thread::current_process_id() else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+    if flags & !(WAIT_STOPPED | WAIT_NO_HANG) != 0 {
+        ctx.set_ret_val_1(1);
+        return;
+    }
+    let id = (id != 0).then_some(id);
+
+    let (child, status) = loop {
+        if let Some((child, status)) = process::process::take_exit_status(parent_id, id) {
+            let status = match status {
+                SnExitStatus::Exited(code) => STATUS_EXITED << 32 | code & 0xffff_ffff,
+                SnExitStatus::Signaled(signal) => STATUS_SIGNALED << 32 | signal as u64,
+                SnExitStatus::Faulted(fault) => STATUS_FAULTED << 32 | fault.vector as u64,
+            };
+            break (child, status);
+        }
+        if flags & WAIT_STOPPED != 0 {
+            if let Some(child) = process::thread::take_stopped_child(parent_id, id) {
+                break (child, STATUS_STOPPED << 32 | SnSignal::TerminalStop as u64);
+            }
+        }
+
+        if !process::thread::has_child(parent_id, id) {
+            ctx.set_ret_val_1(1);
+            return;
+        }
+        if flags & WAIT_NO_HANG != 0 {
+            break (0, 0);
+        }
+        if process::thread::caught_signal() {
+            ctx.set_ret_val_1(2);
+            return;
+        }
+        process::thread::wait(process::process::exit_channel());
+    };
+
+    ctx.set_ret_val_1(0);
+    ctx.set_arg_val_1(child as usize);
+    ctx.set_arg_val_2(status as usize);
 }
 
-fn write(ctx: &mut SnCpuContext, ptr: u64, len: u64, arg3: u64) {
-    // Check all inputs: Does ptr -> ptr+len lie entirely in user address space?
+/// Copies the arguments of the process to the buffer at `ptr`, as much as
+/// fits.
+///
+/// Returns the length of all the arguments in rdi.
+fn args(ctx: &mut SnCpuContext, ptr: u64, len: u64, _arg3: u64) {
+    let Some(process) = process::thread::current_process() else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+    let len = len.min(process.args.len() as u64);
     if len == 0 {
+        set_result::<usize>(ctx, Ok(process.args.len()));
         return;
     }
-    // Convert raw pointer to a slice
-    let u8_slice = unsafe {slice::from_raw_parts(ptr as *mut u8, len as usize)};
+    if !prepare_user_buffer(ptr, len) {
+        ctx.set_ret_val_1(1);
+        return;
+    }
+
+    let out = unsafe { slice::from_raw_parts_mut(ptr as *mut u8, len as usize) };
+    out.copy_from_slice(&process.args[..len as usize]);
+    set_result::<usize>(ctx, Ok(process.args.len()));
+}
+
+/// Fills the `count` `SnProcessInfo`s at `ptr` with the user processes.
+///
+/// Returns how many processes there are in rdi, which may be more.
+fn process_list(ctx: &mut SnCpuContext, ptr: u64, count: u64, _arg3: u64) {
+    let count = count.min(MAX_LIST);
+    if ptr % 8 != 0 || !prepare_user_buffer(ptr, count * size_of::<SnProcessInfo>() as u64) {
+        ctx.set_ret_val_1(1);
+        return;
+    }
+
+    let out = unsafe { slice::from_raw_parts_mut(ptr as *mut SnProcessInfo, count as usize) };
+    set_result::<usize>(ctx, Ok(process::thread::process_list(out)));
+}
+
+/// Restarts the machine
+fn reboot(_ctx: &mut SnCpuContext, _arg1: u64, _arg2: u64, _arg3: u64) {
+    printk!("rebooting");
+    i8042::pulse_reset();
+    instruct::triple_fault();
+}
+
+/// Blocks the calling thread for `millis` milliseconds.
+///
+/// Fails with 2 if a caught signal is pending before the time is up.
+fn sleep(ctx: &mut SnCpuContext, millis: u64, _arg2: u64, _arg3: u64) {
+    match process::thread::sleep(Duration::from_millis(millis)) {
+        true => ctx.set_ret_val_1(0),
+        false => ctx.set_ret_val_1(2),
+    }
+}
 
-    if let Ok(s) = str::from_utf8(u8_slice) {
-        print!("{}", s);
-    } // else error
+/// Returns the milliseconds since boot in rdi
+fn uptime(ctx: &mut SnCpuContext, _arg1: u64, _arg2: u64, _arg3: u64) {
+    set_result::<usize>(ctx, Ok(clock::uptime().as_millis() as usize));
 }
 
 fn fork(ctx: &mut SnCpuContext, _arg1: u64, _arg2: u64, _arg3: u64) {
-    process::thread::fork_current_thread(ctx);
+    process::thread::fork_current_process(ctx);
+}
+
+fn thread_create(ctx: &mut SnCpuContext, _arg1: u64, _arg2: u64, _arg3: u64) {
+    process::thread::new_thread_in_current_process(ctx);
+}
+
+/// Ends the calling thread, the last one sets the exit code of the process
+fn exit(ctx: &mut SnCpuContext, code: u64, _arg2: u64, _arg3: u64) {
+    process::thread::exit_current_thread(ctx, code);
+}
+
+fn arch_prctl(ctx: &mut SnCpuContext, code: u64, addr: u64, _arg3: u64) {
+    match code {
+        ARCH_SET_FS => {
+            if process::thread::set_current_thread_pointer(addr) {
+                ctx.set_ret_val_1(0);
+            } else {
+                ctx.set_ret_val_1(1);
+            }
+        }
+        ARCH_GET_FS => {
+            ctx.set_ret_val_1(0);
+            ctx.set_arg_val_1(crate::hal::interface::cpu::thread_pointer() as usize);
+        }
+        _ => ctx.set_ret_val_1(1),
+    }
+}
+/// Checks that `ptr..ptr + len` lies in user space
+fn in_user_range(ptr: u64, len: u64) -> bool {
+    ptr.checked_add(len).is_some_and(|end| ptr != 0 && end <= process::thread::USER_SPACE_END)
+}
+
+/// Checks that `ptr..ptr + len` lies in pages user code can read, for
+/// buffers only read. Reading an unmapped page would fault in the kernel.
+fn in_user_space(ptr: u64, len: u64) -> bool {
+    in_user_range(ptr, len)
+        && (ptr & !0xfff..ptr + len).step_by(4096).all(|page| paging::is_user_page(SnVirtAddr::new(page)))
+}
+
+/// Checks that `ptr..ptr + len` lies in user pages and makes it writable
+fn prepare_user_buffer(ptr: u64, len: u64) -> bool {
+    if !in_user_range(ptr, len) {
+        return false;
+    }
+    let end = ptr + len;
+
+    // The buffer may sit on copy-on-write or not yet mapped pages
+    (ptr & !0xfff..end)
+        .step_by(4096)
+        .all(|page| paging::prepare_user_page_for_write(SnVirtAddr::new(page)))
+}
+
+/// Fills the `SnMemInfo` at `ptr` with the current memory counters
+fn meminfo(ctx: &mut SnCpuContext, ptr: u64, _arg2: u64, _arg3: u64) {
+    let size = core::mem::size_of::<SnMemInfo>() as u64;
+    if ptr % 8 != 0 || !prepare_user_buffer(ptr, size) {
+        ctx.set_ret_val_1(1);
+        return;
+    }
+
+    let info = crate::memory::info::collect(paging::get_current_page_table_phys_addr());
+    unsafe { (ptr as *mut SnMemInfo).write(info) };
+    ctx.set_ret_val_1(0);
+}
+
+/// Copies kernel log records numbered `cursor` and later to the buffer at `ptr`.
+///
+/// Returns the bytes written in rdi and the cursor to continue from in rsi.
+fn read_klog(ctx: &mut SnCpuContext, cursor: u64, ptr: u64, len: u64) {
+    // The log never holds more than this
+    let len = len.min(logbuf::BUF_SIZE as u64);
+    if !prepare_user_buffer(ptr, len) {
+        ctx.set_ret_val_1(1);
+        return;
+    }
+
+    let out = unsafe { slice::from_raw_parts_mut(ptr as *mut u8, len as usize) };
+    match logger::read_records(cursor, out) {
+        Some((written, next)) => {
+            ctx.set_ret_val_1(0);
+            ctx.set_arg_val_1(written);
+            ctx.set_arg_val_2(next as usize);
+        }
+        None => ctx.set_ret_val_1(1),
+    }
+}
+
+/// Fills the `SnDisplayMode` at `ptr` with the mode of the display
+fn display_info(ctx: &mut SnCpuContext, ptr: u64, _arg2: u64, _arg3: u64) {
+    let size = core::mem::size_of::<SnDisplayMode>() as u64;
+    if ptr % 4 != 0 || !prepare_user_buffer(ptr, size) {
+        ctx.set_ret_val_1(1);
+        return;
+    }
+
+    let Some(mode) = device::mode() else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+    unsafe { (ptr as *mut SnDisplayMode).write(mode) };
+    ctx.set_ret_val_1(0);
+}
+
+/// Maps the framebuffer into the current process and takes the screen over
+/// from the kernel console.
+///
+/// Returns the address of the framebuffer in rdi.
+fn map_display(ctx: &mut SnCpuContext, _arg1: u64, _arg2: u64, _arg3: u64) {
+    let Some(process_id) = process::thread::current_process_id() else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+
+    match device::map(process_id) {
+        Ok(address) => {
+            ctx.set_ret_val_1(0);
+            ctx.set_arg_val_1(address.as_u64() as usize);
+        }
+        Err(err) => ctx.set_ret_val_1(err as usize),
+    }
+}
+
+/// Unmaps the framebuffer and gives the screen back to the kernel console
+fn release_display(ctx: &mut SnCpuContext, _arg1: u64, _arg2: u64, _arg3: u64) {
+    if let Some(process_id) = process::thread::current_process_id() {
+        device::release(process_id, true);
+    }
+    ctx.set_ret_val_1(0);
+}
+
+/// Sets the `TERMIOS_` flags of the controlling tty of the process
+fn set_termios(ctx: &mut SnCpuContext, flags: u64, _arg2: u64, _arg3: u64) {
+    match (controlling_tty(), SnTermios::try_from(flags)) {
+        (Some((_, id)), Ok(termios)) => set_result(ctx, tty::set_termios(id, termios).map(|_| 0)),
+        _ => ctx.set_ret_val_1(1),
+    }
+}
+
+/// Returns the `TERMIOS_` flags of the controlling tty of the process in rdi
+fn get_termios(ctx: &mut SnCpuContext, _arg1: u64, _arg2: u64, _arg3: u64) {
+    match controlling_tty() {
+        Some((_, id)) => set_result(ctx, tty::termios(id).map(|termios| termios.flags as usize)),
+        None => ctx.set_ret_val_1(1),
+    }
+}
+
+/// Makes tty `id` the controlling tty of the process.
+///
+/// A session leader takes the tty over for its session, with its group in
+/// the foreground.
+fn set_tty(ctx: &mut SnCpuContext, id: u64, _arg2: u64, _arg3: u64) {
+    let (Some(process), Ok(id)) = (process::thread::current_process(), SnTtyId::try_from(id)) else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+
+    let mut job = process.job.lock();
+    if job.session_id == process.id {
+        if let Err(err) = tty::attach(id, job.session_id, job.group_id) {
+            ctx.set_ret_val_1(err as usize);
+            return;
+        }
+    }
+    job.tty = Some(id);
+    ctx.set_ret_val_1(0);
+}
+
+/// Opens a pseudo-terminal, returns the id of its slave side in rdi
+fn open_pty(ctx: &mut SnCpuContext, _arg1: u64, _arg2: u64, _arg3: u64) {
+    set_result(ctx, tty::open_pty().map(|id| id.as_u64() as usize));
+}
+
+fn close_pty(ctx: &mut SnCpuContext, id: u64, _arg2: u64, _arg3: u64) {
+    match SnTtyId::try_from(id) {
+        Ok(id) => set_result(ctx, tty::close_pty(id).map(|_| 0)),
+        Err(_) => ctx.set_ret_val_1(1),
+    }
+}
+
+/// Reads the output of pty `id` into the buffer at `ptr`, waiting for some.
+///
+/// Returns the bytes read in rdi, at most `MAX_READ`.
+fn read_pty(ctx: &mut SnCpuContext, id: u64, ptr: u64, len: u64) {
+    let Ok(id) = SnTtyId::try_from(id) else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+    let len = len.min(MAX_READ);
+    if len == 0 || !prepare_user_buffer(ptr, len) {
+        ctx.set_ret_val_1(1);
+        return;
+    }
+
+    // Waiting may run other processes, but not unmap this buffer
+    let out = unsafe { slice::from_raw_parts_mut(ptr as *mut u8, len as usize) };
+    set_result(ctx, tty::read_master(id, out));
+}
+
+/// Passes the buffer at `ptr` to pty `id` as input.
+///
+/// Returns the bytes written in rdi.
+fn write_pty(ctx: &mut SnCpuContext, id: u64, ptr: u64, len: u64) {
+    let Ok(id) = SnTtyId::try_from(id) else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+    if !in_user_space(ptr, len) {
+        ctx.set_ret_val_1(1);
+        return;
+    }
+
+    let bytes = unsafe { slice::from_raw_parts(ptr as *const u8, len as usize) };
+    set_result(ctx, tty::write_master(id, bytes));
+}
+
+/// Starts a new session and process group led by the process, without a
+/// controlling tty. Fails for a process that already leads a group.
+///
+/// Returns the session id in rdi.
+fn set_sid(ctx: &mut SnCpuContext, _arg1: u64, _arg2: u64, _arg3: u64) {
+    let Some(process) = process::thread::current_process() else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+
+    let mut job = process.job.lock();
+    if job.group_id == process.id {
+        ctx.set_ret_val_1(1);
+        return;
+    }
+    job.session_id = process.id;
+    job.group_id = process.id;
+    job.tty = None;
+    set_result::<usize>(ctx, Ok(process.id as usize));
+}
+
+/// Moves process `id`, the caller or one of its children (0 for the
+/// caller), to group `group_id` in the same session (0 for a new group led
+/// by the process).
+fn set_pgid(ctx: &mut SnCpuContext, id: u64, group_id: u64, _arg3: u64) {
+    let Some(caller) = process::thread::current_process() else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+    let target = match id {
+        0 => Some(caller.clone()),
+        id if id == caller.id => Some(caller.clone()),
+        id => process::thread::find_process(id).filter(|process| process.parent_id == caller.id),
+    };
+    let Some(target) = target else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+    let group_id = if group_id == 0 { target.id } else { group_id };
+
+    let session_id = caller.job.lock().session_id;
+    let allowed = {
+        let job = target.job.lock();
+        // Session leaders stay in their own group
+        job.session_id == session_id && job.session_id != target.id
+    };
+    // Joining a group needs someone in it
+    if !allowed || (group_id != target.id && !process::thread::group_exists(session_id, group_id)) {
+        ctx.set_ret_val_1(1);
+        return;
+    }
+
+    target.job.lock().group_id = group_id;
+    ctx.set_ret_val_1(0);
+}
+
+/// Returns the id of the process in rdi and of its group in rsi
+fn process_ids(ctx: &mut SnCpuContext, _arg1: u64, _arg2: u64, _arg3: u64) {
+    let Some(process) = process::thread::current_process() else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+    let group_id = process.job.lock().group_id;
+    ctx.set_ret_val_1(0);
+    ctx.set_arg_val_1(process.id as usize);
+    ctx.set_arg_val_2(group_id as usize);
+}
+
+/// Puts group `group_id` of the session in the foreground of the
+/// controlling tty of the process
+fn set_foreground(ctx: &mut SnCpuContext, group_id: u64, _arg2: u64, _arg3: u64) {
+    let Some((process, id)) = controlling_tty() else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+    let session_id = process.job.lock().session_id;
+    if !process::thread::group_exists(session_id, group_id) {
+        ctx.set_ret_val_1(1);
+        return;
+    }
+    set_result(ctx, tty::set_foreground(id, session_id, group_id).map(|_| 0));
+}
+
+/// Sends a signal to process `target`, or to group `-target`, or to the
+/// group of the caller for 0
+fn kill(ctx: &mut SnCpuContext, target: u64, signal: u64, _arg3: u64) {
+    let (Some(process), Ok(signal)) = (process::thread::current_process(), SnSignal::try_from(signal)) else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+    let target = match target as i64 {
+        0 => SnSignalTarget::Group(process.job.lock().group_id),
+        id if id < 0 => SnSignalTarget::Group(id.unsigned_abs()),
+        id => SnSignalTarget::Process(id as u64),
+    };
+    // Dropped before the signal may kill the caller
+    drop(process);
+
+    if process::thread::send_signal(target, signal) {
+        ctx.set_ret_val_1(0);
+    } else {
+        ctx.set_ret_val_1(1);
+    }
 }
 
-fn exit(ctx: &mut SnCpuContext, _arg1: u64, _arg2: u64, _arg3: u64) {
-    process::thread::exit_current_thread(ctx);
-}
\ No newline at end of file
+/// Sets what `signal` does to the process
+fn set_signal_action(ctx: &mut SnCpuContext, signal: u64, action: u64, _arg3: u64) {
+    let (Some(process), Ok(signal), Ok(action)) = (
+        process::thread::current_process(),
+        SnSignal::try_from(signal),
+        SnSignalAction::try_from(action),
+    ) else {
+        ctx.set_ret_val_1(1);
+        return;
+    };
+
+    if process.signals.set_action(signal, action) {
+        ctx.set_ret_val_1(0);
+    } else {
+        ctx.set_ret_val_1(1);
+    }
+}
+
+/// Returns the bits of the caught signals in rdi, by signal number, and
+/// clears them
+fn take_signals(ctx: &mut SnCpuContext, _arg1: u64, _arg2: u64, _arg3: u64) {
+    match process::thread::current_process() {
+        Some(process) => set_result::<usize>(ctx, Ok(process.signals.take_caught() as usize)),
+        None => ctx.set_ret_val_1(1),
+    }
+}
+
+/// Switches the keyboard layout to the one named by the string at `ptr`
+fn set_keymap(ctx: &mut SnCpuContext, ptr: u64, len: u64, _arg3: u64) {
+    // Only read, the string may sit on read-only pages
+    if len == 0 || len > MAX_KEYMAP_NAME || !in_user_space(ptr, len) {
+        ctx.set_ret_val_1(1);
+        return;
+    }
+
+    let name = unsafe { slice::from_raw_parts(ptr as *const u8, len as usize) };
+    match str::from_utf8(name) {
+        Ok(name) if ps2_keyboard::set_layout(name) => ctx.set_ret_val_1(0),
+        _ => ctx.set_ret_val_1(1),
+    }
+}
+
+#[test_case]
+fn test_kernel_heap_is_not_user_memory() {
+    let mut ctx: SnCpuContext = unsafe { core::mem::zeroed() };
+    let heap = crate::memory::alloc::HEAP_START as u64;
+    meminfo(&mut ctx, heap, 0, 0);
+    assert_eq!({ ctx.rax }, 1);
+    assert!(!in_user_space(heap, 64));
+}
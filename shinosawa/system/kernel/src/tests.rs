@@ -1,10 +1,31 @@
+#[cfg(test)]
+pub trait Testable {
+    fn name(&self) -> &'static str;
+    fn run(&self);
+}
 
 #[cfg(test)]
-pub fn test_runner(tests: &[&dyn Fn()]) {
+impl<T: Fn()> Testable for T {
+    fn name(&self) -> &'static str {
+        core::any::type_name::<T>()
+    }
+
+    fn run(&self) {
+        self();
+    }
+}
+
+/// Runs the tests, or only those with the `test=` option of the command line in their name
+#[cfg(test)]
+pub fn test_runner(tests: &[&dyn Testable]) {
     use crate::printk;
 
-    printk!("running {} tests", tests.len());
-    for test in tests {
-        test();
+    let filter = crate::cmdline::options().test_filter.unwrap_or("");
+    let selected = tests.iter().filter(|test| test.name().contains(filter)).count();
+
+    printk!("running {} of {} tests", selected, tests.len());
+    for test in tests.iter().filter(|test| test.name().contains(filter)) {
+        printk!("{}", test.name());
+        test.run();
     }
-}
\ No newline at end of file
+}
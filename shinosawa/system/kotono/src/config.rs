@@ -0,0 +1,35 @@
+use alloc::{string::String, vec, vec::Vec};
+use shinosawa_system_sysface::syscall;
+
+pub use shinosawa_system_kotono_config::{order, parse, Restart, ServiceConfig};
+
+/// Used when the service description file cannot be read
+const SHELL: &str = "SNSW:/shinosawa/system/shell";
+
+/// The shell on the console, when there is no service description
+pub fn fallback() -> Vec<ServiceConfig> {
+    vec![ServiceConfig {
+        path: String::from(SHELL),
+        args: Vec::from(SHELL.as_bytes()),
+        restart: Restart::Always,
+        console: true,
+        ..ServiceConfig::new("shell")
+    }]
+}
+
+/// Reads the whole file at `path`
+pub fn read(path: &str) -> Option<String> {
+    let fd = syscall::open(path, 0).ok()?;
+    let mut contents = Vec::new();
+    let mut buf = [0u8; 512];
+    let complete = loop {
+        match syscall::read_file(fd, &mut buf) {
+            Ok(0) => break true,
+            Ok(len) => contents.extend_from_slice(&buf[..len]),
+            Err(_) => break false,
+        }
+    };
+    let _ = syscall::close(fd);
+
+    complete.then(|| String::from_utf8(contents).ok()).flatten()
+}
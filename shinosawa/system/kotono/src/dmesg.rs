@@ -0,0 +1,32 @@
+use shinosawa_system_sysface::{_print, klog, print, println, syscall};
+
+/// Prints the kernel log records numbered `cursor` and later, at `max_level` or
+/// more severe.
+///
+/// Returns the cursor to pass next time to only print newer records.
+pub fn dmesg(mut cursor: u64, max_level: u8) -> u64 {
+    let mut buf = [0u8; 4096];
+
+    loop {
+        let Ok((len, next)) = syscall::read_klog(cursor, &mut buf) else {
+            println!("dmesg: cannot read the kernel log");
+            return cursor;
+        };
+
+        for record in klog::records(&buf[..len]) {
+            if record.seq > cursor {
+                // The kernel dropped these before we got to them
+                println!("dmesg: {} records lost", record.seq - cursor);
+            }
+            cursor = record.seq + 1;
+
+            if record.level <= max_level {
+                println!("{}", record);
+            }
+        }
+
+        if len == 0 {
+            return next;
+        }
+    }
+}
@@ -1,21 +1,53 @@
 #![no_std]
 #![no_main]
 
-use core::{arch::asm, panic::PanicInfo};
-#[macro_use]
-use shinosawa_system_sysface::{_print, print, println, syscall};
+extern crate alloc;
+
+use shinosawa_system_sysface::{_print, klog, print, println, syscall};
+
+use supervisor::Supervisor;
+
+mod config;
+mod dmesg;
+mod supervisor;
+
+/// Describes the services to start
+const CONFIG: &str = "SNSW:/shinosawa/config";
 
 #[unsafe(no_mangle)]
 unsafe extern "C" fn main() -> ! {
     println!("shinosawa::system::kotono: starting init");
 
-    extern "C" fn a(a: usize) {
-        println!("shinosawa::system::kotono: we are at pid {:x}", a);
-
-        syscall::exit();
+    if let Ok(info) = syscall::meminfo() {
+        println!(
+            "shinosawa::system::kotono: {}/{} frames free, {} resident pages",
+            info.free_frames, info.total_frames, info.process_resident_pages
+        );
     }
-    let tid = syscall::fork(a, 5).unwrap();
-    println!("shinosawa::system::kotono: we forked with tid {:?}", tid);
 
-    syscall::exit();
-}
\ No newline at end of file
+    println!("shinosawa::system::kotono: kernel warnings since boot:");
+    dmesg::dmesg(0, klog::LEVEL_WARN);
+
+    // Init must not go with a Ctrl-C or Ctrl-Z on its tty
+    let _ = syscall::set_signal_action(syscall::Signal::Interrupt, syscall::SignalAction::Catch);
+    let _ = syscall::set_signal_action(syscall::Signal::TerminalStop, syscall::SignalAction::Ignore);
+
+    let services = match config::read(CONFIG) {
+        Some(text) => {
+            let (services, mut errors) = config::parse(&text);
+            let services = config::order(services, &mut errors);
+            for error in errors {
+                println!("shinosawa::system::kotono: {}: {}", CONFIG, error);
+            }
+            services
+        }
+        None => {
+            println!("shinosawa::system::kotono: cannot read {}, starting the shell", CONFIG);
+            config::fallback()
+        }
+    };
+
+    let mut supervisor = Supervisor::new(services);
+    supervisor.start_all();
+    supervisor.run();
+}
@@ -0,0 +1,197 @@
+use core::time::Duration;
+
+use alloc::vec::Vec;
+use shinosawa_system_sysface::{_print, print, println, syscall::{self, ExitStatus}};
+
+use crate::config::{Restart, ServiceConfig};
+
+/// Wait before starting a service again the first time it ends soon
+const RESTART_DELAY: Duration = Duration::from_millis(500);
+/// Longest wait, however often a service ends
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(30);
+/// A service that ran this long is started again at once
+const STABLE_TIME: Duration = Duration::from_secs(10);
+/// Longest sleep while services wait to be started again, to see to the
+/// ones that end in the meantime
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+struct Service {
+    config: ServiceConfig,
+    /// Process running it
+    pid: Option<u64>,
+    started_at: Duration,
+    /// Times in a row it ended soon after starting
+    failures: u32,
+    /// When to start it again
+    restart_at: Option<Duration>,
+}
+
+pub struct Supervisor {
+    /// In the order to start them
+    services: Vec<Service>,
+    /// Group of kotono, in the foreground when no service has the console
+    pgid: u64,
+}
+
+fn now() -> Duration {
+    syscall::uptime().unwrap_or(Duration::ZERO)
+}
+
+/// How long to wait before starting a service that ended `failures` times
+/// in a row
+fn restart_delay(failures: u32) -> Duration {
+    match failures {
+        0 => Duration::ZERO,
+        failures => RESTART_DELAY.saturating_mul(1 << (failures - 1).min(16)).min(MAX_RESTART_DELAY),
+    }
+}
+
+impl Supervisor {
+    /// Takes the services in dependency order
+    pub fn new(services: Vec<ServiceConfig>) -> Supervisor {
+        let services = services
+            .into_iter()
+            .map(|config| Service { config, pid: None, started_at: Duration::ZERO, failures: 0, restart_at: None })
+            .collect();
+        let (_, pgid) = syscall::process_ids().unwrap_or((0, 0));
+        Supervisor { services, pgid }
+    }
+
+    /// Starts every service, each after the ones it depends on
+    pub fn start_all(&mut self) {
+        for index in 0..self.services.len() {
+            self.start(index);
+        }
+    }
+
+    fn start(&mut self, index: usize) {
+        let service = &mut self.services[index];
+        service.restart_at = None;
+
+        // Services other than the one on the console do not read from it
+        let stdin = if service.config.console { syscall::STDIN } else { syscall::SPAWN_NO_FILE };
+        let files = [stdin, syscall::STDOUT, syscall::STDERR];
+        match syscall::spawn(&service.config.path, &service.config.args, files, syscall::SPAWN_NEW_GROUP) {
+            Ok(pid) => {
+                println!("shinosawa::system::kotono: started {} with pid {}", service.config.name, pid);
+                service.pid = Some(pid);
+                service.started_at = now();
+                if service.config.console {
+                    let _ = syscall::set_foreground(pid);
+                }
+            }
+            Err(err) => {
+                println!("shinosawa::system::kotono: cannot start {}: {:?}", service.config.name, err);
+                service.failures += 1;
+                self.schedule_restart(index, true);
+            }
+        }
+    }
+
+    /// Tells how the service that ran in `pid` ended and plans when to start
+    /// it again. Other children are ignored.
+    fn ended(&mut self, pid: u64, status: ExitStatus) {
+        let Some(index) = self.services.iter().position(|service| service.pid == Some(pid)) else {
+            return;
+        };
+        let service = &mut self.services[index];
+        service.pid = None;
+        if service.config.console {
+            let _ = syscall::set_foreground(self.pgid);
+        }
+
+        let name = &service.config.name;
+        match status {
+            ExitStatus::Exited(code) => {
+                println!("shinosawa::system::kotono: {} exited with {}", name, code);
+            }
+            ExitStatus::Signaled(signal) => {
+                println!("shinosawa::system::kotono: {} was killed by signal {}", name, signal);
+            }
+            ExitStatus::Faulted(vector) => {
+                println!("shinosawa::system::kotono: {} crashed with exception {}", name, vector);
+            }
+            ExitStatus::Stopped(signal) => {
+                println!("shinosawa::system::kotono: {} stopped by signal {}", name, signal);
+            }
+        }
+
+        if now().saturating_sub(service.started_at) >= STABLE_TIME {
+            service.failures = 0;
+        } else {
+            service.failures += 1;
+        }
+        self.schedule_restart(index, status != ExitStatus::Exited(0));
+    }
+
+    fn schedule_restart(&mut self, index: usize, failed: bool) {
+        let service = &mut self.services[index];
+        let restart = match service.config.restart {
+            Restart::Always => true,
+            Restart::OnFailure => failed,
+            Restart::Never => false,
+        };
+        if !restart {
+            println!("shinosawa::system::kotono: not starting {} again", service.config.name);
+            return;
+        }
+
+        let delay = restart_delay(service.failures);
+        if !delay.is_zero() {
+            println!("shinosawa::system::kotono: starting {} again in {} ms", service.config.name, delay.as_millis());
+        }
+        service.restart_at = Some(now() + delay);
+    }
+
+    /// Starts the services whose time came
+    fn restart_due(&mut self) {
+        let time = now();
+        for index in 0..self.services.len() {
+            if self.services[index].restart_at.is_some_and(|at| at <= time) {
+                self.start(index);
+            }
+        }
+    }
+
+    /// Sees to the children that ended, without blocking
+    fn reap(&mut self) {
+        while let Ok((pid, status)) = syscall::wait(0, syscall::WAIT_NO_HANG) {
+            if pid == 0 {
+                break;
+            }
+            self.ended(pid, status);
+        }
+    }
+
+    /// Looks after the services for good
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.reap();
+            self.restart_due();
+
+            let next_restart = self.services.iter().filter_map(|service| service.restart_at).min();
+            if let Some(at) = next_restart {
+                if syscall::sleep(at.saturating_sub(now()).min(POLL_INTERVAL)).is_err() {
+                    let _ = syscall::take_signals();
+                }
+                continue;
+            }
+
+            match syscall::wait(0, 0) {
+                Ok((pid, status)) => self.ended(pid, status),
+                // Ctrl-C on the console while no service had it
+                Err(err) if err.code() == syscall::READ_INTERRUPTED => {
+                    let _ = syscall::take_signals();
+                }
+                Err(_) => break,
+            }
+        }
+
+        println!("shinosawa::system::kotono: no services left to look after");
+        loop {
+            if syscall::sleep(MAX_RESTART_DELAY).is_err() {
+                let _ = syscall::take_signals();
+            }
+        }
+    }
+}
@@ -0,0 +1,251 @@
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+use alloc::{format, string::String, vec::Vec};
+
+/// When a service is started again after it ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Restart {
+    Always,
+    /// Unless it exited with 0
+    OnFailure,
+    Never,
+}
+
+/// A service as described in the file
+pub struct ServiceConfig {
+    pub name: String,
+    pub path: String,
+    /// Arguments to spawn with, NUL-separated, the path first
+    pub args: Vec<u8>,
+    /// Services started before this one
+    pub after: Vec<String>,
+    pub restart: Restart,
+    /// Reads from the console and has it in the foreground
+    pub console: bool,
+}
+
+impl ServiceConfig {
+    /// A service with nothing to exec yet
+    pub fn new(name: &str) -> ServiceConfig {
+        ServiceConfig {
+            name: String::from(name),
+            path: String::new(),
+            args: Vec::new(),
+            after: Vec::new(),
+            restart: Restart::OnFailure,
+            console: false,
+        }
+    }
+}
+
+/// Parses a service description file.
+///
+/// Returns the services that are complete, with the problems found on the
+/// way. Lines in error are skipped.
+pub fn parse(text: &str) -> (Vec<ServiceConfig>, Vec<String>) {
+    let mut services: Vec<ServiceConfig> = Vec::new();
+    let mut errors = Vec::new();
+    // Service the lines are about, none after a rejected `service` line
+    let mut current: Option<usize> = None;
+
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let line = line.split('#').next().unwrap_or("");
+        let mut words = line.split_whitespace();
+        let Some(keyword) = words.next() else {
+            continue;
+        };
+        let values: Vec<&str> = words.collect();
+
+        if keyword == "service" {
+            current = None;
+            match values.as_slice() {
+                [name] if services.iter().any(|service| service.name == *name) => {
+                    errors.push(format!("line {}: service {} is described twice", number, name));
+                }
+                [name] => {
+                    current = Some(services.len());
+                    services.push(ServiceConfig::new(name));
+                }
+                _ => errors.push(format!("line {}: expected one name after service", number)),
+            }
+            continue;
+        }
+
+        let Some(service) = current.map(|index| &mut services[index]) else {
+            errors.push(format!("line {}: {} is not part of a valid service", number, keyword));
+            continue;
+        };
+        match (keyword, values.as_slice()) {
+            ("exec", [path, ..]) => {
+                service.path = String::from(*path);
+                service.args = values.join("\0").into_bytes();
+            }
+            ("after", [_, ..]) => service.after.extend(values.iter().map(|&name| String::from(name))),
+            ("restart", ["always"]) => service.restart = Restart::Always,
+            ("restart", ["on-failure"]) => service.restart = Restart::OnFailure,
+            ("restart", ["never"]) => service.restart = Restart::Never,
+            ("console", []) => service.console = true,
+            _ => errors.push(format!("line {}: cannot make sense of {}", number, line.trim())),
+        }
+    }
+
+    services.retain(|service| {
+        if service.path.is_empty() {
+            errors.push(format!("service {} has nothing to exec", service.name));
+        }
+        !service.path.is_empty()
+    });
+    if services.iter().filter(|service| service.console).count() > 1 {
+        errors.push(String::from("only the first console service gets the console"));
+        services.iter_mut().filter(|service| service.console).skip(1).for_each(|service| service.console = false);
+    }
+    (services, errors)
+}
+
+/// Orders the services so that each comes after those it names in `after`,
+/// keeping the order of the file otherwise.
+///
+/// Services in a loop of `after`, and those after them, are left out.
+/// Unknown services in `after` are reported and not waited for.
+pub fn order(services: Vec<ServiceConfig>, errors: &mut Vec<String>) -> Vec<ServiceConfig> {
+    let known = |name: &String| services.iter().any(|service| &service.name == name);
+    for service in &services {
+        for name in service.after.iter().filter(|name| !known(name)) {
+            errors.push(format!("service {} is after {}, which is not described", service.name, name));
+        }
+    }
+
+    let mut pending: Vec<Option<ServiceConfig>> = services.into_iter().map(Some).collect();
+    let mut ordered: Vec<ServiceConfig> = Vec::new();
+    loop {
+        let ready = pending.iter().position(|service| {
+            service.as_ref().is_some_and(|service| {
+                // Waits for the services still pending
+                !service.after.iter().any(|name| {
+                    pending.iter().flatten().any(|other| &other.name == name)
+                })
+            })
+        });
+        match ready {
+            Some(index) => ordered.push(pending[index].take().unwrap()),
+            None => break,
+        }
+    }
+
+    for service in pending.into_iter().flatten() {
+        errors.push(format!("service {} is in or after a loop of services, not starting it", service.name));
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(services: &[ServiceConfig]) -> Vec<&str> {
+        services.iter().map(|service| service.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_parse_service() {
+        let text = "\
+# comment
+service shell
+    exec SNSW:/shinosawa/system/shell -i # trailing comment
+    restart always
+    console
+";
+        let (services, errors) = parse(text);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(names(&services), ["shell"]);
+        assert_eq!(services[0].path, "SNSW:/shinosawa/system/shell");
+        assert_eq!(services[0].args, b"SNSW:/shinosawa/system/shell\0-i");
+        assert_eq!(services[0].restart, Restart::Always);
+        assert!(services[0].console);
+    }
+
+    #[test]
+    fn test_parse_malformed_lines() {
+        let text = "\
+exec SNSW:/orphan
+service
+    exec SNSW:/lost
+service a
+    exec SNSW:/a
+    restart sometimes
+    console now
+service a
+    exec SNSW:/again
+service b
+";
+        let (services, errors) = parse(text);
+        assert_eq!(names(&services), ["a"]);
+        // Not applied to `a` from the rejected lines around it
+        assert_eq!(services[0].path, "SNSW:/a");
+        assert_eq!(services[0].restart, Restart::OnFailure);
+        assert!(!services[0].console);
+        assert_eq!(
+            errors,
+            [
+                "line 1: exec is not part of a valid service",
+                "line 2: expected one name after service",
+                "line 3: exec is not part of a valid service",
+                "line 6: cannot make sense of restart sometimes",
+                "line 7: cannot make sense of console now",
+                "line 8: service a is described twice",
+                "line 9: exec is not part of a valid service",
+                "service b has nothing to exec",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_order_unknown_dependency() {
+        let text = "\
+service a
+    exec SNSW:/a
+    after b missing
+service b
+    exec SNSW:/b
+";
+        let (services, mut errors) = parse(text);
+        let services = order(services, &mut errors);
+        assert_eq!(names(&services), ["b", "a"]);
+        assert_eq!(errors, ["service a is after missing, which is not described"]);
+    }
+
+    #[test]
+    fn test_order_dependency_cycle() {
+        let text = "\
+service a
+    exec SNSW:/a
+    after c
+service b
+    exec SNSW:/b
+    after a
+service c
+    exec SNSW:/c
+    after b
+service d
+    exec SNSW:/d
+service e
+    exec SNSW:/e
+    after b
+";
+        let (services, mut errors) = parse(text);
+        let services = order(services, &mut errors);
+        assert_eq!(names(&services), ["d"]);
+        assert_eq!(
+            errors,
+            [
+                "service a is in or after a loop of services, not starting it",
+                "service b is in or after a loop of services, not starting it",
+                "service c is in or after a loop of services, not starting it",
+                "service e is in or after a loop of services, not starting it",
+            ]
+        );
+    }
+}
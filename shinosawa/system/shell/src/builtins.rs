@@ -0,0 +1,217 @@
+use core::fmt::Write;
+
+use alloc::{string::String, vec::Vec};
+use shinosawa_system_sysface::syscall::{self, DirEntry, ProcessInfo, Signal};
+
+use crate::{
+    eprintln,
+    io::{Input, Output},
+    path, Shell,
+};
+
+/// Runs in the shell with the arguments, including its name, and returns
+/// the exit status
+pub type Builtin = fn(&mut Shell, &[String], &mut Input, &mut Output) -> u32;
+
+pub fn find(name: &str) -> Option<Builtin> {
+    let builtin: Builtin = match name {
+        "cd" => cd,
+        "pwd" => pwd,
+        "ls" => ls,
+        "cat" => cat,
+        "echo" => echo,
+        "ps" => ps,
+        "mem" => mem,
+        "jobs" => jobs,
+        "fg" => fg,
+        "reboot" => reboot,
+        "exit" => exit,
+        _ => return None,
+    };
+    Some(builtin)
+}
+
+/// Whether `path` can be listed
+fn is_dir(path: &str) -> bool {
+    let Ok(fd) = syscall::open(path, 0) else {
+        return false;
+    };
+    let listed = syscall::read_dir(fd, &mut []).is_ok();
+    let _ = syscall::close(fd);
+    listed
+}
+
+fn cd(shell: &mut Shell, args: &[String], _input: &mut Input, _output: &mut Output) -> u32 {
+    let target = match args.get(1) {
+        Some(dir) => path::resolve(&shell.cwd, dir),
+        None => String::from(path::HOME),
+    };
+    if !is_dir(&target) {
+        eprintln!("cd: {}: no such directory", target);
+        return 1;
+    }
+    shell.cwd = target;
+    0
+}
+
+fn pwd(shell: &mut Shell, _args: &[String], _input: &mut Input, output: &mut Output) -> u32 {
+    let _ = writeln!(output, "{}", shell.cwd);
+    0
+}
+
+fn ls(shell: &mut Shell, args: &[String], _input: &mut Input, output: &mut Output) -> u32 {
+    let targets = match args.len() {
+        1 => Vec::from([shell.cwd.clone()]),
+        _ => args[1..].iter().map(|arg| path::resolve(&shell.cwd, arg)).collect(),
+    };
+
+    let mut status = 0;
+    for target in &targets {
+        let Ok(fd) = syscall::open(target, 0) else {
+            eprintln!("ls: {}: not found", target);
+            status = 1;
+            continue;
+        };
+        if targets.len() > 1 {
+            let _ = writeln!(output, "{}:", target);
+        }
+
+        let mut entries = [DirEntry::EMPTY; 16];
+        loop {
+            match syscall::read_dir(fd, &mut entries) {
+                Ok(0) => break,
+                Ok(count) => {
+                    for entry in &entries[..count] {
+                        let suffix = if entry.kind == syscall::ENTRY_DIR { "/" } else { "" };
+                        let _ = writeln!(output, "{}{}", entry.name(), suffix);
+                    }
+                }
+                // A file, not a directory
+                Err(_) => {
+                    let _ = writeln!(output, "{}", target.rsplit('/').next().unwrap_or(target));
+                    break;
+                }
+            }
+        }
+        let _ = syscall::close(fd);
+    }
+    status
+}
+
+/// Copies `input` to `output`, returns false if either failed
+fn copy(input: &mut Input, output: &mut Output) -> bool {
+    let mut buf = [0u8; 512];
+    loop {
+        match input.read(&mut buf) {
+            Ok(0) => return true,
+            Ok(len) => {
+                if output.write_bytes(&buf[..len]).is_err() {
+                    return false;
+                }
+            }
+            Err(_) => return false,
+        }
+    }
+}
+
+fn cat(shell: &mut Shell, args: &[String], input: &mut Input, output: &mut Output) -> u32 {
+    if args.len() == 1 {
+        return !copy(input, output) as u32;
+    }
+
+    let mut status = 0;
+    for arg in &args[1..] {
+        let target = path::resolve(&shell.cwd, arg);
+        let Ok(fd) = syscall::open(&target, 0) else {
+            eprintln!("cat: {}: not found", target);
+            status = 1;
+            continue;
+        };
+        if !copy(&mut Input::Fd(fd), output) {
+            status = 1;
+        }
+        let _ = syscall::close(fd);
+    }
+    status
+}
+
+fn echo(_shell: &mut Shell, args: &[String], _input: &mut Input, output: &mut Output) -> u32 {
+    let _ = writeln!(output, "{}", args[1..].join(" "));
+    0
+}
+
+fn ps(_shell: &mut Shell, _args: &[String], _input: &mut Input, output: &mut Output) -> u32 {
+    let mut processes = [ProcessInfo::EMPTY; 64];
+    let Ok(count) = syscall::process_list(&mut processes) else {
+        eprintln!("ps: cannot list processes");
+        return 1;
+    };
+
+    let _ = writeln!(output, "  PID  PPID  PGID STATE    THR NAME");
+    for process in &processes[..count.min(processes.len())] {
+        let state = match process.state {
+            syscall::PROCESS_STOPPED => "stopped",
+            syscall::PROCESS_SLEEPING => "sleeping",
+            _ => "running",
+        };
+        let _ = writeln!(
+            output,
+            "{:5} {:5} {:5} {:8} {:3} {}",
+            process.pid, process.parent_pid, process.pgid, state, process.threads, process.name()
+        );
+    }
+    0
+}
+
+fn mem(_shell: &mut Shell, _args: &[String], _input: &mut Input, output: &mut Output) -> u32 {
+    let Ok(info) = syscall::meminfo() else {
+        eprintln!("mem: cannot read the memory counters");
+        return 1;
+    };
+
+    let _ = writeln!(output, "frames: {} free of {}", info.free_frames, info.total_frames);
+    let _ = writeln!(output, "user frames: {}, page tables: {}", info.user_frames, info.page_table_frames);
+    let _ = writeln!(output, "kernel heap: {} of {} bytes used", info.heap_used, info.heap_size);
+    0
+}
+
+fn jobs(shell: &mut Shell, _args: &[String], _input: &mut Input, output: &mut Output) -> u32 {
+    for (index, job) in shell.jobs.iter().enumerate() {
+        let _ = writeln!(output, "[{}] stopped  {}", index + 1, job.command);
+    }
+    0
+}
+
+fn fg(shell: &mut Shell, args: &[String], _input: &mut Input, _output: &mut Output) -> u32 {
+    let index = match args.get(1).map(|arg| arg.trim_start_matches('%').parse::<usize>()) {
+        None => shell.jobs.len(),
+        Some(Ok(number)) => number,
+        Some(Err(_)) => 0,
+    };
+    if index == 0 || index > shell.jobs.len() {
+        eprintln!("fg: no such job");
+        return 1;
+    }
+
+    let job = shell.jobs.remove(index - 1);
+    eprintln!("{}", job.command);
+    let _ = syscall::set_foreground(job.pgid);
+    let _ = syscall::kill(-(job.pgid as i64), Signal::Continue);
+    shell.wait_job(job)
+}
+
+fn reboot(_shell: &mut Shell, _args: &[String], _input: &mut Input, _output: &mut Output) -> u32 {
+    syscall::reboot();
+}
+
+fn exit(shell: &mut Shell, args: &[String], _input: &mut Input, _output: &mut Output) -> u32 {
+    let code = match args.get(1) {
+        Some(code) => code.parse().unwrap_or(1),
+        None => shell.status,
+    };
+    // Stopped jobs would never run again
+    for job in &shell.jobs {
+        let _ = syscall::kill(-(job.pgid as i64), Signal::Kill);
+    }
+    syscall::exit(code);
+}
@@ -0,0 +1,223 @@
+use alloc::{string::String, vec::Vec};
+use shinosawa_system_sysface::{_print, print, println, syscall};
+
+/// Lines kept for the up and down keys
+const MAX_HISTORY: usize = 100;
+
+pub enum ReadLine {
+    Line(String),
+    /// Ctrl-C dropped the line
+    Interrupted,
+    /// Ctrl-D on an empty line, or the end of the input
+    End,
+}
+
+/// Line being edited, in characters
+struct Line {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl Line {
+    fn set(&mut self, text: &str) {
+        self.chars = text.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    fn insert(&mut self, c: char) {
+        self.chars.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    /// Prints the prompt and line again, with the cursor in place
+    fn redraw(&self, prompt: &str) {
+        print!("\r{}{}\x1b[K", prompt, self.text());
+        let back = self.chars.len() - self.cursor;
+        if back > 0 {
+            print!("\x1b[{}D", back);
+        }
+    }
+}
+
+pub struct Editor {
+    history: Vec<String>,
+    /// Read past the last line, without a tty
+    pending: Vec<u8>,
+}
+
+impl Editor {
+    pub fn new() -> Editor {
+        Editor { history: Vec::new(), pending: Vec::new() }
+    }
+
+    /// Shows `prompt` and reads a line from `STDIN`.
+    ///
+    /// The line is edited here with the tty in non-canonical mode, which
+    /// is put back as it was before returning.
+    pub fn read_line(&mut self, prompt: &str) -> ReadLine {
+        print!("{}", prompt);
+        let Ok(saved) = syscall::get_termios() else {
+            return self.read_plain();
+        };
+
+        // Ctrl-C and Ctrl-Z still signal, the rest comes here
+        let _ = syscall::set_termios(syscall::TERMIOS_SIGNALS);
+        let result = self.edit(prompt);
+        let _ = syscall::set_termios(saved);
+
+        if let ReadLine::Line(line) = &result
+            && !line.trim().is_empty()
+            && self.history.last() != Some(line)
+        {
+            if self.history.len() == MAX_HISTORY {
+                self.history.remove(0);
+            }
+            self.history.push(line.clone());
+        }
+        result
+    }
+
+    fn edit(&mut self, prompt: &str) -> ReadLine {
+        let mut line = Line { chars: Vec::new(), cursor: 0 };
+        // Browsed history entry, the line being typed is kept in `draft`
+        let mut history_index = self.history.len();
+        let mut draft = String::new();
+        let mut escape = Vec::new();
+        let mut utf8 = Vec::new();
+        let mut buf = [0u8; 64];
+
+        loop {
+            let len = match syscall::read(&mut buf) {
+                Ok(0) => return ReadLine::End,
+                Ok(len) => len,
+                Err(err) if err.code() == syscall::READ_INTERRUPTED => {
+                    let _ = syscall::take_signals();
+                    println!("^C");
+                    return ReadLine::Interrupted;
+                }
+                Err(_) => return ReadLine::End,
+            };
+
+            for &byte in &buf[..len] {
+                if !escape.is_empty() {
+                    escape.push(byte);
+                    match escape.as_slice() {
+                        // Not complete yet
+                        [0x1b, b'['] | [0x1b, b'[', b'0'..=b'9'] => continue,
+                        [0x1b, b'[', b'A'] if history_index > 0 => {
+                            if history_index == self.history.len() {
+                                draft = line.text();
+                            }
+                            history_index -= 1;
+                            line.set(&self.history[history_index]);
+                        }
+                        [0x1b, b'[', b'B'] if history_index < self.history.len() => {
+                            history_index += 1;
+                            match self.history.get(history_index) {
+                                Some(entry) => line.set(entry),
+                                None => line.set(&draft),
+                            }
+                        }
+                        [0x1b, b'[', b'C'] => line.cursor = (line.cursor + 1).min(line.chars.len()),
+                        [0x1b, b'[', b'D'] => line.cursor = line.cursor.saturating_sub(1),
+                        [0x1b, b'[', b'H'] => line.cursor = 0,
+                        [0x1b, b'[', b'F'] => line.cursor = line.chars.len(),
+                        [0x1b, b'[', b'3', b'~'] => line.delete(),
+                        // Unknown sequences are dropped
+                        _ => {}
+                    }
+                    escape.clear();
+                    line.redraw(prompt);
+                    continue;
+                }
+
+                match byte {
+                    b'\r' | b'\n' => {
+                        println!();
+                        return ReadLine::Line(line.text());
+                    }
+                    0x1b => {
+                        escape.push(byte);
+                        continue;
+                    }
+                    // Ctrl-D
+                    0x04 if line.chars.is_empty() => {
+                        println!();
+                        return ReadLine::End;
+                    }
+                    0x04 => line.delete(),
+                    0x7f | 0x08 => line.backspace(),
+                    // Ctrl-A and Ctrl-E
+                    0x01 => line.cursor = 0,
+                    0x05 => line.cursor = line.chars.len(),
+                    // Ctrl-U and Ctrl-K
+                    0x15 => {
+                        line.chars.drain(..line.cursor);
+                        line.cursor = 0;
+                    }
+                    0x0b => line.chars.truncate(line.cursor),
+                    // Ctrl-L
+                    0x0c => {
+                        print!("\x1b[2J\x1b[H");
+                    }
+                    byte if byte < 0x20 => continue,
+                    byte if byte < 0x80 => line.insert(byte as char),
+                    byte => {
+                        utf8.push(byte);
+                        match core::str::from_utf8(&utf8) {
+                            Ok(text) => {
+                                text.chars().for_each(|c| line.insert(c));
+                                utf8.clear();
+                            }
+                            Err(_) if utf8.len() >= 4 => utf8.clear(),
+                            Err(_) => continue,
+                        }
+                    }
+                }
+                line.redraw(prompt);
+            }
+        }
+    }
+
+    /// Reads a line without editing, when input does not come from a tty
+    fn read_plain(&mut self) -> ReadLine {
+        let mut buf = [0u8; 256];
+        loop {
+            if let Some(end) = self.pending.iter().position(|&byte| byte == b'\n') {
+                let line: Vec<u8> = self.pending.drain(..=end).collect();
+                return ReadLine::Line(String::from_utf8_lossy(&line[..end]).into_owned());
+            }
+
+            match syscall::read(&mut buf) {
+                Ok(0) if self.pending.is_empty() => return ReadLine::End,
+                Ok(0) => {
+                    let line = core::mem::take(&mut self.pending);
+                    return ReadLine::Line(String::from_utf8_lossy(&line).into_owned());
+                }
+                Ok(len) => self.pending.extend_from_slice(&buf[..len]),
+                Err(err) if err.code() == syscall::READ_INTERRUPTED => {
+                    let _ = syscall::take_signals();
+                    return ReadLine::Interrupted;
+                }
+                Err(_) => return ReadLine::End,
+            }
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use core::fmt;
+
+use alloc::vec::Vec;
+use shinosawa_system_sysface::syscall::{self, SyscallError};
+
+/// Where a builtin reads from
+pub enum Input {
+    Fd(u64),
+    /// Output of the builtin before it in the pipeline, and how much was read
+    Buffer(Vec<u8>, usize),
+}
+
+impl Input {
+    /// Reads like `syscall::read_file`, 0 at the end
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, SyscallError> {
+        match self {
+            Input::Fd(fd) => syscall::read_file(*fd, buf),
+            Input::Buffer(data, offset) => {
+                let rest = &data[*offset..];
+                let len = buf.len().min(rest.len());
+                buf[..len].copy_from_slice(&rest[..len]);
+                *offset += len;
+                Ok(len)
+            }
+        }
+    }
+}
+
+/// Where a builtin writes to
+pub enum Output {
+    Fd(u64),
+    /// Kept for the next builtin of the pipeline
+    Buffer(Vec<u8>),
+}
+
+impl Output {
+    pub fn write_bytes(&mut self, mut bytes: &[u8]) -> Result<(), SyscallError> {
+        match self {
+            Output::Fd(fd) => {
+                while !bytes.is_empty() {
+                    let written = syscall::write_file(*fd, bytes)?;
+                    bytes = &bytes[written..];
+                }
+            }
+            Output::Buffer(data) => data.extend_from_slice(bytes),
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Write for Output {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+/// Closes a file the shell opened for a command, leaving its own alone
+pub fn close_own(fd: u64) {
+    if fd > syscall::STDERR {
+        let _ = syscall::close(fd);
+    }
+}
+
+/// Prints to `STDERR`
+#[macro_export]
+macro_rules! eprintln {
+    ($($arg:tt)*) => {{
+        use core::fmt::Write;
+        let _ = writeln!($crate::io::Output::Fd(shinosawa_system_sysface::syscall::STDERR), $($arg)*);
+    }};
+}
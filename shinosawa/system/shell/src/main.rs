@@ -0,0 +1,301 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use shinosawa_system_sysface::{_print, print, println, syscall::{self, ExitStatus, Signal, SignalAction}};
+
+use io::{Input, Output};
+use parse::{Command, Redirect};
+
+mod builtins;
+mod editor;
+mod io;
+mod parse;
+mod path;
+
+/// Commands stopped with Ctrl-Z, continued with `fg`
+pub struct Job {
+    pub pgid: u64,
+    /// Processes still running
+    pub pids: Vec<u64>,
+    pub command: String,
+}
+
+pub struct Shell {
+    /// Current directory, relative paths start here
+    pub cwd: String,
+    pub jobs: Vec<Job>,
+    /// Exit status of the last command
+    pub status: u32,
+    pgid: u64,
+}
+
+impl Shell {
+    /// Finds the executable of a command, given by path or in the search path
+    fn find_program(&self, name: &str) -> Option<String> {
+        if name.contains('/') || name.contains(':') {
+            return Some(path::resolve(&self.cwd, name));
+        }
+        path::SEARCH_PATH.iter().map(|dir| path::resolve(dir, name)).find(|path| {
+            let found = syscall::open(path, 0);
+            if let Ok(fd) = found {
+                let _ = syscall::close(fd);
+            }
+            found.is_ok()
+        })
+    }
+
+    /// Opens the files of the redirections of a command, returns the input
+    /// and output to use instead of the pipeline's
+    fn redirect(&self, command: &Command) -> Result<(Option<u64>, Option<u64>), String> {
+        let mut input = None;
+        let mut output = None;
+        for redirect in &command.redirects {
+            let (path, flags, slot) = match redirect {
+                Redirect::Input(path) => (path, 0, &mut input),
+                Redirect::Output(path) => {
+                    (path, syscall::OPEN_WRITE | syscall::OPEN_CREATE | syscall::OPEN_TRUNCATE, &mut output)
+                }
+                Redirect::Append(path) => (path, syscall::OPEN_WRITE | syscall::OPEN_CREATE, &mut output),
+            };
+            let path = path::resolve(&self.cwd, path);
+            match syscall::open(&path, flags) {
+                Ok(fd) => {
+                    if let Some(previous) = slot.replace(fd) {
+                        io::close_own(previous);
+                    }
+                }
+                Err(_) => {
+                    input.into_iter().chain(output).for_each(io::close_own);
+                    return Err(path);
+                }
+            }
+        }
+        Ok((input, output))
+    }
+
+    /// Runs the commands of a pipeline, each reading what the one before wrote.
+    ///
+    /// Builtins run in the shell, their output kept for the next command.
+    /// Programs run in a new group, put in the foreground until they end or
+    /// stop.
+    fn run_pipeline(&mut self, pipeline: Vec<Command>, line: &str) {
+        let mut job = Job { pgid: 0, pids: Vec::new(), command: String::from(line.trim()) };
+        let mut input = Input::Fd(syscall::STDIN);
+        // Output of builtins, written to the programs after them once all started
+        let mut feeds = Vec::new();
+
+        for (index, command) in pipeline.iter().enumerate() {
+            let last = index + 1 == pipeline.len();
+            let (redirected_input, redirected_output) = match self.redirect(command) {
+                Ok(files) => files,
+                Err(path) => {
+                    eprintln!("shell: cannot open {}", path);
+                    self.status = 1;
+                    break;
+                }
+            };
+            if let Some(fd) = redirected_input {
+                close_input(&input);
+                input = Input::Fd(fd);
+            }
+
+            if let Some(builtin) = builtins::find(&command.args[0]) {
+                // Programs before may wait for their input
+                flush(&mut feeds);
+                let mut output = match redirected_output {
+                    Some(fd) => Output::Fd(fd),
+                    None if last => Output::Fd(syscall::STDOUT),
+                    None => Output::Buffer(Vec::new()),
+                };
+                self.status = builtin(self, &command.args, &mut input, &mut output);
+                close_input(&input);
+                input = match output {
+                    Output::Buffer(data) => Input::Buffer(data, 0),
+                    Output::Fd(fd) => {
+                        io::close_own(fd);
+                        Input::Buffer(Vec::new(), 0)
+                    }
+                };
+                continue;
+            }
+
+            let Some(program) = self.find_program(&command.args[0]) else {
+                eprintln!("shell: {}: command not found", command.args[0]);
+                redirected_output.into_iter().for_each(io::close_own);
+                self.status = 127;
+                break;
+            };
+
+            let stdin = match &mut input {
+                Input::Fd(fd) => *fd,
+                Input::Buffer(data, _) => match syscall::pipe() {
+                    Ok((read_fd, write_fd)) => {
+                        feeds.push((write_fd, core::mem::take(data)));
+                        read_fd
+                    }
+                    Err(_) => syscall::STDIN,
+                },
+            };
+            let mut next_input = Input::Buffer(Vec::new(), 0);
+            let stdout = match redirected_output {
+                Some(fd) => fd,
+                None if last => syscall::STDOUT,
+                None => match syscall::pipe() {
+                    Ok((read_fd, write_fd)) => {
+                        next_input = Input::Fd(read_fd);
+                        write_fd
+                    }
+                    Err(_) => syscall::STDOUT,
+                },
+            };
+
+            let args: Vec<u8> = command.args.join("\0").into_bytes();
+            let group = if job.pgid == 0 { syscall::SPAWN_NEW_GROUP } else { job.pgid };
+            let spawned = syscall::spawn(&program, &args, [stdin, stdout, syscall::STDERR], group);
+            // The program has its own copies, and readers see the end once
+            // it closes them
+            io::close_own(stdin);
+            io::close_own(stdout);
+            input = next_input;
+
+            match spawned {
+                Ok(pid) => {
+                    if job.pgid == 0 {
+                        job.pgid = pid;
+                    }
+                    job.pids.push(pid);
+                }
+                Err(_) => {
+                    eprintln!("shell: {}: cannot run", program);
+                    self.status = 126;
+                    break;
+                }
+            }
+        }
+
+        close_input(&input);
+        if job.pids.is_empty() {
+            flush(&mut feeds);
+            return;
+        }
+
+        let _ = syscall::set_foreground(job.pgid);
+        flush(&mut feeds);
+        self.status = self.wait_job(job);
+    }
+
+    /// Waits for the processes of a job in the foreground to end or stop,
+    /// then takes the terminal back.
+    ///
+    /// Returns the exit status of the last process.
+    pub fn wait_job(&mut self, mut job: Job) -> u32 {
+        let last = job.pids.last().copied();
+        let mut status = 0;
+        let mut stopped = false;
+
+        while let Some(&pid) = job.pids.first() {
+            match syscall::wait(pid, syscall::WAIT_STOPPED) {
+                Ok((_, ExitStatus::Stopped(_))) => {
+                    stopped = true;
+                    break;
+                }
+                Ok((_, exit_status)) => {
+                    job.pids.remove(0);
+                    if Some(pid) == last {
+                        status = report(&job.command, exit_status);
+                    }
+                }
+                // Ctrl-C is for the job, not the shell
+                Err(err) if err.code() == syscall::READ_INTERRUPTED => {
+                    let _ = syscall::take_signals();
+                }
+                Err(_) => {
+                    job.pids.remove(0);
+                }
+            }
+        }
+
+        let _ = syscall::set_foreground(self.pgid);
+        if stopped {
+            println!();
+            println!("[{}] stopped  {}", self.jobs.len() + 1, job.command);
+            self.jobs.push(job);
+            status = 128 + Signal::TerminalStop as u32;
+        }
+        status
+    }
+
+    fn run_line(&mut self, line: &str) {
+        match parse::parse(line) {
+            Ok(pipeline) if pipeline.is_empty() => {}
+            Ok(pipeline) => self.run_pipeline(pipeline, line),
+            Err(err) => {
+                eprintln!("shell: {}", err);
+                self.status = 2;
+            }
+        }
+    }
+}
+
+/// Closes the input of a command once it ran, if the shell opened it
+fn close_input(input: &Input) {
+    if let Input::Fd(fd) = input {
+        io::close_own(*fd);
+    }
+}
+
+/// Writes the output of builtins to the programs reading it
+fn flush(feeds: &mut Vec<(u64, Vec<u8>)>) {
+    for (fd, data) in feeds.drain(..) {
+        // Fails once the program stops reading
+        let _ = Output::Fd(fd).write_bytes(&data);
+        io::close_own(fd);
+    }
+}
+
+/// Tells how a command ended if it did not exit by itself, returns its
+/// exit status
+fn report(command: &str, status: ExitStatus) -> u32 {
+    match status {
+        ExitStatus::Exited(code) => code,
+        // Ctrl-C, the tty already showed it
+        ExitStatus::Signaled(signal) if signal == Signal::Interrupt as u32 => 128 + signal,
+        ExitStatus::Signaled(signal) => {
+            eprintln!("shell: {}: killed by signal {}", command, signal);
+            128 + signal
+        }
+        ExitStatus::Faulted(vector) => {
+            eprintln!("shell: {}: crashed with exception {}", command, vector);
+            128 + vector
+        }
+        ExitStatus::Stopped(signal) => 128 + signal,
+    }
+}
+
+#[unsafe(no_mangle)]
+unsafe extern "C" fn main() {
+    // Ctrl-C drops the line, Ctrl-Z is for the commands
+    let _ = syscall::set_signal_action(Signal::Interrupt, SignalAction::Catch);
+    let _ = syscall::set_signal_action(Signal::TerminalStop, SignalAction::Ignore);
+
+    let (_, pgid) = syscall::process_ids().unwrap_or((0, 0));
+    let mut shell = Shell { cwd: String::from(path::HOME), jobs: Vec::new(), status: 0, pgid };
+    let mut editor = editor::Editor::new();
+
+    println!("shinosawa::system::shell: builtins are cd, pwd, ls, cat, echo, ps, mem, jobs, fg, reboot and exit");
+    loop {
+        let prompt = alloc::format!("{}> ", shell.cwd);
+        match editor.read_line(&prompt) {
+            editor::ReadLine::Line(line) => shell.run_line(&line),
+            editor::ReadLine::Interrupted => {}
+            editor::ReadLine::End => break,
+        }
+    }
+
+    for job in &shell.jobs {
+        let _ = syscall::kill(-(job.pgid as i64), Signal::Kill);
+    }
+}
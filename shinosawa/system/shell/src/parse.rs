@@ -0,0 +1,137 @@
+use alloc::{string::String, vec::Vec};
+
+/// A file read or written instead of the tty
+pub enum Redirect {
+    /// `< path`
+    Input(String),
+    /// `> path`, emptied first
+    Output(String),
+    /// `>> path`
+    Append(String),
+}
+
+/// One program or builtin of a pipeline
+#[derive(Default)]
+pub struct Command {
+    pub args: Vec<String>,
+    pub redirects: Vec<Redirect>,
+}
+
+enum Token {
+    Word(String),
+    Pipe,
+    Less,
+    Greater,
+    GreaterGreater,
+}
+
+/// Splits a line into words and operators. Quotes and backslashes keep
+/// spaces and operators in words, `#` starts a comment.
+fn tokenize(line: &str) -> Result<Vec<Token>, &'static str> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            ' ' | '\t' | '\n' => {
+                chars.next();
+            }
+            '#' => break,
+            '|' => {
+                chars.next();
+                tokens.push(Token::Pipe);
+            }
+            '<' => {
+                chars.next();
+                tokens.push(Token::Less);
+            }
+            '>' => {
+                chars.next();
+                if chars.next_if_eq(&'>').is_some() {
+                    tokens.push(Token::GreaterGreater);
+                } else {
+                    tokens.push(Token::Greater);
+                }
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    match c {
+                        ' ' | '\t' | '\n' | '|' | '<' | '>' => break,
+                        '\'' => {
+                            chars.next();
+                            loop {
+                                match chars.next() {
+                                    Some('\'') => break,
+                                    Some(c) => word.push(c),
+                                    None => return Err("missing closing quote"),
+                                }
+                            }
+                        }
+                        '"' => {
+                            chars.next();
+                            loop {
+                                match chars.next() {
+                                    Some('"') => break,
+                                    Some('\\') => word.push(chars.next().ok_or("missing closing quote")?),
+                                    Some(c) => word.push(c),
+                                    None => return Err("missing closing quote"),
+                                }
+                            }
+                        }
+                        '\\' => {
+                            chars.next();
+                            word.push(chars.next().ok_or("nothing after backslash")?);
+                        }
+                        c => {
+                            chars.next();
+                            word.push(c);
+                        }
+                    }
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parses a line into the commands of a pipeline, none for an empty line
+pub fn parse(line: &str) -> Result<Vec<Command>, &'static str> {
+    let mut pipeline = Vec::new();
+    let mut command = Command::default();
+    let mut tokens = tokenize(line)?.into_iter();
+
+    while let Some(token) = tokens.next() {
+        let redirect: fn(String) -> Redirect = match token {
+            Token::Word(word) => {
+                command.args.push(word);
+                continue;
+            }
+            Token::Pipe => {
+                if command.args.is_empty() {
+                    return Err("missing command before |");
+                }
+                pipeline.push(core::mem::take(&mut command));
+                continue;
+            }
+            Token::Less => Redirect::Input,
+            Token::Greater => Redirect::Output,
+            Token::GreaterGreater => Redirect::Append,
+        };
+        match tokens.next() {
+            Some(Token::Word(path)) => command.redirects.push(redirect(path)),
+            _ => return Err("missing file name to redirect to"),
+        }
+    }
+
+    if command.args.is_empty() {
+        if pipeline.is_empty() && command.redirects.is_empty() {
+            return Ok(pipeline);
+        }
+        return Err("missing command");
+    }
+    pipeline.push(command);
+    Ok(pipeline)
+}
@@ -0,0 +1,25 @@
+use alloc::{format, string::String, vec::Vec};
+
+/// Where the shell starts
+pub const HOME: &str = "SNSW:/";
+/// Directories searched for commands given without a path
+pub const SEARCH_PATH: &[&str] = &["SNSW:/shinosawa/system"];
+
+/// Resolves `path` against the directory `cwd` into a full path such as
+/// `TMP:/notes`, with `.` and `..` taken out
+pub fn resolve(cwd: &str, path: &str) -> String {
+    let full = if path.contains(':') { String::from(path) } else { format!("{}/{}", cwd, path) };
+    let (drive, rest) = full.split_at(full.find(':').map_or(0, |colon| colon + 1));
+
+    let mut parts = Vec::new();
+    for part in rest.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    format!("{}/{}", drive, parts.join("/"))
+}
@@ -0,0 +1,81 @@
+//! Kernel log records, as returned by `syscall::read_klog`
+
+use core::{fmt, str};
+
+/// A record starts with its sequence number and timestamp in microseconds (u64),
+/// the level (u8), the target length (u8) and the message length (u16), all little
+/// endian, followed by the target and the message.
+pub const RECORD_HEADER_SIZE: usize = 20;
+/// Largest record the kernel hands out
+pub const MAX_RECORD_SIZE: usize = RECORD_HEADER_SIZE + 255 + 512;
+
+pub const LEVEL_ERROR: u8 = 1;
+pub const LEVEL_WARN: u8 = 2;
+pub const LEVEL_INFO: u8 = 3;
+pub const LEVEL_DEBUG: u8 = 4;
+pub const LEVEL_TRACE: u8 = 5;
+
+pub struct Record<'a> {
+    pub seq: u64,
+    /// Kernel uptime in microseconds
+    pub timestamp: u64,
+    /// One of the `LEVEL_` constants, lower is more severe
+    pub level: u8,
+    pub target: &'a str,
+    pub message: &'a str,
+}
+
+impl<'a> Record<'a> {
+    /// Decodes the record at the start of `bytes`, returning it with its size
+    pub fn decode(bytes: &'a [u8]) -> Option<(Record<'a>, usize)> {
+        let header = bytes.get(..RECORD_HEADER_SIZE)?;
+        let target_len = header[17] as usize;
+        let message_len = u16::from_le_bytes([header[18], header[19]]) as usize;
+        let size = RECORD_HEADER_SIZE + target_len + message_len;
+        let body = bytes.get(RECORD_HEADER_SIZE..size)?;
+
+        let record = Record {
+            seq: u64::from_le_bytes(header[0..8].try_into().unwrap()),
+            timestamp: u64::from_le_bytes(header[8..16].try_into().unwrap()),
+            level: header[16],
+            target: str::from_utf8(&body[..target_len]).ok()?,
+            message: str::from_utf8(&body[target_len..]).ok()?,
+        };
+        Some((record, size))
+    }
+
+    pub fn level_name(&self) -> &'static str {
+        match self.level {
+            LEVEL_ERROR => "ERROR",
+            LEVEL_WARN => "WARN",
+            LEVEL_INFO => "INFO",
+            LEVEL_DEBUG => "DEBUG",
+            LEVEL_TRACE => "TRACE",
+            _ => "?",
+        }
+    }
+}
+
+/// Same format as the kernel uses on the serial port
+impl fmt::Display for Record<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{:>5}.{:06}] {:<5} {}: {}",
+            self.timestamp / 1_000_000,
+            self.timestamp % 1_000_000,
+            self.level_name(),
+            self.target,
+            self.message
+        )
+    }
+}
+
+/// Iterates over the records filled in by `syscall::read_klog`
+pub fn records(mut bytes: &[u8]) -> impl Iterator<Item = Record<'_>> {
+    core::iter::from_fn(move || {
+        let (record, size) = Record::decode(bytes)?;
+        bytes = &bytes[size..];
+        Some(record)
+    })
+}
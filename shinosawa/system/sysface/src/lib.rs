@@ -1,11 +1,16 @@
 #![no_std]
+#![feature(thread_local)]
 
 pub mod syscall;
 
+pub mod thread;
+
 pub mod linked_list;
 
 pub mod memory;
 
+pub mod klog;
+
 use core::arch::asm;
 use core::fmt;
 use core::format_args;
@@ -16,7 +21,8 @@ struct Writer {}
 
 impl fmt::Write for Writer {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        syscall::write(s);
+        // Output cut short by a signal is not worth a panic
+        let _ = syscall::write(s);
         Ok(())
     }
 }
@@ -47,7 +53,10 @@ macro_rules! println {
 
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
-    println!("Oh no!\n{:?}", _info);
+    // Printing can panic too, so only try once per thread
+    if !thread::PANICKING.replace(true) {
+        println!("Oh no!\n{:?}", _info);
+    }
     loop {}
 }
 
@@ -84,11 +93,9 @@ pub unsafe extern "sysv64" fn _start() -> ! {
             options(pure, nomem, nostack)
         );
     }
-    println!("heap start: {:#016X}", heap_start);
-    println!("heap end: {:#016X}", heap_end);
-
+    // Nothing is printed here, the output may be a pipe
     memory::init(heap_start, heap_end);
     unsafe { main() };
 
-    syscall::exit();
+    syscall::exit(0);
 }
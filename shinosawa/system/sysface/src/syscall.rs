@@ -1,4 +1,4 @@
-use core::arch::asm;
+use core::{arch::asm, time::Duration};
 
 
 pub enum Syscall {
@@ -6,24 +6,538 @@ pub enum Syscall {
     Write = 1,
     Fork = 10,
     Exit = 11,
+    ThreadCreate = 12,
+    ArchPrctl = 13,
+    MemInfo = 14,
+    ReadKlog = 15,
+    DisplayInfo = 16,
+    MapDisplay = 17,
+    ReleaseDisplay = 18,
+    SetTermios = 19,
+    SetKeymap = 20,
+    SetTty = 21,
+    GetTermios = 22,
+    OpenPty = 23,
+    ClosePty = 24,
+    ReadPty = 25,
+    WritePty = 26,
+    SetSid = 27,
+    SetPgid = 28,
+    ProcessIds = 29,
+    SetForeground = 30,
+    Kill = 31,
+    SetSignalAction = 32,
+    TakeSignals = 33,
+    Open = 34,
+    Close = 35,
+    ReadFile = 36,
+    WriteFile = 37,
+    Pipe = 38,
+    Dup2 = 39,
+    ReadDir = 40,
+    Spawn = 41,
+    Wait = 42,
+    Args = 43,
+    ProcessList = 44,
+    Reboot = 45,
+    Sleep = 46,
+    Uptime = 47,
     Max = 255,
 }
 
 #[derive(Debug)]
 pub struct SyscallError(u64);
 
-pub fn write(str: &str)  {
+impl SyscallError {
+    pub fn code(&self) -> u64 {
+        self.0
+    }
+}
+
+/// arch_prctl code to set the FS base
+pub const ARCH_SET_FS: u64 = 0x1002;
+/// arch_prctl code to get the FS base
+pub const ARCH_GET_FS: u64 = 0x1003;
+
+/// Error of blocking calls while a caught signal is pending, see `take_signals`
+pub const READ_INTERRUPTED: u64 = 2;
+/// Error of writing to a pipe nobody reads
+pub const BROKEN_PIPE: u64 = 4;
+
+/// Files every process starts with, the tty unless redirected
+pub const STDIN: u64 = 0;
+pub const STDOUT: u64 = 1;
+pub const STDERR: u64 = 2;
+
+/// Reads from `STDIN` into `buf`, see `read_file`.
+///
+/// On a tty in canonical mode this returns up to one line, ending with a
+/// newline unless it was cut short, and 0 at the end of input (Ctrl-D).
+/// Otherwise bytes are returned as they come, with arrow keys as VT100
+/// escape sequences.
+pub fn read(buf: &mut [u8]) -> Result<usize, SyscallError> {
+    let read: usize;
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::Read as u64,
+             in("rdi") buf.as_mut_ptr(),
+             in("rsi") buf.len(),
+             lateout("rax") errcode,
+             lateout("rdi") read,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(read)
+}
+
+/// Kinds of `InputEvent`
+pub const EVENT_KEY: u8 = 0;
+pub const EVENT_MOUSE: u8 = 1;
+
+/// Bits of `InputEvent::modifiers`
+pub const MODIFIER_SHIFT: u16 = 1 << 0;
+pub const MODIFIER_CTRL: u16 = 1 << 1;
+pub const MODIFIER_ALT: u16 = 1 << 2;
+pub const MODIFIER_ALTGR: u16 = 1 << 3;
+pub const MODIFIER_CAPSLOCK: u16 = 1 << 4;
+pub const MODIFIER_NUMLOCK: u16 = 1 << 5;
+
+/// Bits of `InputEvent::buttons`
+pub const BUTTON_LEFT: u8 = 1 << 0;
+pub const BUTTON_RIGHT: u8 = 1 << 1;
+pub const BUTTON_MIDDLE: u8 = 1 << 2;
+
+/// A key going down or up, or the mouse moving, as read from the console
+/// in raw mode
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InputEvent {
+    /// `EVENT_KEY` or `EVENT_MOUSE`
+    pub kind: u8,
+    /// Key: `pc_keyboard::KeyCode` of the key
+    pub code: u8,
+    /// Key: 1 when the key went down, 0 when it went up
+    pub pressed: u8,
+    /// Mouse: `BUTTON_` bits held after the event
+    pub buttons: u8,
+    /// `MODIFIER_` bits at the time of the event
+    pub modifiers: u16,
+    /// Mouse: motion, y grows downwards like on the screen
+    pub dx: i16,
+    pub dy: i16,
+    /// Mouse: wheel steps, positive away from the user
+    pub wheel: i16,
+    /// Key: character the key types with the current layout, 0 if none
+    pub ch: u32,
+}
+
+/// Reads input events from the console with `TERMIOS_EVENTS`, waiting for the first.
+///
+/// Returns how many events were read.
+pub fn read_events(events: &mut [InputEvent]) -> Result<usize, SyscallError> {
+    let buf = unsafe {
+        core::slice::from_raw_parts_mut(events.as_mut_ptr() as *mut u8, core::mem::size_of_val(events))
+    };
+    Ok(read(buf)? / core::mem::size_of::<InputEvent>())
+}
+
+/// Bits of the termios flags of a tty
+/// Lines are edited by the kernel and read whole
+pub const TERMIOS_CANONICAL: u32 = 1 << 0;
+/// What is typed is shown
+pub const TERMIOS_ECHO: u32 = 1 << 1;
+/// Ctrl-C and Ctrl-Z signal the foreground group
+pub const TERMIOS_SIGNALS: u32 = 1 << 2;
+/// Reads from the console return `InputEvent`s, without canonical mode
+pub const TERMIOS_EVENTS: u32 = 1 << 3;
+/// What a tty starts with
+pub const TERMIOS_DEFAULT: u32 = TERMIOS_CANONICAL | TERMIOS_ECHO | TERMIOS_SIGNALS;
+
+/// Returns the `TERMIOS_` flags of the controlling tty
+pub fn get_termios() -> Result<u32, SyscallError> {
+    let flags: u64;
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::GetTermios as u64,
+             lateout("rax") errcode,
+             lateout("rdi") flags,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(flags as u32)
+}
+
+/// Sets the `TERMIOS_` flags of the controlling tty
+pub fn set_termios(flags: u32) -> Result<(), SyscallError> {
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::SetTermios as u64,
+             in("rdi") flags as u64,
+             lateout("rax") errcode,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(())
+}
+
+/// A terminal: the console, the serial port or the slave side of a pty
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tty(pub u64);
+
+impl Tty {
+    /// Keyboard and mouse in, screen out
+    pub const CONSOLE: Tty = Tty(0);
+    /// COM1
+    pub const SERIAL: Tty = Tty(1);
+}
+
+/// Makes `tty` the controlling tty of this process, forked processes inherit it.
+///
+/// A session leader takes the tty over for its session, with its group in
+/// the foreground.
+pub fn set_tty(tty: Tty) -> Result<(), SyscallError> {
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::SetTty as u64,
+             in("rdi") tty.0,
+             lateout("rax") errcode,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(())
+}
+
+/// Opens a pseudo-terminal, returning its slave side.
+///
+/// A program given the slave side as its tty reads what `write_pty` sends,
+/// after line editing, and what it writes comes out of `read_pty`.
+pub fn open_pty() -> Result<Tty, SyscallError> {
+    let id: u64;
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::OpenPty as u64,
+             lateout("rax") errcode,
+             lateout("rdi") id,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(Tty(id))
+}
+
+/// Closes a pseudo-terminal, every later call on it fails
+pub fn close_pty(pty: Tty) -> Result<(), SyscallError> {
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::ClosePty as u64,
+             in("rdi") pty.0,
+             lateout("rax") errcode,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(())
+}
+
+/// Reads what was written to the slave side of `pty`, waiting for some
+pub fn read_pty(pty: Tty, buf: &mut [u8]) -> Result<usize, SyscallError> {
+    let read: usize;
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::ReadPty as u64,
+             in("rdi") pty.0,
+             in("rsi") buf.as_mut_ptr(),
+             in("rdx") buf.len(),
+             lateout("rax") errcode,
+             lateout("rdi") read,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(read)
+}
+
+/// Sends `bytes` to the slave side of `pty` as if they were typed
+pub fn write_pty(pty: Tty, bytes: &[u8]) -> Result<usize, SyscallError> {
+    let written: usize;
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::WritePty as u64,
+             in("rdi") pty.0,
+             in("rsi") bytes.as_ptr(),
+             in("rdx") bytes.len(),
+             lateout("rax") errcode,
+             lateout("rdi") written,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(written)
+}
+
+/// Starts a new session and process group led by this process, without a
+/// controlling tty.
+///
+/// Returns the session id, which is the id of this process.
+pub fn setsid() -> Result<u64, SyscallError> {
+    let session_id: u64;
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::SetSid as u64,
+             lateout("rax") errcode,
+             lateout("rdi") session_id,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(session_id)
+}
+
+/// Moves process `pid`, this one (0) or a child, to group `pgid` of the
+/// same session, or to a new group of its own for 0
+pub fn setpgid(pid: u64, pgid: u64) -> Result<(), SyscallError> {
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::SetPgid as u64,
+             in("rdi") pid,
+             in("rsi") pgid,
+             lateout("rax") errcode,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(())
+}
+
+/// Returns the id of this process and of its group
+pub fn process_ids() -> Result<(u64, u64), SyscallError> {
+    let pid: u64;
+    let pgid: u64;
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::ProcessIds as u64,
+             lateout("rax") errcode,
+             lateout("rdi") pid,
+             lateout("rsi") pgid,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok((pid, pgid))
+}
+
+/// Puts group `pgid` in the foreground of the controlling tty, it must be
+/// in the session of this process
+pub fn set_foreground(pgid: u64) -> Result<(), SyscallError> {
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::SetForeground as u64,
+             in("rdi") pgid,
+             lateout("rax") errcode,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(())
+}
+
+/// Signals, numbered as on Linux
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Signal {
+    /// Ctrl-C
+    Interrupt = 2,
+    /// Cannot be caught or ignored
+    Kill = 9,
+    Terminate = 15,
+    /// Resumes a stopped process
+    Continue = 18,
+    /// Ctrl-Z
+    TerminalStop = 20,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignalAction {
+    /// Continue resumes, TerminalStop stops, the others kill
+    Default = 0,
+    Ignore = 1,
+    /// Kept pending for `take_signals`, blocking tty calls fail with
+    /// `READ_INTERRUPTED` meanwhile
+    Catch = 2,
+}
+
+/// Sends `signal` to process `target`, to group `-target`, or to the group
+/// of this process for 0
+pub fn kill(target: i64, signal: Signal) -> Result<(), SyscallError> {
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::Kill as u64,
+             in("rdi") target,
+             in("rsi") signal as u64,
+             lateout("rax") errcode,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(())
+}
+
+/// Sets what `signal` does to this process, forked processes inherit it
+pub fn set_signal_action(signal: Signal, action: SignalAction) -> Result<(), SyscallError> {
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::SetSignalAction as u64,
+             in("rdi") signal as u64,
+             in("rsi") action as u64,
+             lateout("rax") errcode,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(())
+}
+
+/// Returns the caught signals as bits by signal number, and clears them
+pub fn take_signals() -> Result<u32, SyscallError> {
+    let signals: u64;
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::TakeSignals as u64,
+             lateout("rax") errcode,
+             lateout("rdi") signals,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(signals as u32)
+}
+
+/// Switches the keyboard layout, by the names `keymap=` takes such as `us` or `de`
+pub fn set_keymap(name: &str) -> Result<(), SyscallError> {
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::SetKeymap as u64,
+             in("rdi") name.as_ptr(),
+             in("rsi") name.len(),
+             lateout("rax") errcode,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(())
+}
+
+/// Writes to `STDOUT`, see `write_file`
+pub fn write(str: &str) -> Result<usize, SyscallError> {
+    let written: usize;
+    let errcode: u64;
     unsafe {
         asm!( // syscall function
              "syscall",
              in("rax") Syscall::Write as u64,
              in("rdi") str.as_ptr(), // First argument
-             in("rsi") str.len()); // Second argument
+             in("rsi") str.len(), // Second argument
+             lateout("rax") errcode,
+             lateout("rdi") written,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
     }
+    Ok(written)
+}
 
+/// Forks the current process.
+///
+/// Returns 0 in the new process, and the id of the new process in the caller.
+pub fn fork() -> Result<u64, SyscallError> {
+    let pid: u64;
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::Fork as u64,
+             lateout("rax") errcode,
+             lateout("rdi") pid,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(pid)
 }
 
-pub fn fork(
+/// Starts a new thread in the current process running `func(param)`.
+pub fn thread_create(
     func: extern "C" fn(usize) -> (),
     param: usize
 ) -> Result<u64, SyscallError> {
@@ -42,11 +556,13 @@ pub fn fork(
              // New thread
              "mov rdi, r9", // Function argument
              "call r8",
-             "mov rax, 1", // exit_current_thread syscall
+             "xor edi, edi", // Exit code
+             "mov rax, {exit}", // exit_current_thread syscall
              "syscall",
              // New thread never leaves this asm block
              "2:",
-             in("rax") Syscall::Fork as u64,
+             exit = const Syscall::Exit as u64,
+             in("rax") Syscall::ThreadCreate as u64,
              in("r8") func,
              in("r9") param,
              lateout("rax") errcode,
@@ -60,10 +576,615 @@ pub fn fork(
     Ok(tid)
 }
 
-pub fn exit() -> ! {
+/// Gets or sets architecture-specific thread state, such as the FS base.
+pub fn arch_prctl(code: u64, addr: u64) -> Result<u64, SyscallError> {
+    let value: u64;
+    let errcode: u64;
     unsafe {
-        asm!("syscall",
-             in("rax") Syscall::Exit as u64,
+        asm!(
+             "syscall",
+             in("rax") Syscall::ArchPrctl as u64,
+             in("rdi") code,
+             in("rsi") addr,
+             lateout("rax") errcode,
+             lateout("rdi") value,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(value)
+}
+
+/// Memory usage counters, as filled in by the kernel
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MemInfo {
+    /// Usable physical frames
+    pub total_frames: u64,
+    pub free_frames: u64,
+    /// Frames backing user pages, over all processes
+    pub user_frames: u64,
+    /// Frames holding user page tables, over all processes
+    pub page_table_frames: u64,
+
+    /// Bytes of mapped kernel heap
+    pub heap_size: u64,
+    /// Bytes of kernel heap handed out
+    pub heap_used: u64,
+    pub heap_allocations: u64,
+    pub heap_deallocations: u64,
+
+    /// User pages mapped by this process
+    pub process_resident_pages: u64,
+    /// Resident pages shared with another process after a fork
+    pub process_shared_pages: u64,
+    /// Page tables of this process
+    pub process_page_tables: u64,
+}
+
+/// Returns the kernel's memory usage counters
+pub fn meminfo() -> Result<MemInfo, SyscallError> {
+    let mut info = MemInfo::default();
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::MemInfo as u64,
+             in("rdi") &mut info as *mut MemInfo,
+             lateout("rax") errcode,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(info)
+}
+
+/// Copies kernel log records numbered `cursor` and later into `buf`, see `klog`.
+///
+/// Returns the bytes written and the cursor to continue from. A buffer of
+/// `klog::MAX_RECORD_SIZE` bytes always fits the next record.
+pub fn read_klog(cursor: u64, buf: &mut [u8]) -> Result<(usize, u64), SyscallError> {
+    let written: usize;
+    let next: u64;
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::ReadKlog as u64,
+             in("rdi") cursor,
+             in("rsi") buf.as_mut_ptr(),
+             in("rdx") buf.len(),
+             lateout("rax") errcode,
+             lateout("rdi") written,
+             lateout("rsi") next,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok((written, next))
+}
+
+/// Mode of the display, as filled in by the kernel
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DisplayMode {
+    pub width: u32,
+    pub height: u32,
+    /// Bytes from one row to the next
+    pub pitch: u32,
+    pub bpp: u16,
+    pub red_size: u8,
+    pub red_shift: u8,
+    pub green_size: u8,
+    pub green_shift: u8,
+    pub blue_size: u8,
+    pub blue_shift: u8,
+}
+
+/// Returns the mode of the display
+pub fn display_info() -> Result<DisplayMode, SyscallError> {
+    let mut mode = DisplayMode::default();
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::DisplayInfo as u64,
+             in("rdi") &mut mode as *mut DisplayMode,
+             lateout("rax") errcode,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(mode)
+}
+
+/// Maps the framebuffer into this process, write-combining, and takes the
+/// screen over from the kernel console.
+///
+/// Returns the start of the framebuffer, `pitch * height` bytes long. Fails
+/// with error 2 if another process owns the display.
+pub fn map_display() -> Result<*mut u8, SyscallError> {
+    let address: u64;
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::MapDisplay as u64,
+             lateout("rax") errcode,
+             lateout("rdi") address,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(address as *mut u8)
+}
+
+/// Unmaps the framebuffer and gives the screen back to the kernel console.
+///
+/// This also happens when the process ends.
+pub fn release_display() {
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::ReleaseDisplay as u64,
+             lateout("rax") _,
+             out("rcx") _,
+             out("r11") _);
+    }
+}
+
+/// Ends the current thread, the last thread sets the exit code of the process
+pub fn exit(code: u32) -> ! {
+    unsafe {
+        asm!("syscall",
+             in("rax") Syscall::Exit as u64,
+             in("rdi") code as u64,
+             options(noreturn));
+    }
+}
+
+/// Flags of `open`
+pub const OPEN_WRITE: u64 = 1 << 0;
+/// Creates the file if it is missing, in a directory that can be written
+pub const OPEN_CREATE: u64 = 1 << 1;
+/// Empties the file, when opened for writing
+pub const OPEN_TRUNCATE: u64 = 1 << 2;
+
+/// Opens a file or directory by its full path, such as `TMP:/notes`.
+///
+/// Files opened for writing are only appended to. Returns the number of
+/// the file, the lowest one free.
+pub fn open(path: &str, flags: u64) -> Result<u64, SyscallError> {
+    let fd: u64;
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::Open as u64,
+             in("rdi") path.as_ptr(),
+             in("rsi") path.len(),
+             in("rdx") flags,
+             lateout("rax") errcode,
+             lateout("rdi") fd,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(fd)
+}
+
+pub fn close(fd: u64) -> Result<(), SyscallError> {
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::Close as u64,
+             in("rdi") fd,
+             lateout("rax") errcode,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(())
+}
+
+/// Reads from file `fd` into `buf`, waiting for input, and on a tty for
+/// the group of this process to be in the foreground.
+///
+/// Returns 0 at the end of a file or of a pipe nobody writes to. Fails with
+/// `READ_INTERRUPTED` while a caught signal is pending.
+pub fn read_file(fd: u64, buf: &mut [u8]) -> Result<usize, SyscallError> {
+    let read: usize;
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::ReadFile as u64,
+             in("rdi") fd,
+             in("rsi") buf.as_mut_ptr(),
+             in("rdx") buf.len(),
+             lateout("rax") errcode,
+             lateout("rdi") read,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(read)
+}
+
+/// Writes `bytes` to file `fd`, waiting for room in a pipe.
+///
+/// Returns the bytes written, fewer if a caught signal came in while
+/// waiting. Fails with `BROKEN_PIPE` for a pipe nobody reads.
+pub fn write_file(fd: u64, bytes: &[u8]) -> Result<usize, SyscallError> {
+    let written: usize;
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::WriteFile as u64,
+             in("rdi") fd,
+             in("rsi") bytes.as_ptr(),
+             in("rdx") bytes.len(),
+             lateout("rax") errcode,
+             lateout("rdi") written,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(written)
+}
+
+/// Opens a pipe, returns the numbers of its read end and of its write end
+pub fn pipe() -> Result<(u64, u64), SyscallError> {
+    let read_fd: u64;
+    let write_fd: u64;
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::Pipe as u64,
+             lateout("rax") errcode,
+             lateout("rdi") read_fd,
+             lateout("rsi") write_fd,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok((read_fd, write_fd))
+}
+
+/// Makes `new_fd` refer to the same file as `fd`, closing what was there
+pub fn dup2(fd: u64, new_fd: u64) -> Result<(), SyscallError> {
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::Dup2 as u64,
+             in("rdi") fd,
+             in("rsi") new_fd,
+             lateout("rax") errcode,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(())
+}
+
+/// Kinds of `DirEntry`
+pub const ENTRY_FILE: u32 = 0;
+pub const ENTRY_DIR: u32 = 1;
+
+/// An entry of a directory, as filled in by the kernel
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct DirEntry {
+    /// `ENTRY_FILE` or `ENTRY_DIR`
+    pub kind: u32,
+    pub name_len: u32,
+    /// Longer names are cut
+    pub name: [u8; 56],
+}
+
+impl DirEntry {
+    pub const EMPTY: DirEntry = DirEntry { kind: ENTRY_FILE, name_len: 0, name: [0; 56] };
+
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_len as usize]).unwrap_or("?")
+    }
+}
+
+/// Fills `entries` with the next entries of directory `fd`.
+///
+/// Returns how many were filled, 0 once all were read.
+pub fn read_dir(fd: u64, entries: &mut [DirEntry]) -> Result<usize, SyscallError> {
+    let count: usize;
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::ReadDir as u64,
+             in("rdi") fd,
+             in("rsi") entries.as_mut_ptr(),
+             in("rdx") entries.len(),
+             lateout("rax") errcode,
+             lateout("rdi") count,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(count)
+}
+
+/// Leaves a file of a spawned process closed
+pub const SPAWN_NO_FILE: u64 = u64::MAX;
+/// Puts a spawned process in the group of this process
+pub const SPAWN_SAME_GROUP: u64 = 0;
+/// Puts a spawned process in a new group it leads
+pub const SPAWN_NEW_GROUP: u64 = u64::MAX;
+
+/// What the kernel reads to spawn a process
+#[repr(C)]
+struct SpawnRequest {
+    path: u64,
+    path_len: u64,
+    args: u64,
+    args_len: u64,
+    files: [u64; 3],
+    group: u64,
+}
+
+/// Starts the executable at `path` as a child process.
+///
+/// `args` are separated by NUL bytes, see `args`. The child gets files
+/// `files` of this process as `STDIN`, `STDOUT` and `STDERR`, and joins
+/// `group`: `SPAWN_SAME_GROUP`, `SPAWN_NEW_GROUP` or a group of the session.
+/// Returns the id of the new process.
+pub fn spawn(path: &str, args: &[u8], files: [u64; 3], group: u64) -> Result<u64, SyscallError> {
+    let request = SpawnRequest {
+        path: path.as_ptr() as u64,
+        path_len: path.len() as u64,
+        args: args.as_ptr() as u64,
+        args_len: args.len() as u64,
+        files,
+        group,
+    };
+    let pid: u64;
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::Spawn as u64,
+             in("rdi") &request as *const SpawnRequest,
+             lateout("rax") errcode,
+             lateout("rdi") pid,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(pid)
+}
+
+/// Flag of `wait`, to also return children that stopped
+pub const WAIT_STOPPED: u64 = 1 << 0;
+/// Flag of `wait`, to return a pid of 0 at once when no child has ended yet
+pub const WAIT_NO_HANG: u64 = 1 << 1;
+
+/// What happened to a child process
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitStatus {
+    Exited(u32),
+    /// Killed by a signal, by number
+    Signaled(u32),
+    /// Killed by a CPU exception, by vector
+    Faulted(u32),
+    /// Stopped by a signal, by number, and can be continued
+    Stopped(u32),
+}
+
+impl ExitStatus {
+    fn from_raw(status: u64) -> ExitStatus {
+        let value = status as u32;
+        match status >> 32 {
+            0 => ExitStatus::Exited(value),
+            1 => ExitStatus::Signaled(value),
+            2 => ExitStatus::Faulted(value),
+            _ => ExitStatus::Stopped(value),
+        }
+    }
+}
+
+/// Waits for child process `pid` to end, or any child for 0, and with
+/// `WAIT_STOPPED` also for one to stop.
+///
+/// Returns the id of the child with what happened to it. Fails with 1
+/// without such children, and with `READ_INTERRUPTED` while a caught
+/// signal is pending.
+pub fn wait(pid: u64, flags: u64) -> Result<(u64, ExitStatus), SyscallError> {
+    let child: u64;
+    let status: u64;
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::Wait as u64,
+             in("rdi") pid,
+             in("rsi") flags,
+             lateout("rax") errcode,
+             lateout("rdi") child,
+             lateout("rsi") status,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok((child, ExitStatus::from_raw(status)))
+}
+
+/// Copies the arguments this process was spawned with into `buf`, as much
+/// as fits, separated by NUL bytes.
+///
+/// Returns the length of all the arguments.
+pub fn args(buf: &mut [u8]) -> Result<usize, SyscallError> {
+    let len: usize;
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::Args as u64,
+             in("rdi") buf.as_mut_ptr(),
+             in("rsi") buf.len(),
+             lateout("rax") errcode,
+             lateout("rdi") len,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(len)
+}
+
+/// Splits arguments as returned by `args`
+pub fn split_args(args: &[u8]) -> impl Iterator<Item = &str> {
+    args.split(|&byte| byte == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| core::str::from_utf8(arg).unwrap_or(""))
+}
+
+/// States of `ProcessInfo`
+pub const PROCESS_RUNNING: u32 = 0;
+/// Every thread is waiting
+pub const PROCESS_SLEEPING: u32 = 1;
+pub const PROCESS_STOPPED: u32 = 2;
+
+/// A user process, as filled in by the kernel
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ProcessInfo {
+    pub pid: u64,
+    pub parent_pid: u64,
+    pub pgid: u64,
+    pub session_id: u64,
+    pub threads: u32,
+    /// One of the `PROCESS_` states
+    pub state: u32,
+    /// Start of the name, padded with zeroes
+    pub name: [u8; 32],
+}
+
+impl ProcessInfo {
+    pub const EMPTY: ProcessInfo = ProcessInfo {
+        pid: 0,
+        parent_pid: 0,
+        pgid: 0,
+        session_id: 0,
+        threads: 0,
+        state: PROCESS_RUNNING,
+        name: [0; 32],
+    };
+
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&byte| byte == 0).unwrap_or(self.name.len());
+        core::str::from_utf8(&self.name[..len]).unwrap_or("?")
+    }
+}
+
+/// Fills `processes` with the user processes.
+///
+/// Returns how many there are, which may be more than fit.
+pub fn process_list(processes: &mut [ProcessInfo]) -> Result<usize, SyscallError> {
+    let count: usize;
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::ProcessList as u64,
+             in("rdi") processes.as_mut_ptr(),
+             in("rsi") processes.len(),
+             lateout("rax") errcode,
+             lateout("rdi") count,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(count)
+}
+
+/// Blocks the calling thread for `duration`, to the millisecond.
+///
+/// Fails with `READ_INTERRUPTED` if a caught signal is pending before the
+/// time is up.
+pub fn sleep(duration: Duration) -> Result<(), SyscallError> {
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::Sleep as u64,
+             in("rdi") duration.as_millis() as u64,
+             lateout("rax") errcode,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(())
+}
+
+/// Time since boot, to the millisecond
+pub fn uptime() -> Result<Duration, SyscallError> {
+    let millis: u64;
+    let errcode: u64;
+    unsafe {
+        asm!(
+             "syscall",
+             in("rax") Syscall::Uptime as u64,
+             lateout("rax") errcode,
+             lateout("rdi") millis,
+             out("rcx") _,
+             out("r11") _);
+    }
+    if errcode != 0 {
+        return Err(SyscallError(errcode));
+    }
+    Ok(Duration::from_millis(millis))
+}
+
+/// Restarts the machine
+pub fn reboot() -> ! {
+    unsafe {
+        asm!("syscall",
+             in("rax") Syscall::Reboot as u64,
              options(noreturn));
     }
 }
\ No newline at end of file
@@ -0,0 +1,24 @@
+use core::cell::Cell;
+
+use crate::syscall::{self, SyscallError, ARCH_GET_FS, ARCH_SET_FS};
+
+/// Whether the current thread is already panicking
+#[thread_local]
+pub(crate) static PANICKING: Cell<bool> = Cell::new(false);
+
+/// Returns the thread pointer of the current thread.
+///
+/// The kernel places the `#[thread_local]` data of each thread right below it.
+pub fn thread_pointer() -> Result<u64, SyscallError> {
+    syscall::arch_prctl(ARCH_GET_FS, 0)
+}
+
+/// Points the current thread to another thread-local storage block.
+///
+/// # Safety
+///
+/// `addr` must point to a valid thread control block, whose first word
+/// contains its own address, with the TLS block right below it.
+pub unsafe fn set_thread_pointer(addr: u64) -> Result<(), SyscallError> {
+    syscall::arch_prctl(ARCH_SET_FS, addr).map(|_| ())
+}
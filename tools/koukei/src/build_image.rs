@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap, fs::{self, File}, io::{self, Seek}
+    collections::HashMap, fs::{self, File}, io::{self, Seek, Write}
 };
 
 use fatfs::Dir;
@@ -9,11 +9,32 @@ pub const IMAGE_FILE: &str = "target/shinosawa.img";
 const TARGET: &str = "x86_64-shinosawa";
 const EFI_ROOT: &str = "efi_root";
 const FAT_FILE: &str = "target/shinosawa-rootfs.img";
+const LIMINE_CONF: &str = "limine/limine.conf";
 
 const PART_SIZE: u64 = 100 * 1024 * 1024; // 100 MB
 const DISK_SIZE: u64 = PART_SIZE + 1024 * 64; // for GPT headers
 
-fn copy_efi_root(root_dir: &Dir<'_, &File>) {
+/// Sets the kernel command line of every entry in limine.conf,
+/// replacing the `cmdline` that is there
+fn limine_conf_with_cmdline(conf: &str, cmdline: &str) -> String {
+    let mut result = String::new();
+    for line in conf.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("cmdline:") {
+            continue;
+        }
+        result.push_str(line);
+        result.push('\n');
+
+        if trimmed.starts_with("kernel_path:") {
+            let indent = &line[..line.len() - trimmed.len()];
+            result.push_str(&format!("{}cmdline: {}\n", indent, cmdline));
+        }
+    }
+    result
+}
+
+fn copy_efi_root(root_dir: &Dir<'_, &File>, cmdline: Option<&str>) {
     use walkdir::WalkDir;
 
     for entry in WalkDir::new(EFI_ROOT).min_depth(1).into_iter().filter_map(|e| e.ok()) {
@@ -26,7 +47,16 @@ fn copy_efi_root(root_dir: &Dir<'_, &File>) {
             println!("rootfs: cp {} {}", actual_path, entry_path);
             let mut file = root_dir.create_file(entry_path).unwrap();
             file.truncate().unwrap();
-            io::copy(&mut fs::File::open(actual_path).unwrap(), &mut file).unwrap();
+            match cmdline.filter(|_| entry_path == LIMINE_CONF) {
+                Some(cmdline) => {
+                    println!("rootfs: cmdline: {}", cmdline);
+                    let conf = fs::read_to_string(actual_path).unwrap();
+                    file.write_all(limine_conf_with_cmdline(&conf, cmdline).as_bytes()).unwrap();
+                }
+                None => {
+                    io::copy(&mut fs::File::open(actual_path).unwrap(), &mut file).unwrap();
+                }
+            }
         }
     }
 }
@@ -45,7 +75,7 @@ fn copy_shinosawa_system_files(root_dir: Dir<'_, &File>, entries: HashMap<String
     }
 }
 
-fn create_fat_image(kernel_path: String) {
+fn create_fat_image(kernel_path: String, cmdline: Option<String>) {
     // create new filesystem image file at the given path and set its length
     let fat_file = fs::OpenOptions::new()
         .read(true)
@@ -64,12 +94,14 @@ fn create_fat_image(kernel_path: String) {
 
     // copy EFI file to FAT filesystem
     let root_dir = filesystem.root_dir();
-    copy_efi_root(&root_dir);
+    copy_efi_root(&root_dir, cmdline.as_deref());
     create_shinosawa_layout(&root_dir);
 
     let mut files = HashMap::new();
     files.insert(kernel_path, String::from("shinosawa/system/kernel"));
     files.insert(String::from("target/x86_64-shinosawa/debug/kotono"), String::from("shinosawa/system/kotono"));
+    files.insert(String::from("target/x86_64-shinosawa/debug/shell"), String::from("shinosawa/system/shell"));
+    files.insert(String::from("shinosawa/config"), String::from("shinosawa/config"));
     copy_shinosawa_system_files(root_dir, files);
 }
 
@@ -125,14 +157,14 @@ fn create_gpt_image() {
     io::copy(&mut File::open(&FAT_FILE).unwrap(), &mut disk_image).unwrap();
 }
 
-pub fn command(profile: String, kernel_image: Option<String>) {
+pub fn command(profile: String, kernel_image: Option<String>, cmdline: Option<String>) {
     let kernel_path = match kernel_image {
         Some(str) => str,
         None => format!("target/{}/{}/kernel", TARGET, profile),
     };
     println!("using kernel {}", kernel_path);
 
-    create_fat_image(kernel_path);
+    create_fat_image(kernel_path, cmdline);
     create_gpt_image();
 
     // Cleanup
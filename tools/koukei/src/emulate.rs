@@ -30,6 +30,12 @@ pub fn command(image: String) {
             "1G",
             "-serial",
             "stdio",
+            // COM2, for the kernel GDB stub
+            "-serial",
+            "tcp::4444,server=on,wait=off",
+            // PCI device with MSI, for the kernel tests
+            "-device",
+            "edu",
             "-s",
             // "-d",
             // "int"
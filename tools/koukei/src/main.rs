@@ -32,6 +32,9 @@ enum Commands {
         /// Kernel image for use
         #[clap(long, short)]
         kernel_image: Option<String>,
+        /// Kernel command line, written into limine.conf
+        #[clap(long)]
+        cmdline: Option<String>,
     },
     /// Start a virtual machine for debugging
     Emulate {
@@ -49,8 +52,8 @@ fn main() {
     }
 
     match &cli.command {
-        Commands::BuildImage { profile, kernel_image} => {
-            build_image::command(profile.to_owned(), kernel_image.to_owned());
+        Commands::BuildImage { profile, kernel_image, cmdline } => {
+            build_image::command(profile.to_owned(), kernel_image.to_owned(), cmdline.to_owned());
         }
         Commands::Emulate { image} => emulate::command(image.to_string())
     }